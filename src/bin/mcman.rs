@@ -1,24 +1,39 @@
 #![feature(stmt_expr_attributes)]
 
-use clap::{App, Arg, ArgMatches, SubCommand};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 use interprocess::local_socket::LocalSocketStream;
-use ipc_channel::ipc::{IpcOneShotServer, IpcReceiver, IpcSender};
+use ipc_channel::ipc::{IpcError, IpcOneShotServer, IpcReceiver, IpcSender};
 use mcman::config::DaemonConfig;
-use mcman::ipc::{DaemonCmd, DaemonIpcEvent, DaemonResponse, NewConnection, ServerEvent};
+use mcman::daemon::backup::BackupInfo;
+use mcman::daemon::crash::CrashReport;
+use mcman::daemon::journal::{JournalEntry, JournalRecord};
+use mcman::daemon::logs::LineFilter;
+use mcman::daemon::notifier;
+use mcman::daemon::records::{LogLevel, LogRecord, RecordParser};
+use mcman::ipc::{
+    DaemonCmd, DaemonIpcEvent, DaemonResponse, NewConnection, ServerEvent, ServerEventType,
+};
 use mcman::ServerType;
 use regex::Regex;
+use rustyline::error::ReadlineError;
+use rustyline::{Editor, ExternalPrinter};
 use semver::Version;
 use std::error::Error;
 use std::io::Write;
 #[cfg(not(debug_assertions))]
 use std::os::unix::io::AsRawFd;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::thread::spawn;
 use term_table::row::Row;
 use term_table::table_cell::TableCell;
 use term_table::{Table, TableStyle};
 
+/// The file in the home directory in which the input history of `mcman attach` is stored.
+const HISTORY_FILE: &str = ".mcman_history";
+
 fn main() {
     let matches = matches();
     //println!("parsed arguments");
@@ -59,6 +74,8 @@ fn main() {
         client.start(args);
     } else if cmd == "stop" {
         client.stop(args);
+    } else if cmd == "restart" {
+        client.restart(args);
     } else if cmd == "install" {
         client.install(args);
     } else if cmd == "update" {
@@ -69,6 +86,30 @@ fn main() {
         client.say(args);
     } else if cmd == "cmd" {
         client.cmd(args);
+    } else if cmd == "events" {
+        client.events(args);
+    } else if cmd == "reload" {
+        client.reload();
+    } else if cmd == "attach" {
+        client.attach(args);
+    } else if cmd == "logs" {
+        client.logs(args);
+    } else if cmd == "players" {
+        client.players(args);
+    } else if cmd == "status" {
+        client.status(args);
+    } else if cmd == "stats" {
+        client.stats(args);
+    } else if cmd == "history" {
+        client.history(args);
+    } else if cmd == "backup" {
+        client.backup(args);
+    } else if cmd == "restore" {
+        client.restore(args);
+    } else if cmd == "lockdown" {
+        client.lockdown(args);
+    } else if cmd == "unlock" {
+        client.unlock(args);
     } else {
         eprintln!("unknown subcommand: {}", cmd);
     }
@@ -108,6 +149,15 @@ fn matches() -> ArgMatches<'static> {
                         .long("no-wait"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("restart")
+                .about("Restart a server")
+                .arg(
+                    Arg::with_name("server-id")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("install")
                 .about("Install a new server")
@@ -157,7 +207,13 @@ fn matches() -> ArgMatches<'static> {
                         .short("t")
                         .takes_value(true)
                         .required(true)
-                        .possible_value("paper"),
+                        .possible_value("paper")
+                        .possible_value("vanilla")
+                        .possible_value("spigot")
+                        .possible_value("bukkit")
+                        .possible_value("folia")
+                        .possible_value("velocity")
+                        .possible_value("waterfall"),
                 ).arg(
                 Arg::with_name("eula")
                     .help("Writes the accept eula file to the installation directory. Only set this option, if you have read the EULA.")
@@ -228,6 +284,290 @@ fn matches() -> ArgMatches<'static> {
                     .takes_value(true)
                     .required(true)
             ))
+        .subcommand(SubCommand::with_name("events")
+            .about("Stream the events of all servers and the daemon")
+            .arg(
+                Arg::with_name("unit-id")
+                    .help("Only stream the events of these units")
+                    .short("u")
+                    .long("unit-id")
+                    .takes_value(true)
+                    .multiple(true),
+            )
+            .arg(
+                Arg::with_name("json")
+                    .help("Print every event as a JSON object")
+                    .long("json"),
+            )
+            .arg(
+                Arg::with_name("console")
+                    .help("Include the console output of the servers")
+                    .long("console"),
+            )
+            .arg(
+                Arg::with_name("custom")
+                    .help("Only stream the custom events of triggers with these names")
+                    .long("custom")
+                    .takes_value(true)
+                    .multiple(true),
+            ))
+        .subcommand(SubCommand::with_name("attach")
+            .about("Attach to the console of a server, press Ctrl-D to detach")
+            .arg(
+                Arg::with_name("unit-id")
+                    .help("The unit id of the server")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("lines")
+                    .help("The number of previous output lines to show")
+                    .short("n")
+                    .long("lines")
+                    .takes_value(true)
+                    .default_value("50")
+                    .validator(|str| {
+                        str.parse::<usize>()
+                            .map(|_| ())
+                            .map_err(|_| "expected a number".to_string())
+                    }),
+            ))
+        .subcommand(SubCommand::with_name("players")
+            .about("List the players that are online on a server")
+            .arg(
+                Arg::with_name("unit-id")
+                    .help("The unit id of the server")
+                    .takes_value(true)
+                    .required(true),
+            ))
+        .subcommand(SubCommand::with_name("stats")
+            .about("Show the tick rate history of a running server")
+            .arg(
+                Arg::with_name("unit-id")
+                    .help("The unit id of the server")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("samples")
+                    .help("The number of poll intervals to show")
+                    .short("n")
+                    .long("samples")
+                    .takes_value(true)
+                    .default_value("10")
+                    .validator(|str| {
+                        str.parse::<usize>()
+                            .map(|_| ())
+                            .map_err(|_| "expected a number".to_string())
+                    }),
+            ))
+        .subcommand(SubCommand::with_name("history")
+            .about("Show the journal of commands and events")
+            .arg(
+                Arg::with_name("unit-id")
+                    .help("Only show the entries of this unit")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("since")
+                    .help("Only show entries since a time (e.g. \"2021-03-01 03:00\") or for a duration (e.g. 30m, 12h, 7d)")
+                    .long("since")
+                    .takes_value(true)
+                    .validator(|str| {
+                        parse_since(&str)
+                            .map(|_| ())
+                            .ok_or_else(|| "expected a time or a duration".to_string())
+                    }),
+            )
+            .arg(
+                Arg::with_name("type")
+                    .help("Only show entries of these types, e.g. Stop, ServerFailed, command or event")
+                    .long("type")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("limit")
+                    .help("The maximum number of entries to show, 0 shows all entries")
+                    .short("n")
+                    .long("limit")
+                    .takes_value(true)
+                    .default_value("50")
+                    .validator(|str| {
+                        str.parse::<usize>()
+                            .map(|_| ())
+                            .map_err(|_| "expected a number".to_string())
+                    }),
+            ))
+        .subcommand(SubCommand::with_name("backup")
+            .about("Back up the worlds of a server, list or prune backups")
+            .setting(AppSettings::ArgsNegateSubcommands)
+            .setting(AppSettings::SubcommandsNegateReqs)
+            .subcommand(SubCommand::with_name("list")
+                .about("List the backups of a unit")
+                .arg(
+                    Arg::with_name("unit-id")
+                        .help("The unit id of the server")
+                        .takes_value(true)
+                        .required(true),
+                ))
+            .subcommand(SubCommand::with_name("prune")
+                .about("Delete the backups that are not kept by the retention policies of the units")
+                .arg(
+                    Arg::with_name("unit-id")
+                        .help("Only prune the backups of this unit")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .help("Only show the backups that would be deleted")
+                        .long("dry-run"),
+                ))
+            .subcommand(SubCommand::with_name("verify")
+                .about("Check that backups can be restored")
+                .arg(
+                    Arg::with_name("unit-id")
+                        .help("Only verify the backups of this unit")
+                        .takes_value(true),
+                ))
+            .subcommand(SubCommand::with_name("gc")
+                .about("Delete the chunks that no chunked backup refers to anymore")
+                .arg(
+                    Arg::with_name("dry-run")
+                        .help("Only show the size of the chunks that would be deleted")
+                        .long("dry-run"),
+                ))
+            .arg(
+                Arg::with_name("unit-id")
+                    .help("The unit id of the server")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("label")
+                    .help("A label that describes the backup")
+                    .short("l")
+                    .long("label")
+                    .takes_value(true),
+            ))
+        .subcommand(SubCommand::with_name("restore")
+            .about("Restore a backup of a server, the current worlds are backed up first")
+            .arg(
+                Arg::with_name("unit-id")
+                    .help("The unit id of the server")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("backup-id")
+                    .help("The id of the backup, see `mcman backup list`")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("jar")
+                    .help("Restore the server jar as well")
+                    .long("jar"),
+            )
+            .arg(
+                Arg::with_name("config")
+                    .help("Restore the unit file as well")
+                    .long("config"),
+            ))
+        .subcommand(SubCommand::with_name("lockdown")
+            .about("Kick all players, save and suspend a server and take a snapshot of its worlds")
+            .arg(
+                Arg::with_name("unit-id")
+                    .help("The unit id of the server")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("message")
+                    .help("The message the players are kicked with")
+                    .short("m")
+                    .long("message")
+                    .takes_value(true),
+            ))
+        .subcommand(SubCommand::with_name("unlock")
+            .about("Resume a server in lockdown")
+            .arg(
+                Arg::with_name("unit-id")
+                    .help("The unit id of the server")
+                    .takes_value(true)
+                    .required(true),
+            ))
+        .subcommand(SubCommand::with_name("status")
+            .about("Show the status and the last failure of a server")
+            .arg(
+                Arg::with_name("unit-id")
+                    .help("The unit id of the server")
+                    .takes_value(true)
+                    .required(true),
+            ))
+        .subcommand(SubCommand::with_name("logs")
+            .about("Show or search the logs of a server")
+            .arg(
+                Arg::with_name("unit-id")
+                    .help("The unit id of the server")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("lines")
+                    .help("The number of lines to show")
+                    .short("n")
+                    .long("lines")
+                    .takes_value(true)
+                    .default_value("50")
+                    .validator(|str| {
+                        str.parse::<usize>()
+                            .map(|_| ())
+                            .map_err(|_| "expected a number".to_string())
+                    }),
+            )
+            .arg(
+                Arg::with_name("follow")
+                    .help("Keep printing new output of the server")
+                    .short("f")
+                    .long("follow")
+                    .conflicts_with("session"),
+            )
+            .arg(
+                Arg::with_name("grep")
+                    .help("Only show lines matching this regular expression")
+                    .long("grep")
+                    .takes_value(true)
+                    .validator(|str| Regex::new(&str).map(|_| ()).map_err(|e| e.to_string())),
+            )
+            .arg(
+                Arg::with_name("level")
+                    .help("Only show lines with at least this level (trace, debug, info, warn, error, fatal)")
+                    .long("level")
+                    .takes_value(true)
+                    .validator(|str| str.parse::<LogLevel>().map(|_| ())),
+            )
+            .arg(
+                Arg::with_name("json")
+                    .help("Print the lines as JSON records with time, thread, level, logger and message")
+                    .long("json"),
+            )
+            .arg(
+                Arg::with_name("session")
+                    .help("The log session to show, the newest session if not set")
+                    .long("session")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("sessions")
+                    .help("List the log sessions instead of showing lines")
+                    .long("sessions"),
+            ))
+        .subcommand(
+            SubCommand::with_name("reload")
+                .about("Reload the unit files of all stopped servers")
+        )
         .subcommand(
             SubCommand::with_name("stop-daemon")
                 .about("Shut down the minecraft server manager daemon")
@@ -235,6 +575,30 @@ fn matches() -> ArgMatches<'static> {
         .get_matches()
 }
 
+/// Prints a log record as a single line of JSON.
+fn print_record(record: &LogRecord) {
+    println!(
+        "{}",
+        serde_json::to_string(record).expect("serialize log record")
+    );
+}
+
+/// Prints that a server has failed to start, followed by its last output on stderr.
+fn print_failure(
+    server_id: &str,
+    error: &str,
+    stderr: &[String],
+    crash_report: Option<&CrashReport>,
+) {
+    println!("Starting unit {} failed: {}", server_id, error);
+    for line in stderr {
+        println!("  {}", line);
+    }
+    if let Some(crash_report) = crash_report {
+        println!("Crash report: {}", crash_report.path);
+    }
+}
+
 #[inline(never)]
 fn send_connection_request() -> Result<IpcOneShotServer<DaemonResponse>, Box<dyn Error>> {
     let config = DaemonConfig::load(Path::new("mcman.toml"));
@@ -276,6 +640,16 @@ struct Client {
 
 impl Client {
     fn recv_other(&self, response: DaemonResponse) {
+        if let DaemonResponse::HookFailed {
+            unit_id,
+            hook,
+            error,
+        } = response
+        {
+            eprintln!("{} hook of unit {} has failed: {}", hook, unit_id, error);
+            eprintln!("see the log of the daemon for the output of the hook");
+            exit(1);
+        }
         panic!("unexpected response at this time: {:?}", response);
     }
 
@@ -295,6 +669,7 @@ impl Client {
                         TableCell::new(&server.server_type),
                         TableCell::new(&server.server_version),
                         TableCell::new(&server.server_status),
+                        TableCell::new(format!("{} online", server.online_players.len())),
                     ]))
                 }
 
@@ -348,9 +723,17 @@ impl Client {
                 if let DaemonResponse::ServerEvent { event } = response {
                     if let ServerEvent::ServerStarting { server_id } = event {
                         spinner.set_message(format!("Starting {}", server_id).as_str());
-                    } else if let ServerEvent::ServerFailed { server_id, error } = event {
+                    } else if let ServerEvent::ServerFailed {
+                        server_id,
+                        error,
+                        stderr,
+                        crash_report,
+                        ..
+                    } = event
+                    {
                         spinner.finish_and_clear();
-                        spinner.println(format!("Starting unit {} failed: {}", server_id, error))
+                        print_failure(&server_id, &error, &stderr, crash_report.as_ref());
+                        return;
                     } else {
                         panic!()
                     }
@@ -364,9 +747,16 @@ impl Client {
                 if let DaemonResponse::ServerEvent { event } = response {
                     if let ServerEvent::ServerStarted { server_id } = event {
                         spinner.finish_with_message(format!("Started {}", server_id).as_str());
-                    } else if let ServerEvent::ServerFailed { server_id, error } = event {
+                    } else if let ServerEvent::ServerFailed {
+                        server_id,
+                        error,
+                        stderr,
+                        crash_report,
+                        ..
+                    } = event
+                    {
                         spinner.finish_and_clear();
-                        spinner.println(format!("Starting unit {} failed: {}", server_id, error))
+                        print_failure(&server_id, &error, &stderr, crash_report.as_ref())
                     } else {
                         panic!()
                     }
@@ -445,6 +835,24 @@ impl Client {
         }
     }
 
+    fn restart(&self, args: Option<&ArgMatches>) {
+        let server_id = args.unwrap().value_of("server-id").unwrap().to_string();
+        self.cmd_out
+            .send(DaemonCmd::Restart {
+                server_id: server_id.clone(),
+            })
+            .unwrap();
+
+        match self.res_in.recv() {
+            Ok(DaemonResponse::Ok) => println!("Restarting {}", server_id),
+            Ok(DaemonResponse::ServerNotFound { server_id }) => {
+                println!("unknown server id {}", server_id)
+            }
+            Ok(response) => self.recv_other(response),
+            Err(_) => panic!(),
+        }
+    }
+
     pub fn install(&self, args: Option<&ArgMatches>) {
         let args = args.unwrap();
         let version = match args.value_of("server-version") {
@@ -458,6 +866,12 @@ impl Client {
         let eula = args.is_present("eula");
         let server_type = match args.value_of("server-type").unwrap() {
             "paper" => ServerType::Paper,
+            "vanilla" => ServerType::Vanilla,
+            "spigot" => ServerType::Spigot,
+            "bukkit" => ServerType::Bukkit,
+            "folia" => ServerType::Folia,
+            "velocity" => ServerType::Velocity,
+            "waterfall" => ServerType::Waterfall,
             _ => panic!("unknown server type"),
         };
 
@@ -533,8 +947,18 @@ impl Client {
         spinner.set_message("Waiting for daemon");
         spinner.enable_steady_tick(100);
 
-        if let Ok(DaemonResponse::Ok) = self.res_in.recv() {
-            spinner.set_message("Starting update")
+        match self.res_in.recv() {
+            Ok(DaemonResponse::Ok) => spinner.set_message("Starting update"),
+            Ok(DaemonResponse::ServerNotFound { server_id }) => {
+                spinner.finish_and_clear();
+                println!("unknown server id {}", server_id);
+                return;
+            }
+            Ok(response) => {
+                spinner.finish_and_clear();
+                self.recv_other(response)
+            }
+            Err(_) => panic!(),
         }
 
         while let Ok(DaemonResponse::ServerEvent { event }) = self.res_in.recv() {
@@ -562,6 +986,418 @@ impl Client {
         }
     }
 
+    fn backup(&self, args: Option<&ArgMatches>) {
+        let args = args.unwrap();
+        match args.subcommand() {
+            ("list", args) => return self.list_backups(args),
+            ("prune", args) => return self.prune_backups(args),
+            ("verify", args) => return self.verify_backups(args),
+            ("gc", args) => return self.collect_backup_garbage(args),
+            _ => (),
+        }
+        let unit_id = args.value_of("unit-id").unwrap().to_string();
+        let label = args.value_of("label").map(|str| str.to_string());
+
+        self.cmd_out
+            .send(DaemonCmd::Backup { unit_id, label })
+            .expect("send to daemon");
+
+        let spinner = ProgressBar::new_spinner()
+            .with_style(ProgressStyle::default_spinner().tick_chars("⣷⣯⣟⡿⢿⣻⣽⣾✓"));
+
+        spinner.set_draw_target(ProgressDrawTarget::stdout());
+        spinner.set_message("Waiting for daemon");
+        spinner.enable_steady_tick(100);
+
+        match self.res_in.recv() {
+            Ok(DaemonResponse::Ok) => spinner.set_message("Starting backup"),
+            Ok(DaemonResponse::ServerNotFound { server_id }) => {
+                spinner.finish_and_clear();
+                println!("unknown server id {}", server_id);
+                return;
+            }
+            Ok(DaemonResponse::Error { error }) => {
+                spinner.finish_and_clear();
+                eprintln!("{}", error);
+                exit(1);
+            }
+            Ok(response) => {
+                spinner.finish_and_clear();
+                self.recv_other(response)
+            }
+            Err(_) => panic!(),
+        }
+
+        while let Ok(DaemonResponse::ServerEvent { event }) = self.res_in.recv() {
+            match event {
+                ServerEvent::ActionProgress {
+                    server_id,
+                    action,
+                    progress,
+                    maximum,
+                    ..
+                } => match (progress, maximum) {
+                    (Some(progress), Some(maximum)) if maximum > 0 => spinner.set_message(
+                        format!("[{}] {} ({}%)", server_id, action, progress * 100 / maximum)
+                            .as_str(),
+                    ),
+                    _ => spinner.set_message(format!("[{}] {}", server_id, action).as_str()),
+                },
+                ServerEvent::BackupComplete { server_id, backup } => {
+                    spinner.finish_with_message(
+                        format!(
+                            "[DONE] backed up {} as {} ({})",
+                            server_id,
+                            backup.id,
+                            format_size(backup.size)
+                        )
+                        .as_str(),
+                    );
+                    break;
+                }
+                ServerEvent::BackupFailed { server_id, error } => {
+                    spinner.abandon_with_message(
+                        format!("[ERROR] error while backing up {}: {}", server_id, error).as_str(),
+                    );
+                    exit(1);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn restore(&self, args: Option<&ArgMatches>) {
+        let args = args.unwrap();
+        let unit_id = args.value_of("unit-id").unwrap().to_string();
+        let backup_id = args.value_of("backup-id").unwrap().to_string();
+
+        self.cmd_out
+            .send(DaemonCmd::Restore {
+                unit_id,
+                backup_id,
+                jar: args.is_present("jar"),
+                config: args.is_present("config"),
+            })
+            .expect("send to daemon");
+
+        let spinner = ProgressBar::new_spinner()
+            .with_style(ProgressStyle::default_spinner().tick_chars("⣷⣯⣟⡿⢿⣻⣽⣾✓"));
+
+        spinner.set_draw_target(ProgressDrawTarget::stdout());
+        spinner.set_message("Waiting for daemon");
+        spinner.enable_steady_tick(100);
+
+        match self.res_in.recv() {
+            Ok(DaemonResponse::Ok) => spinner.set_message("Starting restore"),
+            Ok(DaemonResponse::ServerNotFound { server_id }) => {
+                spinner.finish_and_clear();
+                println!("unknown server id {}", server_id);
+                return;
+            }
+            Ok(DaemonResponse::Error { error }) => {
+                spinner.finish_and_clear();
+                eprintln!("{}", error);
+                exit(1);
+            }
+            Ok(response) => {
+                spinner.finish_and_clear();
+                self.recv_other(response)
+            }
+            Err(_) => panic!(),
+        }
+
+        while let Ok(DaemonResponse::ServerEvent { event }) = self.res_in.recv() {
+            match event {
+                ServerEvent::ActionProgress {
+                    server_id,
+                    action,
+                    progress,
+                    maximum,
+                    ..
+                } => match (progress, maximum) {
+                    (Some(progress), Some(maximum)) if maximum > 0 => spinner.set_message(
+                        format!("[{}] {} ({}%)", server_id, action, progress * 100 / maximum)
+                            .as_str(),
+                    ),
+                    _ => spinner.set_message(format!("[{}] {}", server_id, action).as_str()),
+                },
+                ServerEvent::RestoreComplete {
+                    server_id,
+                    backup_id,
+                    snapshot_id,
+                } => {
+                    let snapshot = match snapshot_id {
+                        Some(snapshot_id) => format!(", previous worlds saved as {}", snapshot_id),
+                        None => String::new(),
+                    };
+                    spinner.finish_with_message(
+                        format!("[DONE] restored {} of {}{}", backup_id, server_id, snapshot)
+                            .as_str(),
+                    );
+                    break;
+                }
+                ServerEvent::RestoreFailed { server_id, error } => {
+                    spinner.abandon_with_message(
+                        format!("[ERROR] error while restoring {}: {}", server_id, error).as_str(),
+                    );
+                    exit(1);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn lockdown(&self, args: Option<&ArgMatches>) {
+        let args = args.unwrap();
+        let unit_id = args.value_of("unit-id").unwrap().to_string();
+
+        self.cmd_out
+            .send(DaemonCmd::Lockdown {
+                unit_id,
+                message: args.value_of("message").map(str::to_string),
+            })
+            .expect("send to daemon");
+
+        let spinner = ProgressBar::new_spinner()
+            .with_style(ProgressStyle::default_spinner().tick_chars("⣷⣯⣟⡿⢿⣻⣽⣾✓"));
+
+        spinner.set_draw_target(ProgressDrawTarget::stdout());
+        spinner.set_message("Waiting for daemon");
+        spinner.enable_steady_tick(100);
+
+        match self.res_in.recv() {
+            Ok(DaemonResponse::Ok) => spinner.set_message("Starting lockdown"),
+            Ok(DaemonResponse::ServerNotFound { server_id }) => {
+                spinner.finish_and_clear();
+                println!("unknown server id {}", server_id);
+                return;
+            }
+            Ok(DaemonResponse::Error { error }) => {
+                spinner.finish_and_clear();
+                eprintln!("{}", error);
+                exit(1);
+            }
+            Ok(response) => {
+                spinner.finish_and_clear();
+                self.recv_other(response)
+            }
+            Err(_) => panic!(),
+        }
+
+        while let Ok(DaemonResponse::ServerEvent { event }) = self.res_in.recv() {
+            match event {
+                ServerEvent::ActionProgress {
+                    server_id,
+                    action,
+                    progress,
+                    maximum,
+                    ..
+                } => match (progress, maximum) {
+                    (Some(progress), Some(maximum)) if maximum > 0 => spinner.set_message(
+                        format!("[{}] {} ({}%)", server_id, action, progress * 100 / maximum)
+                            .as_str(),
+                    ),
+                    _ => spinner.set_message(format!("[{}] {}", server_id, action).as_str()),
+                },
+                ServerEvent::LockdownComplete {
+                    server_id,
+                    snapshot_id,
+                    snapshot_error,
+                } => {
+                    match (snapshot_id, snapshot_error) {
+                        (Some(snapshot_id), _) => spinner.finish_with_message(
+                            format!(
+                                "[DONE] {} is in lockdown, snapshot {}",
+                                server_id, snapshot_id
+                            )
+                            .as_str(),
+                        ),
+                        (None, error) => spinner.abandon_with_message(
+                            format!(
+                                "[WARN] {} is in lockdown, but no snapshot has been taken: {}",
+                                server_id,
+                                error.unwrap_or_default()
+                            )
+                            .as_str(),
+                        ),
+                    }
+                    break;
+                }
+                ServerEvent::LockdownFailed { server_id, error } => {
+                    spinner.abandon_with_message(
+                        format!(
+                            "[ERROR] error during the lockdown of {}: {}",
+                            server_id, error
+                        )
+                        .as_str(),
+                    );
+                    exit(1);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn unlock(&self, args: Option<&ArgMatches>) {
+        let unit_id = args.unwrap().value_of("unit-id").unwrap().to_string();
+        self.cmd_out
+            .send(DaemonCmd::Unlock {
+                unit_id: unit_id.clone(),
+            })
+            .expect("send to daemon");
+        match self.res_in.recv() {
+            Ok(DaemonResponse::Ok) => println!("Unlocked {}", unit_id),
+            Ok(DaemonResponse::ServerNotFound { server_id }) => {
+                println!("unknown server id {}", server_id)
+            }
+            Ok(DaemonResponse::Error { error }) => {
+                eprintln!("{}", error);
+                exit(1);
+            }
+            Ok(response) => self.recv_other(response),
+            Err(_) => panic!(),
+        }
+    }
+
+    fn list_backups(&self, args: Option<&ArgMatches>) {
+        let unit_id = args.unwrap().value_of("unit-id").unwrap().to_string();
+        self.cmd_out
+            .send(DaemonCmd::ListBackups { unit_id })
+            .expect("send to daemon");
+        match self.res_in.recv() {
+            Ok(DaemonResponse::Backups { unit_id, backups }) => {
+                if backups.is_empty() {
+                    println!("unit {} has no backups", unit_id);
+                    return;
+                }
+                print_backups(&backups);
+            }
+            Ok(DaemonResponse::Error { error }) => {
+                eprintln!("{}", error);
+                exit(1);
+            }
+            Ok(response) => self.recv_other(response),
+            Err(_) => panic!(),
+        }
+    }
+
+    fn verify_backups(&self, args: Option<&ArgMatches>) {
+        let unit_id = args.unwrap().value_of("unit-id").map(|str| str.to_string());
+        self.cmd_out
+            .send(DaemonCmd::VerifyBackups { unit_id })
+            .expect("send to daemon");
+
+        let spinner = ProgressBar::new_spinner()
+            .with_style(ProgressStyle::default_spinner().tick_chars("⣷⣯⣟⡿⢿⣻⣽⣾✓"));
+        spinner.set_draw_target(ProgressDrawTarget::stdout());
+        spinner.set_message("Verifying backups");
+        spinner.enable_steady_tick(100);
+
+        let mut response = self.res_in.recv();
+        if let Ok(DaemonResponse::Ok) = response {
+            response = self.res_in.recv();
+        }
+        spinner.finish_and_clear();
+        match response {
+            Ok(DaemonResponse::BackupsVerified { verifications }) => {
+                if verifications.is_empty() {
+                    println!("no backups to verify");
+                    return;
+                }
+                let mut damaged = 0;
+                for verification in &verifications {
+                    let backup = &verification.backup;
+                    if verification.problems.is_empty() {
+                        println!("{} {}: ok", backup.unit_id, backup.id);
+                    } else {
+                        damaged += 1;
+                        println!("{} {}: damaged", backup.unit_id, backup.id);
+                        for problem in &verification.problems {
+                            println!("  {}", problem);
+                        }
+                    }
+                }
+                if damaged > 0 {
+                    eprintln!("{} of {} backups are damaged", damaged, verifications.len());
+                    exit(1);
+                }
+            }
+            Ok(DaemonResponse::Error { error }) => {
+                eprintln!("{}", error);
+                exit(1);
+            }
+            Ok(response) => self.recv_other(response),
+            Err(_) => panic!(),
+        }
+    }
+
+    fn collect_backup_garbage(&self, args: Option<&ArgMatches>) {
+        let dry_run = args.unwrap().is_present("dry-run");
+        self.cmd_out
+            .send(DaemonCmd::CollectBackupGarbage { dry_run })
+            .expect("send to daemon");
+        let mut response = self.res_in.recv();
+        if let Ok(DaemonResponse::Ok) = response {
+            response = self.res_in.recv();
+        }
+        match response {
+            Ok(DaemonResponse::BackupGarbageCollected {
+                chunks,
+                size,
+                dry_run,
+            }) => {
+                if dry_run {
+                    println!("would delete {} chunks ({})", chunks, format_size(size));
+                } else {
+                    println!("deleted {} chunks ({})", chunks, format_size(size));
+                }
+            }
+            Ok(DaemonResponse::Error { error }) => {
+                eprintln!("{}", error);
+                exit(1);
+            }
+            Ok(response) => self.recv_other(response),
+            Err(_) => panic!(),
+        }
+    }
+
+    fn prune_backups(&self, args: Option<&ArgMatches>) {
+        let args = args.unwrap();
+        let unit_id = args.value_of("unit-id").map(|str| str.to_string());
+        let dry_run = args.is_present("dry-run");
+        self.cmd_out
+            .send(DaemonCmd::PruneBackups { unit_id, dry_run })
+            .expect("send to daemon");
+        match self.deferred_response() {
+            Ok(DaemonResponse::BackupsPruned { backups, dry_run }) => {
+                if backups.is_empty() {
+                    println!("no backups to prune");
+                    return;
+                }
+                let size: u64 = backups.iter().map(|backup| backup.size).sum();
+                if dry_run {
+                    println!(
+                        "would delete {} backups ({}):",
+                        backups.len(),
+                        format_size(size)
+                    );
+                } else {
+                    println!("deleted {} backups ({}):", backups.len(), format_size(size));
+                }
+                print_backups(&backups);
+            }
+            Ok(DaemonResponse::ServerNotFound { server_id }) => {
+                println!("unknown server id {}", server_id)
+            }
+            Ok(DaemonResponse::Error { error }) => {
+                eprintln!("{}", error);
+                exit(1);
+            }
+            Ok(response) => self.recv_other(response),
+            Err(_) => panic!(),
+        }
+    }
+
     pub fn stop_daemon(&self, _args: Option<&ArgMatches>) {
         self.cmd_out.send(DaemonCmd::StopDaemon).unwrap();
 
@@ -624,4 +1460,619 @@ impl Client {
             }
         }
     }
+
+    fn events(&self, args: Option<&ArgMatches>) {
+        let args = args.unwrap();
+        let server_ids = args
+            .values_of("unit-id")
+            .map(|units| units.map(|unit| unit.to_string()).collect::<Vec<String>>());
+        let json = args.is_present("json");
+        let console = args.is_present("console");
+        let event_types = match args.values_of("custom") {
+            Some(names) => names
+                .map(|name| ServerEventType::Custom(name.to_string()))
+                .collect(),
+            None => ServerEventType::all().to_vec(),
+        };
+
+        for event_type in event_types {
+            if event_type == ServerEventType::ConsoleLine && !console {
+                continue;
+            }
+            self.cmd_out
+                .send(DaemonCmd::SubscribeEvent {
+                    event_type,
+                    server_ids: server_ids.clone(),
+                })
+                .unwrap();
+        }
+
+        while let Ok(response) = self.res_in.recv() {
+            match response {
+                DaemonResponse::Ok => (),
+                DaemonResponse::ServerEvent { event } => {
+                    if json {
+                        println!("{}", serde_json::to_string(&event).unwrap());
+                    } else {
+                        println!("[{}] {:?}", event.server_id().unwrap_or("daemon"), event);
+                    }
+                }
+                DaemonResponse::DaemonEvent(DaemonIpcEvent::Stopped) => {
+                    println!("daemon has stopped");
+                    break;
+                }
+                response => self.recv_other(response),
+            }
+        }
+    }
+
+    fn attach(self, args: Option<&ArgMatches>) {
+        let args = args.unwrap();
+        let unit_id = args.value_of("unit-id").unwrap().to_string();
+        let backlog = args.value_of("lines").unwrap().parse().unwrap();
+
+        self.cmd_out
+            .send(DaemonCmd::AttachConsole {
+                unit_id: unit_id.clone(),
+                backlog,
+            })
+            .unwrap();
+
+        match self.res_in.recv() {
+            Ok(DaemonResponse::ConsoleBacklog { lines, .. }) => {
+                for line in lines {
+                    println!("{}", line);
+                }
+            }
+            Ok(DaemonResponse::ServerNotFound { server_id }) => {
+                println!("unknown server id {}", server_id);
+                return;
+            }
+            Ok(response) => self.recv_other(response),
+            Err(_) => panic!(),
+        }
+
+        let mut editor = Editor::<()>::new().expect("create line editor");
+        let history_path = std::env::var_os("HOME")
+            .map(|home| Path::new(&home).join(HISTORY_FILE))
+            .unwrap_or_else(|| PathBuf::from(HISTORY_FILE));
+        let _ = editor.load_history(&history_path);
+        // without a terminal the output is printed directly
+        let mut printer = editor.create_external_printer().ok();
+
+        let Client { cmd_out, res_in } = self;
+        spawn(move || {
+            while let Ok(response) = res_in.recv() {
+                let message = match response {
+                    DaemonResponse::ServerEvent {
+                        event: ServerEvent::ConsoleLine { line, stream, .. },
+                    } => stream.tag(&line),
+                    DaemonResponse::DaemonEvent(DaemonIpcEvent::Stopped) => {
+                        "daemon has stopped, press Ctrl-D to exit".to_string()
+                    }
+                    _ => continue,
+                };
+                let printed = match &mut printer {
+                    Some(printer) => printer.print(format!("{}\n", message)).is_ok(),
+                    None => {
+                        println!("{}", message);
+                        true
+                    }
+                };
+                if !printed {
+                    break;
+                }
+            }
+        });
+
+        let prompt = format!("{}> ", unit_id);
+        loop {
+            match editor.readline(&prompt) {
+                Ok(line) => {
+                    let command = line.trim();
+                    if command.is_empty() {
+                        continue;
+                    }
+                    editor.add_history_entry(command);
+                    if cmd_out
+                        .send(DaemonCmd::SendCommand {
+                            unit_id: unit_id.clone(),
+                            command: command.to_string(),
+                        })
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                Err(ReadlineError::Interrupted) => continue,
+                Err(_) => break,
+            }
+        }
+
+        if let Err(e) = editor.save_history(&history_path) {
+            eprintln!("could not save history: {}", e);
+        }
+        println!("detached from {}", unit_id);
+    }
+
+    fn players(&self, args: Option<&ArgMatches>) {
+        let unit_id = args.unwrap().value_of("unit-id").unwrap();
+        self.cmd_out.send(DaemonCmd::List).unwrap();
+
+        match self.res_in.recv() {
+            Ok(DaemonResponse::List { servers }) => {
+                match servers.into_iter().find(|server| server.name == unit_id) {
+                    Some(server) if server.online_players.is_empty() => {
+                        println!("no players online on {}", unit_id)
+                    }
+                    Some(server) => {
+                        println!(
+                            "{} player(s) online on {}:",
+                            server.online_players.len(),
+                            unit_id
+                        );
+                        for player in server.online_players {
+                            println!("  {}", player);
+                        }
+                    }
+                    None => println!("unknown server id {}", unit_id),
+                }
+            }
+            Ok(response) => self.recv_other(response),
+            Err(_) => panic!(),
+        }
+    }
+
+    fn status(&self, args: Option<&ArgMatches>) {
+        let unit_id = args.unwrap().value_of("unit-id").unwrap();
+        self.cmd_out.send(DaemonCmd::List).unwrap();
+
+        let server = match self.res_in.recv() {
+            Ok(DaemonResponse::List { servers }) => {
+                match servers.into_iter().find(|server| server.name == unit_id) {
+                    Some(server) => server,
+                    None => {
+                        println!("unknown server id {}", unit_id);
+                        return;
+                    }
+                }
+            }
+            Ok(response) => return self.recv_other(response),
+            Err(_) => panic!(),
+        };
+        println!(
+            "{} ({} {})",
+            server.name, server.server_type, server.server_version
+        );
+        println!("  Status:  {}", server.server_status);
+        println!("  Players: {}", server.online_players.len());
+        let failure = match server.last_failure {
+            Some(failure) => failure,
+            None => return,
+        };
+        println!();
+        println!(
+            "Last failure at {}: {}",
+            failure
+                .time
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S"),
+            failure.error
+        );
+        if !failure.exception.is_empty() {
+            println!("Exception:");
+            for line in &failure.exception {
+                println!("  {}", line);
+            }
+        }
+        if let Some(crash_report) = &failure.crash_report {
+            println!("Crash report {}:", crash_report.path);
+            for line in &crash_report.lines {
+                println!("  {}", line);
+            }
+        }
+        if !failure.stderr.is_empty() {
+            println!("Stderr:");
+            for line in &failure.stderr {
+                println!("  {}", line);
+            }
+        }
+    }
+
+    fn history(&self, args: Option<&ArgMatches>) {
+        let args = args.unwrap();
+        self.cmd_out
+            .send(DaemonCmd::QueryJournal {
+                unit_id: args.value_of("unit-id").map(str::to_string),
+                since: args.value_of("since").and_then(parse_since),
+                types: args
+                    .values_of("type")
+                    .map(|types| types.map(str::to_string).collect())
+                    .unwrap_or_default(),
+                limit: args.value_of("limit").unwrap().parse().unwrap(),
+            })
+            .unwrap();
+
+        match self.deferred_response() {
+            Ok(DaemonResponse::Journal { entries }) => {
+                if entries.is_empty() {
+                    println!("no matching journal entries");
+                }
+                for entry in entries {
+                    println!("{}", format_journal_entry(&entry));
+                }
+            }
+            Ok(DaemonResponse::Error { error }) => println!("error: {}", error),
+            Ok(response) => self.recv_other(response),
+            Err(_) => panic!(),
+        }
+    }
+
+    fn stats(&self, args: Option<&ArgMatches>) {
+        let args = args.unwrap();
+        let unit_id = args.value_of("unit-id").unwrap().to_string();
+        let count: usize = args.value_of("samples").unwrap().parse().unwrap();
+        self.cmd_out
+            .send(DaemonCmd::GetPerformance { unit_id })
+            .unwrap();
+
+        let (unit_id, stats) = match self.res_in.recv() {
+            Ok(DaemonResponse::Performance { unit_id, stats }) => (unit_id, stats),
+            Ok(response) => return self.recv_other(response),
+            Err(_) => panic!(),
+        };
+        let latest = match stats.samples.last() {
+            Some(latest) => latest,
+            None => {
+                println!("no performance data for {} yet", unit_id);
+                return;
+            }
+        };
+        let format = |value: Option<f64>| match value {
+            Some(value) => format!("{:.1}", value),
+            None => "-".to_string(),
+        };
+        println!(
+            "{}{}",
+            unit_id,
+            if stats.degraded { " (degraded)" } else { "" }
+        );
+        match latest.tps {
+            Some([tps_1m, tps_5m, tps_15m]) => println!(
+                "  TPS (1m, 5m, 15m):  {:.1}, {:.1}, {:.1}",
+                tps_1m, tps_5m, tps_15m
+            ),
+            None => println!("  TPS (1m, 5m, 15m):  -"),
+        }
+        println!(
+            "  mspt (1m avg/max):  {}/{}",
+            format(latest.mspt),
+            format(latest.max_mspt)
+        );
+        println!(
+            "  Can't keep up:      {} time(s), {} tick(s) skipped",
+            stats.total_overloads, stats.total_skipped_ticks
+        );
+
+        let mut table = Table::new();
+        table.style = TableStyle::rounded();
+        table.add_row(Row::new(vec![
+            TableCell::new("Time"),
+            TableCell::new("TPS"),
+            TableCell::new("mspt"),
+            TableCell::new("max mspt"),
+            TableCell::new("Can't keep up"),
+        ]));
+        let skip = stats.samples.len().saturating_sub(count);
+        for sample in stats.samples.iter().skip(skip) {
+            table.add_row(Row::new(vec![
+                TableCell::new(sample.time.with_timezone(&Local).format("%H:%M:%S")),
+                TableCell::new(format(sample.tps.map(|[tps_1m, _, _]| tps_1m))),
+                TableCell::new(format(sample.mspt)),
+                TableCell::new(format(sample.max_mspt)),
+                TableCell::new(sample.overloads),
+            ]));
+        }
+        println!("{}", table.render());
+    }
+
+    fn logs(&self, args: Option<&ArgMatches>) {
+        let args = args.unwrap();
+        let unit_id = args.value_of("unit-id").unwrap().to_string();
+        let limit: usize = args.value_of("lines").unwrap().parse().unwrap();
+        let grep = args.value_of("grep").map(|grep| grep.to_string());
+        let level = args
+            .value_of("level")
+            .map(|level| level.parse::<LogLevel>().expect("validated by clap"));
+        let json = args.is_present("json");
+
+        if args.is_present("sessions") {
+            self.cmd_out
+                .send(DaemonCmd::GetLogs {
+                    unit_id,
+                    session: None,
+                    from: None,
+                    limit: 0,
+                    grep: None,
+                    level: None,
+                    records: false,
+                })
+                .unwrap();
+            match self.deferred_response() {
+                Ok(DaemonResponse::Logs { sessions, .. }) => {
+                    let mut table = Table::new();
+                    table.style = TableStyle::rounded();
+                    for session in sessions {
+                        table.add_row(Row::new(vec![
+                            TableCell::new(&session.id),
+                            TableCell::new(format!("{} KiB", session.size.div_ceil(1024))),
+                            TableCell::new(if session.compressed { "compressed" } else { "" }),
+                        ]));
+                    }
+                    println!("{}", table.render());
+                }
+                Ok(response) => self.print_logs_error(response),
+                Err(_) => panic!(),
+            }
+            return;
+        }
+
+        if args.is_present("follow") {
+            let filter = LineFilter {
+                grep: grep.map(|grep| Regex::new(&grep).expect("validated by clap")),
+                level,
+            };
+            self.follow_logs(unit_id, limit, filter, json);
+            return;
+        }
+
+        self.cmd_out
+            .send(DaemonCmd::GetLogs {
+                unit_id,
+                session: args.value_of("session").map(|session| session.to_string()),
+                from: None,
+                limit,
+                grep,
+                level,
+                records: json,
+            })
+            .unwrap();
+        match self.deferred_response() {
+            Ok(DaemonResponse::Logs { lines, unit_id, .. }) => match lines {
+                Some(lines) if json => {
+                    for record in lines.records {
+                        print_record(&record);
+                    }
+                }
+                Some(lines) => {
+                    for line in lines.lines {
+                        println!("{}", line);
+                    }
+                }
+                None => println!("no logs found for unit {}", unit_id),
+            },
+            Ok(response) => self.print_logs_error(response),
+            Err(_) => panic!(),
+        }
+    }
+
+    /// Receives the response to a command that the daemon answers on a worker thread, e.g.
+    /// [`DaemonCmd::GetLogs`], skipping the [`DaemonResponse::Ok`] that is sent before.
+    fn deferred_response(&self) -> Result<DaemonResponse, IpcError> {
+        match self.res_in.recv() {
+            Ok(DaemonResponse::Ok) => self.res_in.recv(),
+            response => response,
+        }
+    }
+
+    /// Prints the newest lines of the console and then every new line until the daemon stops.
+    ///
+    /// The lines are parsed into records and filtered by the client.
+    fn follow_logs(&self, unit_id: String, limit: usize, filter: LineFilter, json: bool) {
+        let mut parser = RecordParser::default();
+        let print = |line: &str, record: &LogRecord| {
+            if json {
+                print_record(record)
+            } else {
+                println!("{}", line)
+            }
+        };
+
+        // with a filter the whole buffer is searched for the last matching lines
+        let filtered = filter.grep.is_some() || filter.level.is_some();
+        self.cmd_out
+            .send(DaemonCmd::AttachConsole {
+                unit_id,
+                backlog: if filtered { usize::MAX } else { limit },
+            })
+            .unwrap();
+        match self.res_in.recv() {
+            Ok(DaemonResponse::ConsoleBacklog { lines, .. }) => {
+                let lines: Vec<(String, LogRecord)> = lines
+                    .into_iter()
+                    .map(|line| {
+                        let record = parser.parse_tagged(&line);
+                        (line, record)
+                    })
+                    .filter(|(line, record)| filter.matches(line, record))
+                    .collect();
+                for (line, record) in lines.iter().skip(lines.len().saturating_sub(limit)) {
+                    print(line, record);
+                }
+            }
+            Ok(response) => {
+                self.print_logs_error(response);
+                return;
+            }
+            Err(_) => panic!(),
+        }
+
+        while let Ok(response) = self.res_in.recv() {
+            match response {
+                DaemonResponse::ServerEvent {
+                    event: ServerEvent::ConsoleLine { line, stream, .. },
+                } => {
+                    let record = parser.parse(&line, stream);
+                    let line = stream.tag(&line);
+                    if filter.matches(&line, &record) {
+                        print(&line, &record);
+                    }
+                }
+                DaemonResponse::DaemonEvent(DaemonIpcEvent::Stopped) => {
+                    println!("daemon has stopped");
+                    break;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn print_logs_error(&self, response: DaemonResponse) {
+        match response {
+            DaemonResponse::ServerNotFound { server_id } => {
+                println!("unknown server id {}", server_id)
+            }
+            DaemonResponse::LogSessionNotFound { session, .. } => {
+                println!("unknown log session {}", session)
+            }
+            DaemonResponse::InvalidRequest { error } | DaemonResponse::Error { error } => {
+                println!("error: {}", error)
+            }
+            response => self.recv_other(response),
+        }
+    }
+
+    fn reload(&self) {
+        self.cmd_out.send(DaemonCmd::ReloadUnits).unwrap();
+
+        match self.res_in.recv() {
+            Ok(DaemonResponse::UnitsReloaded {
+                added,
+                reloaded,
+                removed,
+                skipped,
+            }) => {
+                for (label, units) in [
+                    ("added", added),
+                    ("reloaded", reloaded),
+                    ("removed", removed),
+                    ("skipped (running)", skipped),
+                ]
+                .iter()
+                {
+                    if !units.is_empty() {
+                        println!("{}: {}", label, units.join(", "));
+                    }
+                }
+            }
+            Ok(response) => self.recv_other(response),
+            Err(_) => panic!(),
+        }
+    }
+}
+
+/// Parses the argument of `history --since`.
+///
+/// Accepts a duration before now (`30s`, `30m`, `12h`, `7d`), a local date or time
+/// (`2021-03-01`, `2021-03-01 03:00`, `2021-03-01 03:00:00`) or an RFC 3339 time.
+fn parse_since(value: &str) -> Option<DateTime<Utc>> {
+    if let Some(unit) = value.chars().last() {
+        if let Ok(amount) = value[..value.len() - unit.len_utf8()].parse::<i64>() {
+            let duration = match unit {
+                's' => chrono::Duration::seconds(amount),
+                'm' => chrono::Duration::minutes(amount),
+                'h' => chrono::Duration::hours(amount),
+                'd' => chrono::Duration::days(amount),
+                _ => return None,
+            };
+            return Some(Utc::now() - duration);
+        }
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })?;
+    Local
+        .from_local_datetime(&naive)
+        .single()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// Formats a journal entry as a single line.
+fn format_journal_entry(entry: &JournalEntry) -> String {
+    let description = match &entry.record {
+        JournalRecord::Command {
+            client_id,
+            client_name,
+            client_uid,
+            ..
+        } => {
+            let client = client_name.as_deref().unwrap_or("unknown client");
+            match client_uid {
+                Some(uid) => format!("by {} (client {}, uid {})", client, client_id, uid),
+                None => format!("by {} (client {})", client, client_id),
+            }
+        }
+        JournalRecord::Event { event } => notifier::message(event),
+    };
+    format!(
+        "{}  {:<12} {:<20} {}",
+        entry.time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+        entry.unit_id.as_deref().unwrap_or("-"),
+        entry.type_name(),
+        description
+    )
+}
+
+/// Formats a size in bytes with a binary unit, e.g. `1.5 GiB`.
+fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+/// Prints backups as a table.
+fn print_backups(backups: &[BackupInfo]) {
+    let mut table = Table::new();
+    table.style = TableStyle::rounded();
+    table.add_row(Row::new(vec![
+        TableCell::new("Unit"),
+        TableCell::new("Backup"),
+        TableCell::new("Time"),
+        TableCell::new("Size"),
+        TableCell::new("Version"),
+        TableCell::new("Label"),
+    ]));
+    for backup in backups {
+        table.add_row(Row::new(vec![
+            TableCell::new(&backup.unit_id),
+            TableCell::new(&backup.id),
+            TableCell::new(
+                backup
+                    .time
+                    .with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M:%S"),
+            ),
+            TableCell::new(format_size(backup.size)),
+            TableCell::new(&backup.server_version),
+            TableCell::new(backup.label.as_deref().unwrap_or("")),
+        ]));
+    }
+    println!("{}", table.render());
 }
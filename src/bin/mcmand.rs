@@ -1,30 +1,52 @@
 use interprocess::local_socket::LocalSocketListener;
 use ipc_channel::ipc::IpcSender;
-use mcman::config::{DaemonConfig, ServerConfig, ServerUnitConfig, UnitConfig};
+use mcman::config::{DaemonConfig, ServerUnitConfig, UnitConfig};
+use mcman::daemon::backup::{
+    self, BackupError, BackupInfo, BackupSource, BackupStore, RestoreOptions,
+};
 use mcman::daemon::basic_log::BasicLogService;
-use mcman::daemon::event::{EventHandler, EventManager, EventManagerCmd};
-use mcman::daemon::{create_server, DaemonEvent, LogService, OutputState, Server};
-use mcman::ipc::install::{InstallError, PaperServerInstaller, ServerInstaller};
+use mcman::daemon::client::{peer_uid, ClientConnection, ClientRegistry};
+use mcman::daemon::crash::{LastFailure, ServerFailure};
+use mcman::daemon::event::{self, EventHandler, EventManager, EventManagerCmd};
+use mcman::daemon::hooks::{self, Hook, LifecycleHook};
+use mcman::daemon::http::HttpInterface;
+use mcman::daemon::journal::{query_files, Journal, JournalEntry, JournalQuery, SharedJournal};
+use mcman::daemon::lockdown;
+use mcman::daemon::logs::{self, LogQuery, LOG_DIRECTORY};
+use mcman::daemon::metrics::{
+    DaemonMetrics, MetricsExporter, ProcessStats, UnitCounters, UnitMetrics,
+};
+use mcman::daemon::notifier::{Notifier, FAILURE_LOG};
+use mcman::daemon::performance::{Performance, PerformanceStats};
+use mcman::daemon::triggers::TriggerMatch;
+use mcman::daemon::{create_server, DaemonEvent, LogService, OnlinePlayers, OutputState, Server};
+use mcman::ipc::install::buildtools::BuildTools;
+use mcman::ipc::install::{
+    InstallError, PaperServerInstaller, ServerInstaller, SpigotServerInstaller,
+    VanillaServerInstaller,
+};
 use mcman::ipc::update::UpdateError::UnsupportedServerType;
-use mcman::ipc::update::{PaperServerUpdater, ServerUpdater, UpdateError};
+use mcman::ipc::update::{PaperServerUpdater, ServerUpdater, UpdateError, VanillaServerUpdater};
 use mcman::ipc::{
     DaemonCmd, DaemonIpcEvent, DaemonResponse, NewConnection, ServerEvent, ServerEventType,
 };
+use mcman::repo::paper::{PaperProject, PaperRepository};
+use mcman::repo::vanilla::VanillaRepository;
 use mcman::{ServerInfo, ServerStatus, ServerType};
 #[cfg(feature = "systemd")]
 use sd_notify::NotifyState;
 use semver::Version;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::fs::remove_file;
-use std::io::Read;
+use std::io::{self, Read};
 use std::ops::DerefMut;
 use std::path::{Path, PathBuf};
 use std::process::{exit, Child};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread::{sleep, spawn};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[macro_use]
 extern crate log;
@@ -35,6 +57,9 @@ fn main() {
     debug!("config: {:?}", config);
 
     let server_name = config.socket_file.clone();
+    let http_config = config.http.clone();
+    let metrics_config = config.metrics.clone();
+    let webhooks = config.webhooks.clone();
 
     let (queue, daemon_queue) = channel();
     let (event_manager_ctrl, event_queue) = channel();
@@ -43,9 +68,10 @@ fn main() {
         daemon_queue,
         queue.clone(),
         event_manager_ctrl.clone(),
-        Box::new(BasicLogService::new(EventHandler::new(
-            event_manager_ctrl.clone(),
-        ))),
+        Box::new(BasicLogService::new(
+            EventHandler::new(event_manager_ctrl.clone()),
+            queue.clone(),
+        )),
     );
 
     daemon.autostart();
@@ -57,15 +83,52 @@ fn main() {
 
     let listener = LocalSocketListener::bind(server_name).unwrap();
 
-    let senders = daemon.senders();
-    let mut counter: u32 = 0;
+    let clients = daemon.clients();
+    let event_handler = EventHandler::new(event_manager_ctrl.clone());
     let mut receiver_buffer = Vec::with_capacity(64);
 
-    let event_manager = EventManager::new(event_queue, queue.clone());
+    let event_manager =
+        EventManager::new(event_queue, queue.clone()).with_journal(daemon.journal());
     event_manager.run();
 
+    if !webhooks.is_empty() {
+        Notifier::new(
+            webhooks,
+            clients.clone(),
+            event_manager_ctrl.clone(),
+            PathBuf::from(FAILURE_LOG),
+        )
+        .run();
+    }
+
+    if let Some(http_config) = http_config {
+        let http_interface = HttpInterface::new(
+            http_config,
+            queue.clone(),
+            event_manager_ctrl.clone(),
+            clients.clone(),
+        );
+        if let Err(e) = http_interface.run() {
+            error!("could not start http interface: {}", e);
+        }
+    }
+
+    if let Some(metrics_config) = metrics_config {
+        let exporter = MetricsExporter::new(metrics_config, queue.clone(), clients.clone());
+        if let Err(e) = exporter.run() {
+            error!("could not start metrics endpoint: {}", e);
+        }
+    }
+
     daemon.start_thread();
 
+    let tick_queue = queue.clone();
+    spawn(move || {
+        while tick_queue.send(DaemonEvent::Tick).is_ok() {
+            sleep(Duration::from_secs(1));
+        }
+    });
+
     #[cfg(feature = "systemd")]
     if let Ok(true) = sd_notify::booted() {
         if let Ok(ctrl) = std::env::var("MCMAND_CTRL") {
@@ -93,7 +156,11 @@ fn main() {
                             socket_path,
                             client_name,
                         } = new_con;
-                        info!("client {} ({}) connected", client_name, client_version);
+                        let client_uid = peer_uid(&rx);
+                        info!(
+                            "client {} ({}) of user {:?} connected",
+                            client_name, client_version, client_uid
+                        );
                         debug!("client requires minimum version {:?}", min_version);
 
                         let connect_result = IpcSender::connect(socket_path);
@@ -118,11 +185,18 @@ fn main() {
                                 }
                             }
 
-                            let mut write = senders.lock().unwrap();
-
-                            write.insert(counter, res_queue);
+                            let id = clients.register_with_uid(
+                                client_name.clone(),
+                                client_uid,
+                                ClientConnection::Ipc(res_queue),
+                            );
+                            let mut event_handler = event_handler.clone();
+                            event_handler.raise_daemon_event(ServerEvent::ClientConnected {
+                                client_id: id,
+                                client_name: client_name.clone(),
+                            });
                             let queue_clone = queue.clone();
-                            let id = counter;
+                            let clients = clients.clone();
 
                             spawn(move || {
                                 while let Ok(cmd) = cmd_queue.recv() {
@@ -130,12 +204,16 @@ fn main() {
                                         .send(DaemonEvent::IncomingCmd { id, cmd })
                                         .unwrap();
                                 }
+                                clients.unregister(id);
                                 event_queue
                                     .send(EventManagerCmd::RemoveAllSubscriptions { client_id: id })
                                     .unwrap();
+                                event_handler.raise_daemon_event(ServerEvent::ClientDisconnected {
+                                    client_id: id,
+                                    client_name,
+                                });
                                 debug!("ending client thread")
                             });
-                            counter += 1;
                         } else {
                             warn!("error on incoming connection {:?}", connect_result)
                         }
@@ -151,11 +229,23 @@ fn main() {
 struct Daemon {
     config: DaemonConfig,
     servers: HashMap<String, DaemonServer>,
-    senders: Arc<Mutex<HashMap<u32, IpcSender<DaemonResponse>>>>,
+    clients: ClientRegistry,
     queue: Receiver<DaemonEvent>,
     queue_sender: Sender<DaemonEvent>,
     log_service: Box<dyn LogService + Send>,
     event_manager_ctrl: Sender<EventManagerCmd>,
+    /// The journal of commands and events, `None` if it is disabled or could not be opened
+    journal: Option<SharedJournal>,
+    /// The counters of the metrics of each unit, kept across reloads of the units
+    unit_counters: HashMap<String, UnitCounters>,
+    /// The units whose worlds are currently being backed up
+    backups: HashSet<String>,
+    /// The units that are currently being restored from a backup
+    restores: HashSet<String>,
+    /// The units whose expired backups are currently being deleted
+    prunes: HashSet<String>,
+    /// Whether unreferenced chunks are currently being deleted
+    collecting_garbage: bool,
 }
 
 impl Daemon {
@@ -169,26 +259,81 @@ impl Daemon {
         let servers = daemon_config.create_servers();
         let mut daemon_servers = HashMap::with_capacity(servers.len());
         for (id, server) in servers.into_iter() {
-            daemon_servers.insert(
-                id.clone(),
-                DaemonServer {
-                    server,
-                    process: None,
-                    status: None,
-                    server_id: id,
-                },
-            );
+            daemon_servers.insert(id.clone(), DaemonServer::new(id, server));
         }
 
-        Daemon {
+        let journal = if daemon_config.journal.enabled {
+            match Journal::open(
+                Path::new(&daemon_config.state_directory),
+                daemon_config.journal.clone(),
+            ) {
+                Ok(journal) => Some(Arc::new(Mutex::new(journal))),
+                Err(e) => {
+                    error!("could not open journal: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let daemon = Daemon {
             config: daemon_config,
             servers: daemon_servers,
-            senders: Arc::new(Mutex::new(HashMap::new())),
+            clients: ClientRegistry::new(),
             queue,
             queue_sender,
             log_service,
             event_manager_ctrl,
+            journal,
+            unit_counters: HashMap::new(),
+            backups: HashSet::new(),
+            restores: HashSet::new(),
+            prunes: HashSet::new(),
+            collecting_garbage: false,
+        };
+        daemon.subscribe_lifecycle_events();
+        daemon
+    }
+
+    /// Subscribes the daemon to the events that lifecycle hooks are run for and that are counted
+    /// in the metrics.
+    ///
+    /// The daemon is registered as a local client, whose events are put back into the daemon
+    /// queue as [`DaemonEvent::LifecycleEvent`]s.
+    fn subscribe_lifecycle_events(&self) {
+        let (sender, responses) = channel();
+        let client_id = self.clients.register(
+            "lifecycle hooks".to_string(),
+            ClientConnection::Local(sender),
+        );
+        for event_type in [
+            ServerEventType::ServerStarted,
+            ServerEventType::ServerStopped,
+            ServerEventType::ServerFailed,
+            ServerEventType::InstallationComplete,
+            ServerEventType::InstallationFailed,
+            ServerEventType::UpdateComplete,
+            ServerEventType::UpdateFailed,
+        ] {
+            self.event_manager_ctrl
+                .send(EventManagerCmd::AddSubscription {
+                    server_id: None,
+                    event_type,
+                    client_id,
+                })
+                .expect("send to event manager");
         }
+        let queue = self.queue_sender.clone();
+        spawn(move || {
+            for response in responses {
+                if let DaemonResponse::ServerEvent { event } = response {
+                    if queue.send(DaemonEvent::LifecycleEvent { event }).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
     }
 
     pub fn autostart(&mut self) {
@@ -197,7 +342,7 @@ impl Daemon {
         }
         for server_id in &self.config.autostart {
             if let Some(server) = self.servers.get_mut(server_id.as_str()) {
-                server.start(self.log_service.deref_mut());
+                server.start(self.log_service.deref_mut(), &self.queue_sender, None);
             }
         }
     }
@@ -223,6 +368,8 @@ impl Daemon {
                         server_status: server.status(),
                         server_version: server.server.version(),
                         server_type: server.server.server_type(),
+                        online_players: server.online_players(),
+                        last_failure: server.last_failure(),
                     })
                     .collect();
                 DaemonResponse::List { servers: list }
@@ -231,22 +378,38 @@ impl Daemon {
                 version: get_version(),
             },
             DaemonCmd::Start { server_id, wait } => {
-                let server = self.servers.get_mut(server_id.as_str());
-                if let Some(server) = server {
-                    if let ServerStatus::Down = server.status() {
-                        server.start(self.log_service.deref_mut());
-                    }
+                if self.restores.contains(&server_id) {
+                    return DaemonResponse::Error {
+                        error: format!("unit {} is being restored", server_id),
+                    };
+                }
+                if self.servers.contains_key(&server_id) {
+                    // subscribe before starting, so that no event of the new process is missed
                     if wait {
-                        self.subscribe_event(
+                        for event_type in [
                             ServerEventType::ServerStarting,
-                            Some(vec![server_id.clone()]),
-                            client_id,
-                        );
-                        self.subscribe_event(
                             ServerEventType::ServerStarted,
-                            Some(vec![server_id.clone()]),
-                            client_id,
+                            ServerEventType::ServerFailed,
+                        ] {
+                            self.subscribe_event(
+                                event_type,
+                                Some(vec![server_id.clone()]),
+                                client_id,
+                            );
+                        }
+                    }
+                    let server = self
+                        .servers
+                        .get_mut(server_id.as_str())
+                        .expect("get server");
+                    if let ServerStatus::Down | ServerStatus::Errored(_) = server.status() {
+                        server.start(
+                            self.log_service.deref_mut(),
+                            &self.queue_sender,
+                            Some(client_id),
                         );
+                    }
+                    if wait {
                         DaemonResponse::Ok
                     } else {
                         DaemonResponse::ServerStarted { server_id }
@@ -258,8 +421,8 @@ impl Daemon {
             DaemonCmd::Stop { server_id, wait } => {
                 let server = self.servers.get_mut(server_id.as_str());
                 if let Some(server) = server {
-                    if let ServerStatus::Running = server.status() {
-                        server.stop();
+                    if let ServerStatus::Running | ServerStatus::Lockdown = server.status() {
+                        server.stop(&self.queue_sender, Box::new(|_| {}));
                     }
                     if wait {
                         self.subscribe_event(
@@ -280,6 +443,59 @@ impl Daemon {
                     DaemonResponse::ServerNotFound { server_id }
                 }
             }
+            DaemonCmd::Restart { server_id } => {
+                if self.restores.contains(&server_id) {
+                    return DaemonResponse::Error {
+                        error: format!("unit {} is being restored", server_id),
+                    };
+                }
+                let server = self.servers.get_mut(server_id.as_str());
+                if let Some(server) = server {
+                    match server.status() {
+                        // a start that waits for its `pre_start` hook starts a new process anyway
+                        ServerStatus::Starting if server.waits_for_hook() => {}
+                        ServerStatus::Running | ServerStatus::Starting | ServerStatus::Unknown => {
+                            // only a stop followed by a start counts as a restart
+                            self.unit_counters
+                                .entry(server_id.clone())
+                                .or_default()
+                                .restarts += 1;
+                            let queue = self.queue_sender.clone();
+                            server.stop(
+                                &self.queue_sender,
+                                Box::new(move |child| {
+                                    if let Some(mut child) = child {
+                                        spawn(move || {
+                                            let exit_status = child.wait();
+                                            debug!(
+                                                "unit {} stopped for restart with exit status {:?}",
+                                                server_id, exit_status
+                                            );
+                                            queue
+                                                .send(DaemonEvent::StartUnit { server_id })
+                                                .expect("send to daemon main event queue");
+                                        });
+                                    }
+                                }),
+                            );
+                        }
+                        ServerStatus::Stopping => {}
+                        ServerStatus::Lockdown => {
+                            return DaemonResponse::Error {
+                                error: format!("unit {} is in lockdown", server_id),
+                            }
+                        }
+                        _ => server.start(
+                            self.log_service.deref_mut(),
+                            &self.queue_sender,
+                            Some(client_id),
+                        ),
+                    }
+                    DaemonResponse::Ok
+                } else {
+                    DaemonResponse::ServerNotFound { server_id }
+                }
+            }
             DaemonCmd::SubscribeEvent {
                 event_type,
                 server_ids: server_names,
@@ -311,6 +527,11 @@ impl Daemon {
                     Some(vec![unit_id.clone()]),
                     client_id,
                 );
+                self.unit_counters
+                    .entry(unit_id.clone())
+                    .or_default()
+                    .installs
+                    .start();
                 self.install_server(
                     EventHandler::new(self.event_manager_ctrl.clone()),
                     unit_id.clone(),
@@ -332,8 +553,24 @@ impl Daemon {
                 if let Some(unit) = unit {
                     let unit_file_path = unit.server.unit_file_path();
                     let server_type = unit.server.server_type();
-                    let unit_config = unit.server.unit_config();
-                    let server_config = unit.server.server_config();
+                    let server_unit_config = ServerUnitConfig {
+                        unit: unit.server.unit_config(),
+                        server: unit.server.server_config(),
+                        triggers: unit.server.triggers(),
+                    };
+
+                    let environment = match &server_version {
+                        Some(version) => {
+                            vec![("MCMAN_UPDATE_VERSION".to_string(), version.to_string())]
+                        }
+                        None => vec![],
+                    };
+                    let pre_update = LifecycleHook::PreUpdate.for_unit(
+                        &unit_id,
+                        &server_unit_config.server,
+                        &unit_file_path,
+                        environment,
+                    );
 
                     self.subscribe_event(
                         ServerEventType::UpdateComplete,
@@ -351,14 +588,19 @@ impl Daemon {
                         client_id,
                     );
 
+                    self.unit_counters
+                        .entry(unit_id.clone())
+                        .or_default()
+                        .updates
+                        .start();
                     self.update_server(
                         EventHandler::new(self.event_manager_ctrl.clone()),
                         unit_id,
                         server_version,
                         server_type,
-                        unit_config,
-                        server_config,
+                        server_unit_config,
                         unit_file_path,
+                        pre_update,
                         self.queue_sender.clone(),
                     );
 
@@ -374,24 +616,489 @@ impl Daemon {
             DaemonCmd::SendMessage { unit_id, message } => {
                 let unit = self.servers.get_mut(&unit_id);
                 match unit {
-                    Some(server) => {
-                        server.say(message);
-                        DaemonResponse::Ok
-                    }
+                    Some(server) => match server.say(message) {
+                        Ok(()) => DaemonResponse::Ok,
+                        Err(e) => DaemonResponse::Error {
+                            error: format!("could not send message to unit {}: {}", unit_id, e),
+                        },
+                    },
                     None => DaemonResponse::ServerNotFound { server_id: unit_id },
                 }
             }
             DaemonCmd::SendCommand { unit_id, command } => {
                 let unit = self.servers.get_mut(&unit_id);
                 match unit {
-                    Some(server) => {
-                        server.send_command(command);
-                        DaemonResponse::Ok
+                    Some(server) => match server.send_command(command) {
+                        Ok(()) => DaemonResponse::Ok,
+                        Err(e) => DaemonResponse::Error {
+                            error: format!("could not send command to unit {}: {}", unit_id, e),
+                        },
+                    },
+                    None => DaemonResponse::ServerNotFound { server_id: unit_id },
+                }
+            }
+            DaemonCmd::ReloadUnits => self.reload_units(),
+            DaemonCmd::AttachConsole { unit_id, backlog } => {
+                if !self.servers.contains_key(&unit_id) {
+                    return DaemonResponse::ServerNotFound { server_id: unit_id };
+                }
+                let server_ids = Some(vec![unit_id.clone()]);
+                let lines = match self.log_service.console(&unit_id) {
+                    Some(console) => console.backlog(backlog, || {
+                        self.subscribe_event(ServerEventType::ConsoleLine, server_ids, client_id)
+                    }),
+                    None => {
+                        self.subscribe_event(ServerEventType::ConsoleLine, server_ids, client_id);
+                        vec![]
+                    }
+                };
+                DaemonResponse::ConsoleBacklog {
+                    server_id: unit_id,
+                    lines,
+                }
+            }
+            DaemonCmd::GetLogs {
+                unit_id,
+                session,
+                from,
+                limit,
+                grep,
+                level,
+                records,
+            } => {
+                if !self.servers.contains_key(&unit_id) {
+                    return DaemonResponse::ServerNotFound { server_id: unit_id };
+                }
+                // reading a large or compressed session takes long, the lines are sent when read
+                let clients = self.clients.clone();
+                spawn(move || {
+                    let response = logs::get_logs(
+                        Path::new(LOG_DIRECTORY),
+                        unit_id,
+                        LogQuery {
+                            session,
+                            from,
+                            limit,
+                            grep,
+                            level,
+                            records,
+                        },
+                    );
+                    clients.send(client_id, response);
+                });
+                DaemonResponse::Ok
+            }
+            DaemonCmd::QueryJournal {
+                unit_id,
+                since,
+                types,
+                limit,
+            } => match &self.journal {
+                Some(journal) => {
+                    let query = JournalQuery {
+                        unit_id,
+                        since,
+                        types,
+                        limit,
+                    };
+                    // the event manager records events under the lock, so it is only held to list
+                    // the files, which are read on a worker thread
+                    let files = journal.lock().expect("lock journal").files();
+                    let clients = self.clients.clone();
+                    spawn(move || {
+                        let response = match files.and_then(|files| query_files(&files, &query)) {
+                            Ok(entries) => DaemonResponse::Journal { entries },
+                            Err(e) => DaemonResponse::Error {
+                                error: format!("could not read journal: {}", e),
+                            },
+                        };
+                        clients.send(client_id, response);
+                    });
+                    DaemonResponse::Ok
+                }
+                None => DaemonResponse::Error {
+                    error: "the journal is disabled".to_string(),
+                },
+            },
+            DaemonCmd::GetMetrics => self.metrics(),
+            DaemonCmd::Backup { unit_id, label } => {
+                if !self.servers.contains_key(&unit_id) {
+                    return DaemonResponse::ServerNotFound { server_id: unit_id };
+                }
+                if self.backups.contains(&unit_id) {
+                    return DaemonResponse::Error {
+                        error: format!("a backup of unit {} is already running", unit_id),
+                    };
+                }
+                if self.restores.contains(&unit_id) {
+                    return DaemonResponse::Error {
+                        error: format!("unit {} is being restored", unit_id),
+                    };
+                }
+                if self.prunes.contains(&unit_id) {
+                    return DaemonResponse::Error {
+                        error: format!("backups of unit {} are being pruned", unit_id),
+                    };
+                }
+                if self.collecting_garbage {
+                    return DaemonResponse::Error {
+                        error: "garbage of the backups is being collected".to_string(),
+                    };
+                }
+                for event_type in [
+                    ServerEventType::ActionProgress,
+                    ServerEventType::BackupComplete,
+                    ServerEventType::BackupFailed,
+                ] {
+                    self.subscribe_event(event_type, Some(vec![unit_id.clone()]), client_id);
+                }
+                self.backup_server(unit_id, label);
+                DaemonResponse::Ok
+            }
+            DaemonCmd::ListBackups { unit_id } => {
+                match BackupStore::from_config(&self.config.backup).list(&unit_id) {
+                    Ok(backups) => DaemonResponse::Backups { unit_id, backups },
+                    Err(e) => DaemonResponse::Error {
+                        error: format!("could not list backups: {}", e),
+                    },
+                }
+            }
+            DaemonCmd::PruneBackups { unit_id, dry_run } => {
+                let unit_ids: Vec<String> = match unit_id {
+                    Some(unit_id) if self.servers.contains_key(&unit_id) => vec![unit_id],
+                    Some(unit_id) => return DaemonResponse::ServerNotFound { server_id: unit_id },
+                    None => self.servers.keys().cloned().collect(),
+                };
+                // a running restore may be extracting a backup that has expired
+                if !dry_run {
+                    if let Some(unit_id) = unit_ids.iter().find(|unit_id| {
+                        self.backups.contains(*unit_id)
+                            || self.restores.contains(*unit_id)
+                            || self.prunes.contains(*unit_id)
+                    }) {
+                        return DaemonResponse::Error {
+                            error: format!(
+                                "a backup, restore or prune of unit {} is running",
+                                unit_id
+                            ),
+                        };
+                    }
+                }
+                let mut retentions = vec![];
+                for unit_id in unit_ids {
+                    let server = &self.servers[&unit_id].server;
+                    let source = BackupSource::from_config(
+                        &unit_id,
+                        &server.server_config(),
+                        &server.unit_file_path(),
+                    );
+                    if let Some(retention) = source.retention {
+                        retentions.push((unit_id, retention));
+                    }
+                }
+                // a dry run does not delete anything, so it does not block other actions
+                let unit_ids: Vec<String> = if dry_run {
+                    vec![]
+                } else {
+                    retentions
+                        .iter()
+                        .map(|(unit_id, _)| unit_id.clone())
+                        .collect()
+                };
+                self.prunes.extend(unit_ids.iter().cloned());
+                let store = BackupStore::from_config(&self.config.backup);
+                let daemon_queue = self.queue_sender.clone();
+                spawn(move || {
+                    let mut pruned = vec![];
+                    let mut error = None;
+                    for (unit_id, retention) in retentions {
+                        match store.prune(&unit_id, &retention, dry_run) {
+                            Ok(backups) => pruned.extend(backups),
+                            Err(e) => {
+                                error = Some(format!(
+                                    "could not prune backups of unit {}: {}",
+                                    unit_id, e
+                                ));
+                                break;
+                            }
+                        }
+                    }
+                    let response = match error {
+                        Some(error) => DaemonResponse::Error { error },
+                        None => DaemonResponse::BackupsPruned {
+                            backups: pruned,
+                            dry_run,
+                        },
+                    };
+                    if daemon_queue
+                        .send(DaemonEvent::BackupsPruned {
+                            client_id,
+                            unit_ids,
+                            response,
+                        })
+                        .is_err()
+                    {
+                        warn!("could not pass the result of pruning backups");
+                    }
+                });
+                DaemonResponse::Ok
+            }
+            DaemonCmd::Restore {
+                unit_id,
+                backup_id,
+                jar,
+                config,
+            } => {
+                if !self.servers.contains_key(&unit_id) {
+                    return DaemonResponse::ServerNotFound { server_id: unit_id };
+                }
+                if self.backups.contains(&unit_id)
+                    || self.restores.contains(&unit_id)
+                    || self.prunes.contains(&unit_id)
+                {
+                    return DaemonResponse::Error {
+                        error: format!("a backup, restore or prune of unit {} is running", unit_id),
+                    };
+                }
+                if self.collecting_garbage {
+                    return DaemonResponse::Error {
+                        error: "garbage of the backups is being collected".to_string(),
+                    };
+                }
+                let backup = match BackupStore::from_config(&self.config.backup)
+                    .find(&unit_id, &backup_id)
+                {
+                    Ok(backup) => backup,
+                    Err(e) => {
+                        return DaemonResponse::Error {
+                            error: e.to_string(),
+                        }
+                    }
+                };
+                for event_type in [
+                    ServerEventType::ActionProgress,
+                    ServerEventType::RestoreComplete,
+                    ServerEventType::RestoreFailed,
+                ] {
+                    self.subscribe_event(event_type, Some(vec![unit_id.clone()]), client_id);
+                }
+                self.restore_server(unit_id, backup, RestoreOptions { jar, config });
+                DaemonResponse::Ok
+            }
+            DaemonCmd::Lockdown { unit_id, message } => {
+                let server = match self.servers.get_mut(&unit_id) {
+                    Some(server) => server,
+                    None => return DaemonResponse::ServerNotFound { server_id: unit_id },
+                };
+                if server.server.server_type().is_proxy() {
+                    return DaemonResponse::Error {
+                        error: format!("unit {} is a proxy, which has no worlds", unit_id),
+                    };
+                }
+                if !matches!(server.status(), ServerStatus::Running) {
+                    return DaemonResponse::Error {
+                        error: format!("unit {} is not running", unit_id),
+                    };
+                }
+                if self.backups.contains(&unit_id) {
+                    return DaemonResponse::Error {
+                        error: format!("a backup of unit {} is running", unit_id),
+                    };
+                }
+                if self.collecting_garbage {
+                    return DaemonResponse::Error {
+                        error: "garbage of the backups is being collected".to_string(),
+                    };
+                }
+                for event_type in [
+                    ServerEventType::ActionProgress,
+                    ServerEventType::LockdownComplete,
+                    ServerEventType::LockdownFailed,
+                ] {
+                    self.subscribe_event(event_type, Some(vec![unit_id.clone()]), client_id);
+                }
+                let message = message.unwrap_or_else(|| lockdown::DEFAULT_MESSAGE.to_string());
+                self.lockdown_server(unit_id, message);
+                DaemonResponse::Ok
+            }
+            DaemonCmd::Unlock { unit_id } => {
+                let server = match self.servers.get_mut(&unit_id) {
+                    Some(server) => server,
+                    None => return DaemonResponse::ServerNotFound { server_id: unit_id },
+                };
+                match server.lockdown {
+                    None => DaemonResponse::Error {
+                        error: format!("unit {} is not in lockdown", unit_id),
+                    },
+                    Some(_) if self.backups.contains(&unit_id) => DaemonResponse::Error {
+                        error: format!("the lockdown of unit {} is in progress", unit_id),
+                    },
+                    Some(_) => match server.unlock() {
+                        Ok(()) => {
+                            server.send_daemon_command("save-on".to_string());
+                            info!("unit {} has been unlocked", unit_id);
+                            EventHandler::new(self.event_manager_ctrl.clone()).raise_event(
+                                &unit_id,
+                                ServerEvent::ServerUnlocked {
+                                    server_id: unit_id.clone(),
+                                },
+                            );
+                            DaemonResponse::Ok
+                        }
+                        Err(e) => DaemonResponse::Error {
+                            error: format!("could not resume unit {}: {}", unit_id, e),
+                        },
+                    },
+                }
+            }
+            DaemonCmd::VerifyBackups { unit_id } => {
+                // reading every backup takes long, the result is sent when it is done
+                let store = BackupStore::from_config(&self.config.backup);
+                let clients = self.clients.clone();
+                spawn(move || {
+                    let response = match store.verify(unit_id.as_deref()) {
+                        Ok(verifications) => DaemonResponse::BackupsVerified { verifications },
+                        Err(e) => DaemonResponse::Error {
+                            error: format!("could not verify backups: {}", e),
+                        },
+                    };
+                    clients.send(client_id, response);
+                });
+                DaemonResponse::Ok
+            }
+            DaemonCmd::CollectBackupGarbage { dry_run } => {
+                // chunks that a running backup has just stored are not referenced yet
+                if !dry_run && !(self.backups.is_empty() && self.restores.is_empty()) {
+                    return DaemonResponse::Error {
+                        error: "backups or restores are running".to_string(),
+                    };
+                }
+                if !dry_run && self.collecting_garbage {
+                    return DaemonResponse::Error {
+                        error: "garbage is already being collected".to_string(),
+                    };
+                }
+                // no backup may store chunks until the result has been passed to the daemon
+                self.collecting_garbage |= !dry_run;
+                let store = BackupStore::from_config(&self.config.backup);
+                let daemon_queue = self.queue_sender.clone();
+                spawn(move || {
+                    let response = match store.collect_garbage(dry_run) {
+                        Ok((chunks, size)) => {
+                            if !dry_run {
+                                info!("deleted {} unreferenced chunks ({} bytes)", chunks, size);
+                            }
+                            DaemonResponse::BackupGarbageCollected {
+                                chunks,
+                                size,
+                                dry_run,
+                            }
+                        }
+                        Err(e) => DaemonResponse::Error {
+                            error: format!("could not collect garbage: {}", e),
+                        },
+                    };
+                    if daemon_queue
+                        .send(DaemonEvent::GarbageCollected {
+                            client_id,
+                            dry_run,
+                            response,
+                        })
+                        .is_err()
+                    {
+                        warn!("could not pass the result of the garbage collection");
                     }
-                    None => DaemonResponse::ServerNotFound { server_id: unit_id }
+                });
+                DaemonResponse::Ok
+            }
+            DaemonCmd::GetPerformance { unit_id } => match self.servers.get(&unit_id) {
+                Some(server) => DaemonResponse::Performance {
+                    stats: server.performance_stats(),
+                    unit_id,
+                },
+                None => DaemonResponse::ServerNotFound { server_id: unit_id },
+            },
+            DaemonCmd::DetachConsole { unit_id } => {
+                self.event_manager_ctrl
+                    .send(EventManagerCmd::RemoveSubscription {
+                        server_id: Some(unit_id),
+                        event_type: ServerEventType::ConsoleLine,
+                        client_id,
+                    })
+                    .unwrap();
+                DaemonResponse::Ok
+            }
+        }
+    }
+
+    /// Scans the unit directories again and adds, reloads or removes units that are stopped.
+    fn reload_units(&mut self) -> DaemonResponse {
+        let mut units = self.config.create_servers();
+        let mut added = vec![];
+        let mut reloaded = vec![];
+        let mut removed = vec![];
+        let mut skipped = vec![];
+
+        let unit_ids: Vec<String> = self.servers.keys().cloned().collect();
+        for unit_id in unit_ids {
+            let server = self
+                .servers
+                .get_mut(&unit_id)
+                .expect("unit id taken from key set");
+            let stopped = matches!(
+                server.status(),
+                ServerStatus::Down | ServerStatus::Errored(_)
+            ) && !self.restores.contains(&unit_id);
+            match units.remove(&unit_id) {
+                Some(unit) if stopped => {
+                    *server = DaemonServer::new(unit_id.clone(), unit);
+                    reloaded.push(unit_id);
+                }
+                None if stopped => {
+                    self.servers.remove(&unit_id);
+                    removed.push(unit_id);
                 }
+                _ => skipped.push(unit_id),
             }
         }
+        for (unit_id, unit) in units {
+            self.servers
+                .insert(unit_id.clone(), DaemonServer::new(unit_id.clone(), unit));
+            added.push(unit_id);
+        }
+
+        let mut event_handler = EventHandler::new(self.event_manager_ctrl.clone());
+        for server_id in &added {
+            event_handler.raise_event(
+                server_id,
+                ServerEvent::UnitAdded {
+                    server_id: server_id.clone(),
+                },
+            );
+        }
+        for server_id in &reloaded {
+            event_handler.raise_event(
+                server_id,
+                ServerEvent::UnitReloaded {
+                    server_id: server_id.clone(),
+                },
+            );
+        }
+        for server_id in &removed {
+            event_handler.raise_event(
+                server_id,
+                ServerEvent::UnitRemoved {
+                    server_id: server_id.clone(),
+                },
+            );
+        }
+
+        DaemonResponse::UnitsReloaded {
+            added,
+            reloaded,
+            removed,
+            skipped,
+        }
     }
 
     pub fn subscribe_event(
@@ -400,21 +1107,51 @@ impl Daemon {
         server_ids: Option<Vec<String>>,
         client_id: u32,
     ) {
-        if let Some(server_ids) = server_ids {
-            for server_id in server_ids {
-                self.event_manager_ctrl
-                    .send(EventManagerCmd::AddSubscription {
-                        server_id,
-                        event_type,
-                        client_id,
-                    })
-                    .unwrap();
+        match server_ids {
+            Some(server_ids) => {
+                for server_id in server_ids {
+                    self.event_manager_ctrl
+                        .send(EventManagerCmd::AddSubscription {
+                            server_id: Some(server_id),
+                            event_type: event_type.clone(),
+                            client_id,
+                        })
+                        .unwrap();
+                }
             }
+            None => self
+                .event_manager_ctrl
+                .send(EventManagerCmd::AddSubscription {
+                    server_id: None,
+                    event_type,
+                    client_id,
+                })
+                .unwrap(),
         }
     }
 
-    pub fn senders(&self) -> Arc<Mutex<HashMap<u32, IpcSender<DaemonResponse>>>> {
-        self.senders.clone()
+    pub fn clients(&self) -> ClientRegistry {
+        self.clients.clone()
+    }
+
+    /// Returns the journal of the daemon, if it is enabled.
+    pub fn journal(&self) -> Option<SharedJournal> {
+        self.journal.clone()
+    }
+
+    /// Records a command of a client in the journal.
+    fn record_cmd(&self, cmd: &DaemonCmd, client_id: u32) {
+        if let Some(journal) = &self.journal {
+            let mut journal = journal.lock().expect("lock journal");
+            if !cmd.is_query() || journal.records_queries() {
+                journal.record_or_warn(&JournalEntry::command(
+                    client_id,
+                    self.clients.client_name(client_id),
+                    self.clients.client_uid(client_id),
+                    cmd.clone(),
+                ));
+            }
+        }
     }
 
     pub fn start_thread(mut self) {
@@ -422,28 +1159,38 @@ impl Daemon {
             while let Ok(cmd) = self.queue.recv() {
                 match cmd {
                     DaemonEvent::IncomingCmd { id, cmd } => {
+                        self.record_cmd(&cmd, id);
                         let response = self.handle_cmd(cmd, id);
 
-                        let mut senders = self.senders.lock().unwrap();
-                        let sender = senders.get_mut(&id).unwrap();
-                        match sender.send(response) {
-                            Ok(_) => {}
-                            Err(_) => {
-                                self.event_manager_ctrl
-                                    .send(EventManagerCmd::RemoveAllSubscriptions { client_id: id })
-                                    .unwrap();
-                            }
+                        if !self.clients.send(id, response) {
+                            self.event_manager_ctrl
+                                .send(EventManagerCmd::RemoveAllSubscriptions { client_id: id })
+                                .unwrap();
                         }
                     }
                     DaemonEvent::SendEvent { client_id, event } => {
-                        let mut senders = self.senders.lock().unwrap();
-                        let sender = senders.get_mut(&client_id).unwrap();
-                        match sender.send(DaemonResponse::ServerEvent { event }) {
-                            Ok(_) => {}
-                            Err(_) => {
-                                self.event_manager_ctrl
-                                    .send(EventManagerCmd::RemoveAllSubscriptions { client_id })
-                                    .unwrap();
+                        if !self
+                            .clients
+                            .send(client_id, DaemonResponse::ServerEvent { event })
+                        {
+                            self.event_manager_ctrl
+                                .send(EventManagerCmd::RemoveAllSubscriptions { client_id })
+                                .unwrap();
+                        }
+                    }
+                    DaemonEvent::StartUnit { server_id } => {
+                        if let Some(server) = self.servers.get_mut(&server_id) {
+                            match server.status() {
+                                ServerStatus::Down | ServerStatus::Errored(_) => {
+                                    server.start(
+                                        self.log_service.deref_mut(),
+                                        &self.queue_sender,
+                                        None,
+                                    );
+                                }
+                                status => {
+                                    debug!("not starting unit {} with status {}", server_id, status)
+                                }
                             }
                         }
                     }
@@ -452,27 +1199,97 @@ impl Daemon {
                         unit_file,
                     } => {
                         let unit_id = server_unit_config.unit.id.clone();
-                        match create_server(server_unit_config, unit_file) {
+                        match create_server(*server_unit_config, unit_file) {
                             Ok(server) => {
+                                let event = if self.servers.contains_key(&unit_id) {
+                                    ServerEvent::UnitReloaded {
+                                        server_id: unit_id.clone(),
+                                    }
+                                } else {
+                                    ServerEvent::UnitAdded {
+                                        server_id: unit_id.clone(),
+                                    }
+                                };
                                 self.servers.insert(
                                     unit_id.clone(),
-                                    DaemonServer {
-                                        process: None,
-                                        server,
-                                        status: None,
-                                        server_id: unit_id,
-                                    },
+                                    DaemonServer::new(unit_id.clone(), server),
                                 );
+                                EventHandler::new(self.event_manager_ctrl.clone())
+                                    .raise_event(&unit_id, event);
                             }
                             _ => (),
                         }
                     }
-                    DaemonEvent::StopDaemon => {
-                        #[cfg(feature = "systemd")]
-                        if let Ok(true) = sd_notify::booted() {
-                            if let Ok(ctrl) = std::env::var("MCMAND_CTRL") {
-                                if ctrl == "systemd" {
-                                    debug!("systemd detected, notifying systemd");
+                    DaemonEvent::HookFinished {
+                        server_id,
+                        hook,
+                        result,
+                    } => self.finish_hook(server_id, hook, result),
+                    DaemonEvent::TriggerFired { server_id, trigger } => {
+                        self.perform_trigger(server_id, trigger)
+                    }
+                    DaemonEvent::LifecycleEvent { event } => {
+                        self.count_event(&event);
+                        self.run_lifecycle_hook(event);
+                    }
+                    DaemonEvent::BackupFinished { server_id, result } => {
+                        self.finish_backup(server_id, result)
+                    }
+                    DaemonEvent::LockdownSaved { server_id, saved } => {
+                        self.suspend_server(server_id, saved)
+                    }
+                    DaemonEvent::LockdownFinished {
+                        server_id,
+                        snapshot,
+                    } => {
+                        self.backups.remove(&server_id);
+                        let (snapshot_id, snapshot_error) = match snapshot {
+                            Ok(snapshot) => (Some(snapshot.id), None),
+                            Err(error) => (None, Some(error)),
+                        };
+                        info!("unit {} is in lockdown", server_id);
+                        EventHandler::new(self.event_manager_ctrl.clone()).raise_event(
+                            &server_id,
+                            ServerEvent::LockdownComplete {
+                                server_id: server_id.clone(),
+                                snapshot_id,
+                                snapshot_error,
+                            },
+                        );
+                    }
+                    DaemonEvent::RestoreFinished {
+                        server_id,
+                        backup_id,
+                        result,
+                        start,
+                    } => self.finish_restore(server_id, backup_id, result, start),
+                    DaemonEvent::GarbageCollected {
+                        client_id,
+                        dry_run,
+                        response,
+                    } => {
+                        if !dry_run {
+                            self.collecting_garbage = false;
+                        }
+                        self.clients.send(client_id, response);
+                    }
+                    DaemonEvent::BackupsPruned {
+                        client_id,
+                        unit_ids,
+                        response,
+                    } => {
+                        for unit_id in &unit_ids {
+                            self.prunes.remove(unit_id);
+                        }
+                        self.clients.send(client_id, response);
+                    }
+                    DaemonEvent::Tick => self.poll_performance(),
+                    DaemonEvent::StopDaemon => {
+                        #[cfg(feature = "systemd")]
+                        if let Ok(true) = sd_notify::booted() {
+                            if let Ok(ctrl) = std::env::var("MCMAND_CTRL") {
+                                if ctrl == "systemd" {
+                                    debug!("systemd detected, notifying systemd");
                                     let _ = sd_notify::notify(false, &[NotifyState::Stopping]);
                                 }
                             }
@@ -483,19 +1300,31 @@ impl Daemon {
                             .map(|(unit_id, server)| {
                                 debug!("Stopping unit {}", unit_id);
                                 match server.status() {
+                                    // a start that waits for its hook is not continued
+                                    ServerStatus::Starting if server.cancel_start() => {
+                                        (unit_id, None)
+                                    }
                                     ServerStatus::Starting => {
                                         if server.has_started() {
-                                            (unit_id, server.stop())
+                                            (unit_id, server.stop_now())
                                         } else {
                                             (unit_id, None)
                                         }
                                     }
-                                    ServerStatus::Running => (unit_id, server.stop()),
-                                    ServerStatus::Updating => {
-                                        panic!("currently no strategy implemented!")
+                                    // a server in lockdown is resumed by stop
+                                    ServerStatus::Running | ServerStatus::Lockdown => {
+                                        (unit_id, server.stop_now())
+                                    }
+                                    // the daemon does not wait for a running `pre_stop` hook
+                                    ServerStatus::Stopping => {
+                                        match server.take_pending(LifecycleHook::PreStop) {
+                                            Some(_) => (unit_id, server.send_stop()),
+                                            None => (unit_id, None),
+                                        }
                                     }
-                                    ServerStatus::Lockdown => {
-                                        panic!("currently no strategy implemented!")
+                                    ServerStatus::Updating => {
+                                        debug!("not waiting for the update of unit {}", unit_id);
+                                        (unit_id, None)
                                     }
                                     _ => {
                                         debug!("Nothing to do for unit {}", unit_id);
@@ -517,12 +1346,9 @@ impl Daemon {
                             .expect("send to own event queue");
                     }
                     DaemonEvent::SendDaemonEvent(DaemonIpcEvent::Stopped) => {
-                        let mut senders = self.senders.lock().unwrap();
-                        for sender in senders.values_mut() {
-                            //ignore because sockets are closed anyway when we exit
-                            let _ =
-                                sender.send(DaemonResponse::DaemonEvent(DaemonIpcEvent::Stopped));
-                        }
+                        //errors are ignored because sockets are closed anyway when we exit
+                        self.clients
+                            .broadcast(DaemonResponse::DaemonEvent(DaemonIpcEvent::Stopped));
                         sleep(Duration::from_millis(500)); // might not really be necessary but leave time to propagate events
                         exit(0);
                     }
@@ -531,6 +1357,182 @@ impl Daemon {
         });
     }
 
+    /// Performs the actions of a console trigger that has fired.
+    fn perform_trigger(&mut self, server_id: String, trigger: TriggerMatch) {
+        let server = match self.servers.get_mut(&server_id) {
+            Some(server) => server,
+            None => return,
+        };
+        debug!("trigger fired on unit {}: {}", server_id, trigger.line);
+        if let Some(event) = trigger.event(&server_id) {
+            EventHandler::new(self.event_manager_ctrl.clone()).raise_event(&server_id, event);
+        }
+        if let Some(command) = &trigger.command {
+            server.send_daemon_command(command.clone());
+        }
+        if let Some(hook) = &trigger.hook {
+            Hook {
+                name: format!("trigger of unit {}", server_id),
+                command: hook.clone(),
+                directory: PathBuf::from(server.server.path()),
+                environment: trigger.hook_environment(&server_id),
+                timeout: hooks::DEFAULT_TIMEOUT,
+            }
+            .spawn();
+        }
+    }
+
+    /// Updates the counters of the unit of an event.
+    fn count_event(&mut self, event: &ServerEvent) {
+        let server_id = match event.server_id() {
+            Some(server_id) => server_id.to_string(),
+            None => return,
+        };
+        let counters = self.unit_counters.entry(server_id).or_default();
+        match event {
+            ServerEvent::ServerStarted { .. } => counters.starts += 1,
+            ServerEvent::ServerFailed { .. } => counters.failures += 1,
+            ServerEvent::InstallationComplete { .. } => counters.installs.finish(true),
+            ServerEvent::InstallationFailed { .. } => counters.installs.finish(false),
+            ServerEvent::UpdateComplete { .. } => counters.updates.finish(true),
+            ServerEvent::UpdateFailed { .. } => counters.updates.finish(false),
+            _ => (),
+        }
+    }
+
+    /// Collects the current metrics of the units and of the daemon.
+    fn metrics(&mut self) -> DaemonResponse {
+        let mut units = Vec::with_capacity(self.unit_counters.len());
+        for (unit_id, server) in &mut self.servers {
+            let last_sample = server.performance_stats().samples.pop();
+            units.push(UnitMetrics {
+                unit_id: unit_id.clone(),
+                server_type: Some(server.server.server_type()),
+                status: Some(server.status()),
+                process: server
+                    .process
+                    .as_ref()
+                    .and_then(|process| ProcessStats::read(process.id())),
+                online_players: server.online_players().len(),
+                tps: last_sample
+                    .as_ref()
+                    .and_then(|sample| sample.tps.map(|tps| tps[0])),
+                mspt: last_sample.and_then(|sample| sample.mspt),
+                counters: self.unit_counters.get(unit_id).cloned().unwrap_or_default(),
+            });
+        }
+        // e.g. units whose installation has failed
+        for (unit_id, counters) in &self.unit_counters {
+            if !self.servers.contains_key(unit_id) {
+                units.push(UnitMetrics {
+                    unit_id: unit_id.clone(),
+                    server_type: None,
+                    status: None,
+                    process: None,
+                    online_players: 0,
+                    tps: None,
+                    mspt: None,
+                    counters: counters.clone(),
+                });
+            }
+        }
+        units.sort_by(|a, b| a.unit_id.cmp(&b.unit_id));
+
+        let ipc_clients = self.clients.ipc_len();
+        DaemonResponse::Metrics {
+            units,
+            daemon: DaemonMetrics {
+                ipc_clients,
+                local_clients: self.clients.len() - ipc_clients,
+                pending_events: event::pending_events(),
+            },
+        }
+    }
+
+    /// Continues the start or stop of a server when its `pre_start` or `pre_stop` hook has
+    /// finished.
+    ///
+    /// The result is ignored if the action has been cancelled or the unit has been reloaded in the
+    /// meantime.
+    fn finish_hook(&mut self, server_id: String, hook: LifecycleHook, result: Result<(), String>) {
+        let server = match self.servers.get_mut(&server_id) {
+            Some(server) => server,
+            None => return,
+        };
+        match (server.take_pending(hook), result) {
+            (Some(PendingHook::Start { .. }), Ok(())) => server.spawn(self.log_service.deref_mut()),
+            (Some(PendingHook::Start { client_id }), Err(error)) => {
+                warn!("not starting unit {}: {}", server_id, error);
+                if let Some(client_id) = client_id {
+                    self.clients.send(
+                        client_id,
+                        DaemonResponse::HookFailed {
+                            unit_id: server_id,
+                            hook: hook.name().to_string(),
+                            error,
+                        },
+                    );
+                }
+            }
+            // the server is stopped even if the hook has failed
+            (Some(PendingHook::Stop { then }), _) => then(server.send_stop()),
+            (None, _) => debug!(
+                "{} hook of unit {} has finished, no action waits for it",
+                hook.name(),
+                server_id
+            ),
+        }
+    }
+
+    /// Runs the `post_start`, `post_stop` or `on_failure` hook of the unit of an event in the
+    /// background.
+    fn run_lifecycle_hook(&self, event: ServerEvent) {
+        let (server_id, hook, environment) = match event {
+            ServerEvent::ServerStarted { server_id } => {
+                (server_id, LifecycleHook::PostStart, vec![])
+            }
+            ServerEvent::ServerStopped { server_id } => {
+                (server_id, LifecycleHook::PostStop, vec![])
+            }
+            ServerEvent::ServerFailed {
+                server_id, error, ..
+            } => (
+                server_id,
+                LifecycleHook::OnFailure,
+                vec![("MCMAN_ERROR".to_string(), error)],
+            ),
+            _ => return,
+        };
+        if let Some(server) = self.servers.get(&server_id) {
+            if let Some(hook) = hook.for_unit(
+                &server_id,
+                &server.server.server_config(),
+                &server.server.unit_file_path(),
+                environment,
+            ) {
+                hook.spawn();
+            }
+        }
+    }
+
+    /// Polls the performance of all running servers whose poll interval has elapsed and raises
+    /// the events for crossed thresholds.
+    fn poll_performance(&mut self) {
+        let mut event_handler = EventHandler::new(self.event_manager_ctrl.clone());
+        for (unit_id, server) in &mut self.servers {
+            if let Some(event) = server.poll_performance() {
+                if let ServerEvent::PerformanceDegraded { reasons, .. } = &event {
+                    warn!(
+                        "performance of unit {} degraded: {}",
+                        unit_id,
+                        reasons.join(", ")
+                    );
+                }
+                event_handler.raise_event(unit_id, event);
+            }
+        }
+    }
+
     pub fn install_server(
         &mut self,
         mut event_handler: EventHandler,
@@ -551,93 +1553,129 @@ impl Daemon {
                     error: "a unit with that name already exists".to_string(),
                 },
             );
-        } else {
-            spawn(move || {
-                let server_id = unit_id.clone();
-                let install_result = Daemon::perform_installation(
-                    &mut event_handler,
-                    unit_id,
-                    install_path,
-                    unit_file_path.clone(),
-                    server_version,
-                    server_type,
-                    accept_eula,
-                    server_name,
+            return;
+        }
+        let installer = match self.installer(server_type, &event_handler, &unit_id) {
+            Ok(installer) => installer,
+            Err(e) => {
+                event_handler.raise_event(
+                    unit_id.as_str(),
+                    ServerEvent::InstallationFailed {
+                        server_id: unit_id.clone(),
+                        error: format!("{:?}", e),
+                    },
                 );
-                match install_result {
-                    Ok(server_unit_config) => {
-                        let server_id = server_unit_config.unit.id.clone();
-                        daemon_queue
-                            .send(DaemonEvent::AddServerUnit {
-                                server_unit_config,
-                                unit_file: unit_file_path.unwrap().into(),
-                            })
-                            .expect("send to daemon main event queue");
-                        event_handler.raise_event(
-                            server_id.as_ref(),
-                            ServerEvent::InstallationComplete {
-                                server_id: server_id.clone(),
-                            },
-                        )
-                    }
-                    Err(e) => event_handler.raise_event(
+                return;
+            }
+        };
+        spawn(move || {
+            let server_id = unit_id.clone();
+            let install_result = Daemon::perform_installation(
+                installer,
+                unit_id,
+                install_path,
+                unit_file_path.clone(),
+                server_version,
+                accept_eula,
+                server_name,
+            );
+            match install_result {
+                Ok(server_unit_config) => {
+                    let server_id = server_unit_config.unit.id.clone();
+                    daemon_queue
+                        .send(DaemonEvent::AddServerUnit {
+                            server_unit_config: Box::new(server_unit_config),
+                            unit_file: unit_file_path.unwrap().into(),
+                        })
+                        .expect("send to daemon main event queue");
+                    event_handler.raise_event(
                         server_id.as_ref(),
-                        ServerEvent::InstallationFailed {
+                        ServerEvent::InstallationComplete {
                             server_id: server_id.clone(),
-                            error: format!("{:?}", e),
                         },
-                    ),
+                    )
                 }
-            });
-        }
+                Err(e) => event_handler.raise_event(
+                    server_id.as_ref(),
+                    ServerEvent::InstallationFailed {
+                        server_id: server_id.clone(),
+                        error: format!("{:?}", e),
+                    },
+                ),
+            }
+        });
+    }
+
+    /// Returns the installer for a type of server.
+    fn installer(
+        &self,
+        server_type: ServerType,
+        event_handler: &EventHandler,
+        unit_id: &str,
+    ) -> Result<Box<dyn ServerInstaller + Send>, InstallError> {
+        let event_handler = event_handler.clone();
+        let unit_id = unit_id.to_string();
+        Ok(match server_type {
+            ServerType::Paper
+            | ServerType::Folia
+            | ServerType::Velocity
+            | ServerType::Waterfall => {
+                let project = PaperProject::for_server_type(&server_type)
+                    .expect("server type is a PaperMC project");
+                Box::new(PaperServerInstaller::new(
+                    event_handler,
+                    unit_id,
+                    PaperRepository::new(project),
+                ))
+            }
+            ServerType::Vanilla => Box::new(VanillaServerInstaller::new(
+                event_handler,
+                unit_id,
+                VanillaRepository::default(),
+            )),
+            ServerType::Spigot | ServerType::Bukkit => Box::new(SpigotServerInstaller::new(
+                event_handler,
+                unit_id,
+                server_type,
+                BuildTools::new(PathBuf::from(&self.config.build_cache)),
+            )?),
+        })
     }
 
     fn perform_installation(
-        event_handler: &mut EventHandler,
+        mut installer: Box<dyn ServerInstaller + Send>,
         unit_id: String,
         install_path: String,
         unit_file_path: Option<String>,
         server_version: Option<Version>,
-        server_type: ServerType,
         accept_eula: bool,
         server_name: Option<String>,
     ) -> Result<ServerUnitConfig, InstallError> {
-        match server_type {
-            ServerType::Paper => {
-                let mut paper_installer =
-                    PaperServerInstaller::new(event_handler.clone(), unit_id.clone());
-                if let Some(unit_file_path) = unit_file_path {
-                    let path = Path::new(unit_file_path.as_str());
-                    let mut unit_path = PathBuf::new();
-                    unit_path.push(path);
-                    //TODO check if unit dir is writeable
-
-                    let server_config = paper_installer.install_server(
-                        install_path,
-                        server_version,
-                        accept_eula,
-                        server_name,
-                    )?;
-                    let server_unit_config = ServerUnitConfig {
-                        unit: UnitConfig {
-                            id: unit_id.clone(),
-                            unit_type: "server".to_string(),
-                        },
-                        server: server_config,
-                    };
+        if let Some(unit_file_path) = unit_file_path {
+            let path = Path::new(unit_file_path.as_str());
+            let mut unit_path = PathBuf::new();
+            unit_path.push(path);
+            //TODO check if unit dir is writeable
 
-                    let config_string = toml::to_string(&server_unit_config).unwrap();
-                    debug!("writing configuration {} to {:?}", config_string, unit_path);
-                    fs::write(unit_path, config_string)
-                        .map_err(|e| InstallError::WriteUnitFile(e))?;
+            let server_config =
+                installer.install_server(install_path, server_version, accept_eula, server_name)?;
+            let server_unit_config = ServerUnitConfig {
+                unit: UnitConfig {
+                    id: unit_id.clone(),
+                    unit_type: "server".to_string(),
+                },
+                server: server_config,
+                triggers: vec![],
+            };
 
-                    Ok(server_unit_config)
-                } else {
-                    //TODO construct path
-                    Err(InstallError::DirExists)
-                }
-            }
-            _ => Err(InstallError::UnsupportedServerType(server_type)),
+            let config_string = toml::to_string(&server_unit_config).unwrap();
+            debug!("writing configuration {} to {:?}", config_string, unit_path);
+            fs::write(unit_path, config_string).map_err(|e| InstallError::WriteUnitFile(e))?;
+
+            Ok(server_unit_config)
+        } else {
+            //TODO construct path
+            Err(InstallError::DirExists)
         }
     }
 
@@ -647,29 +1685,48 @@ impl Daemon {
         unit_id: String,
         server_version: Option<Version>,
         server_type: ServerType,
-        unit_config: UnitConfig,
-        server_config: ServerConfig,
+        server_unit_config: ServerUnitConfig,
         unit_file_path: PathBuf,
+        pre_update: Option<Hook>,
         daemon_queue: Sender<DaemonEvent>,
     ) {
         spawn(move || {
             let server_id = unit_id.clone();
+            if let Some(hook) = pre_update {
+                if let Err(error) = hook.run() {
+                    event_handler.raise_event(
+                        &server_id,
+                        ServerEvent::UpdateFailed {
+                            server_id: server_id.clone(),
+                            error: format!("{}: {}", LifecycleHook::PreUpdate.name(), error),
+                        },
+                    );
+                    return;
+                }
+            }
             let update_result = Daemon::perform_update(
                 event_handler.clone(),
                 unit_id,
                 server_version,
                 server_type,
-                unit_config,
-                server_config,
+                server_unit_config,
                 unit_file_path.clone(),
             );
 
             match update_result {
                 Ok(server_unit_config) => {
                     let server_id = server_unit_config.unit.id.clone();
+                    if let Some(hook) = LifecycleHook::PostUpdate.for_unit(
+                        &server_id,
+                        &server_unit_config.server,
+                        &unit_file_path,
+                        vec![],
+                    ) {
+                        hook.spawn();
+                    }
                     daemon_queue
                         .send(DaemonEvent::AddServerUnit {
-                            server_unit_config,
+                            server_unit_config: Box::new(server_unit_config),
                             unit_file: unit_file_path.into(),
                         })
                         .expect("send to daemon main event queue");
@@ -691,38 +1748,586 @@ impl Daemon {
         });
     }
 
+    /// Backs up the worlds of a server on a new thread.
+    ///
+    /// If the server is running, automatic saving is turned off and the worlds are saved first.
+    /// Proxies have no worlds to save, they are backed up like stopped servers.
+    /// The thread sends [`DaemonEvent::BackupFinished`] when it is done.
+    fn backup_server(&mut self, unit_id: String, label: Option<String>) {
+        let server = self
+            .servers
+            .get_mut(&unit_id)
+            .expect("backup of existing unit");
+        let source = BackupSource::from_config(
+            &unit_id,
+            &server.server.server_config(),
+            &server.server.unit_file_path(),
+        );
+        let mut event_handler = EventHandler::new(self.event_manager_ctrl.clone());
+
+        // the console is subscribed before the commands are sent, so the saved line is not missed
+        let console = if matches!(server.status(), ServerStatus::Running)
+            && !server.server.server_type().is_proxy()
+        {
+            let console = Daemon::subscribe_console(
+                &self.clients,
+                &self.event_manager_ctrl,
+                &unit_id,
+                format!("backup of {}", unit_id),
+            );
+            event_handler.raise_event(
+                &unit_id,
+                ServerEvent::ActionProgress {
+                    server_id: unit_id.clone(),
+                    action: "saving worlds".to_string(),
+                    progress: None,
+                    maximum: None,
+                    action_number: 1,
+                },
+            );
+            server.send_daemon_command("save-off".to_string());
+            server.send_daemon_command("save-all flush".to_string());
+            Some(console)
+        } else {
+            None
+        };
+
+        self.backups.insert(unit_id.clone());
+        let store = BackupStore::from_config(&self.config.backup);
+        let save_timeout = Duration::from_secs(self.config.backup.save_timeout_secs);
+        let clients = self.clients.clone();
+        let event_manager_ctrl = self.event_manager_ctrl.clone();
+        let daemon_queue = self.queue_sender.clone();
+        spawn(move || {
+            let result = Daemon::perform_backup(
+                &mut event_handler,
+                &source,
+                label,
+                &store,
+                console.as_ref().map(|(_, console)| console),
+                save_timeout,
+            );
+            if let Some((client_id, _)) = console {
+                clients.unregister(client_id);
+                let _ =
+                    event_manager_ctrl.send(EventManagerCmd::RemoveAllSubscriptions { client_id });
+            }
+            daemon_queue
+                .send(DaemonEvent::BackupFinished {
+                    server_id: source.unit_id,
+                    result: result.map_err(|e| e.to_string()),
+                })
+                .expect("send to daemon main event queue");
+        });
+    }
+
+    fn perform_backup(
+        event_handler: &mut EventHandler,
+        source: &BackupSource,
+        label: Option<String>,
+        store: &BackupStore,
+        console: Option<&Receiver<DaemonResponse>>,
+        save_timeout: Duration,
+    ) -> Result<BackupInfo, BackupError> {
+        if let Some(console) = console {
+            backup::wait_for_save(console, save_timeout)?;
+        }
+        let backup = store.create(source, label, &mut |archived, total| {
+            event_handler.raise_event(
+                &source.unit_id,
+                ServerEvent::ActionProgress {
+                    server_id: source.unit_id.clone(),
+                    action: "archiving worlds".to_string(),
+                    progress: Some(archived as usize),
+                    maximum: Some(total as usize),
+                    action_number: 1,
+                },
+            )
+        })?;
+
+        // a failed pruning does not fail the backup
+        if let Some(retention) = &source.retention {
+            match store.prune(&source.unit_id, retention, false) {
+                Ok(pruned) => {
+                    for pruned in pruned {
+                        info!("pruned backup {} of unit {}", pruned.id, source.unit_id);
+                    }
+                }
+                Err(e) => warn!("could not prune backups of unit {}: {}", source.unit_id, e),
+            }
+        }
+        Ok(backup)
+    }
+
+    /// Registers a local client that receives the console lines of a server.
+    ///
+    /// Returns the id of the client, which must be unregistered when it is not needed anymore,
+    /// and the receiver of the lines.
+    fn subscribe_console(
+        clients: &ClientRegistry,
+        event_manager_ctrl: &Sender<EventManagerCmd>,
+        unit_id: &str,
+        name: String,
+    ) -> (u32, Receiver<DaemonResponse>) {
+        let (sender, console) = channel();
+        let client_id = clients.register(name, ClientConnection::Local(sender));
+        event_manager_ctrl
+            .send(EventManagerCmd::AddSubscription {
+                server_id: Some(unit_id.to_string()),
+                event_type: ServerEventType::ConsoleLine,
+                client_id,
+            })
+            .expect("send to event manager");
+        (client_id, console)
+    }
+
+    /// Puts a running server into lockdown.
+    ///
+    /// The players are kicked and the worlds are saved with automatic saving turned off. A thread
+    /// waits for the save and sends [`DaemonEvent::LockdownSaved`].
+    fn lockdown_server(&mut self, unit_id: String, message: String) {
+        let server = self
+            .servers
+            .get_mut(&unit_id)
+            .expect("lockdown of existing unit");
+        warn!("putting unit {} into lockdown", unit_id);
+        server.lockdown = Some(LockdownStage::Saving);
+        let (client_id, console) = Daemon::subscribe_console(
+            &self.clients,
+            &self.event_manager_ctrl,
+            &unit_id,
+            format!("lockdown of {}", unit_id),
+        );
+        for player in server.online_players() {
+            server.send_daemon_command(lockdown::kick_command(&player, &message));
+        }
+        EventHandler::new(self.event_manager_ctrl.clone()).raise_event(
+            &unit_id,
+            ServerEvent::ActionProgress {
+                server_id: unit_id.clone(),
+                action: "saving worlds".to_string(),
+                progress: None,
+                maximum: None,
+                action_number: 1,
+            },
+        );
+        server.send_daemon_command("save-off".to_string());
+        server.send_daemon_command("save-all flush".to_string());
+
+        // the lockdown counts as a backup, so no other backup or restore runs meanwhile
+        self.backups.insert(unit_id.clone());
+        let save_timeout = Duration::from_secs(self.config.backup.save_timeout_secs);
+        let clients = self.clients.clone();
+        let event_manager_ctrl = self.event_manager_ctrl.clone();
+        let daemon_queue = self.queue_sender.clone();
+        spawn(move || {
+            let saved = backup::wait_for_save(&console, save_timeout).map_err(|e| e.to_string());
+            clients.unregister(client_id);
+            let _ = event_manager_ctrl.send(EventManagerCmd::RemoveAllSubscriptions { client_id });
+            daemon_queue
+                .send(DaemonEvent::LockdownSaved {
+                    server_id: unit_id,
+                    saved,
+                })
+                .expect("send to daemon main event queue");
+        });
+    }
+
+    /// Suspends the process of a server in lockdown and takes a snapshot of its worlds on a new
+    /// thread, which sends [`DaemonEvent::LockdownFinished`].
+    ///
+    /// The server is suspended even if it has not saved its worlds in time, but no snapshot is
+    /// taken then.
+    fn suspend_server(&mut self, unit_id: String, saved: Result<(), String>) {
+        let mut event_handler = EventHandler::new(self.event_manager_ctrl.clone());
+        let server = match self.servers.get_mut(&unit_id) {
+            Some(server) if matches!(server.lockdown, Some(LockdownStage::Saving)) => server,
+            _ => {
+                self.backups.remove(&unit_id);
+                event_handler.raise_event(
+                    &unit_id,
+                    ServerEvent::LockdownFailed {
+                        server_id: unit_id.clone(),
+                        error: "the server has been stopped during the lockdown".to_string(),
+                    },
+                );
+                return;
+            }
+        };
+        let suspended = match server.pid() {
+            Some(pid) => lockdown::suspend(pid),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no server process")),
+        };
+        if let Err(e) = suspended {
+            server.lockdown = None;
+            server.send_daemon_command("save-on".to_string());
+            self.backups.remove(&unit_id);
+            event_handler.raise_event(
+                &unit_id,
+                ServerEvent::LockdownFailed {
+                    server_id: unit_id.clone(),
+                    error: format!("could not suspend the server: {}", e),
+                },
+            );
+            return;
+        }
+        server.lockdown = Some(LockdownStage::Suspended);
+        warn!("unit {} has been suspended", unit_id);
+
+        let source = BackupSource::from_config(
+            &unit_id,
+            &server.server.server_config(),
+            &server.server.unit_file_path(),
+        );
+        let store = BackupStore::from_config(&self.config.backup);
+        let daemon_queue = self.queue_sender.clone();
+        spawn(move || {
+            let snapshot = saved
+                .map_err(|e| format!("the worlds have not been saved: {}", e))
+                .and_then(|_| {
+                    store
+                        .create(
+                            &source,
+                            Some(lockdown::SNAPSHOT_LABEL.to_string()),
+                            &mut |archived, total| {
+                                event_handler.raise_event(
+                                    &source.unit_id,
+                                    ServerEvent::ActionProgress {
+                                        server_id: source.unit_id.clone(),
+                                        action: "taking snapshot".to_string(),
+                                        progress: Some(archived as usize),
+                                        maximum: Some(total as usize),
+                                        action_number: 2,
+                                    },
+                                )
+                            },
+                        )
+                        .map_err(|e| e.to_string())
+                });
+            daemon_queue
+                .send(DaemonEvent::LockdownFinished {
+                    server_id: source.unit_id,
+                    snapshot,
+                })
+                .expect("send to daemon main event queue");
+        });
+    }
+
+    /// Turns automatic saving on again after a backup and raises the result of the backup.
+    fn finish_backup(&mut self, server_id: String, result: Result<BackupInfo, String>) {
+        self.backups.remove(&server_id);
+        if let Some(server) = self.servers.get_mut(&server_id) {
+            if let ServerStatus::Running = server.status() {
+                server.send_daemon_command("save-on".to_string());
+            }
+        }
+        let event = match result {
+            Ok(backup) => {
+                info!(
+                    "backup {} of unit {} is complete ({} bytes)",
+                    backup.id, server_id, backup.size
+                );
+                ServerEvent::BackupComplete {
+                    server_id: server_id.clone(),
+                    backup: Box::new(backup),
+                }
+            }
+            Err(error) => {
+                warn!("backup of unit {} has failed: {}", server_id, error);
+                ServerEvent::BackupFailed {
+                    server_id: server_id.clone(),
+                    error,
+                }
+            }
+        };
+        EventHandler::new(self.event_manager_ctrl.clone()).raise_event(&server_id, event);
+    }
+
+    /// Restores a backup of a server on a new thread.
+    ///
+    /// A running server is stopped first and started again when the restore has succeeded. The
+    /// thread sends [`DaemonEvent::RestoreFinished`] when it is done.
+    fn restore_server(&mut self, unit_id: String, backup: BackupInfo, options: RestoreOptions) {
+        let server = self
+            .servers
+            .get_mut(&unit_id)
+            .expect("restore of existing unit");
+        let source = BackupSource::from_config(
+            &unit_id,
+            &server.server.server_config(),
+            &server.server.unit_file_path(),
+        );
+        let start = matches!(
+            server.status(),
+            ServerStatus::Running | ServerStatus::Starting | ServerStatus::Unknown
+        );
+        // a start that waits for its hook would spawn the server during the restore
+        server.cancel_start();
+
+        let mut event_handler = EventHandler::new(self.event_manager_ctrl.clone());
+        let store = BackupStore::from_config(&self.config.backup);
+        let daemon_queue = self.queue_sender.clone();
+        // the restore waits on its own thread for the process to exit
+        let restore = move |child: Option<Child>| {
+            spawn(move || {
+                if let Some(mut child) = child {
+                    event_handler.raise_event(
+                        &source.unit_id,
+                        ServerEvent::ActionProgress {
+                            server_id: source.unit_id.clone(),
+                            action: "stopping server".to_string(),
+                            progress: None,
+                            maximum: None,
+                            action_number: 1,
+                        },
+                    );
+                    let exit_status = child.wait();
+                    debug!(
+                        "unit {} stopped for restore with exit status {:?}",
+                        source.unit_id, exit_status
+                    );
+                }
+                let result = Daemon::perform_restore(
+                    &event_handler,
+                    &source,
+                    &store,
+                    &backup,
+                    options,
+                    &daemon_queue,
+                );
+                // a server whose worlds could not be rolled back is left stopped
+                let (result, start) = match result {
+                    Ok(snapshot_id) => (Ok(snapshot_id), start),
+                    Err((error, consistent)) => (Err(error), start && consistent),
+                };
+                daemon_queue
+                    .send(DaemonEvent::RestoreFinished {
+                        server_id: source.unit_id,
+                        backup_id: backup.id,
+                        result,
+                        start,
+                    })
+                    .expect("send to daemon main event queue");
+            });
+        };
+        server.stop(&self.queue_sender, Box::new(restore));
+        self.restores.insert(unit_id);
+    }
+
+    /// Takes a snapshot of the current worlds and restores a backup.
+    ///
+    /// If the restore fails, the snapshot is restored. Returns the id of the snapshot, which is
+    /// `None` if the server had no worlds, or the reason of the failure and whether the server
+    /// directory is in a consistent state, i.e. the restore has not touched it or has been
+    /// rolled back.
+    fn perform_restore(
+        event_handler: &EventHandler,
+        source: &BackupSource,
+        store: &BackupStore,
+        backup: &BackupInfo,
+        options: RestoreOptions,
+        daemon_queue: &Sender<DaemonEvent>,
+    ) -> Result<Option<String>, (String, bool)> {
+        let progress = |action: &str, action_number| {
+            let unit_id = source.unit_id.clone();
+            let action = action.to_string();
+            let mut event_handler = event_handler.clone();
+            move |read: u64, total: u64| {
+                event_handler.raise_event(
+                    &unit_id,
+                    ServerEvent::ActionProgress {
+                        server_id: unit_id.clone(),
+                        action: action.clone(),
+                        progress: Some(read as usize),
+                        maximum: Some(total as usize),
+                        action_number,
+                    },
+                )
+            }
+        };
+
+        let label = format!("before restore of {}", backup.id);
+        let snapshot = match store.create(source, Some(label), &mut progress("saving snapshot", 2))
+        {
+            Ok(snapshot) => Some(snapshot),
+            Err(BackupError::NoWorlds) => None,
+            Err(e) => return Err((format!("could not save snapshot: {}", e), true)),
+        };
+        let unit_file = source.unit_file.clone().unwrap_or_default();
+        let restored = store
+            .restore(
+                backup,
+                &source.server_path,
+                &unit_file,
+                options,
+                &mut progress("restoring backup", 3),
+            )
+            .map_err(|e| e.to_string())
+            .and_then(|_| {
+                if options.config {
+                    Daemon::reload_unit_file(&source.unit_id, &unit_file, daemon_queue)
+                } else {
+                    Ok(())
+                }
+            });
+        let error = match restored {
+            Ok(()) => return Ok(snapshot.map(|snapshot| snapshot.id)),
+            Err(error) => error,
+        };
+
+        warn!(
+            "restore of unit {} has failed, rolling back: {}",
+            source.unit_id, error
+        );
+        // worlds that only exist in the backup are removed, the others are replaced
+        let rolled_back = backup
+            .worlds
+            .iter()
+            .filter(|world| {
+                snapshot
+                    .as_ref()
+                    .is_none_or(|snapshot| !snapshot.worlds.contains(world))
+            })
+            .map(|world| source.server_path.join(world))
+            .filter(|world| world.exists())
+            .try_for_each(fs::remove_dir_all)
+            .map_err(BackupError::from)
+            .and_then(|_| match &snapshot {
+                Some(snapshot) => {
+                    let options = RestoreOptions {
+                        jar: options.jar && snapshot.jar.is_some(),
+                        config: options.config && snapshot.unit_file,
+                    };
+                    store.restore(
+                        snapshot,
+                        &source.server_path,
+                        &unit_file,
+                        options,
+                        &mut progress("rolling back", 4),
+                    )
+                }
+                None => Ok(()),
+            });
+        match rolled_back {
+            Ok(()) => Err((
+                format!("{}, the previous worlds have been restored", error),
+                true,
+            )),
+            Err(e) => Err((
+                format!(
+                    "{}, the previous worlds could not be restored: {}",
+                    error, e
+                ),
+                false,
+            )),
+        }
+    }
+
+    /// Reads a restored unit file and replaces the unit with it.
+    fn reload_unit_file(
+        unit_id: &str,
+        unit_file: &Path,
+        daemon_queue: &Sender<DaemonEvent>,
+    ) -> Result<(), String> {
+        let content = fs::read_to_string(unit_file)
+            .map_err(|e| format!("could not read restored unit file: {}", e))?;
+        let server_unit_config: ServerUnitConfig = toml::from_str(&content)
+            .map_err(|e| format!("could not parse restored unit file: {}", e))?;
+        if server_unit_config.unit.id != unit_id {
+            return Err(format!(
+                "the restored unit file belongs to unit {}",
+                server_unit_config.unit.id
+            ));
+        }
+        daemon_queue
+            .send(DaemonEvent::AddServerUnit {
+                server_unit_config: Box::new(server_unit_config),
+                unit_file: unit_file.to_path_buf(),
+            })
+            .expect("send to daemon main event queue");
+        Ok(())
+    }
+
+    /// Starts a server again after a restore or its rollback and raises the result of the restore.
+    fn finish_restore(
+        &mut self,
+        server_id: String,
+        backup_id: String,
+        result: Result<Option<String>, String>,
+        start: bool,
+    ) {
+        self.restores.remove(&server_id);
+        // a failing `pre_start` hook is logged, the restore itself has succeeded or failed already
+        if let (true, Some(server)) = (start, self.servers.get_mut(&server_id)) {
+            server.start(self.log_service.deref_mut(), &self.queue_sender, None);
+        }
+        let event = match result {
+            Ok(snapshot_id) => {
+                info!("restored backup {} of unit {}", backup_id, server_id);
+                ServerEvent::RestoreComplete {
+                    server_id: server_id.clone(),
+                    backup_id,
+                    snapshot_id,
+                }
+            }
+            Err(error) => {
+                warn!(
+                    "restore of backup {} of unit {} has failed: {}",
+                    backup_id, server_id, error
+                );
+                ServerEvent::RestoreFailed {
+                    server_id: server_id.clone(),
+                    error,
+                }
+            }
+        };
+        EventHandler::new(self.event_manager_ctrl.clone()).raise_event(&server_id, event);
+    }
+
     fn perform_update(
         event_handler: EventHandler,
         unit_id: String,
         server_version: Option<Version>,
         server_type: ServerType,
-        unit_config: UnitConfig,
-        server_config: ServerConfig,
+        server_unit_config: ServerUnitConfig,
         unit_file_path: PathBuf,
     ) -> Result<ServerUnitConfig, UpdateError> {
-        match server_type {
-            ServerType::Paper => {
-                let mut paper_updater =
-                    PaperServerUpdater::new(unit_id, unit_file_path.clone(), event_handler);
-                let server_config = paper_updater.update_server(server_version, server_config)?;
-
-                let server_unit_config = ServerUnitConfig {
-                    unit: unit_config,
-                    server: server_config,
-                };
+        let mut updater: Box<dyn ServerUpdater> = match server_type {
+            ServerType::Paper
+            | ServerType::Folia
+            | ServerType::Velocity
+            | ServerType::Waterfall => {
+                let project = PaperProject::for_server_type(&server_type)
+                    .expect("server type is a PaperMC project");
+                Box::new(PaperServerUpdater::new(
+                    unit_id,
+                    unit_file_path.clone(),
+                    event_handler,
+                    PaperRepository::new(project),
+                ))
+            }
+            ServerType::Vanilla => Box::new(VanillaServerUpdater::new(
+                unit_id,
+                event_handler,
+                VanillaRepository::default(),
+            )),
+            _ => return Err(UnsupportedServerType(server_type)),
+        };
+        let server_config = updater.update_server(server_version, server_unit_config.server)?;
 
-                let config_string = toml::to_string(&server_unit_config).unwrap();
-                debug!(
-                    "writing configuration {} to {:?}",
-                    config_string, unit_file_path
-                );
-                fs::write(unit_file_path, config_string)
-                    .map_err(|e| UpdateError::WriteUnitFile(e))?;
+        let server_unit_config = ServerUnitConfig {
+            server: server_config,
+            ..server_unit_config
+        };
 
-                Ok(server_unit_config)
-            }
-            _ => Err(UnsupportedServerType(server_type)),
-        }
+        let config_string = toml::to_string(&server_unit_config).unwrap();
+        debug!(
+            "writing configuration {} to {:?}",
+            config_string, unit_file_path
+        );
+        fs::write(unit_file_path, config_string).map_err(|e| UpdateError::WriteUnitFile(e))?;
+
+        Ok(server_unit_config)
     }
 }
 
@@ -731,17 +2336,212 @@ struct DaemonServer {
     server: Box<dyn Server + Send + 'static>,
     status: Option<Arc<RwLock<OutputState>>>,
     server_id: String,
+    players: Option<OnlinePlayers>,
+    failure: Option<LastFailure>,
+    performance: Option<Performance>,
+    /// The time at which the performance has been polled last
+    last_poll: Option<Instant>,
+    /// The stage of the lockdown of the server, `None` if it is not in lockdown
+    lockdown: Option<LockdownStage>,
+    /// The start or stop that waits for a lifecycle hook running on a worker thread
+    pending: Option<PendingHook>,
+}
+
+/// Performed with the process of a stopped server once the stop command has been sent.
+type AfterStop = Box<dyn FnOnce(Option<Child>) + Send>;
+
+/// A start or stop of a server that waits for its `pre_start` or `pre_stop` hook.
+///
+/// The hook runs on a worker thread, which sends [`DaemonEvent::HookFinished`] when it is done.
+enum PendingHook {
+    /// The server is started if the `pre_start` hook succeeds, a failure is reported to the client
+    Start { client_id: Option<u32> },
+    /// The stop command is sent when the `pre_stop` hook has finished, then `then` is performed
+    Stop { then: AfterStop },
+}
+
+impl PendingHook {
+    /// Returns the hook the action waits for.
+    fn hook(&self) -> LifecycleHook {
+        match self {
+            PendingHook::Start { .. } => LifecycleHook::PreStart,
+            PendingHook::Stop { .. } => LifecycleHook::PreStop,
+        }
+    }
+}
+
+/// The stages of a lockdown, see [`mcman::daemon::lockdown`].
+#[derive(Debug, Clone, Copy)]
+enum LockdownStage {
+    /// The players have been kicked and the server is saving its worlds
+    Saving,
+    /// The server process is suspended
+    Suspended,
 }
 
 impl DaemonServer {
-    pub fn start(&mut self, log_service: &mut (dyn LogService + Send)) {
+    pub fn new(server_id: String, server: Box<dyn Server + Send + 'static>) -> Self {
+        Self {
+            process: None,
+            server,
+            status: None,
+            server_id,
+            players: None,
+            failure: None,
+            performance: None,
+            last_poll: None,
+            lockdown: None,
+            pending: None,
+        }
+    }
+
+    /// Starts the server, if the unit has a `pre_start` hook only once it has succeeded.
+    ///
+    /// The hook runs on a worker thread, the unit is `Starting` in the meantime. If it fails, the
+    /// server is not started and the client that has asked for the start is sent
+    /// [`DaemonResponse::HookFailed`].
+    pub fn start(
+        &mut self,
+        log_service: &mut (dyn LogService + Send),
+        queue: &Sender<DaemonEvent>,
+        client_id: Option<u32>,
+    ) {
+        match self.lifecycle_hook(LifecycleHook::PreStart) {
+            Some(hook) => self.run_pending_hook(hook, PendingHook::Start { client_id }, queue),
+            None => self.spawn(log_service),
+        }
+    }
+
+    /// Spawns the server process.
+    fn spawn(&mut self, log_service: &mut (dyn LogService + Send)) {
         debug!("starting unit {}", self.server_id);
         let (child, status) = self.server.spawn(log_service);
         self.process = Some(child);
         self.status = Some(status);
+        self.players = log_service.online_players(&self.server_id);
+        self.failure = log_service.last_failure(&self.server_id);
+        self.performance = log_service.performance(&self.server_id);
+        self.last_poll = None;
+    }
+
+    /// Runs a `pre_start` or `pre_stop` hook on a worker thread, `pending` continues when it has
+    /// finished.
+    fn run_pending_hook(&mut self, hook: Hook, pending: PendingHook, queue: &Sender<DaemonEvent>) {
+        let server_id = self.server_id.clone();
+        let lifecycle_hook = pending.hook();
+        let queue = queue.clone();
+        self.pending = Some(pending);
+        spawn(move || {
+            let result = hook.run().map_err(|error| error.to_string());
+            queue
+                .send(DaemonEvent::HookFinished {
+                    server_id,
+                    hook: lifecycle_hook,
+                    result,
+                })
+                .expect("send to daemon main event queue");
+        });
+    }
+
+    /// Returns whether a start or stop of the server waits for its hook.
+    pub fn waits_for_hook(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Cancels a start that waits for the `pre_start` hook, returns whether a start has waited.
+    pub fn cancel_start(&mut self) -> bool {
+        self.take_pending(LifecycleHook::PreStart).is_some()
+    }
+
+    /// Takes the action that waits for the given hook, `None` if no action waits for it.
+    fn take_pending(&mut self, hook: LifecycleHook) -> Option<PendingHook> {
+        match &self.pending {
+            Some(pending) if pending.hook() == hook => self.pending.take(),
+            _ => None,
+        }
+    }
+
+    /// Returns a lifecycle hook of the unit, if it is configured.
+    fn lifecycle_hook(&self, hook: LifecycleHook) -> Option<Hook> {
+        hook.for_unit(
+            &self.server_id,
+            &self.server.server_config(),
+            &self.server.unit_file_path(),
+            vec![],
+        )
+    }
+
+    pub fn online_players(&self) -> Vec<String> {
+        match &self.players {
+            Some(players) => players
+                .read()
+                .expect("lock players for read")
+                .iter()
+                .cloned()
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn last_failure(&self) -> Option<ServerFailure> {
+        self.failure
+            .as_ref()
+            .and_then(|failure| failure.read().expect("lock failure for read").clone())
+    }
+
+    pub fn performance_stats(&self) -> PerformanceStats {
+        match &self.performance {
+            Some(performance) => performance
+                .read()
+                .expect("lock performance for read")
+                .stats(),
+            None => PerformanceStats::default(),
+        }
+    }
+
+    /// Sends `tps` and `mspt` to the server if it is running and its poll interval has elapsed.
+    ///
+    /// The replies to the previous poll are closed into a sample first, the event for crossed
+    /// thresholds is returned.
+    pub fn poll_performance(&mut self) -> Option<ServerEvent> {
+        let config = self.server.server_config().performance.unwrap_or_default();
+        // proxies have no tick loop, so they do not know `tps` and `mspt`
+        if config.poll_interval_secs == 0 || self.server.server_type().is_proxy() {
+            return None;
+        }
+        if !matches!(self.status(), ServerStatus::Running) {
+            self.last_poll = None;
+            return None;
+        }
+        let now = Instant::now();
+        if let Some(last_poll) = self.last_poll {
+            if now.duration_since(last_poll) < Duration::from_secs(config.poll_interval_secs) {
+                return None;
+            }
+        }
+        let event = match (&self.performance, self.last_poll) {
+            (Some(performance), Some(_)) => performance
+                .write()
+                .expect("lock performance for write")
+                .finish_sample(&self.server_id, &config),
+            _ => None,
+        };
+        self.last_poll = Some(now);
+        // servers without the commands still report "Can't keep up!" warnings
+        for command in self.server.performance_commands() {
+            if !self.send_daemon_command(command) {
+                break;
+            }
+        }
+        event
     }
 
     pub fn status(&mut self) -> ServerStatus {
+        match &self.pending {
+            Some(PendingHook::Start { .. }) => return ServerStatus::Starting,
+            Some(PendingHook::Stop { .. }) => return ServerStatus::Stopping,
+            None => {}
+        }
         if let Some(child) = &mut self.process {
             match child.try_wait() {
                 Ok(Some(status)) => {
@@ -751,6 +2551,7 @@ impl DaemonServer {
                         ServerStatus::Errored(status.code())
                     }
                 }
+                _ if self.lockdown.is_some() => ServerStatus::Lockdown,
                 _ => {
                     if let Some(status) = &self.status {
                         let guard = status.read().unwrap();
@@ -772,17 +2573,85 @@ impl DaemonServer {
         }
     }
 
-    pub fn send_command(&mut self, command: String) {
-        self.server.send_command(command);
+    pub fn send_command(&mut self, command: String) -> io::Result<()> {
+        self.server.send_command(command)
     }
 
-    pub fn say(&mut self, message: String) {
+    /// Sends a command on behalf of the daemon itself, e.g. for a backup or a trigger.
+    ///
+    /// A console that can not be written is logged, usually the process has just exited and the
+    /// next status poll picks that up. Returns whether the command has been sent.
+    pub fn send_daemon_command(&mut self, command: String) -> bool {
+        match self.server.send_command(command) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(
+                    "could not write to console of unit {}: {}",
+                    self.server_id, e
+                );
+                false
+            }
+        }
+    }
+
+    pub fn say(&mut self, message: String) -> io::Result<()> {
         self.send_command(format!("say {}", message))
     }
 
-    pub fn stop(&mut self) -> Option<Child> {
+    /// Returns the id of the server process, if the server has been started.
+    pub fn pid(&self) -> Option<u32> {
+        self.process.as_ref().map(Child::id)
+    }
+
+    /// Resumes the process of a server in lockdown.
+    pub fn unlock(&mut self) -> io::Result<()> {
+        if let (Some(LockdownStage::Suspended), Some(pid)) = (self.lockdown, self.pid()) {
+            lockdown::resume(pid)?;
+        }
+        self.lockdown = None;
+        Ok(())
+    }
+
+    /// Sends the stop command of the server to it and performs `then` with the process.
+    ///
+    /// If the unit has a `pre_stop` hook, it runs on a worker thread first and the unit is
+    /// `Stopping` in the meantime. A server in lockdown is resumed first. The server is stopped even
+    /// if the hook fails.
+    pub fn stop(&mut self, queue: &Sender<DaemonEvent>, then: AfterStop) {
+        if let Err(e) = self.unlock() {
+            warn!("could not resume unit {}: {}", self.server_id, e);
+        }
+        let hook = self
+            .process
+            .as_ref()
+            .and_then(|_| self.lifecycle_hook(LifecycleHook::PreStop));
+        match hook {
+            Some(hook) => self.run_pending_hook(hook, PendingHook::Stop { then }, queue),
+            None => then(self.send_stop()),
+        }
+    }
+
+    /// Runs the `pre_stop` hook of the unit on the current thread and sends the stop command of
+    /// the server to it.
+    ///
+    /// Only used while the daemon shuts down, when it waits for all servers anyway.
+    pub fn stop_now(&mut self) -> Option<Child> {
+        if let Err(e) = self.unlock() {
+            warn!("could not resume unit {}: {}", self.server_id, e);
+        }
+        if self.process.is_some() {
+            if let Some(hook) = self.lifecycle_hook(LifecycleHook::PreStop) {
+                let _ = hook.run();
+            }
+        }
+        self.send_stop()
+    }
+
+    /// Sends the stop command of the server to it and returns its process.
+    fn send_stop(&mut self) -> Option<Child> {
         if self.process.is_some() {
-            self.send_command("stop".to_string());
+            let command = self.server.stop_command();
+            self.send_daemon_command(command);
             self.process.take()
         } else {
             None
@@ -1,5 +1,6 @@
 //! Contains structs for loading and modifying daemon and server configurations.
 use crate::daemon::Server;
+use crate::ipc::ServerEventType;
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 use semver::Version;
@@ -21,6 +22,225 @@ pub struct DaemonConfig {
     /// If this parameter is a relative path, make sure, that daemon and client are run
     /// in the same directory!
     pub socket_file: String,
+    /// Configuration of the embedded HTTP interface.
+    ///
+    /// The interface is only started if this section is present.
+    pub http: Option<HttpConfig>,
+    /// Configuration of the Prometheus metrics endpoint.
+    ///
+    /// The endpoint is only started if this section is present.
+    pub metrics: Option<MetricsConfig>,
+    /// Webhooks that are notified about server events (`[[webhook]]`)
+    #[serde(default, rename = "webhook")]
+    pub webhooks: Vec<WebhookConfig>,
+    /// The directory in which the daemon keeps its state, e.g. the journal
+    #[serde(default = "default_state_directory")]
+    pub state_directory: String,
+    /// Settings of the journal of commands and events
+    #[serde(default)]
+    pub journal: JournalConfig,
+    /// Settings of the world backups
+    #[serde(default)]
+    pub backup: BackupConfig,
+    /// The directory in which BuildTools builds Spigot and Bukkit servers, see
+    /// [`crate::ipc::install::buildtools`]
+    #[serde(default = "default_build_cache")]
+    pub build_cache: String,
+}
+
+/// Default value of [`DaemonConfig::build_cache`].
+fn default_build_cache() -> String {
+    "buildtools".to_string()
+}
+
+/// Default value of [`DaemonConfig::state_directory`].
+fn default_state_directory() -> String {
+    "state".to_string()
+}
+
+/// Settings of the journal (`[journal]`), see [`crate::daemon::journal`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JournalConfig {
+    /// Whether commands and events are recorded
+    #[serde(default = "default_journal_enabled")]
+    pub enabled: bool,
+    /// The size in MiB after which the journal file is rotated
+    #[serde(default = "default_journal_rotate_size_mb")]
+    pub rotate_size_mb: u64,
+    /// The number of rotated journal files that are kept
+    #[serde(default = "default_journal_keep_files")]
+    pub keep_files: usize,
+    /// Whether commands that only read information (e.g. `List`) are recorded as well
+    #[serde(default)]
+    pub record_queries: bool,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_journal_enabled(),
+            rotate_size_mb: default_journal_rotate_size_mb(),
+            keep_files: default_journal_keep_files(),
+            record_queries: false,
+        }
+    }
+}
+
+/// Default value of [`JournalConfig::enabled`].
+fn default_journal_enabled() -> bool {
+    true
+}
+
+/// Default value of [`JournalConfig::rotate_size_mb`].
+fn default_journal_rotate_size_mb() -> u64 {
+    16
+}
+
+/// Default value of [`JournalConfig::keep_files`].
+fn default_journal_keep_files() -> usize {
+    8
+}
+
+/// Settings of the world backups (`[backup]`), see [`crate::daemon::backup`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackupConfig {
+    /// The directory in which the backups are stored, in a subdirectory per unit
+    #[serde(default = "default_backup_root")]
+    pub root: String,
+    /// The seconds to wait for a running server to save its worlds
+    #[serde(default = "default_save_timeout_secs")]
+    pub save_timeout_secs: u64,
+    /// The format of new backups, existing backups keep their format
+    #[serde(default)]
+    pub format: BackupFormat,
+}
+
+/// The ways a backup can be stored.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupFormat {
+    /// A zstd compressed tar archive per backup
+    #[default]
+    Archive,
+    /// A manifest per backup that refers to deduplicated chunks, see [`crate::daemon::chunks`]
+    Chunked,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            root: default_backup_root(),
+            save_timeout_secs: default_save_timeout_secs(),
+            format: BackupFormat::default(),
+        }
+    }
+}
+
+/// Default value of [`BackupConfig::root`].
+fn default_backup_root() -> String {
+    "backups".to_string()
+}
+
+/// Default value of [`BackupConfig::save_timeout_secs`].
+fn default_save_timeout_secs() -> u64 {
+    120
+}
+
+/// Config of the embedded HTTP interface of the daemon.
+///
+/// If neither `address` nor `socket_file` is set, the interface listens on
+/// [`crate::daemon::http::DEFAULT_ADDRESS`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HttpConfig {
+    /// The address (`host:port`) the interface listens on.
+    ///
+    /// The interface has no authentication, so this should be a loopback address.
+    pub address: Option<String>,
+    /// Path to a unix socket the interface listens on instead of a TCP port.
+    pub socket_file: Option<String>,
+    /// Whether the web dashboard is served (enabled by default).
+    #[serde(default = "default_dashboard")]
+    pub dashboard: bool,
+}
+
+/// Default value of [`HttpConfig::dashboard`].
+fn default_dashboard() -> bool {
+    true
+}
+
+/// Config of the Prometheus metrics endpoint of the daemon (`[metrics]`).
+///
+/// If neither `address` nor `socket_file` is set, the endpoint listens on
+/// [`crate::daemon::metrics::DEFAULT_ADDRESS`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MetricsConfig {
+    /// The address (`host:port`) the endpoint listens on
+    pub address: Option<String>,
+    /// Path to a unix socket the endpoint listens on instead of a TCP port
+    pub socket_file: Option<String>,
+}
+
+/// A webhook that receives server events as HTTP `POST` requests with a JSON body.
+///
+/// The body is rendered from `template` or, if it is not set, from the template of `format`, see
+/// [`crate::daemon::notifier`]. Failed requests are retried `retries` times, the delay starts at
+/// `backoff_secs` and doubles after every attempt.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookConfig {
+    /// The URL the events are posted to
+    pub url: String,
+    /// The event types that are sent, e.g. `["ServerFailed", { Custom = "anticheat_alert" }]`
+    #[serde(default = "default_webhook_events")]
+    pub events: Vec<ServerEventType>,
+    /// Only the events of these units are sent, the events of all units if missing
+    pub units: Option<Vec<String>>,
+    /// The preset of the request body
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// A template of the request body that replaces the preset of `format`
+    pub template: Option<String>,
+    /// The number of retries of a failed request
+    #[serde(default = "default_webhook_retries")]
+    pub retries: u32,
+    /// The delay in seconds before the first retry
+    #[serde(default = "default_webhook_backoff_secs")]
+    pub backoff_secs: u64,
+}
+
+/// Presets for the request body of a webhook.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    /// The event with its server, type and a message
+    #[default]
+    Json,
+    /// A message for a Discord webhook
+    Discord,
+    /// A message for a Slack webhook
+    Slack,
+}
+
+/// Default value of [`WebhookConfig::events`].
+fn default_webhook_events() -> Vec<ServerEventType> {
+    vec![
+        ServerEventType::ServerFailed,
+        ServerEventType::InstallationFailed,
+        ServerEventType::UpdateFailed,
+        ServerEventType::BackupFailed,
+        ServerEventType::RestoreFailed,
+        ServerEventType::LockdownFailed,
+        ServerEventType::PerformanceDegraded,
+    ]
+}
+
+/// Default value of [`WebhookConfig::retries`].
+fn default_webhook_retries() -> u32 {
+    3
+}
+
+/// Default value of [`WebhookConfig::backoff_secs`].
+fn default_webhook_backoff_secs() -> u64 {
+    1
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -39,6 +259,9 @@ pub struct SimpleUnitConfig {
 pub struct ServerUnitConfig {
     pub unit: UnitConfig,
     pub server: ServerConfig,
+    /// Console triggers of the unit (`[[trigger]]`)
+    #[serde(default, rename = "trigger", skip_serializing_if = "Vec::is_empty")]
+    pub triggers: Vec<TriggerConfig>,
 }
 
 /// Config of a server
@@ -57,6 +280,269 @@ pub struct ServerConfig {
     pub version: Version,
     /// The amount of memory dedicated to a server in gigabyte
     pub memory: u32,
+    /// Rules that replace or extend the built-in log patterns of the server type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_patterns: Option<LogPatternConfig>,
+    /// Rotation, compression and retention of the log files of the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logs: Option<LogConfig>,
+    /// Polling of the tick rate and the thresholds for performance warnings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub performance: Option<PerformanceConfig>,
+    /// Shell commands that are run at lifecycle transitions of the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hooks: Option<HooksConfig>,
+    /// The world directories that are backed up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<UnitBackupConfig>,
+}
+
+/// Rotation, compression and retention of the log files of a server unit (`[server.logs]`).
+///
+/// A log file is closed and a new one is started when it exceeds `rotate_size_mb` or is older than
+/// `rotate_interval_hours`. Closed files are compressed, after that the oldest closed files are
+/// deleted until all retention limits are met.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LogConfig {
+    /// The size in MiB after which a log file is rotated
+    #[serde(default = "default_rotate_size_mb")]
+    pub rotate_size_mb: u64,
+    /// The age in hours after which a log file is rotated
+    #[serde(default = "default_rotate_interval_hours")]
+    pub rotate_interval_hours: u64,
+    /// The compression of closed log files
+    #[serde(default)]
+    pub compression: LogCompression,
+    /// The maximum number of closed log files that are kept
+    pub keep_files: Option<usize>,
+    /// The maximum age in days of closed log files
+    pub keep_days: Option<u64>,
+    /// The maximum total size in MiB of all closed log files
+    pub keep_total_mb: Option<u64>,
+}
+
+/// Compression algorithms for closed log files.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogCompression {
+    /// Closed files are kept as they are
+    None,
+    /// Closed files are compressed with gzip (`.gz`)
+    #[default]
+    Gzip,
+    /// Closed files are compressed with zstd (`.zst`)
+    Zstd,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            rotate_size_mb: default_rotate_size_mb(),
+            rotate_interval_hours: default_rotate_interval_hours(),
+            compression: LogCompression::default(),
+            keep_files: None,
+            keep_days: None,
+            keep_total_mb: None,
+        }
+    }
+}
+
+/// Default value of [`LogConfig::rotate_size_mb`].
+fn default_rotate_size_mb() -> u64 {
+    100
+}
+
+/// Default value of [`LogConfig::rotate_interval_hours`].
+fn default_rotate_interval_hours() -> u64 {
+    24
+}
+
+/// Performance monitoring of a server unit (`[server.performance]`).
+///
+/// While the server is running, the daemon sends the `tps` and `mspt` commands (if the server
+/// knows them) every `poll_interval_secs` and counts the "Can't keep up!" warnings of the server. The results of an
+/// interval are compared with the thresholds; when one is crossed, a
+/// [`crate::ipc::ServerEvent::PerformanceDegraded`] event is raised.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PerformanceConfig {
+    /// The seconds between two polls, 0 disables polling
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    /// The number of intervals that are kept in the history
+    #[serde(default = "default_history")]
+    pub history: usize,
+    /// The ticks per second over the last minute below which the server is degraded
+    #[serde(default = "default_min_tps")]
+    pub min_tps: f64,
+    /// The average milliseconds per tick over the last minute above which the server is degraded
+    #[serde(default = "default_max_mspt")]
+    pub max_mspt: f64,
+    /// The number of "Can't keep up!" warnings in an interval at which the server is degraded,
+    /// 0 disables the threshold
+    #[serde(default = "default_max_overloads")]
+    pub max_overloads: u32,
+}
+
+impl Default for PerformanceConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_secs: default_poll_interval_secs(),
+            history: default_history(),
+            min_tps: default_min_tps(),
+            max_mspt: default_max_mspt(),
+            max_overloads: default_max_overloads(),
+        }
+    }
+}
+
+/// Default value of [`PerformanceConfig::poll_interval_secs`].
+fn default_poll_interval_secs() -> u64 {
+    60
+}
+
+/// Default value of [`PerformanceConfig::history`].
+fn default_history() -> usize {
+    60
+}
+
+/// Default value of [`PerformanceConfig::min_tps`].
+fn default_min_tps() -> f64 {
+    18.0
+}
+
+/// Default value of [`PerformanceConfig::max_mspt`].
+fn default_max_mspt() -> f64 {
+    50.0
+}
+
+/// Default value of [`PerformanceConfig::max_overloads`].
+fn default_max_overloads() -> u32 {
+    3
+}
+
+/// Lifecycle hooks of a server unit (`[server.hooks]`).
+///
+/// Every hook is a shell command that is run in the server directory, see
+/// [`crate::daemon::hooks`]. `pre_start`, `pre_stop` and `pre_update` are run before the action
+/// and the action of the unit waits for them, while the daemon keeps serving other units and
+/// clients; a failing `pre_start` or `pre_update` cancels the action. The other hooks are run in
+/// the background after the corresponding event.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HooksConfig {
+    /// Run before the server is started
+    pub pre_start: Option<String>,
+    /// Run after the server has started
+    pub post_start: Option<String>,
+    /// Run before the `stop` command is sent to the server
+    pub pre_stop: Option<String>,
+    /// Run after the server has stopped
+    pub post_stop: Option<String>,
+    /// Run after the server has failed
+    pub on_failure: Option<String>,
+    /// Run before the server software is updated
+    pub pre_update: Option<String>,
+    /// Run after the server software has been updated
+    pub post_update: Option<String>,
+    /// The seconds after which a hook is killed
+    #[serde(default = "default_hook_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+/// Default value of [`HooksConfig::timeout_secs`].
+fn default_hook_timeout_secs() -> u64 {
+    60
+}
+
+/// Backup settings of a server unit (`[server.backup]`).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UnitBackupConfig {
+    /// The world directories relative to the server directory.
+    ///
+    /// If not set, the world of `level-name` in `server.properties` is backed up together with its
+    /// nether and end directories, as far as they exist.
+    pub worlds: Option<Vec<String>>,
+    /// The backups that are kept, all backups are kept if not set
+    pub retention: Option<RetentionConfig>,
+}
+
+/// Retention policy of the backups of a unit (`[server.backup.retention]`).
+///
+/// A backup is kept if it is one of the `keep_last` newest backups or if it is the newest backup
+/// of an hour, day, week or month within the configured period. The newest backup is always
+/// kept. The policy is enforced after each backup and by `mcman backup prune`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RetentionConfig {
+    /// The number of newest backups that are kept
+    #[serde(default)]
+    pub keep_last: usize,
+    /// The number of hours for which the newest backup of every hour is kept
+    #[serde(default)]
+    pub hourly: u32,
+    /// The number of days for which the newest backup of every day is kept
+    #[serde(default)]
+    pub daily: u32,
+    /// The number of weeks for which the newest backup of every week is kept
+    #[serde(default)]
+    pub weekly: u32,
+    /// The number of months (of 30 days) for which the newest backup of every month is kept
+    #[serde(default)]
+    pub monthly: u32,
+}
+
+/// Log pattern rules of a server unit (`[server.log_patterns]`).
+///
+/// The rules of the unit are checked before the built-in rules of the server type.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LogPatternConfig {
+    /// Whether the built-in rules of the server type are used after the rules of the unit
+    #[serde(default = "default_inherit")]
+    pub inherit: bool,
+    /// The rules of the unit (`[[server.log_patterns.rule]]`)
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<LogRuleConfig>,
+}
+
+/// A single log pattern rule.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LogRuleConfig {
+    /// The regular expression a line of the server output has to match
+    pub pattern: String,
+    /// What a matching line means for the state of the server
+    pub action: crate::daemon::patterns::LogAction,
+    /// The error reported for [`crate::daemon::patterns::LogAction::Failed`].
+    ///
+    /// Capture groups of the pattern can be referenced with `$1`, `$name`, ...
+    pub error: Option<String>,
+}
+
+/// A console trigger (`[[trigger]]`), which performs actions when a line of the server output
+/// matches its pattern.
+///
+/// Every matching trigger fires, unless it has fired less than `cooldown_secs` ago. A trigger can
+/// have any combination of the actions `event`, `command` and `hook`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TriggerConfig {
+    /// The regular expression a line of the server output has to match
+    pub pattern: String,
+    /// The minimum number of seconds between two firings of the trigger
+    #[serde(default)]
+    pub cooldown_secs: u64,
+    /// Raise a [`crate::ipc::ServerEvent::Custom`] event with this name
+    pub event: Option<String>,
+    /// Send this command to the server console.
+    ///
+    /// Capture groups of the pattern can be referenced with `$1`, `$name`, ...
+    pub command: Option<String>,
+    /// Run this shell command in the server directory.
+    ///
+    /// The capture groups are passed as the environment variables `MCMAN_CAPTURE_1`,
+    /// `MCMAN_CAPTURE_<NAME>`, ... instead of being substituted.
+    pub hook: Option<String>,
+}
+
+/// Default value of [`LogPatternConfig::inherit`].
+fn default_inherit() -> bool {
+    true
 }
 
 impl DaemonConfig {
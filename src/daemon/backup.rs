@@ -0,0 +1,1239 @@
+//! Backups of the worlds of server units.
+//!
+//! A backup is a zstd compressed tar archive of the world directories of a server. It is stored as
+//! `<root>/<unit id>/<backup id>.tar.zst` in the backup root of the daemon, next to a
+//! `<backup id>.json` file with its [`BackupInfo`].
+//!
+//! While a server is running, the daemon turns off automatic saving (`save-off`), lets the server
+//! write its worlds to disk (`save-all flush`) and waits for the "Saved the game" line before the
+//! worlds are archived, so the archive is consistent. Automatic saving is turned on again
+//! (`save-on`) when the archive is complete. A stopped server is archived directly. Besides the
+//! worlds, the archive contains the server jar and the unit file, so a backup can be restored
+//! together with the server software it has been made with.
+//!
+//! After each backup, the backups of the unit are pruned by its [`RetentionConfig`].
+//!
+//! A backup is restored by extracting it into a directory inside the server directory and moving
+//! the world directories into place, see [`BackupStore::restore`].
+//!
+//! With `format = "chunked"` in the `[backup]` section of the daemon config, new backups are not
+//! archived but split into deduplicated chunks, see [`crate::daemon::chunks`]. Such a backup is
+//! stored as `<root>/<unit id>/<backup id>.manifest.zst` and only costs the space of the chunks
+//! that have changed since the previous backups. Both formats are handled by [`BackupStore`].
+
+use crate::config::{BackupConfig, BackupFormat, RetentionConfig, ServerConfig};
+use crate::daemon::chunks::{ChunkStore, Manifest, ManifestEntry, CHUNK_DIRECTORY};
+use crate::daemon::console::LogStream;
+use crate::daemon::records::RecordParser;
+use crate::ipc::{DaemonResponse, ServerEvent};
+use chrono::{DateTime, Datelike, Duration as TimeDuration, Local, Timelike, Utc};
+use log::warn;
+use semver::Version;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, create_dir_all, read_dir, remove_dir_all, remove_file, rename, File};
+use std::io::{self, Read};
+use std::os::unix::fs::symlink;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+/// The file extension of backup archives.
+pub const ARCHIVE_EXTENSION: &str = "tar.zst";
+
+/// The file extension of the manifests of chunked backups.
+pub const MANIFEST_EXTENSION: &str = "manifest.zst";
+
+/// The message a server prints when it has written its worlds to disk.
+pub const SAVED_LINE: &str = "Saved the game";
+
+/// The file in the server directory that names the world.
+const PROPERTIES_FILE: &str = "server.properties";
+
+/// Files that are left out of backups, the server holds a lock on them while it is running.
+const SKIPPED_FILES: [&str; 1] = ["session.lock"];
+
+/// The name of the unit file in backup archives.
+const UNIT_FILE_ENTRY: &str = ".mcman/unit.toml";
+
+/// Returns the hour, day, week or month a backup time belongs to.
+type Bucket = fn(DateTime<Local>) -> (i32, u32, u32);
+
+/// Describes a backup, stored next to its archive.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupInfo {
+    /// The id of the backup, unique per unit
+    pub id: String,
+    /// The id of the unit
+    pub unit_id: String,
+    /// The time the backup has been started
+    pub time: DateTime<Utc>,
+    /// The label given when the backup has been requested
+    pub label: Option<String>,
+    /// The server type of the unit at the time of the backup
+    pub server_type: String,
+    /// The version of the server software at the time of the backup
+    pub server_version: Version,
+    /// The archived world directories, relative to the server directory
+    pub worlds: Vec<String>,
+    /// The archived server jar, relative to the server directory
+    #[serde(default)]
+    pub jar: Option<String>,
+    /// Whether the unit file has been archived
+    #[serde(default)]
+    pub unit_file: bool,
+    /// How the backup is stored
+    #[serde(default)]
+    pub format: BackupFormat,
+    /// The size of the archive in bytes, for a chunked backup the size of its manifest and of the
+    /// chunks it has added
+    pub size: u64,
+}
+
+/// The result of the verification of a backup.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupVerification {
+    /// The verified backup
+    pub backup: BackupInfo,
+    /// The problems that have been found, empty if the backup is intact
+    pub problems: Vec<String>,
+}
+
+/// The parts of a backup that are restored besides the worlds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct RestoreOptions {
+    /// Whether the server jar is restored
+    pub jar: bool,
+    /// Whether the unit file is restored
+    pub config: bool,
+}
+
+/// The unit a backup is made of.
+#[derive(Debug, Clone)]
+pub struct BackupSource {
+    /// The id of the unit
+    pub unit_id: String,
+    /// The server directory
+    pub server_path: PathBuf,
+    /// The name of the server type
+    pub server_type: String,
+    /// The version of the server software
+    pub server_version: Version,
+    /// The configured world directories, see [`crate::config::UnitBackupConfig::worlds`]
+    pub worlds: Option<Vec<String>>,
+    /// The server jar relative to the server directory
+    pub jar: Option<String>,
+    /// The unit file of the unit
+    pub unit_file: Option<PathBuf>,
+    /// The retention policy of the backups of the unit
+    pub retention: Option<RetentionConfig>,
+}
+
+/// The reason why a backup has failed.
+#[derive(Debug)]
+pub enum BackupError {
+    /// The server directory contains none of the world directories
+    NoWorlds,
+    /// The backup does not exist
+    NotFound(String),
+    /// A part of the backup that should be restored has not been archived
+    NotInBackup(&'static str),
+    /// The server has not saved its worlds in time
+    SaveTimedOut(Duration),
+    /// The archive could not be written
+    Io(io::Error),
+}
+
+/// The backups in the backup root of the daemon.
+#[derive(Debug, Clone)]
+pub struct BackupStore {
+    /// The directory that contains a directory of backups per unit
+    root: PathBuf,
+    /// The format of new backups
+    format: BackupFormat,
+}
+
+impl Display for BackupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BackupError::NoWorlds => write!(f, "the server has no world directories"),
+            BackupError::NotFound(id) => write!(f, "backup {} does not exist", id),
+            BackupError::NotInBackup(part) => write!(f, "the backup does not contain the {}", part),
+            BackupError::SaveTimedOut(timeout) => write!(
+                f,
+                "the server has not saved its worlds within {}s",
+                timeout.as_secs()
+            ),
+            BackupError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for BackupError {
+    fn from(e: io::Error) -> Self {
+        BackupError::Io(e)
+    }
+}
+
+impl BackupSource {
+    /// Describes the unit with the given server config and unit file.
+    pub fn from_config(unit_id: &str, config: &ServerConfig, unit_file: &Path) -> Self {
+        Self {
+            unit_id: unit_id.to_string(),
+            server_path: config.path.to_path_buf(),
+            server_type: config.type_name.clone(),
+            server_version: config.version.clone(),
+            jar: Some(config.jar.clone()),
+            unit_file: Some(unit_file.to_path_buf()),
+            worlds: config
+                .backup
+                .as_ref()
+                .and_then(|backup| backup.worlds.clone()),
+            retention: config
+                .backup
+                .as_ref()
+                .and_then(|backup| backup.retention.clone()),
+        }
+    }
+
+    /// Returns the world directories that are backed up.
+    ///
+    /// Unless the worlds are configured, these are the directories of the world named by
+    /// `level-name` in `server.properties` (`world` by default) and of its nether and end, as far
+    /// as they exist.
+    pub fn world_directories(&self) -> Vec<String> {
+        if let Some(worlds) = &self.worlds {
+            return worlds.clone();
+        }
+        let level = level_name(&self.server_path);
+        vec![
+            level.clone(),
+            format!("{}_nether", level),
+            format!("{}_the_end", level),
+        ]
+        .into_iter()
+        .filter(|world| self.server_path.join(world).is_dir())
+        .collect()
+    }
+}
+
+impl BackupStore {
+    /// Creates a store in the given backup root that archives new backups.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            format: BackupFormat::Archive,
+        }
+    }
+
+    /// Creates the store configured in the daemon config.
+    pub fn from_config(config: &BackupConfig) -> Self {
+        Self {
+            root: PathBuf::from(&config.root),
+            format: config.format,
+        }
+    }
+
+    /// Returns the directory that contains the backups of a unit.
+    pub fn unit_directory(&self, unit_id: &str) -> PathBuf {
+        self.root.join(unit_id)
+    }
+
+    /// Returns the path of the archive of a backup.
+    pub fn archive_path(&self, unit_id: &str, backup_id: &str) -> PathBuf {
+        self.unit_directory(unit_id)
+            .join(format!("{}.{}", backup_id, ARCHIVE_EXTENSION))
+    }
+
+    /// Returns the path of the manifest of a chunked backup.
+    pub fn manifest_path(&self, unit_id: &str, backup_id: &str) -> PathBuf {
+        self.unit_directory(unit_id)
+            .join(format!("{}.{}", backup_id, MANIFEST_EXTENSION))
+    }
+
+    /// Returns the path of the description of a backup.
+    fn info_path(&self, unit_id: &str, backup_id: &str) -> PathBuf {
+        self.unit_directory(unit_id)
+            .join(format!("{}.json", backup_id))
+    }
+
+    /// Returns a new backup id for a backup started at `time`.
+    ///
+    /// Ids are derived from the time, so they sort chronologically.
+    fn new_id(&self, unit_id: &str, time: DateTime<Utc>) -> String {
+        let base = time.format("%Y%m%d-%H%M%S").to_string();
+        let mut id = base.clone();
+        let mut number = 1;
+        while self.info_path(unit_id, &id).exists() || self.archive_path(unit_id, &id).exists() {
+            number += 1;
+            id = format!("{}-{}", base, number);
+        }
+        id
+    }
+
+    /// Returns the backups of a unit, oldest first.
+    ///
+    /// Descriptions that can not be read are skipped.
+    pub fn list(&self, unit_id: &str) -> io::Result<Vec<BackupInfo>> {
+        let directory = self.unit_directory(unit_id);
+        if !directory.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut backups = vec![];
+        for entry in read_dir(directory)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                match fs::read(&path)
+                    .ok()
+                    .and_then(|json| serde_json::from_slice::<BackupInfo>(&json).ok())
+                {
+                    Some(backup) => backups.push(backup),
+                    None => warn!("skipping unreadable backup description {:?}", path),
+                }
+            }
+        }
+        backups.sort_by(|a, b| a.time.cmp(&b.time).then_with(|| a.id.cmp(&b.id)));
+        Ok(backups)
+    }
+
+    /// Returns the backup of a unit with the given id.
+    pub fn find(&self, unit_id: &str, backup_id: &str) -> Result<BackupInfo, BackupError> {
+        match fs::read(self.info_path(unit_id, backup_id)) {
+            Ok(json) => serde_json::from_slice(&json)
+                .map_err(|e| BackupError::Io(io::Error::new(io::ErrorKind::InvalidData, e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(BackupError::NotFound(backup_id.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Deletes the archive or the manifest and the description of a backup.
+    ///
+    /// The chunks of a chunked backup are deleted by [`BackupStore::collect_garbage`].
+    pub fn delete(&self, unit_id: &str, backup_id: &str) -> io::Result<()> {
+        for path in [
+            self.archive_path(unit_id, backup_id),
+            self.manifest_path(unit_id, backup_id),
+        ] {
+            match remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        remove_file(self.info_path(unit_id, backup_id))
+    }
+
+    /// Deletes the backups of a unit that are not kept by a retention policy and returns them.
+    ///
+    /// If `dry_run` is set, the backups are only returned.
+    pub fn prune(
+        &self,
+        unit_id: &str,
+        retention: &RetentionConfig,
+        dry_run: bool,
+    ) -> io::Result<Vec<BackupInfo>> {
+        let expired = expired(&self.list(unit_id)?, retention, Utc::now());
+        if !dry_run {
+            for backup in &expired {
+                self.delete(unit_id, &backup.id)?;
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Archives the world directories of a unit in the format of the store.
+    ///
+    /// `progress` is called with the archived and the total number of bytes whenever another
+    /// percent has been archived. The archive or the manifest is written to a temporary file
+    /// first, an interrupted backup never leaves an incomplete backup behind.
+    pub fn create(
+        &self,
+        source: &BackupSource,
+        label: Option<String>,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<BackupInfo, BackupError> {
+        let worlds = source.world_directories();
+        if worlds.is_empty() {
+            return Err(BackupError::NoWorlds);
+        }
+        let mut entries = vec![];
+        for world in &worlds {
+            for entry in WalkDir::new(source.server_path.join(world)) {
+                let entry = entry.map_err(io::Error::from)?;
+                if SKIPPED_FILES.contains(&entry.file_name().to_string_lossy().as_ref()) {
+                    continue;
+                }
+                let name = entry
+                    .path()
+                    .strip_prefix(&source.server_path)
+                    .expect("world directory is inside the server directory")
+                    .to_path_buf();
+                entries.push(ArchiveEntry::new(entry.path().to_path_buf(), name)?);
+            }
+        }
+        let jar = source
+            .jar
+            .clone()
+            .filter(|jar| source.server_path.join(jar).is_file());
+        if let Some(jar) = &jar {
+            entries.push(ArchiveEntry::new(
+                source.server_path.join(jar),
+                PathBuf::from(jar),
+            )?);
+        }
+        let unit_file = source.unit_file.clone().filter(|file| file.is_file());
+        if let Some(unit_file) = &unit_file {
+            entries.push(ArchiveEntry::new(
+                unit_file.clone(),
+                PathBuf::from(UNIT_FILE_ENTRY),
+            )?);
+        }
+
+        let time = Utc::now();
+        create_dir_all(self.unit_directory(&source.unit_id))?;
+        let id = self.new_id(&source.unit_id, time);
+        let size = match self.format {
+            BackupFormat::Archive => {
+                let archive = self.archive_path(&source.unit_id, &id);
+                let partial = archive.with_extension(format!("{}.partial", ARCHIVE_EXTENSION));
+                if let Err(e) = write_archive(&entries, &partial, progress) {
+                    let _ = remove_file(&partial);
+                    return Err(e.into());
+                }
+                rename(&partial, &archive)?;
+                fs::metadata(&archive)?.len()
+            }
+            BackupFormat::Chunked => {
+                let chunks = ChunkStore::new(&self.root);
+                let (manifest, written) = write_chunks(&chunks, &entries, progress)?;
+                let path = self.manifest_path(&source.unit_id, &id);
+                manifest.write(&path)?;
+                written + fs::metadata(&path)?.len()
+            }
+        };
+
+        let info = BackupInfo {
+            id,
+            unit_id: source.unit_id.clone(),
+            time,
+            label,
+            server_type: source.server_type.clone(),
+            server_version: source.server_version.clone(),
+            worlds,
+            jar,
+            unit_file: unit_file.is_some(),
+            format: self.format,
+            size,
+        };
+        fs::write(
+            self.info_path(&source.unit_id, &info.id),
+            serde_json::to_vec_pretty(&info).expect("serialize backup info"),
+        )?;
+        Ok(info)
+    }
+
+    /// Restores the worlds of a backup into a server directory and, depending on `options`, the
+    /// server jar and the unit file.
+    ///
+    /// The backup is extracted into a directory inside the server directory first, then the
+    /// current world directories are replaced. `progress` is called with the read and the total
+    /// number of bytes of the archive or of the files of a chunked backup whenever another
+    /// percent has been read. The server must not be running.
+    pub fn restore(
+        &self,
+        backup: &BackupInfo,
+        server_path: &Path,
+        unit_file: &Path,
+        options: RestoreOptions,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<(), BackupError> {
+        if options.jar && backup.jar.is_none() {
+            return Err(BackupError::NotInBackup("server jar"));
+        }
+        if options.config && !backup.unit_file {
+            return Err(BackupError::NotInBackup("unit file"));
+        }
+        let staging = server_path.join(format!(".mcman-restore-{}", backup.id));
+        if staging.exists() {
+            remove_dir_all(&staging)?;
+        }
+        create_dir_all(&staging)?;
+        let extracted = match backup.format {
+            BackupFormat::Archive => extract_archive(
+                &self.archive_path(&backup.unit_id, &backup.id),
+                &staging,
+                progress,
+            ),
+            BackupFormat::Chunked => Manifest::read(
+                &self.manifest_path(&backup.unit_id, &backup.id),
+            )
+            .and_then(|manifest| {
+                extract_chunks(&ChunkStore::new(&self.root), &manifest, &staging, progress)
+            }),
+        };
+        let result = extracted
+            .and_then(|_| replace_files(backup, &staging, server_path, unit_file, options));
+        let _ = remove_dir_all(&staging);
+        result.map_err(BackupError::from)
+    }
+
+    /// Checks that the backups of a unit, or of all units, can be restored.
+    ///
+    /// Archives are decompressed completely, the chunks of chunked backups are read and compared
+    /// with their hashes. Chunks that are shared between backups are only read once.
+    pub fn verify(&self, unit_id: Option<&str>) -> io::Result<Vec<BackupVerification>> {
+        let chunks = ChunkStore::new(&self.root);
+        let mut verified = HashMap::new();
+        let mut verifications = vec![];
+        for unit_id in self.unit_ids(unit_id)? {
+            for backup in self.list(&unit_id)? {
+                let problems = match backup.format {
+                    BackupFormat::Archive => {
+                        match verify_archive(&self.archive_path(&unit_id, &backup.id)) {
+                            Ok(()) => vec![],
+                            Err(e) => vec![format!("the archive is unreadable: {}", e)],
+                        }
+                    }
+                    BackupFormat::Chunked => {
+                        match Manifest::read(&self.manifest_path(&unit_id, &backup.id)) {
+                            Ok(manifest) => chunks.verify(&manifest, &mut verified),
+                            Err(e) => vec![format!("the manifest is unreadable: {}", e)],
+                        }
+                    }
+                };
+                verifications.push(BackupVerification { backup, problems });
+            }
+        }
+        Ok(verifications)
+    }
+
+    /// Deletes the chunks that no chunked backup refers to anymore.
+    ///
+    /// No backup may be created while the garbage is collected. If a manifest can not be read,
+    /// nothing is deleted. If `dry_run` is set, the chunks are only counted. Returns the number of
+    /// deleted chunks and their size in bytes.
+    pub fn collect_garbage(&self, dry_run: bool) -> io::Result<(usize, u64)> {
+        let mut referenced = HashSet::new();
+        for unit_id in self.unit_ids(None)? {
+            for backup in self.list(&unit_id)? {
+                if let BackupFormat::Chunked = backup.format {
+                    let path = self.manifest_path(&unit_id, &backup.id);
+                    let manifest = Manifest::read(&path).map_err(|e| {
+                        io::Error::new(e.kind(), format!("could not read {:?}: {}", path, e))
+                    })?;
+                    referenced.extend(manifest.chunk_ids().cloned());
+                }
+            }
+        }
+        ChunkStore::new(&self.root).collect_garbage(&referenced, dry_run)
+    }
+
+    /// Returns the given unit id or the ids of all units that have backups.
+    fn unit_ids(&self, unit_id: Option<&str>) -> io::Result<Vec<String>> {
+        if let Some(unit_id) = unit_id {
+            return Ok(vec![unit_id.to_string()]);
+        }
+        if !self.root.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut unit_ids = vec![];
+        for entry in read_dir(&self.root)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_dir() && name != CHUNK_DIRECTORY {
+                unit_ids.push(name);
+            }
+        }
+        unit_ids.sort();
+        Ok(unit_ids)
+    }
+}
+
+/// A file or directory that is added to an archive.
+struct ArchiveEntry {
+    /// The path of the file
+    path: PathBuf,
+    /// The path in the archive
+    name: PathBuf,
+    /// The size of the file, `0` for directories
+    size: u64,
+}
+
+impl ArchiveEntry {
+    /// Reads the size of the file at `path`.
+    fn new(path: PathBuf, name: PathBuf) -> io::Result<Self> {
+        let metadata = fs::symlink_metadata(&path)?;
+        let size = if metadata.is_file() {
+            metadata.len()
+        } else {
+            0
+        };
+        Ok(Self { path, name, size })
+    }
+}
+
+/// Counts the bytes read from a reader.
+struct CountingReader<R> {
+    /// The reader
+    inner: R,
+    /// The number of bytes read so far, shared with the owner of the reader
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count.set(self.count.get() + read as u64);
+        Ok(read)
+    }
+}
+
+/// Waits until a server has written its worlds to disk.
+///
+/// `console` receives the [`ServerEvent::ConsoleLine`]s of the server, the subscription must have
+/// been made before `save-all flush` has been sent.
+pub fn wait_for_save(
+    console: &Receiver<DaemonResponse>,
+    timeout: Duration,
+) -> Result<(), BackupError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match console.recv_timeout(remaining) {
+            Ok(DaemonResponse::ServerEvent {
+                event: ServerEvent::ConsoleLine { line, stream, .. },
+            }) if is_saved_line(&line, stream) => return Ok(()),
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {
+                return Err(BackupError::SaveTimedOut(timeout))
+            }
+        }
+    }
+}
+
+/// Returns whether a console line is the message of the server that it has saved its worlds.
+///
+/// The whole message of the server itself has to match, so chat and broadcasts of players and
+/// plugins are ignored.
+fn is_saved_line(line: &str, stream: LogStream) -> bool {
+    let record = RecordParser::default().parse(line, stream);
+    record.time.is_some() && record.logger.is_none() && record.message == SAVED_LINE
+}
+
+/// Returns the backups that are not kept by a retention policy at the time `now`.
+///
+/// `backups` must be sorted oldest first, the expired backups are returned in the same order.
+pub fn expired(
+    backups: &[BackupInfo],
+    retention: &RetentionConfig,
+    now: DateTime<Utc>,
+) -> Vec<BackupInfo> {
+    let newest_first: Vec<&BackupInfo> = backups.iter().rev().collect();
+    // the newest backup is kept by every policy
+    let mut kept: HashSet<&str> = newest_first
+        .iter()
+        .take(retention.keep_last.max(1))
+        .map(|backup| backup.id.as_str())
+        .collect();
+
+    let periods: [(u32, TimeDuration, Bucket); 4] = [
+        (retention.hourly, TimeDuration::hours(1), |time| {
+            (time.year(), time.ordinal(), time.hour())
+        }),
+        (retention.daily, TimeDuration::days(1), |time| {
+            (time.year(), time.ordinal(), 0)
+        }),
+        (retention.weekly, TimeDuration::weeks(1), |time| {
+            (time.iso_week().year(), time.iso_week().week(), 0)
+        }),
+        (retention.monthly, TimeDuration::days(30), |time| {
+            (time.year(), time.month(), 0)
+        }),
+    ];
+    for (count, length, bucket) in periods.iter() {
+        let start = now - *length * *count as i32;
+        let mut buckets = HashSet::new();
+        for backup in newest_first.iter().filter(|backup| backup.time > start) {
+            if buckets.insert(bucket(backup.time.with_timezone(&Local))) {
+                kept.insert(&backup.id);
+            }
+        }
+    }
+
+    backups
+        .iter()
+        .filter(|backup| !kept.contains(backup.id.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// Returns the `level-name` in the `server.properties` of a server, `world` if it is not set.
+fn level_name(server_path: &Path) -> String {
+    fs::read_to_string(server_path.join(PROPERTIES_FILE))
+        .ok()
+        .and_then(|properties| {
+            properties.lines().find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                if key.trim() == "level-name" && !value.trim().is_empty() {
+                    Some(value.trim().to_string())
+                } else {
+                    None
+                }
+            })
+        })
+        .unwrap_or_else(|| "world".to_string())
+}
+
+/// Writes files into a compressed tar archive.
+fn write_archive(
+    entries: &[ArchiveEntry],
+    archive: &Path,
+    progress: &mut dyn FnMut(u64, u64),
+) -> io::Result<()> {
+    let total: u64 = entries.iter().map(|entry| entry.size).sum();
+    let mut builder = tar::Builder::new(zstd::Encoder::new(File::create(archive)?, 0)?);
+    builder.follow_symlinks(false);
+    let mut archived = 0;
+    let mut reported = 0;
+    progress(0, total);
+    for entry in entries {
+        builder.append_path_with_name(&entry.path, &entry.name)?;
+        if entry.size > 0 {
+            archived += entry.size;
+            let percent = archived * 100 / total.max(1);
+            if percent > reported {
+                reported = percent;
+                progress(archived, total);
+            }
+        }
+    }
+    builder.into_inner()?.finish()?.sync_all()
+}
+
+/// Extracts a compressed tar archive into a directory.
+///
+/// Entries that would be written outside of the directory are skipped.
+fn extract_archive(
+    archive: &Path,
+    directory: &Path,
+    progress: &mut dyn FnMut(u64, u64),
+) -> io::Result<()> {
+    let total = fs::metadata(archive)?.len();
+    let count = Rc::new(Cell::new(0));
+    let reader = CountingReader {
+        inner: File::open(archive)?,
+        count: count.clone(),
+    };
+    let mut tar = tar::Archive::new(zstd::Decoder::new(reader)?);
+    tar.set_preserve_mtime(true);
+    let mut reported = 0;
+    progress(0, total);
+    for entry in tar.entries()? {
+        entry?.unpack_in(directory)?;
+        let percent = count.get() * 100 / total.max(1);
+        if percent > reported {
+            reported = percent;
+            progress(count.get().min(total), total);
+        }
+    }
+    progress(total, total);
+    Ok(())
+}
+
+/// Decompresses a compressed tar archive completely.
+fn verify_archive(archive: &Path) -> io::Result<()> {
+    let mut tar = tar::Archive::new(zstd::Decoder::new(File::open(archive)?)?);
+    for entry in tar.entries()? {
+        io::copy(&mut entry?, &mut io::sink())?;
+    }
+    Ok(())
+}
+
+/// Stores files in deduplicated chunks and returns their manifest and the number of bytes that
+/// have been written.
+fn write_chunks(
+    chunks: &ChunkStore,
+    entries: &[ArchiveEntry],
+    progress: &mut dyn FnMut(u64, u64),
+) -> io::Result<(Manifest, u64)> {
+    let total: u64 = entries.iter().map(|entry| entry.size).sum();
+    let mut manifest = Manifest::default();
+    let mut written = 0;
+    let mut stored = 0;
+    let mut reported = 0;
+    progress(0, total);
+    for entry in entries {
+        let file_type = fs::symlink_metadata(&entry.path)?.file_type();
+        let name = entry.name.clone();
+        if file_type.is_dir() {
+            manifest.entries.push(ManifestEntry::Directory { name });
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&entry.path)?;
+            manifest
+                .entries
+                .push(ManifestEntry::Symlink { name, target });
+        } else {
+            let (ids, file_written) = chunks.store_file(&entry.path, &mut |length| {
+                stored += length;
+                let percent = stored * 100 / total.max(1);
+                if percent > reported {
+                    reported = percent;
+                    progress(stored.min(total), total);
+                }
+            })?;
+            written += file_written;
+            manifest.entries.push(ManifestEntry::File {
+                name,
+                size: entry.size,
+                chunks: ids,
+            });
+        }
+    }
+    progress(total, total);
+    Ok((manifest, written))
+}
+
+/// Writes the files of a chunked backup into a directory.
+///
+/// Entries that would be written outside of the directory are rejected.
+fn extract_chunks(
+    chunks: &ChunkStore,
+    manifest: &Manifest,
+    directory: &Path,
+    progress: &mut dyn FnMut(u64, u64),
+) -> io::Result<()> {
+    let total = manifest.size();
+    let mut restored = 0;
+    let mut reported = 0;
+    progress(0, total);
+    for entry in &manifest.entries {
+        let name = match entry {
+            ManifestEntry::Directory { name }
+            | ManifestEntry::File { name, .. }
+            | ManifestEntry::Symlink { name, .. } => name,
+        };
+        if name.is_absolute()
+            || name
+                .components()
+                .any(|component| matches!(component, Component::ParentDir))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid path in manifest: {:?}", name),
+            ));
+        }
+        let path = directory.join(name);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        match entry {
+            ManifestEntry::Directory { .. } => create_dir_all(&path)?,
+            ManifestEntry::File { chunks: ids, .. } => {
+                chunks.restore_file(ids, &path, &mut |length| {
+                    restored += length;
+                    let percent = restored * 100 / total.max(1);
+                    if percent > reported {
+                        reported = percent;
+                        progress(restored.min(total), total);
+                    }
+                })?
+            }
+            ManifestEntry::Symlink { target, .. } => symlink(target, &path)?,
+        }
+    }
+    progress(total, total);
+    Ok(())
+}
+
+/// Moves the extracted world directories and files of a backup into place.
+fn replace_files(
+    backup: &BackupInfo,
+    staging: &Path,
+    server_path: &Path,
+    unit_file: &Path,
+    options: RestoreOptions,
+) -> io::Result<()> {
+    for world in &backup.worlds {
+        let target = server_path.join(world);
+        if target.exists() {
+            remove_dir_all(&target)?;
+        }
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)?;
+        }
+        rename(staging.join(world), &target)?;
+    }
+    if let (true, Some(jar)) = (options.jar, &backup.jar) {
+        rename(staging.join(jar), server_path.join(jar))?;
+    }
+    if options.config {
+        fs::copy(staging.join(UNIT_FILE_ENTRY), unit_file)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{BackupConfig, BackupFormat, RetentionConfig};
+    use crate::daemon::backup::{
+        expired, wait_for_save, BackupError, BackupInfo, BackupSource, BackupStore, RestoreOptions,
+    };
+    use crate::daemon::console::LogStream;
+    use crate::ipc::{DaemonResponse, ServerEvent};
+    use chrono::{Duration, TimeZone, Utc};
+    use semver::Version;
+    use std::collections::HashSet;
+    use std::fs::{create_dir_all, read_dir, read_to_string, write, File};
+    use std::path::Path;
+    use std::sync::mpsc::channel;
+    use std::time::Duration as StdDuration;
+
+    fn source(server_path: &Path) -> BackupSource {
+        BackupSource {
+            unit_id: "a".to_string(),
+            server_path: server_path.to_path_buf(),
+            server_type: "paper".to_string(),
+            server_version: Version::new(1, 16, 5),
+            worlds: None,
+            retention: None,
+            jar: None,
+            unit_file: None,
+        }
+    }
+
+    #[test]
+    fn test_world_directories() {
+        let server = tempfile::tempdir().expect("create temp dir");
+        let mut source = source(server.path());
+        assert!(source.world_directories().is_empty());
+
+        create_dir_all(server.path().join("world")).expect("create world");
+        create_dir_all(server.path().join("world_the_end")).expect("create end");
+        assert_eq!(source.world_directories(), vec!["world", "world_the_end"]);
+
+        write(
+            server.path().join("server.properties"),
+            "#Minecraft server properties\nlevel-name=survival\n",
+        )
+        .expect("write properties");
+        create_dir_all(server.path().join("survival")).expect("create world");
+        assert_eq!(source.world_directories(), vec!["survival"]);
+
+        source.worlds = Some(vec!["custom".to_string()]);
+        assert_eq!(source.world_directories(), vec!["custom"]);
+    }
+
+    #[test]
+    fn test_create() {
+        let server = tempfile::tempdir().expect("create temp dir");
+        create_dir_all(server.path().join("world/region")).expect("create world");
+        create_dir_all(server.path().join("world_nether/DIM-1")).expect("create nether");
+        write(server.path().join("world/level.dat"), "level").expect("write level");
+        write(server.path().join("world/region/r.0.0.mca"), vec![7; 4096]).expect("write region");
+        write(server.path().join("world/session.lock"), "lock").expect("write lock");
+        write(server.path().join("world_nether/DIM-1/x"), "nether").expect("write nether");
+        write(server.path().join("paper.jar"), "jar").expect("write jar");
+
+        let backups = tempfile::tempdir().expect("create temp dir");
+        let store = BackupStore::new(backups.path());
+        let mut reports = vec![];
+        let info = store
+            .create(
+                &source(server.path()),
+                Some("before update".to_string()),
+                &mut |archived, total| reports.push((archived, total)),
+            )
+            .expect("create backup");
+        assert_eq!(info.worlds, vec!["world", "world_nether"]);
+        assert_eq!(info.label.as_deref(), Some("before update"));
+        assert_eq!(reports.first(), Some(&(0, 4107)));
+        assert_eq!(reports.last(), Some(&(4107, 4107)));
+
+        let archive = store.archive_path("a", &info.id);
+        assert_eq!(
+            archive.metadata().expect("read archive metadata").len(),
+            info.size
+        );
+        let mut tar = tar::Archive::new(
+            zstd::Decoder::new(File::open(&archive).expect("open archive")).expect("decompress"),
+        );
+        let names: HashSet<String> = tar
+            .entries()
+            .expect("read archive")
+            .map(|entry| {
+                let entry = entry.expect("read archive entry");
+                let path = entry.path().expect("read entry path");
+                path.to_string_lossy().trim_end_matches('/').to_string()
+            })
+            .collect();
+        assert!(names.contains("world/level.dat"));
+        assert!(names.contains("world/region/r.0.0.mca"));
+        assert!(names.contains("world_nether/DIM-1/x"));
+        assert!(!names.contains("world/session.lock"));
+        assert!(!names.contains("paper.jar"));
+
+        let json = read_to_string(backups.path().join("a").join(format!("{}.json", info.id)))
+            .expect("read backup info");
+        assert!(json.contains("before update"));
+
+        let second = store
+            .create(&source(server.path()), None, &mut |_, _| {})
+            .expect("create second backup");
+        assert_ne!(second.id, info.id);
+    }
+
+    #[test]
+    fn test_restore() {
+        let server = tempfile::tempdir().expect("create temp dir");
+        let unit_file = server.path().join("a.toml");
+        create_dir_all(server.path().join("world/region")).expect("create world");
+        write(server.path().join("world/level.dat"), "old level").expect("write level");
+        write(server.path().join("world/region/r.0.0.mca"), "region").expect("write region");
+        write(server.path().join("paper.jar"), "old jar").expect("write jar");
+        write(&unit_file, "old unit").expect("write unit file");
+
+        let backups = tempfile::tempdir().expect("create temp dir");
+        let store = BackupStore::new(backups.path());
+        let mut source = source(server.path());
+        source.jar = Some("paper.jar".to_string());
+        source.unit_file = Some(unit_file.clone());
+        let info = store
+            .create(&source, None, &mut |_, _| {})
+            .expect("create backup");
+        assert_eq!(info.jar.as_deref(), Some("paper.jar"));
+        assert!(info.unit_file);
+        assert_eq!(store.find("a", &info.id).expect("find backup").id, info.id);
+        assert!(matches!(
+            store.find("a", "missing"),
+            Err(BackupError::NotFound(_))
+        ));
+
+        write(server.path().join("world/level.dat"), "new level").expect("write level");
+        write(server.path().join("world/region/r.1.0.mca"), "new").expect("write region");
+        write(server.path().join("paper.jar"), "new jar").expect("write jar");
+        write(&unit_file, "new unit").expect("write unit file");
+
+        let read = |path: &str| read_to_string(server.path().join(path)).expect("read file");
+        let mut reports = vec![];
+        store
+            .restore(
+                &info,
+                server.path(),
+                &unit_file,
+                RestoreOptions::default(),
+                &mut |read, total| reports.push((read, total)),
+            )
+            .expect("restore worlds");
+        assert_eq!(read("world/level.dat"), "old level");
+        assert_eq!(read("world/region/r.0.0.mca"), "region");
+        assert!(!server.path().join("world/region/r.1.0.mca").exists());
+        assert_eq!(read("paper.jar"), "new jar");
+        assert_eq!(read("a.toml"), "new unit");
+        assert_eq!(reports.last(), Some(&(info.size, info.size)));
+
+        let options = RestoreOptions {
+            jar: true,
+            config: true,
+        };
+        store
+            .restore(&info, server.path(), &unit_file, options, &mut |_, _| {})
+            .expect("restore jar and config");
+        assert_eq!(read("paper.jar"), "old jar");
+        assert_eq!(read("a.toml"), "old unit");
+        // the staging directory is removed
+        let names: HashSet<String> = read_dir(server.path())
+            .expect("list server directory")
+            .map(|entry| {
+                entry
+                    .expect("read entry")
+                    .file_name()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            ["world", "paper.jar", "a.toml"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect()
+        );
+
+        let worlds_only = store
+            .create(&self::source(server.path()), None, &mut |_, _| {})
+            .expect("create backup");
+        assert!(matches!(
+            store.restore(
+                &worlds_only,
+                server.path(),
+                &unit_file,
+                options,
+                &mut |_, _| {}
+            ),
+            Err(BackupError::NotInBackup(_))
+        ));
+    }
+
+    #[test]
+    fn test_chunked() {
+        let server = tempfile::tempdir().expect("create temp dir");
+        create_dir_all(server.path().join("world/region")).expect("create world");
+        write(server.path().join("world/level.dat"), "old level").expect("write level");
+        let region: Vec<u8> = (0..2_000_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        write(server.path().join("world/region/r.0.0.mca"), &region).expect("write region");
+
+        let backups = tempfile::tempdir().expect("create temp dir");
+        let store = BackupStore::from_config(&BackupConfig {
+            root: backups.path().to_string_lossy().to_string(),
+            format: BackupFormat::Chunked,
+            ..Default::default()
+        });
+        let first = store
+            .create(&source(server.path()), None, &mut |_, _| {})
+            .expect("create backup");
+        assert_eq!(first.format, BackupFormat::Chunked);
+        assert!(store.manifest_path("a", &first.id).exists());
+        assert!(!store.archive_path("a", &first.id).exists());
+
+        // only the changed level is stored again
+        write(server.path().join("world/level.dat"), "new level").expect("write level");
+        let second = store
+            .create(&source(server.path()), None, &mut |_, _| {})
+            .expect("create backup");
+        assert!(
+            second.size * 10 < first.size,
+            "{} {}",
+            second.size,
+            first.size
+        );
+
+        store
+            .restore(
+                &first,
+                server.path(),
+                Path::new("a.toml"),
+                RestoreOptions::default(),
+                &mut |_, _| {},
+            )
+            .expect("restore backup");
+        assert_eq!(
+            read_to_string(server.path().join("world/level.dat")).expect("read level"),
+            "old level"
+        );
+        assert_eq!(
+            std::fs::read(server.path().join("world/region/r.0.0.mca")).expect("read region"),
+            region
+        );
+
+        let verifications = store.verify(None).expect("verify backups");
+        assert_eq!(verifications.len(), 2);
+        assert!(verifications.iter().all(|v| v.problems.is_empty()));
+
+        // the chunks of the region file are shared, only the old level is garbage
+        store.delete("a", &first.id).expect("delete backup");
+        assert_eq!(store.collect_garbage(false).expect("collect garbage").0, 1);
+        assert!(store.verify(Some("a")).expect("verify backups")[0]
+            .problems
+            .is_empty());
+        store.delete("a", &second.id).expect("delete backup");
+        assert!(store.collect_garbage(false).expect("collect garbage").0 > 1);
+        assert_eq!(store.collect_garbage(false).expect("collect garbage").0, 0);
+    }
+
+    #[test]
+    fn test_wait_for_save() {
+        let (sender, console) = channel();
+        let send = |line: &str| {
+            sender
+                .send(DaemonResponse::ServerEvent {
+                    event: ServerEvent::ConsoleLine {
+                        server_id: "a".to_string(),
+                        line: line.to_string(),
+                        stream: LogStream::Stdout,
+                    },
+                })
+                .expect("send console line");
+        };
+        send("[12:00:00 INFO]: <Steve> Saved the game");
+        send("[12:00:00 INFO]: [Server] Saved the game");
+        send("[12:00:00] [Server thread/INFO]: <Steve> Saved the game");
+        assert!(matches!(
+            wait_for_save(&console, StdDuration::from_millis(50)),
+            Err(BackupError::SaveTimedOut(_))
+        ));
+
+        send("[12:00:01 INFO]: Saved the game");
+        assert!(wait_for_save(&console, StdDuration::from_millis(50)).is_ok());
+        send("[12:00:01] [Server thread/INFO]: Saved the game");
+        assert!(wait_for_save(&console, StdDuration::from_millis(50)).is_ok());
+    }
+
+    #[test]
+    fn test_expired() {
+        let first = Utc.ymd(2021, 1, 1).and_hms(12, 0, 0);
+        let backups: Vec<BackupInfo> = (0..60)
+            .map(|day| BackupInfo {
+                id: format!("{:02}", day),
+                unit_id: "a".to_string(),
+                time: first + Duration::days(day),
+                label: None,
+                server_type: "paper".to_string(),
+                server_version: Version::new(1, 16, 5),
+                worlds: vec!["world".to_string()],
+                jar: None,
+                unit_file: false,
+                format: BackupFormat::Archive,
+                size: 0,
+            })
+            .collect();
+        let now = first + Duration::days(59) + Duration::hours(1);
+        let ids = |backups: &[BackupInfo]| -> Vec<String> {
+            backups.iter().map(|backup| backup.id.clone()).collect()
+        };
+
+        // the newest backup is kept by every policy
+        let all_but_newest = expired(&backups, &RetentionConfig::default(), now);
+        assert_eq!(ids(&all_but_newest), ids(&backups[..59]));
+
+        let keep_last = RetentionConfig {
+            keep_last: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(&expired(&backups, &keep_last, now)),
+            ids(&backups[..57])
+        );
+
+        let gfs = RetentionConfig {
+            keep_last: 2,
+            hourly: 24,
+            daily: 7,
+            weekly: 4,
+            ..Default::default()
+        };
+        let expired_ids = ids(&expired(&backups, &gfs, now));
+        // one backup per day of the last week
+        assert!(ids(&backups[53..])
+            .iter()
+            .all(|id| !expired_ids.contains(id)));
+        // one backup per week of the last four weeks
+        let weekly: Vec<&BackupInfo> = backups[..53]
+            .iter()
+            .filter(|backup| !expired_ids.contains(&backup.id))
+            .collect();
+        assert!((2..=4).contains(&weekly.len()), "kept {:?}", weekly);
+        assert!(weekly
+            .iter()
+            .all(|backup| backup.time > now - Duration::weeks(4)));
+        assert_eq!(expired_ids.len(), 60 - 7 - weekly.len());
+    }
+}
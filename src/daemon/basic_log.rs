@@ -1,34 +1,76 @@
 //! This module contains a simple implementation for a log service.
 
+use crate::config::LogConfig;
+use crate::daemon::console::{ConsoleBuffer, LogStream};
+use crate::daemon::crash::{ExceptionTracker, LastFailure, ServerFailure};
 use crate::daemon::event::EventHandler;
-use crate::daemon::{LogService, OutputState};
+use crate::daemon::logs::LOG_DIRECTORY;
+use crate::daemon::patterns::{LogAction, LogPatternSet};
+use crate::daemon::performance::Performance;
+use crate::daemon::records::RecordParser;
+use crate::daemon::rotation::LogWriter;
+use crate::daemon::{DaemonEvent, LogService, OnlinePlayers, OutputState};
 use crate::ipc::ServerEvent;
 use log::{info, warn};
-use std::fs::{create_dir_all, File};
+use std::collections::{HashMap, VecDeque};
 use std::io::BufRead;
 use std::io::BufReader;
-use std::io::BufWriter;
-use std::io::Write;
+use std::io::Read;
 use std::ops::DerefMut;
-use std::path::PathBuf;
-use std::process::ChildStdout;
+use std::path::{Path, PathBuf};
+use std::process::{ChildStderr, ChildStdout};
+use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
 use std::sync::RwLock;
 use std::thread::spawn;
+use std::time::SystemTime;
+
+/// The number of stderr lines that are attached to a [`ServerEvent::ServerFailed`] event.
+const STDERR_LINES: usize = 10;
 
 /// Basic implementation for a [`LogService`].
-/// It parses the Output of a process to determine the current state of a server process.
+/// It parses the Output of a process with the [`LogPatternSet`] of the server to determine the
+/// current state of a server process.
+///
+/// The output is then redirected to `log/<unit_name>/<time_and_date>_out.log`, which is rotated
+/// according to the [`LogConfig`] of the unit, and published as [`ServerEvent::ConsoleLine`] events.
+/// Lines from stdout and stderr are interleaved in the order they are read, lines from stderr are
+/// tagged with [`LogStream::tag`].
+///
+/// Every line is also passed to the [`crate::daemon::performance::PerformanceMonitor`] of the
+/// process, which picks up the tick rate of the server, and checked against the console triggers
+/// of the unit, which are performed by the daemon.
 ///
-/// The output is then redirected to `log/<unit_name>/<time_and_date>_out.log`.
+/// A failure is detected by the patterns or if the output ends before the server has stopped. It
+/// is published with the last stderr lines, the last exception and the newest crash report.
 pub struct BasicLogService {
     /// The event handler for detected server events
     event_handler: EventHandler,
+    /// The queue of the main daemon thread, which performs the actions of fired triggers
+    daemon_queue: Sender<DaemonEvent>,
+    /// The console buffers of the most recent process of every server
+    consoles: HashMap<String, ConsoleBuffer>,
+    /// The online players of the most recent process of every server
+    players: HashMap<String, OnlinePlayers>,
+    /// The last failure of every server
+    failures: HashMap<String, LastFailure>,
+    /// The performance monitors of the most recent process of every server
+    performance: HashMap<String, Performance>,
 }
 
 impl BasicLogService {
     /// Creates a new log service with the given [`EventHandler`].
-    pub fn new(event_handler: EventHandler) -> Self {
-        Self { event_handler }
+    ///
+    /// Fired console triggers are sent to `daemon_queue`.
+    pub fn new(event_handler: EventHandler, daemon_queue: Sender<DaemonEvent>) -> Self {
+        Self {
+            event_handler,
+            daemon_queue,
+            consoles: HashMap::new(),
+            players: HashMap::new(),
+            failures: HashMap::new(),
+            performance: HashMap::new(),
+        }
     }
 }
 
@@ -38,24 +80,83 @@ struct BasicLogServiceHandler {
     state: Arc<RwLock<OutputState>>,
     /// The output of the child process
     out: ChildStdout,
+    /// The error output of the child process
+    err: ChildStderr,
     /// The [`EventHandler`] to which the events should be passed
     event_handler: EventHandler,
+    /// The queue to which fired triggers are sent
+    daemon_queue: Sender<DaemonEvent>,
     /// The id of the server this service is logging for
     server_id: String,
+    /// The buffer for attached consoles
+    console: ConsoleBuffer,
+    /// The rules that map output lines to states and events
+    patterns: LogPatternSet,
+    /// The players that are online on the server process
+    players: OnlinePlayers,
+    /// The rotation, compression and retention of the log files
+    log_config: LogConfig,
+    /// The directory of the server, which contains its crash reports
+    server_directory: PathBuf,
+    /// The last failure of the server
+    failure: LastFailure,
+    /// The performance monitor of the server process
+    performance: Performance,
 }
 
 impl LogService for BasicLogService {
-    fn manage_output(&mut self, out: ChildStdout, server_id: String) -> Arc<RwLock<OutputState>> {
+    fn manage_output(
+        &mut self,
+        out: ChildStdout,
+        err: ChildStderr,
+        server_id: String,
+        patterns: LogPatternSet,
+        log_config: LogConfig,
+        server_directory: PathBuf,
+    ) -> Arc<RwLock<OutputState>> {
         let state = Arc::new(RwLock::new(OutputState::Unknown));
+        let console = ConsoleBuffer::default();
+        self.consoles.insert(server_id.clone(), console.clone());
+        let players = OnlinePlayers::default();
+        self.players.insert(server_id.clone(), players.clone());
+        let failure = self.failures.entry(server_id.clone()).or_default().clone();
+        let performance = Performance::default();
+        self.performance
+            .insert(server_id.clone(), performance.clone());
         let handler = BasicLogServiceHandler {
             state: state.clone(),
             out,
+            err,
             event_handler: self.event_handler.clone(),
+            daemon_queue: self.daemon_queue.clone(),
             server_id,
+            console,
+            patterns,
+            players,
+            log_config,
+            server_directory,
+            failure,
+            performance,
         };
         handler.run();
         state
     }
+
+    fn console(&self, server_name: &str) -> Option<ConsoleBuffer> {
+        self.consoles.get(server_name).cloned()
+    }
+
+    fn online_players(&self, server_name: &str) -> Option<OnlinePlayers> {
+        self.players.get(server_name).cloned()
+    }
+
+    fn last_failure(&self, server_name: &str) -> Option<LastFailure> {
+        self.failures.get(server_name).cloned()
+    }
+
+    fn performance(&self, server_name: &str) -> Option<Performance> {
+        self.performance.get(server_name).cloned()
+    }
 }
 
 impl BasicLogServiceHandler {
@@ -66,81 +167,185 @@ impl BasicLogServiceHandler {
             let Self {
                 state,
                 out,
+                err,
                 mut event_handler,
+                daemon_queue,
                 server_id,
+                console,
+                mut patterns,
+                players,
+                log_config,
+                server_directory,
+                failure,
+                performance,
             } = self;
-            let reader = BufReader::new(out);
+            let started = SystemTime::now();
+            let mut exceptions = ExceptionTracker::default();
+            let collect_failure =
+                |error: String, stderr: &VecDeque<String>, exceptions: &ExceptionTracker| {
+                    let details = ServerFailure::collect(
+                        error,
+                        stderr.iter().cloned().collect(),
+                        exceptions,
+                        &server_directory,
+                        started,
+                    );
+                    *failure.write().expect("lock failure for write") = Some(details.clone());
+                    details
+                };
+            let (line_sender, lines) = channel();
+            forward_lines(out, LogStream::Stdout, line_sender.clone());
+            forward_lines(err, LogStream::Stderr, line_sender);
+            let mut stderr = VecDeque::with_capacity(STDERR_LINES);
 
-            let mut out_path = PathBuf::new();
-            out_path.push("log");
-            out_path.push(&server_id);
-            out_path.push(format!("{}_out.log", chrono::Utc::now()));
-            create_dir_all(out_path.parent().expect("get parent path"))
-                .expect("create dirs to log directory");
-            let mut writer = BufWriter::new(File::create(out_path).expect("create output file"));
+            // the output is still parsed if it cannot be stored
+            let mut writer =
+                match LogWriter::create(Path::new(LOG_DIRECTORY), &server_id, log_config) {
+                    Ok(writer) => Some(writer),
+                    Err(e) => {
+                        warn!("could not create log of server {}: {}", server_id, e);
+                        None
+                    }
+                };
+            let mut parser = RecordParser::default();
 
-            for line in reader.lines() {
+            // ends when both streams have been closed
+            for (stream, line) in lines {
                 match line {
                     Ok(line) => {
-                        writeln!(writer, "{}", line).unwrap();
-                        if line.starts_with("Loading libraries") {
-                            info!("server {} starting", server_id);
-                            *state.write().expect("lock rwlock for write").deref_mut() =
-                                OutputState::Starting;
-                            event_handler.raise_event(
-                                &server_id,
-                                ServerEvent::ServerStarting {
-                                    server_id: server_id.clone(),
-                                },
-                            )
-                        } else if line.contains("Done (") && line.contains("s)! For help") {
-                            info!("server {} started", server_id);
-                            *state.write().expect("lock rwlock for write").deref_mut() =
-                                OutputState::Started;
-                            event_handler.raise_event(
-                                &server_id,
-                                ServerEvent::ServerStarted {
-                                    server_id: server_id.clone(),
-                                },
-                            )
-                        } else if line.contains("Stopping the server") {
-                            info!("server {} stopping", server_id);
-                            *state.write().expect("lock rwlock for write").deref_mut() =
-                                OutputState::Stopping;
-                            event_handler.raise_event(
-                                &server_id,
-                                ServerEvent::ServerStopping {
-                                    server_id: server_id.clone(),
-                                },
-                            )
-                        } else if line.contains("Closing Server") {
-                            info!("server {} stopped", server_id);
-                            *state.write().expect("lock rwlock for write").deref_mut() =
-                                OutputState::Stopped;
-                            event_handler.raise_event(
-                                &server_id,
-                                ServerEvent::ServerStopped {
-                                    server_id: server_id.clone(),
-                                },
-                            )
-                        } else if line.contains("Failed to load eula.txt") {
-                            warn!("eula not accepted for unit {}", server_id);
-                            *state.write().expect("lock rwlock for write").deref_mut() =
-                                OutputState::Errored;
+                        let tagged = stream.tag(&line);
+                        let record = parser.parse(&line, stream);
+                        if let Some(Err(e)) =
+                            writer.as_mut().map(|w| w.write_line(&tagged, &record))
+                        {
+                            warn!("stopped writing log of server {}: {}", server_id, e);
+                            writer = None;
+                        }
+                        exceptions.push(&record);
+                        performance
+                            .write()
+                            .expect("lock performance for write")
+                            .push(&record);
+                        console.push(tagged, |_| {
                             event_handler.raise_event(
                                 &server_id,
-                                ServerEvent::ServerFailed {
+                                ServerEvent::ConsoleLine {
                                     server_id: server_id.clone(),
-                                    error: "EULA not accepted".to_string(),
+                                    line: line.clone(),
+                                    stream,
                                 },
                             )
+                        });
+                        if stream == LogStream::Stderr {
+                            if stderr.len() == STDERR_LINES {
+                                stderr.pop_front();
+                            }
+                            stderr.push_back(line.clone());
                         }
+                        for trigger in patterns.fire_triggers(&line) {
+                            let fired = DaemonEvent::TriggerFired {
+                                server_id: server_id.clone(),
+                                trigger,
+                            };
+                            if daemon_queue.send(fired).is_err() {
+                                warn!("could not pass fired trigger of server {}", server_id);
+                            }
+                        }
+                        let log_match = match patterns.match_line(&line) {
+                            Some(log_match) => log_match,
+                            None => continue,
+                        };
+                        match log_match.state() {
+                            Some(new_state) => {
+                                let mut state = state.write().expect("lock rwlock for write");
+                                // an errored server can not change its state by log output
+                                if let OutputState::Errored = *state {
+                                    continue;
+                                }
+                                if !log_match.applies_to(&state) {
+                                    continue;
+                                }
+                                match log_match.action {
+                                    LogAction::Failed => warn!(
+                                        "server {} failed: {}",
+                                        server_id,
+                                        log_match.error.as_deref().unwrap_or_default()
+                                    ),
+                                    action => info!("server {} {:?}", server_id, action),
+                                }
+                                if let OutputState::Stopped = new_state {
+                                    players.write().expect("lock players for write").clear();
+                                }
+                                *state.deref_mut() = new_state;
+                            }
+                            None => {
+                                let player = log_match.player.clone().unwrap_or_default();
+                                let mut players = players.write().expect("lock players for write");
+                                match log_match.action {
+                                    LogAction::PlayerJoined => {
+                                        players.insert(player);
+                                    }
+                                    LogAction::PlayerLeft => {
+                                        players.remove(&player);
+                                    }
+                                    _ => (),
+                                }
+                            }
+                        }
+                        let event = match log_match.action {
+                            LogAction::Failed => collect_failure(
+                                log_match.error.clone().unwrap_or_default(),
+                                &stderr,
+                                &exceptions,
+                            )
+                            .event(&server_id),
+                            _ => log_match.event(&server_id),
+                        };
+                        event_handler.raise_event(&server_id, event);
                     }
+                    Err(e) => {
+                        warn!("failed to read {:?} of server {}: {}", stream, server_id, e);
+                    }
+                }
+            }
+            players.write().expect("lock players for write").clear();
+            // the output ends when the process exits, it should have stopped before
+            let crashed = {
+                let mut state = state.write().expect("lock rwlock for write");
+                match *state {
+                    OutputState::Stopped | OutputState::Errored => false,
                     _ => {
-                        break;
+                        *state = OutputState::Errored;
+                        true
                     }
                 }
+            };
+            if crashed {
+                let error = "server process has exited unexpectedly".to_string();
+                warn!("server {} failed: {}", server_id, error);
+                let details = collect_failure(error, &stderr, &exceptions);
+                event_handler.raise_event(&server_id, details.event(&server_id));
+            }
+            if let Some(Err(e)) = writer.map(LogWriter::close) {
+                warn!("failed to close log of server {}: {}", server_id, e);
             }
         });
     }
 }
+
+/// Spawns a thread which reads the lines of an output stream of a process and sends them, tagged
+/// with the stream, until the stream is closed or cannot be read anymore.
+fn forward_lines<R: Read + Send + 'static>(
+    input: R,
+    stream: LogStream,
+    sender: Sender<(LogStream, std::io::Result<String>)>,
+) {
+    spawn(move || {
+        for line in BufReader::new(input).lines() {
+            let failed = line.is_err();
+            if sender.send((stream, line)).is_err() || failed {
+                break;
+            }
+        }
+    });
+}
@@ -0,0 +1,507 @@
+//! Deduplicating repository of content-defined chunks, used by chunked backups.
+//!
+//! Files are split into chunks at positions that depend on their content (a gear rolling hash, as
+//! in FastCDC), so a change in a file only affects the chunks around it and the chunks of an
+//! unchanged region file are the same in every backup. A chunk is identified by the SHA-256 hash
+//! of its content and stored once, zstd compressed, as `<root>/.chunks/<xx>/<hash>` where `xx`
+//! are the first two digits of the hash.
+//!
+//! A backup is a [`Manifest`] that lists its files with the ids of their chunks. Deleting a
+//! manifest does not delete chunks, [`ChunkStore::collect_garbage`] deletes the chunks that no
+//! manifest refers to anymore.
+
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, create_dir_all, read_dir, remove_file, rename, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The directory in the backup root that contains the chunks.
+pub const CHUNK_DIRECTORY: &str = ".chunks";
+
+/// The extension of chunks and manifests that are being written.
+const TEMPORARY_EXTENSION: &str = "tmp";
+
+/// Chunks are at least this long, unless they end a file.
+const MIN_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Chunks are at most this long.
+const MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+/// A chunk ends where the rolling hash has none of these bits set, every 64 KiB on average after
+/// the minimum size.
+const CUT_MASK: u64 = 0xffff << 48;
+
+/// The random values the rolling hash adds per byte.
+const GEAR: [u64; 256] = gear_table();
+
+/// Distinguishes the temporary files of chunks that are written at the same time.
+static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// The files of a chunked backup.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Manifest {
+    /// The files and directories, a directory is listed before its content
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// A file or directory of a chunked backup.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ManifestEntry {
+    /// A directory
+    Directory {
+        /// The path in the backup
+        name: PathBuf,
+    },
+    /// A regular file
+    File {
+        /// The path in the backup
+        name: PathBuf,
+        /// The size of the file in bytes
+        size: u64,
+        /// The ids of the chunks of the content, in order
+        chunks: Vec<String>,
+    },
+    /// A symbolic link
+    Symlink {
+        /// The path in the backup
+        name: PathBuf,
+        /// The target of the link
+        target: PathBuf,
+    },
+}
+
+/// Splits the content of a reader into chunks.
+pub struct Chunker<R> {
+    /// The reader
+    reader: R,
+    /// The bytes that have been read but not returned yet
+    buffer: Vec<u8>,
+    /// Whether the reader is exhausted
+    eof: bool,
+}
+
+/// The chunks in a directory of the backup root.
+pub struct ChunkStore {
+    /// The directory that contains the chunks
+    directory: PathBuf,
+}
+
+impl Manifest {
+    /// Reads a zstd compressed manifest.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let json = zstd::decode_all(File::open(path)?)?;
+        serde_json::from_slice(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Writes the manifest zstd compressed, an interrupted write leaves no manifest behind.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec(self).expect("serialize manifest");
+        let temporary = path.with_extension(TEMPORARY_EXTENSION);
+        fs::write(&temporary, zstd::encode_all(json.as_slice(), 0)?)?;
+        File::open(&temporary)?.sync_all()?;
+        rename(&temporary, path)
+    }
+
+    /// Returns the ids of the chunks the manifest refers to.
+    pub fn chunk_ids(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().flat_map(|entry| match entry {
+            ManifestEntry::File { chunks, .. } => chunks.iter(),
+            _ => [].iter(),
+        })
+    }
+
+    /// Returns the total size of the files.
+    pub fn size(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| match entry {
+                ManifestEntry::File { size, .. } => *size,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+impl<R: Read> Chunker<R> {
+    /// Creates a chunker that reads from `reader`.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: Vec::with_capacity(MAX_CHUNK_SIZE),
+            eof: false,
+        }
+    }
+
+    /// Returns the next chunk, `None` at the end of the content.
+    pub fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        while !self.eof && self.buffer.len() < MAX_CHUNK_SIZE {
+            let start = self.buffer.len();
+            self.buffer.resize(MAX_CHUNK_SIZE, 0);
+            let read = match self.reader.read(&mut self.buffer[start..]) {
+                Ok(read) => read,
+                Err(e) => {
+                    self.buffer.truncate(start);
+                    if e.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(e);
+                }
+            };
+            self.buffer.truncate(start + read);
+            self.eof = read == 0;
+        }
+        if self.buffer.is_empty() {
+            return Ok(None);
+        }
+        let rest = self.buffer.split_off(cut_point(&self.buffer));
+        Ok(Some(std::mem::replace(&mut self.buffer, rest)))
+    }
+}
+
+impl ChunkStore {
+    /// Opens the chunks in the backup root `root`.
+    pub fn new(root: &Path) -> Self {
+        Self {
+            directory: root.join(CHUNK_DIRECTORY),
+        }
+    }
+
+    /// Returns the path of a chunk.
+    fn chunk_path(&self, id: &str) -> PathBuf {
+        self.directory.join(&id[..2]).join(id)
+    }
+
+    /// Stores a chunk unless it is stored already.
+    ///
+    /// Returns the id of the chunk and the number of bytes that have been written, which is `0`
+    /// if the chunk has been stored before.
+    pub fn store(&self, data: &[u8]) -> io::Result<(String, u64)> {
+        let id = chunk_id(data);
+        let path = self.chunk_path(&id);
+        if path.exists() {
+            return Ok((id, 0));
+        }
+        create_dir_all(path.parent().expect("chunk has a parent directory"))?;
+        let compressed = zstd::encode_all(data, 0)?;
+        let temporary = path.with_extension(format!(
+            "{}.{}",
+            TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed),
+            TEMPORARY_EXTENSION
+        ));
+        let mut file = File::create(&temporary)?;
+        file.write_all(&compressed)?;
+        file.sync_all()?;
+        rename(&temporary, &path)?;
+        Ok((id, compressed.len() as u64))
+    }
+
+    /// Reads a chunk and checks that its content matches its id.
+    pub fn load(&self, id: &str) -> io::Result<Vec<u8>> {
+        if id.len() != 64 || !id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid chunk id {:?}", id),
+            ));
+        }
+        let file = match File::open(self.chunk_path(id)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("chunk {} is missing", id),
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+        let corrupt = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("chunk {} is corrupt", id),
+            )
+        };
+        let data = zstd::decode_all(file).map_err(|_| corrupt())?;
+        if chunk_id(&data) == id {
+            Ok(data)
+        } else {
+            Err(corrupt())
+        }
+    }
+
+    /// Splits a file into chunks and stores them.
+    ///
+    /// `progress` is called with the number of bytes of each chunk. Returns the ids of the chunks
+    /// and the number of bytes that have been written.
+    pub fn store_file(
+        &self,
+        path: &Path,
+        progress: &mut dyn FnMut(u64),
+    ) -> io::Result<(Vec<String>, u64)> {
+        let mut chunker = Chunker::new(File::open(path)?);
+        let mut chunks = vec![];
+        let mut written = 0;
+        while let Some(chunk) = chunker.next_chunk()? {
+            let (id, chunk_written) = self.store(&chunk)?;
+            chunks.push(id);
+            written += chunk_written;
+            progress(chunk.len() as u64);
+        }
+        Ok((chunks, written))
+    }
+
+    /// Writes the content of a file from its chunks.
+    ///
+    /// `progress` is called with the number of bytes of each chunk.
+    pub fn restore_file(
+        &self,
+        chunks: &[String],
+        path: &Path,
+        progress: &mut dyn FnMut(u64),
+    ) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        for id in chunks {
+            let chunk = self.load(id)?;
+            file.write_all(&chunk)?;
+            progress(chunk.len() as u64);
+        }
+        file.sync_all()
+    }
+
+    /// Checks the chunks of a manifest and returns the problems that have been found.
+    ///
+    /// `verified` maps the ids of intact chunks to their length. Chunks in it are not read again,
+    /// the chunks that are found intact are added to it, so chunks that are shared between
+    /// backups are only read once.
+    pub fn verify(&self, manifest: &Manifest, verified: &mut HashMap<String, u64>) -> Vec<String> {
+        let mut problems = vec![];
+        for entry in &manifest.entries {
+            if let ManifestEntry::File { name, size, chunks } = entry {
+                let mut length = Some(0);
+                for id in chunks {
+                    let chunk_length = match verified.get(id) {
+                        Some(chunk_length) => Some(*chunk_length),
+                        None => match self.load(id) {
+                            Ok(chunk) => {
+                                verified.insert(id.clone(), chunk.len() as u64);
+                                Some(chunk.len() as u64)
+                            }
+                            Err(e) => {
+                                problems.push(format!("{}: {}", name.display(), e));
+                                None
+                            }
+                        },
+                    };
+                    length = length.and_then(|length| Some(length + chunk_length?));
+                }
+                if let Some(length) = length.filter(|length| length != size) {
+                    problems.push(format!(
+                        "{}: the chunks contain {} bytes instead of {}",
+                        name.display(),
+                        length,
+                        size
+                    ));
+                }
+            }
+        }
+        problems
+    }
+
+    /// Deletes the chunks that are not in `referenced` and the files of interrupted writes.
+    ///
+    /// No backup may be written while the garbage is collected. If `dry_run` is set, nothing is
+    /// deleted. Returns the number of deleted chunks and their size in bytes.
+    pub fn collect_garbage(
+        &self,
+        referenced: &HashSet<String>,
+        dry_run: bool,
+    ) -> io::Result<(usize, u64)> {
+        if !self.directory.is_dir() {
+            return Ok((0, 0));
+        }
+        let mut chunks = 0;
+        let mut size = 0;
+        for prefix in read_dir(&self.directory)? {
+            let prefix = prefix?.path();
+            if !prefix.is_dir() {
+                continue;
+            }
+            for entry in read_dir(&prefix)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                let temporary = name.ends_with(TEMPORARY_EXTENSION);
+                if !temporary && referenced.contains(&name) {
+                    continue;
+                }
+                if !temporary {
+                    chunks += 1;
+                    size += entry.metadata()?.len();
+                }
+                if !dry_run {
+                    remove_file(entry.path())?;
+                }
+            }
+        }
+        Ok((chunks, size))
+    }
+}
+
+/// Returns the id of a chunk, the hex encoded SHA-256 hash of its content.
+fn chunk_id(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Returns the length of the first chunk of `data`.
+///
+/// `data` must contain [`MAX_CHUNK_SIZE`] bytes unless it is the end of the content.
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK_SIZE);
+    let mut hash: u64 = 0;
+    for (index, byte) in data[MIN_CHUNK_SIZE..end].iter().enumerate() {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        if hash & CUT_MASK == 0 {
+            return MIN_CHUNK_SIZE + index + 1;
+        }
+    }
+    end
+}
+
+/// Generates the values of the rolling hash with splitmix64, they must never change, otherwise
+/// the chunks of new backups would not match the stored chunks.
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0;
+    let mut index = 0;
+    while index < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut value = state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[index] = value ^ (value >> 31);
+        index += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::chunks::{ChunkStore, Chunker, Manifest, ManifestEntry, MAX_CHUNK_SIZE};
+    use std::collections::{HashMap, HashSet};
+    use std::fs::{read, write};
+    use std::path::PathBuf;
+
+    /// Returns reproducible pseudo-random bytes.
+    fn random_bytes(length: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed | 1;
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunks(data: &[u8]) -> Vec<Vec<u8>> {
+        let mut chunker = Chunker::new(data);
+        let mut chunks = vec![];
+        while let Some(chunk) = chunker.next_chunk().expect("read chunk") {
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_chunker() {
+        assert!(chunks(&[]).is_empty());
+        assert_eq!(chunks(&[1, 2, 3]), vec![vec![1, 2, 3]]);
+
+        let data = random_bytes(4 * 1024 * 1024, 7);
+        let original = chunks(&data);
+        assert_eq!(original.concat(), data);
+        assert!(original.len() > 16, "{} chunks", original.len());
+        assert!(original.iter().all(|chunk| chunk.len() <= MAX_CHUNK_SIZE));
+
+        // inserting bytes only changes the chunks around the insertion
+        let mut changed = data.clone();
+        changed.splice(1_000_000..1_000_000, vec![42; 100]);
+        let changed = chunks(&changed);
+        let original: HashSet<&Vec<u8>> = original.iter().collect();
+        let shared = changed
+            .iter()
+            .filter(|chunk| original.contains(chunk))
+            .count();
+        assert!(
+            shared + 3 >= changed.len(),
+            "{} of {}",
+            shared,
+            changed.len()
+        );
+    }
+
+    #[test]
+    fn test_store_and_collect_garbage() {
+        let root = tempfile::tempdir().expect("create temp dir");
+        let store = ChunkStore::new(root.path());
+        let file = root.path().join("region.mca");
+        let data = random_bytes(1024 * 1024, 3);
+        write(&file, &data).expect("write file");
+
+        let mut stored = 0;
+        let (ids, written) = store
+            .store_file(&file, &mut |length| stored += length)
+            .expect("store file");
+        assert!(written > 0);
+        assert_eq!(stored, data.len() as u64);
+        let (same, written) = store.store_file(&file, &mut |_| {}).expect("store file");
+        assert_eq!(same, ids);
+        assert_eq!(written, 0);
+
+        let restored = root.path().join("restored.mca");
+        store
+            .restore_file(&ids, &restored, &mut |_| {})
+            .expect("restore file");
+        assert_eq!(read(&restored).expect("read restored file"), data);
+
+        let manifest = Manifest {
+            entries: vec![ManifestEntry::File {
+                name: PathBuf::from("world/region.mca"),
+                size: data.len() as u64,
+                chunks: ids.clone(),
+            }],
+        };
+        let mut verified = HashMap::new();
+        assert!(store.verify(&manifest, &mut verified).is_empty());
+        assert_eq!(verified.len(), ids.len());
+        write(store.chunk_path(&ids[0]), b"garbage").expect("corrupt chunk");
+        let problems = store.verify(&manifest, &mut HashMap::new());
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("corrupt"), "{}", problems[0]);
+
+        let referenced: HashSet<String> = ids[1..].iter().cloned().collect();
+        let (deleted, _) = store
+            .collect_garbage(&referenced, true)
+            .expect("collect garbage");
+        assert_eq!(deleted, 1);
+        assert!(store.chunk_path(&ids[0]).exists());
+        store
+            .collect_garbage(&referenced, false)
+            .expect("collect garbage");
+        assert!(!store.chunk_path(&ids[0]).exists());
+        assert!(store.load(&ids[1]).is_ok());
+        assert_eq!(
+            store
+                .collect_garbage(&HashSet::new(), false)
+                .expect("collect garbage")
+                .0,
+            ids.len() - 1
+        );
+    }
+}
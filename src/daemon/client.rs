@@ -0,0 +1,196 @@
+//! Bookkeeping for the clients that are currently connected to the daemon.
+//!
+//! Clients are either connected over the IPC socket (e.g. `mcman`) or live inside the daemon
+//! process itself (e.g. the HTTP interface). Both are addressed by a numeric client id.
+
+use crate::ipc::DaemonResponse;
+use ipc_channel::ipc::IpcSender;
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+/// The channel over which responses are delivered to a client.
+pub enum ClientConnection {
+    /// A client connected over the IPC socket
+    Ipc(IpcSender<DaemonResponse>),
+    /// A client running inside the daemon process
+    Local(Sender<DaemonResponse>),
+}
+
+impl ClientConnection {
+    /// Send a response to the client.
+    ///
+    /// Returns `false` if the client could not be reached anymore.
+    pub fn send(&self, response: DaemonResponse) -> bool {
+        match self {
+            ClientConnection::Ipc(sender) => sender.send(response).is_ok(),
+            ClientConnection::Local(sender) => sender.send(response).is_ok(),
+        }
+    }
+}
+
+/// A connected client.
+struct Client {
+    /// The name the client has announced when connecting
+    name: String,
+    /// The user id of the process on the other end of the IPC socket, if known
+    uid: Option<u32>,
+    /// The connection to the client
+    connection: ClientConnection,
+}
+
+/// Thread safe registry of all connected clients.
+///
+/// Cloning the registry yields a handle to the same set of clients.
+#[derive(Clone)]
+pub struct ClientRegistry {
+    /// The currently connected clients by their id
+    clients: Arc<Mutex<HashMap<u32, Client>>>,
+    /// The id that is assigned to the next client
+    next_id: Arc<AtomicU32>,
+}
+
+impl ClientRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self {
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            next_id: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// Registers a new client and returns the id assigned to it.
+    pub fn register(&self, name: String, connection: ClientConnection) -> u32 {
+        self.register_with_uid(name, None, connection)
+    }
+
+    /// Registers a new client whose user id is known and returns the id assigned to it.
+    pub fn register_with_uid(
+        &self,
+        name: String,
+        uid: Option<u32>,
+        connection: ClientConnection,
+    ) -> u32 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.clients.lock().expect("lock client registry").insert(
+            id,
+            Client {
+                name,
+                uid,
+                connection,
+            },
+        );
+        id
+    }
+
+    /// Removes a client from the registry.
+    pub fn unregister(&self, client_id: u32) {
+        self.clients
+            .lock()
+            .expect("lock client registry")
+            .remove(&client_id);
+    }
+
+    /// Sends a response to the client with the given id.
+    ///
+    /// Returns `false` if the client is unknown or could not be reached.
+    pub fn send(&self, client_id: u32, response: DaemonResponse) -> bool {
+        match self
+            .clients
+            .lock()
+            .expect("lock client registry")
+            .get(&client_id)
+        {
+            Some(client) => client.connection.send(response),
+            None => false,
+        }
+    }
+
+    /// Sends a response to every connected client, ignoring clients that can not be reached.
+    pub fn broadcast(&self, response: DaemonResponse) {
+        for client in self.clients.lock().expect("lock client registry").values() {
+            let _ = client.connection.send(response.clone());
+        }
+    }
+
+    /// Returns the name of the client with the given id.
+    pub fn client_name(&self, client_id: u32) -> Option<String> {
+        self.clients
+            .lock()
+            .expect("lock client registry")
+            .get(&client_id)
+            .map(|client| client.name.clone())
+    }
+
+    /// Returns the user id of the client with the given id, if it is known.
+    pub fn client_uid(&self, client_id: u32) -> Option<u32> {
+        self.clients
+            .lock()
+            .expect("lock client registry")
+            .get(&client_id)
+            .and_then(|client| client.uid)
+    }
+
+    /// Returns the number of clients that are connected over the IPC socket.
+    pub fn ipc_len(&self) -> usize {
+        self.clients
+            .lock()
+            .expect("lock client registry")
+            .values()
+            .filter(|client| matches!(client.connection, ClientConnection::Ipc(_)))
+            .count()
+    }
+
+    /// Returns the number of currently connected clients.
+    pub fn len(&self) -> usize {
+        self.clients.lock().expect("lock client registry").len()
+    }
+
+    /// Returns `true` if no client is connected.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for ClientRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the user id of the process connected to the other end of a unix socket.
+#[cfg(target_os = "linux")]
+pub fn peer_uid(socket: &impl AsRawFd) -> Option<u32> {
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: `credentials` and `length` are valid for writes and `length` is the size of
+    // `credentials`.
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    if result == 0 {
+        Some(credentials.uid)
+    } else {
+        None
+    }
+}
+
+/// Returns the user id of the process connected to the other end of a unix socket.
+///
+/// Peer credentials are only supported on Linux, so this always returns `None`.
+#[cfg(not(target_os = "linux"))]
+pub fn peer_uid(_socket: &impl AsRawFd) -> Option<u32> {
+    None
+}
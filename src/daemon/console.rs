@@ -0,0 +1,100 @@
+//! Buffering of the console output of servers for attached clients.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// The number of output lines kept per server.
+pub const CONSOLE_BUFFER_SIZE: usize = 1000;
+
+/// The output stream of a server process a line was written to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogStream {
+    /// The standard output of the process
+    #[default]
+    Stdout,
+    /// The standard error of the process
+    Stderr,
+}
+
+impl LogStream {
+    /// Returns the line as it is written to the log: lines from stderr are prefixed with
+    /// `[stderr] `, lines from stdout are unchanged.
+    pub fn tag(self, line: &str) -> String {
+        match self {
+            LogStream::Stdout => line.to_string(),
+            LogStream::Stderr => format!("[stderr] {}", line),
+        }
+    }
+}
+
+/// A bounded buffer of the most recent output lines of a server process.
+///
+/// Adding a line and announcing it happen under the same lock as taking a backlog and subscribing
+/// to new lines. This way an attaching client receives every line exactly once, either in the
+/// backlog or as an event.
+#[derive(Clone)]
+pub struct ConsoleBuffer {
+    /// The buffered lines, oldest first
+    lines: Arc<Mutex<VecDeque<String>>>,
+    /// The maximum number of buffered lines
+    capacity: usize,
+}
+
+impl ConsoleBuffer {
+    /// Creates an empty buffer holding at most `capacity` lines.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Adds a line to the buffer and calls `announce` with it while the buffer is locked.
+    pub fn push<F: FnOnce(&str)>(&self, line: String, announce: F) {
+        let mut lines = self.lines.lock().expect("lock console buffer");
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        announce(&line);
+        lines.push_back(line);
+    }
+
+    /// Returns up to `count` of the most recent lines and calls `subscribe` while the buffer is
+    /// locked.
+    pub fn backlog<F: FnOnce()>(&self, count: usize, subscribe: F) -> Vec<String> {
+        let lines = self.lines.lock().expect("lock console buffer");
+        subscribe();
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for ConsoleBuffer {
+    fn default() -> Self {
+        Self::new(CONSOLE_BUFFER_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::console::ConsoleBuffer;
+
+    #[test]
+    fn test_backlog() {
+        let buffer = ConsoleBuffer::new(3);
+        let mut announced = vec![];
+        for line in &["a", "b", "c", "d"] {
+            buffer.push(line.to_string(), |line| announced.push(line.to_string()));
+        }
+        assert_eq!(announced, vec!["a", "b", "c", "d"]);
+
+        let mut subscribed = false;
+        assert_eq!(buffer.backlog(2, || subscribed = true), vec!["c", "d"]);
+        assert!(subscribed);
+        assert_eq!(buffer.backlog(10, || ()), vec!["b", "c", "d"]);
+    }
+}
@@ -0,0 +1,244 @@
+//! Collection of the details of server failures.
+//!
+//! When a server fails, the daemon attaches the last exception stack trace from its output and
+//! the newest crash report written since the process has started. Crash reports are the reports
+//! of Minecraft in `crash-reports/` and the fatal error logs of the JVM (`hs_err_pid*.log`) in the
+//! server directory.
+
+use crate::daemon::records::LogRecord;
+use crate::ipc::ServerEvent;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use std::fs::{read_dir, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::SystemTime;
+
+/// The number of lines of a crash report that are attached to a failure.
+pub const CRASH_REPORT_LINES: usize = 20;
+
+/// The maximum number of lines of an exception stack trace that are kept.
+pub const EXCEPTION_LINES: usize = 30;
+
+/// The first line of an exception stack trace, e.g. `java.lang.IllegalStateException: message`.
+const EXCEPTION_PATTERN: &str = r#"^(Exception in thread "[^"]*" )?([A-Za-z_$][\w$]*\.)+[\w$]*(Exception|Error|Throwable)(: .*)?$"#;
+
+/// The start of a crash report of the server or the JVM.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    /// The path of the report file
+    pub path: String,
+    /// The first lines of the report
+    pub lines: Vec<String>,
+}
+
+/// The reason and the details of the last failure of a server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerFailure {
+    /// The time at which the failure has been detected
+    pub time: DateTime<Utc>,
+    /// The reason of the failure
+    pub error: String,
+    /// The last lines the server process has written to stderr
+    pub stderr: Vec<String>,
+    /// The last exception stack trace in the output of the server
+    pub exception: Vec<String>,
+    /// The newest crash report written by the server process
+    pub crash_report: Option<CrashReport>,
+}
+
+impl ServerFailure {
+    /// Collects the details of a failure that has just been detected.
+    ///
+    /// `started` is the start time of the server process, older crash reports are ignored.
+    pub fn collect(
+        error: String,
+        stderr: Vec<String>,
+        exceptions: &ExceptionTracker,
+        server_directory: &Path,
+        started: SystemTime,
+    ) -> Self {
+        Self {
+            time: Utc::now(),
+            error,
+            stderr,
+            exception: exceptions.last(),
+            crash_report: find_crash_report(server_directory, started),
+        }
+    }
+
+    /// Returns the [`ServerEvent::ServerFailed`] event for this failure.
+    pub fn event(&self, server_id: &str) -> ServerEvent {
+        ServerEvent::ServerFailed {
+            server_id: server_id.to_string(),
+            error: self.error.clone(),
+            stderr: self.stderr.clone(),
+            exception: self.exception.clone(),
+            crash_report: self.crash_report.clone(),
+        }
+    }
+}
+
+/// The last failure of a server, shared between the log service and the daemon.
+pub type LastFailure = Arc<RwLock<Option<ServerFailure>>>;
+
+/// Keeps track of the exception stack traces in the output of a server process.
+#[derive(Default)]
+pub struct ExceptionTracker {
+    /// The stack trace that is currently being written
+    current: Vec<String>,
+    /// The last complete stack trace
+    last: Vec<String>,
+}
+
+impl ExceptionTracker {
+    /// Processes the next output line of the server.
+    pub fn push(&mut self, record: &LogRecord) {
+        let message = record.message.as_str();
+        if exception_regex().is_match(message) {
+            self.finish();
+            self.current.push(message.to_string());
+        } else if !self.current.is_empty() && is_stack_trace_line(message) {
+            if self.current.len() < EXCEPTION_LINES {
+                self.current.push(message.to_string());
+            }
+        } else {
+            self.finish();
+        }
+    }
+
+    /// Returns the most recent stack trace, empty if the server has not written any.
+    pub fn last(&self) -> Vec<String> {
+        if self.current.is_empty() {
+            self.last.clone()
+        } else {
+            self.current.clone()
+        }
+    }
+
+    /// Completes the stack trace that is currently being written.
+    fn finish(&mut self) {
+        if !self.current.is_empty() {
+            self.last = std::mem::take(&mut self.current);
+        }
+    }
+}
+
+/// Returns whether a line continues a stack trace.
+fn is_stack_trace_line(message: &str) -> bool {
+    let trimmed = message.trim_start();
+    let indented = message.starts_with(char::is_whitespace);
+    (indented && (trimmed.starts_with("at ") || trimmed.starts_with("...")))
+        || message.starts_with("Caused by: ")
+        || message.starts_with("Suppressed: ")
+}
+
+/// Returns the compiled [`EXCEPTION_PATTERN`].
+fn exception_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(EXCEPTION_PATTERN).expect("compile exception pattern"))
+}
+
+/// Returns the newest crash report in the server directory that has been written at or after
+/// `since`.
+pub fn find_crash_report(server_directory: &Path, since: SystemTime) -> Option<CrashReport> {
+    let mut candidates = report_files(&server_directory.join("crash-reports"), |name| {
+        name.starts_with("crash-") && name.ends_with(".txt")
+    });
+    candidates.extend(report_files(server_directory, |name| {
+        name.starts_with("hs_err_pid") && name.ends_with(".log")
+    }));
+    let (path, _) = candidates
+        .into_iter()
+        .filter(|(_, modified)| *modified >= since)
+        .max_by_key(|(_, modified)| *modified)?;
+    let lines = BufReader::new(File::open(&path).ok()?)
+        .lines()
+        .take(CRASH_REPORT_LINES)
+        .collect::<Result<Vec<String>, _>>()
+        .ok()?;
+    Some(CrashReport {
+        path: path.to_string_lossy().to_string(),
+        lines,
+    })
+}
+
+/// Lists the files in a directory whose name is accepted by `filter`, with their modification
+/// time. A missing directory has no files.
+fn report_files<F: Fn(&str) -> bool>(directory: &Path, filter: F) -> Vec<(PathBuf, SystemTime)> {
+    let entries = match read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| filter(&entry.file_name().to_string_lossy()))
+        .filter_map(|entry| Some((entry.path(), entry.metadata().ok()?.modified().ok()?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::console::LogStream;
+    use crate::daemon::crash::{find_crash_report, ExceptionTracker};
+    use crate::daemon::records::RecordParser;
+    use std::fs::{create_dir_all, write};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_exception_tracker() {
+        let mut parser = RecordParser::default();
+        let mut tracker = ExceptionTracker::default();
+        for line in &[
+            "[12:00:00 ERROR]: Encountered an unexpected exception",
+            "java.lang.IllegalStateException: Chunk not loaded",
+            "\tat net.minecraft.server.World.getChunk(World.java:12)",
+            "\t... 3 more",
+            "Caused by: java.lang.NullPointerException",
+            "\tat net.minecraft.server.Chunk.load(Chunk.java:5)",
+            "[12:00:01 INFO]: Stopping server",
+        ] {
+            tracker.push(&parser.parse(line, LogStream::Stdout));
+        }
+        assert_eq!(
+            tracker.last(),
+            vec![
+                "java.lang.IllegalStateException: Chunk not loaded",
+                "\tat net.minecraft.server.World.getChunk(World.java:12)",
+                "\t... 3 more",
+                "Caused by: java.lang.NullPointerException",
+                "\tat net.minecraft.server.Chunk.load(Chunk.java:5)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_crash_report() {
+        let server_directory = tempfile::tempdir().expect("create temp dir");
+        let since = SystemTime::now() - Duration::from_secs(60);
+        assert!(find_crash_report(server_directory.path(), since).is_none());
+
+        create_dir_all(server_directory.path().join("crash-reports")).expect("create dir");
+        write(
+            server_directory
+                .path()
+                .join("crash-reports/crash-2021-01-01_12.00.00-server.txt"),
+            "---- Minecraft Crash Report ----\n// Oops.\n\nDescription: Ticking entity\n",
+        )
+        .expect("write crash report");
+        let report = find_crash_report(server_directory.path(), since).expect("find report");
+        assert!(report
+            .path
+            .ends_with("crash-2021-01-01_12.00.00-server.txt"));
+        assert_eq!(report.lines[0], "---- Minecraft Crash Report ----");
+        assert_eq!(report.lines.len(), 4);
+
+        // reports of earlier processes are ignored
+        assert!(find_crash_report(
+            server_directory.path(),
+            SystemTime::now() + Duration::from_secs(60)
+        )
+        .is_none());
+    }
+}
@@ -1,23 +1,37 @@
 //! This module contains structs and traits for event management in the daemon.
 //!
-//! The events should be reduced to events, which could be associated with a server, but the
-//! framework is generic enough to also allow daemon events.
+//! Most events are associated with a server. Daemon events (e.g. a client has connected) are
+//! dispatched without a server id and are only delivered to subscriptions for all servers.
 
+use crate::daemon::journal::{JournalEntry, JournalRecord, SharedJournal};
 use crate::daemon::DaemonEvent;
 use crate::ipc::{ServerEvent, ServerEventType};
 use log::{debug, info};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread::spawn;
 
+/// The number of events that have been raised but not yet dispatched by the event manager.
+static PENDING_EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of events that wait in the queue of the event manager.
+pub fn pending_events() -> usize {
+    PENDING_EVENTS.load(Ordering::Relaxed)
+}
+
 /// Manages subscriptions and the dispatch of events.
 pub struct EventManager {
-    /// The subscriptions which are currently handled
+    /// The subscriptions for events of a specific server
     subscriptions: HashMap<(String, ServerEventType), Vec<u32>>,
+    /// The subscriptions for events of all servers and the daemon itself
+    wildcard_subscriptions: HashMap<ServerEventType, Vec<u32>>,
     /// Incoming queue for events
     cmd_queue: Receiver<EventManagerCmd>,
     /// Sender to daemon (used for sending to clients)
     daemon_sender: Sender<DaemonEvent>,
+    /// The journal every dispatched event is recorded in
+    journal: Option<SharedJournal>,
 }
 
 impl EventManager {
@@ -25,11 +39,18 @@ impl EventManager {
     pub fn new(cmd_queue: Receiver<EventManagerCmd>, daemon_sender: Sender<DaemonEvent>) -> Self {
         Self {
             subscriptions: HashMap::new(),
+            wildcard_subscriptions: HashMap::new(),
             cmd_queue,
             daemon_sender,
+            journal: None,
         }
     }
 
+    /// Records all dispatched events in the given journal.
+    pub fn with_journal(self, journal: Option<SharedJournal>) -> Self {
+        Self { journal, ..self }
+    }
+
     /// Spawns a new thread and dispatches incoming events.
     pub fn run(mut self) {
         debug!("spawning thread for EventManager");
@@ -39,39 +60,44 @@ impl EventManager {
                 debug!("incoming EventManager cmd: {:?}", cmd);
                 match cmd {
                     EventManagerCmd::DispatchEvent { server_id, event } => {
-                        let subscriptions =
-                            self.subscriptions.get(&(server_id, event.get_event_type()));
-                        if let Some(subscriptions) = subscriptions {
-                            for subscription in subscriptions {
-                                self.daemon_sender
-                                    .send(DaemonEvent::SendEvent {
-                                        client_id: *subscription,
-                                        event: event.clone(),
-                                    })
-                                    .expect("send to daemon main event queue");
+                        PENDING_EVENTS.fetch_sub(1, Ordering::Relaxed);
+                        if let Some(journal) = &self.journal {
+                            if JournalRecord::is_recorded(&event) {
+                                journal
+                                    .lock()
+                                    .expect("lock journal")
+                                    .record_or_warn(&JournalEntry::event(event.clone()));
                             }
                         }
+                        for client_id in
+                            self.recipients(server_id.as_deref(), &event.get_event_type())
+                        {
+                            self.daemon_sender
+                                .send(DaemonEvent::SendEvent {
+                                    client_id,
+                                    event: event.clone(),
+                                })
+                                .expect("send to daemon main event queue");
+                        }
                     }
                     EventManagerCmd::AddSubscription {
                         server_id,
                         event_type,
                         client_id,
                     } => {
-                        let subscriptions = self
-                            .subscriptions
-                            .entry((server_id, event_type))
-                            .or_default();
-                        subscriptions.push(client_id);
+                        self.add_subscription(server_id, event_type, client_id);
                     }
                     EventManagerCmd::RemoveSubscription {
                         server_id,
                         event_type,
                         client_id,
                     } => {
-                        if let Some(subscriptions) =
-                            self.subscriptions.get_mut(&(server_id, event_type))
-                        {
-                            subscriptions.retain(|id| id == &client_id);
+                        let subscriptions = match server_id {
+                            Some(server_id) => self.subscriptions.get_mut(&(server_id, event_type)),
+                            None => self.wildcard_subscriptions.get_mut(&event_type),
+                        };
+                        if let Some(subscriptions) = subscriptions {
+                            subscriptions.retain(|id| id != &client_id);
                         }
                     }
                     EventManagerCmd::RemoveAllSubscriptions { client_id } => {
@@ -83,10 +109,62 @@ impl EventManager {
         });
     }
 
+    /// Adds a subscription, `server_id` `None` subscribes to the events of all servers.
+    ///
+    /// Subscribing twice to the same events has no effect.
+    fn add_subscription(
+        &mut self,
+        server_id: Option<String>,
+        event_type: ServerEventType,
+        client_id: u32,
+    ) {
+        let subscriptions = match server_id {
+            Some(server_id) => self
+                .subscriptions
+                .entry((server_id, event_type))
+                .or_default(),
+            None => self.wildcard_subscriptions.entry(event_type).or_default(),
+        };
+        if !subscriptions.contains(&client_id) {
+            subscriptions.push(client_id);
+        }
+    }
+
+    /// Returns the clients that should receive an event of the given type.
+    ///
+    /// Every client is contained at most once, even if it has subscribed to the event for the
+    /// specific server and for all servers. Custom events are also delivered to the subscriptions
+    /// for [`ServerEventType::AnyCustom`].
+    fn recipients(&self, server_id: Option<&str>, event_type: &ServerEventType) -> BTreeSet<u32> {
+        let mut event_types = vec![event_type];
+        if let ServerEventType::Custom(_) = event_type {
+            event_types.push(&ServerEventType::AnyCustom);
+        }
+        let mut recipients = BTreeSet::new();
+        for event_type in event_types {
+            if let Some(server_id) = server_id {
+                if let Some(subscriptions) = self
+                    .subscriptions
+                    .get(&(server_id.to_string(), event_type.clone()))
+                {
+                    recipients.extend(subscriptions);
+                }
+            }
+            if let Some(subscriptions) = self.wildcard_subscriptions.get(event_type) {
+                recipients.extend(subscriptions);
+            }
+        }
+        recipients
+    }
+
     /// Removes all subscriptions for a given client.
     fn remove_all_subscriptions(&mut self, client_id: u32) {
-        for subscriptions in self.subscriptions.values_mut() {
-            subscriptions.retain(|id| id == &client_id);
+        for subscriptions in self
+            .subscriptions
+            .values_mut()
+            .chain(self.wildcard_subscriptions.values_mut())
+        {
+            subscriptions.retain(|id| id != &client_id);
         }
     }
 }
@@ -96,15 +174,15 @@ impl EventManager {
 pub enum EventManagerCmd {
     /// Dispatch an event received from server identified by `server_id`.
     DispatchEvent {
-        /// The server that generated that event
-        server_id: String,
+        /// The server that generated that event, `None` for events of the daemon itself
+        server_id: Option<String>,
         /// The event that has been generated
         event: ServerEvent,
     },
     /// Client with the given client id wants to listen for events of type `event_type` on server `server_id`.
     AddSubscription {
-        /// The server on which the subscription should be added
-        server_id: String,
+        /// The server on which the subscription should be added, `None` for all servers
+        server_id: Option<String>,
         /// The type of event, the subscription should listen for
         event_type: ServerEventType,
         /// The client which should be notified about events
@@ -112,8 +190,8 @@ pub enum EventManagerCmd {
     },
     /// Remove a subscription of a client for a specific server and event type
     RemoveSubscription {
-        /// The server on which the listening should be stopped
-        server_id: String,
+        /// The server on which the listening should be stopped, `None` for all servers
+        server_id: Option<String>,
         /// The type of event, the subscription should be cancelled
         event_type: ServerEventType,
         /// The client which wants to remove the subscription
@@ -143,11 +221,84 @@ impl EventHandler {
     /// The event is encapsulated into an [`EventManagerCmd`] and sent over the sender to the event manager thread.
     pub fn raise_event(&mut self, server_id: &str, event: ServerEvent) {
         debug!("raising event {:?} for unit {}", event, server_id);
+        PENDING_EVENTS.fetch_add(1, Ordering::Relaxed);
         self.event_dispatcher
             .send(EventManagerCmd::DispatchEvent {
-                server_id: server_id.to_owned(),
+                server_id: Some(server_id.to_owned()),
                 event,
             })
             .expect("send to event manager queue");
     }
+
+    /// Raise an event of the daemon itself, which is not associated with a server.
+    ///
+    /// These events are only delivered to clients that have subscribed to all servers.
+    pub fn raise_daemon_event(&mut self, event: ServerEvent) {
+        debug!("raising daemon event {:?}", event);
+        PENDING_EVENTS.fetch_add(1, Ordering::Relaxed);
+        self.event_dispatcher
+            .send(EventManagerCmd::DispatchEvent {
+                server_id: None,
+                event,
+            })
+            .expect("send to event manager queue");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::event::EventManager;
+    use crate::ipc::ServerEventType;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_recipients() {
+        let (_, cmd_queue) = channel();
+        let (daemon_sender, _) = channel();
+        let mut manager = EventManager::new(cmd_queue, daemon_sender);
+
+        manager.add_subscription(Some("a".to_string()), ServerEventType::ServerStarted, 1);
+        manager.add_subscription(Some("a".to_string()), ServerEventType::ServerStarted, 1);
+        manager.add_subscription(None, ServerEventType::ServerStarted, 1);
+        manager.add_subscription(None, ServerEventType::ServerStarted, 2);
+        manager.add_subscription(Some("b".to_string()), ServerEventType::ServerStarted, 3);
+
+        let recipients: Vec<u32> = manager
+            .recipients(Some("a"), &ServerEventType::ServerStarted)
+            .into_iter()
+            .collect();
+        assert_eq!(recipients, vec![1, 2]);
+
+        let recipients: Vec<u32> = manager
+            .recipients(None, &ServerEventType::ServerStarted)
+            .into_iter()
+            .collect();
+        assert_eq!(recipients, vec![1, 2]);
+
+        assert!(manager
+            .recipients(Some("a"), &ServerEventType::ServerStopped)
+            .is_empty());
+
+        manager.remove_all_subscriptions(1);
+        let recipients: Vec<u32> = manager
+            .recipients(Some("a"), &ServerEventType::ServerStarted)
+            .into_iter()
+            .collect();
+        assert_eq!(recipients, vec![2]);
+    }
+
+    #[test]
+    fn test_custom_recipients() {
+        let (_, cmd_queue) = channel();
+        let (daemon_sender, _) = channel();
+        let mut manager = EventManager::new(cmd_queue, daemon_sender);
+        let alert = ServerEventType::Custom("anticheat_alert".to_string());
+
+        manager.add_subscription(Some("a".to_string()), alert.clone(), 1);
+        manager.add_subscription(None, ServerEventType::AnyCustom, 2);
+        manager.add_subscription(None, ServerEventType::Custom("other".to_string()), 3);
+
+        let recipients: Vec<u32> = manager.recipients(Some("a"), &alert).into_iter().collect();
+        assert_eq!(recipients, vec![1, 2]);
+    }
 }
@@ -0,0 +1,291 @@
+//! External programs that the daemon runs on behalf of units.
+//!
+//! A hook is a shell command (run with `sh -c`) that is executed in the server directory. Values
+//! are passed to it as environment variables, never by substituting them into the command. The
+//! output of a hook is written to the log of the daemon. A hook that does not exit within its
+//! timeout is killed together with the processes it has started.
+//!
+//! Besides the hooks of console triggers, units can declare [`LifecycleHook`]s in their unit file:
+//!
+//! ```toml
+//! [server.hooks]
+//! pre_start = "git pull --ff-only"
+//! post_stop = "./flush-caches.sh"
+//! on_failure = "./page-admin.sh \"$MCMAN_ERROR\""
+//! timeout_secs = 120
+//! ```
+
+use crate::config::{HooksConfig, ServerConfig};
+use log::{info, log, warn, Level};
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+/// The timeout of hooks that have no configured timeout, e.g. the hooks of console triggers.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The interval in which a running hook is checked for its exit.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A shell command with the environment it is run in.
+#[derive(Debug, Clone)]
+pub struct Hook {
+    /// Identifies the hook in the log of the daemon
+    pub name: String,
+    /// The shell command
+    pub command: String,
+    /// The working directory
+    pub directory: PathBuf,
+    /// Additional environment variables
+    pub environment: Vec<(String, String)>,
+    /// The time after which the hook is killed
+    pub timeout: Duration,
+}
+
+/// The reason why a hook has not succeeded.
+#[derive(Debug)]
+pub enum HookError {
+    /// The shell could not be started or waited for
+    Io(io::Error),
+    /// The hook has exited with a failure status
+    Failed(ExitStatus),
+    /// The hook has not exited within its timeout and has been killed
+    TimedOut(Duration),
+}
+
+impl Display for HookError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            HookError::Io(e) => write!(f, "could not run hook: {}", e),
+            HookError::Failed(status) => write!(f, "hook has failed: {}", status),
+            HookError::TimedOut(timeout) => {
+                write!(f, "hook has timed out after {}s", timeout.as_secs())
+            }
+        }
+    }
+}
+
+/// The points in the lifecycle of a server unit at which hooks can be run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleHook {
+    /// Before the server is started, a failure cancels the start
+    PreStart,
+    /// After the server has started
+    PostStart,
+    /// Before the server is stopped
+    PreStop,
+    /// After the server has stopped
+    PostStop,
+    /// After the server has failed
+    OnFailure,
+    /// Before the server software is updated, a failure cancels the update
+    PreUpdate,
+    /// After the server software has been updated
+    PostUpdate,
+}
+
+impl Hook {
+    /// Runs the hook and waits until it has exited or its timeout has elapsed.
+    pub fn run(&self) -> Result<(), HookError> {
+        info!("running hook {}: {}", self.name, self.command);
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .current_dir(&self.directory)
+            .envs(self.environment.iter().map(|(key, value)| (key, value)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // the hook gets its own process group, so its children can be killed with it
+            .process_group(0)
+            .spawn()
+            .map_err(HookError::Io)?;
+        // the output is logged while the hook runs, so a hook that hangs can be diagnosed
+        log_output(
+            self.name.clone(),
+            child.stdout.take().expect("capture stdout of hook"),
+            Level::Info,
+        );
+        log_output(
+            self.name.clone(),
+            child.stderr.take().expect("capture stderr of hook"),
+            Level::Warn,
+        );
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            match child.try_wait().map_err(HookError::Io)? {
+                Some(status) => break status,
+                None if Instant::now() >= deadline => {
+                    // the id of the process group is the id of the shell
+                    unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+                    let _ = child.wait();
+                    let error = HookError::TimedOut(self.timeout);
+                    warn!("hook {}: {}", self.name, error);
+                    return Err(error);
+                }
+                None => sleep(POLL_INTERVAL),
+            }
+        };
+        if status.success() {
+            Ok(())
+        } else {
+            let error = HookError::Failed(status);
+            warn!("hook {}: {}", self.name, error);
+            Err(error)
+        }
+    }
+
+    /// Runs the hook on a new thread, without waiting for it.
+    pub fn spawn(self) {
+        spawn(move || {
+            if let Err(HookError::Io(e)) = self.run() {
+                warn!("could not run hook {}: {}", self.name, e);
+            }
+        });
+    }
+}
+
+impl LifecycleHook {
+    /// Returns the name of the hook in the unit file.
+    pub fn name(self) -> &'static str {
+        match self {
+            LifecycleHook::PreStart => "pre_start",
+            LifecycleHook::PostStart => "post_start",
+            LifecycleHook::PreStop => "pre_stop",
+            LifecycleHook::PostStop => "post_stop",
+            LifecycleHook::OnFailure => "on_failure",
+            LifecycleHook::PreUpdate => "pre_update",
+            LifecycleHook::PostUpdate => "post_update",
+        }
+    }
+
+    /// Returns the configured command of the hook.
+    pub fn command(self, config: &HooksConfig) -> Option<&str> {
+        match self {
+            LifecycleHook::PreStart => config.pre_start.as_deref(),
+            LifecycleHook::PostStart => config.post_start.as_deref(),
+            LifecycleHook::PreStop => config.pre_stop.as_deref(),
+            LifecycleHook::PostStop => config.post_stop.as_deref(),
+            LifecycleHook::OnFailure => config.on_failure.as_deref(),
+            LifecycleHook::PreUpdate => config.pre_update.as_deref(),
+            LifecycleHook::PostUpdate => config.post_update.as_deref(),
+        }
+    }
+
+    /// Returns the hook of a unit, if the unit has configured it.
+    ///
+    /// The hook receives the metadata of the unit in the environment variables `MCMAN_UNIT`,
+    /// `MCMAN_HOOK`, `MCMAN_UNIT_FILE`, `MCMAN_SERVER_NAME`, `MCMAN_SERVER_TYPE`,
+    /// `MCMAN_SERVER_VERSION` and `MCMAN_SERVER_PATH`, `environment` is added to them.
+    pub fn for_unit(
+        self,
+        unit_id: &str,
+        config: &ServerConfig,
+        unit_file: &Path,
+        environment: Vec<(String, String)>,
+    ) -> Option<Hook> {
+        let hooks = config.hooks.as_ref()?;
+        let command = self.command(hooks)?;
+        let mut unit_environment = vec![
+            ("MCMAN_UNIT".to_string(), unit_id.to_string()),
+            ("MCMAN_HOOK".to_string(), self.name().to_string()),
+            (
+                "MCMAN_UNIT_FILE".to_string(),
+                unit_file.to_string_lossy().to_string(),
+            ),
+            ("MCMAN_SERVER_NAME".to_string(), config.name.clone()),
+            ("MCMAN_SERVER_TYPE".to_string(), config.type_name.clone()),
+            (
+                "MCMAN_SERVER_VERSION".to_string(),
+                config.version.to_string(),
+            ),
+            (
+                "MCMAN_SERVER_PATH".to_string(),
+                config.path.to_string_lossy().to_string(),
+            ),
+        ];
+        unit_environment.extend(environment);
+        Some(Hook {
+            name: format!("{} of unit {}", self.name(), unit_id),
+            command: command.to_string(),
+            directory: config.path.to_path_buf(),
+            environment: unit_environment,
+            timeout: Duration::from_secs(hooks.timeout_secs),
+        })
+    }
+}
+
+/// Logs the lines of an output stream of a hook on a new thread.
+fn log_output(name: String, output: impl Read + Send + 'static, level: Level) {
+    spawn(move || {
+        for line in BufReader::new(output).lines() {
+            match line {
+                Ok(line) => log!(level, "hook {}: {}", name, line),
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::hooks::{Hook, HookError};
+    use std::fs::read_to_string;
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    fn hook(command: &str, timeout: Duration) -> Hook {
+        Hook {
+            name: "test".to_string(),
+            command: command.to_string(),
+            directory: std::env::temp_dir(),
+            environment: vec![("MCMAN_UNIT".to_string(), "a".to_string())],
+            timeout,
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let timeout = Duration::from_secs(10);
+        assert!(hook("test \"$MCMAN_UNIT\" = a", timeout).run().is_ok());
+        assert!(matches!(
+            hook("echo failed >&2; exit 3", timeout).run(),
+            Err(HookError::Failed(status)) if status.code() == Some(3)
+        ));
+
+        let start = Instant::now();
+        assert!(matches!(
+            hook("sleep 10; true", Duration::from_millis(200)).run(),
+            Err(HookError::TimedOut(_))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_kill_children() {
+        let directory = tempfile::tempdir().expect("create temp dir");
+        let mut hook = hook(
+            "sleep 10 & echo $! > sleep.pid; wait; true",
+            Duration::from_millis(500),
+        );
+        hook.directory = directory.path().to_path_buf();
+        assert!(matches!(hook.run(), Err(HookError::TimedOut(_))));
+
+        let pid = read_to_string(directory.path().join("sleep.pid")).expect("read pid");
+        let stat = format!("/proc/{}/stat", pid.trim());
+        // the killed child is reaped by init, until then it is a zombie
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while read_to_string(&stat).map_or(false, |stat| !stat.contains(") Z ")) {
+            assert!(
+                Instant::now() < deadline,
+                "child of the hook is still running"
+            );
+            sleep(Duration::from_millis(10));
+        }
+    }
+}
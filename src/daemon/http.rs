@@ -0,0 +1,787 @@
+//! Embedded HTTP interface of the daemon.
+//!
+//! The interface offers a small REST API and a WebSocket event stream. Requests are translated
+//! into [`DaemonCmd`]s and processed by the daemon like commands of any other client, the HTTP
+//! interface registers itself as a local client in the [`ClientRegistry`] for that.
+//!
+//! | Method | Path                          | Description                                 |
+//! |--------|-------------------------------|---------------------------------------------|
+//! | GET    | `/api/version`                | Version of the daemon                       |
+//! | GET    | `/api/units`                  | List all units                              |
+//! | POST   | `/api/units`                  | Install a new server (returns a job)        |
+//! | GET    | `/api/units/<id>`             | Info about a single unit                    |
+//! | POST   | `/api/units/<id>/start`       | Start a server                              |
+//! | POST   | `/api/units/<id>/stop`        | Stop a server                               |
+//! | POST   | `/api/units/<id>/restart`     | Restart a server                            |
+//! | POST   | `/api/units/<id>/command`     | Send `{"command": ...}` to the console      |
+//! | POST   | `/api/units/<id>/say`         | Broadcast `{"message": ...}` on the server  |
+//! | POST   | `/api/units/<id>/update`      | Update a server (returns a job)             |
+//! | GET    | `/api/units/<id>/stats`       | Tick rate history of a running server       |
+//! | GET    | `/api/units/<id>/logs`        | List the log sessions of a unit             |
+//! | GET    | `/api/units/<id>/logs/<s>`    | Lines of log session `s` (or `latest`)      |
+//! | GET    | `/api/jobs`                   | List install and update jobs                |
+//! | GET    | `/api/jobs/<id>`              | State of a single job                       |
+//! | GET    | `/api/events`                 | WebSocket event stream                      |
+//!
+//! The log endpoints accept the query parameters `from` (index of the first line), `limit`
+//! (maximum number of lines), `grep` (a regular expression), `level` (the minimum level, e.g.
+//! `warn`) and `records` (`true` to include the lines as structured records). Without `from` the
+//! last `limit` lines are returned.
+//!
+//! Unless disabled in the [`HttpConfig`], all other `GET` requests are answered with the files of
+//! the web [`dashboard`].
+//!
+//! The interface has no authentication, so it must not be usable from web pages of other sites
+//! that are open in the browser of the operator. If it is bound to a loopback address, the `Host`
+//! header of every request has to name that address. WebSocket upgrades and requests other than
+//! `GET` are rejected if their `Origin` header names another host, and request bodies are only
+//! accepted with `Content-Type: application/json`, which browsers do not send cross-site without
+//! a CORS preflight.
+
+pub mod dashboard;
+pub mod request;
+pub mod websocket;
+
+use crate::config::HttpConfig;
+use crate::daemon::client::{ClientConnection, ClientRegistry};
+use crate::daemon::event::EventManagerCmd;
+use crate::daemon::http::request::{HttpRequest, HttpResponse, HttpStream};
+use crate::daemon::records::LogLevel;
+use crate::daemon::DaemonEvent;
+use crate::ipc::{DaemonCmd, DaemonResponse, ServerEvent};
+use crate::ServerType;
+use log::{debug, info, warn};
+use semver::Version;
+use std::fs::remove_file;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+
+/// Address the HTTP interface binds to if neither an address nor a socket file is configured.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7520";
+
+/// Time to wait for the daemon to answer a command.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time a client has to send its request, so idle connections do not keep their threads.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of log lines returned if the request does not specify a limit.
+const DEFAULT_LOG_LIMIT: usize = 500;
+
+/// The embedded HTTP interface.
+///
+/// Cloning the interface yields a handle to the same daemon connection and job list.
+#[derive(Clone)]
+pub struct HttpInterface {
+    /// The configuration of the interface
+    config: HttpConfig,
+    /// Queue of the main daemon thread
+    daemon_queue: Sender<DaemonEvent>,
+    /// Queue of the event manager
+    event_manager_ctrl: Sender<EventManagerCmd>,
+    /// The registry in which HTTP clients are registered
+    clients: ClientRegistry,
+    /// Install and update jobs started over the HTTP interface
+    jobs: Arc<Mutex<Vec<Job>>>,
+    /// Values of the `Host` header that are accepted, `None` if every host is accepted
+    allowed_hosts: Option<Arc<Vec<String>>>,
+}
+
+/// Kind of a long running job.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub enum JobKind {
+    /// Installation of a new server
+    Install,
+    /// Update of an existing server
+    Update,
+}
+
+/// State of a long running job.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    /// The job is still running
+    Running,
+    /// The job has completed successfully
+    Complete,
+    /// The job has failed
+    Failed,
+}
+
+/// An install or update job started over the HTTP interface.
+#[derive(Serialize, Debug, Clone)]
+pub struct Job {
+    /// The id of the job
+    pub id: usize,
+    /// The kind of the job
+    pub kind: JobKind,
+    /// The unit the job is working on
+    pub unit_id: String,
+    /// The state of the job
+    pub state: JobState,
+    /// The last reported action
+    pub action: Option<String>,
+    /// The progress of the last reported action
+    pub progress: Option<usize>,
+    /// The maximum progress of the last reported action
+    pub maximum: Option<usize>,
+    /// The error message if the job has failed
+    pub error: Option<String>,
+}
+
+/// Request body of `POST /api/units`.
+#[derive(Deserialize, Debug)]
+struct InstallRequest {
+    /// Unit id of the new server
+    unit_id: String,
+    /// Installation path of the server
+    install_path: String,
+    /// Path of the unit file
+    unit_file_path: Option<String>,
+    /// Desired version of the server software
+    server_version: Option<Version>,
+    /// Type of the server
+    server_type: ServerType,
+    /// Whether the EULA has been accepted
+    #[serde(default)]
+    accept_eula: bool,
+    /// Name displayed in the server list
+    server_name: Option<String>,
+}
+
+/// Request body of `POST /api/units/<id>/update`.
+#[derive(Deserialize, Debug, Default)]
+struct UpdateRequest {
+    /// Desired version of the server software
+    server_version: Option<Version>,
+}
+
+/// Request body of `POST /api/units/<id>/command`.
+#[derive(Deserialize, Debug)]
+struct CommandRequest {
+    /// The command to send to the server
+    command: String,
+}
+
+/// Request body of `POST /api/units/<id>/say`.
+#[derive(Deserialize, Debug)]
+struct MessageRequest {
+    /// The message to broadcast
+    message: String,
+}
+
+impl HttpInterface {
+    /// Creates a new HTTP interface.
+    pub fn new(
+        config: HttpConfig,
+        daemon_queue: Sender<DaemonEvent>,
+        event_manager_ctrl: Sender<EventManagerCmd>,
+        clients: ClientRegistry,
+    ) -> Self {
+        Self {
+            config,
+            daemon_queue,
+            event_manager_ctrl,
+            clients,
+            jobs: Arc::new(Mutex::new(vec![])),
+            allowed_hosts: None,
+        }
+    }
+
+    /// Binds the configured socket and spawns a thread accepting connections.
+    ///
+    /// Every connection is handled in a separate thread.
+    pub fn run(mut self) -> io::Result<()> {
+        if let Some(socket_file) = self.config.socket_file.clone() {
+            let path = Path::new(&socket_file);
+            if path.exists() {
+                remove_file(path)?;
+            }
+            let listener = UnixListener::bind(path)?;
+            info!("http interface listening on unix socket {}", socket_file);
+            spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => self.handle_connection(HttpStream::Unix(stream)),
+                        Err(e) => warn!("error on incoming http connection: {}", e),
+                    }
+                }
+            });
+        } else {
+            let address = self
+                .config
+                .address
+                .clone()
+                .unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+            for socket_address in address.to_socket_addrs()? {
+                if !socket_address.ip().is_loopback() {
+                    warn!(
+                        "http interface is bound to non-loopback address {}, it is reachable from other hosts!",
+                        socket_address
+                    );
+                }
+            }
+            let listener = TcpListener::bind(&address)?;
+            self.allowed_hosts = allowed_hosts(&listener.local_addr()?).map(Arc::new);
+            info!("http interface listening on {}", address);
+            spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => self.handle_connection(HttpStream::Tcp(stream)),
+                        Err(e) => warn!("error on incoming http connection: {}", e),
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Handles a connection in a new thread.
+    fn handle_connection(&self, mut stream: HttpStream) {
+        let interface = self.clone();
+        if let Err(e) = stream.set_read_timeout(Some(REQUEST_TIMEOUT)) {
+            debug!("could not set read timeout of http connection: {}", e);
+        }
+        spawn(move || match HttpRequest::read_from(&mut stream) {
+            Ok(request) => {
+                debug!(
+                    "http request {} /{}",
+                    request.method,
+                    request.segments.join("/")
+                );
+                if let Err(response) = interface.check_origin(&request) {
+                    warn!(
+                        "rejected http request {} /{} (host {:?}, origin {:?})",
+                        request.method,
+                        request.segments.join("/"),
+                        request.header("host"),
+                        request.header("origin")
+                    );
+                    let _ = response.write_to(&mut stream);
+                } else if request.is_websocket_upgrade() {
+                    if request.method == "GET" && request.segments == ["api", "events"] {
+                        websocket::serve(&interface, request, stream);
+                    } else {
+                        let _ = HttpResponse::error(404, "not found").write_to(&mut stream);
+                    }
+                } else {
+                    let response = interface.route(&request);
+                    if let Err(e) = response.write_to(&mut stream) {
+                        debug!("could not write http response: {}", e);
+                    }
+                }
+            }
+            Err(e) => {
+                debug!("could not read http request: {}", e);
+                let _ = HttpResponse::error(400, &e.to_string()).write_to(&mut stream);
+            }
+        });
+    }
+
+    /// Rejects requests that a web page of another site could have sent.
+    ///
+    /// The `Host` header has to be one of the allowed hosts, which defeats DNS rebinding. Requests
+    /// that change state (every method but `GET`) and WebSocket upgrades must not carry an
+    /// `Origin` header of another host.
+    fn check_origin(&self, request: &HttpRequest) -> Result<(), HttpResponse> {
+        let host = request.header("host");
+        if let Some(allowed_hosts) = &self.allowed_hosts {
+            let allowed = host.is_some_and(|host| {
+                allowed_hosts
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(host.trim()))
+            });
+            if !allowed {
+                return Err(HttpResponse::error(403, "host not allowed"));
+            }
+        }
+
+        if request.method != "GET" || request.is_websocket_upgrade() {
+            if let Some(origin) = request.header("origin") {
+                let origin_host = origin
+                    .strip_prefix("http://")
+                    .or_else(|| origin.strip_prefix("https://"));
+                let same_origin = match (origin_host, host) {
+                    (Some(origin_host), Some(host)) => origin_host
+                        .trim_end_matches('/')
+                        .eq_ignore_ascii_case(host.trim()),
+                    _ => false,
+                };
+                if !same_origin {
+                    return Err(HttpResponse::error(403, "cross-origin request rejected"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Dispatches a request to its handler.
+    fn route(&self, request: &HttpRequest) -> HttpResponse {
+        let segments: Vec<&str> = request.segments.iter().map(|s| s.as_str()).collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["api", "version"]) => self.call_to_response(DaemonCmd::GetVersion),
+            ("GET", ["api", "units"]) => self.call_to_response(DaemonCmd::List),
+            ("POST", ["api", "units"]) => match parse_body::<InstallRequest>(request) {
+                Ok(install) => self.start_job(
+                    JobKind::Install,
+                    install.unit_id.clone(),
+                    DaemonCmd::InstallServer {
+                        unit_id: install.unit_id,
+                        install_path: install.install_path,
+                        unit_file_path: install.unit_file_path,
+                        server_version: install.server_version,
+                        server_type: install.server_type,
+                        accept_eula: install.accept_eula,
+                        server_name: install.server_name,
+                    },
+                ),
+                Err(response) => response,
+            },
+            ("GET", ["api", "units", unit_id]) => match self.call(DaemonCmd::List) {
+                Some(DaemonResponse::List { servers }) => {
+                    match servers.iter().find(|server| &server.name == unit_id) {
+                        Some(server) => HttpResponse::json(200, server),
+                        None => HttpResponse::error(404, &format!("unknown unit {}", unit_id)),
+                    }
+                }
+                Some(response) => to_http_response(response),
+                None => HttpResponse::error(504, "daemon did not respond"),
+            },
+            ("POST", ["api", "units", unit_id, "start"]) => {
+                self.call_to_response(DaemonCmd::Start {
+                    server_id: unit_id.to_string(),
+                    wait: false,
+                })
+            }
+            ("POST", ["api", "units", unit_id, "stop"]) => self.call_to_response(DaemonCmd::Stop {
+                server_id: unit_id.to_string(),
+                wait: false,
+            }),
+            ("POST", ["api", "units", unit_id, "restart"]) => {
+                self.call_to_response(DaemonCmd::Restart {
+                    server_id: unit_id.to_string(),
+                })
+            }
+            ("POST", ["api", "units", unit_id, "command"]) => {
+                match parse_body::<CommandRequest>(request) {
+                    Ok(body) => self.call_to_response(DaemonCmd::SendCommand {
+                        unit_id: unit_id.to_string(),
+                        command: body.command,
+                    }),
+                    Err(response) => response,
+                }
+            }
+            ("POST", ["api", "units", unit_id, "say"]) => {
+                match parse_body::<MessageRequest>(request) {
+                    Ok(body) => self.call_to_response(DaemonCmd::SendMessage {
+                        unit_id: unit_id.to_string(),
+                        message: body.message,
+                    }),
+                    Err(response) => response,
+                }
+            }
+            ("POST", ["api", "units", unit_id, "update"]) => {
+                let body = if request.body.is_empty() {
+                    Ok(UpdateRequest::default())
+                } else {
+                    parse_body::<UpdateRequest>(request)
+                };
+                match body {
+                    Ok(body) => self.start_job(
+                        JobKind::Update,
+                        unit_id.to_string(),
+                        DaemonCmd::UpdateServer {
+                            unit_id: unit_id.to_string(),
+                            server_version: body.server_version,
+                        },
+                    ),
+                    Err(response) => response,
+                }
+            }
+            ("GET", ["api", "units", unit_id, "stats"]) => {
+                match self.call(DaemonCmd::GetPerformance {
+                    unit_id: unit_id.to_string(),
+                }) {
+                    Some(DaemonResponse::Performance { stats, .. }) => {
+                        HttpResponse::json(200, &stats)
+                    }
+                    Some(response) => to_http_response(response),
+                    None => HttpResponse::error(504, "daemon did not respond"),
+                }
+            }
+            ("GET", ["api", "units", unit_id, "logs"]) => {
+                match self.call_deferred(DaemonCmd::GetLogs {
+                    unit_id: unit_id.to_string(),
+                    session: None,
+                    from: None,
+                    limit: 0,
+                    grep: None,
+                    level: None,
+                    records: false,
+                }) {
+                    Some(DaemonResponse::Logs { sessions, .. }) => {
+                        HttpResponse::json(200, &sessions)
+                    }
+                    Some(response) => to_http_response(response),
+                    None => HttpResponse::error(504, "daemon did not respond"),
+                }
+            }
+            ("GET", ["api", "units", unit_id, "logs", session]) => {
+                let from = match query_number(request, "from") {
+                    Ok(from) => from,
+                    Err(response) => return response,
+                };
+                let limit = match query_number(request, "limit") {
+                    Ok(limit) => limit.unwrap_or(DEFAULT_LOG_LIMIT),
+                    Err(response) => return response,
+                };
+                let level = match request
+                    .query
+                    .get("level")
+                    .map(|level| level.parse::<LogLevel>())
+                {
+                    Some(Ok(level)) => Some(level),
+                    Some(Err(error)) => return HttpResponse::error(400, error.as_str()),
+                    None => None,
+                };
+                let session = if *session == "latest" {
+                    None
+                } else {
+                    Some(session.to_string())
+                };
+                match self.call_deferred(DaemonCmd::GetLogs {
+                    unit_id: unit_id.to_string(),
+                    session,
+                    from,
+                    limit,
+                    grep: request.query.get("grep").cloned(),
+                    level,
+                    records: request
+                        .query
+                        .get("records")
+                        .is_some_and(|records| records == "true"),
+                }) {
+                    Some(DaemonResponse::Logs {
+                        lines: Some(lines), ..
+                    }) => HttpResponse::json(200, &lines),
+                    Some(DaemonResponse::Logs { unit_id, .. }) => {
+                        HttpResponse::error(404, &format!("no logs found for unit {}", unit_id))
+                    }
+                    Some(response) => to_http_response(response),
+                    None => HttpResponse::error(504, "daemon did not respond"),
+                }
+            }
+            ("GET", ["api", "jobs"]) => {
+                let jobs = self.jobs.lock().expect("lock job list");
+                HttpResponse::json(200, &*jobs)
+            }
+            ("GET", ["api", "jobs", job_id]) => {
+                let jobs = self.jobs.lock().expect("lock job list");
+                match job_id.parse::<usize>().ok().and_then(|id| jobs.get(id)) {
+                    Some(job) => HttpResponse::json(200, job),
+                    None => HttpResponse::error(404, &format!("unknown job {}", job_id)),
+                }
+            }
+            ("GET", ["api", ..]) => HttpResponse::error(404, "not found"),
+            ("GET", file) if self.config.dashboard => match dashboard::file(file) {
+                Some(response) => response,
+                None => HttpResponse::error(404, "not found"),
+            },
+            _ => HttpResponse::error(404, "not found"),
+        }
+    }
+
+    /// Registers a new local client and returns its id and the receiving end of its responses.
+    pub fn connect(&self, name: &str) -> (u32, Receiver<DaemonResponse>) {
+        let (sender, receiver) = channel();
+        let client_id = self
+            .clients
+            .register(name.to_string(), ClientConnection::Local(sender));
+        (client_id, receiver)
+    }
+
+    /// Removes a local client and all of its subscriptions.
+    pub fn disconnect(&self, client_id: u32) {
+        self.clients.unregister(client_id);
+        let _ = self
+            .event_manager_ctrl
+            .send(EventManagerCmd::RemoveAllSubscriptions { client_id });
+    }
+
+    /// Sends a command to the daemon on behalf of the given client without waiting for a response.
+    pub fn send_as(&self, client_id: u32, cmd: DaemonCmd) {
+        self.daemon_queue
+            .send(DaemonEvent::IncomingCmd { id: client_id, cmd })
+            .expect("send to daemon main event queue");
+    }
+
+    /// Sends a command on behalf of the given client and waits for the response.
+    pub fn call_as(
+        &self,
+        client_id: u32,
+        responses: &Receiver<DaemonResponse>,
+        cmd: DaemonCmd,
+    ) -> Option<DaemonResponse> {
+        self.send_as(client_id, cmd);
+        responses.recv_timeout(RESPONSE_TIMEOUT).ok()
+    }
+
+    /// Sends a single command with a short lived client and waits for the response.
+    fn call(&self, cmd: DaemonCmd) -> Option<DaemonResponse> {
+        let (client_id, responses) = self.connect("http");
+        let response = self.call_as(client_id, &responses, cmd);
+        self.disconnect(client_id);
+        response
+    }
+
+    /// Sends a single command that the daemon acknowledges with [`DaemonResponse::Ok`] before it
+    /// sends the result, and waits for the result.
+    fn call_deferred(&self, cmd: DaemonCmd) -> Option<DaemonResponse> {
+        let (client_id, responses) = self.connect("http");
+        let response = match self.call_as(client_id, &responses, cmd) {
+            Some(DaemonResponse::Ok) => responses.recv_timeout(RESPONSE_TIMEOUT).ok(),
+            response => response,
+        };
+        self.disconnect(client_id);
+        response
+    }
+
+    /// Sends a single command and converts the response to a HTTP response.
+    fn call_to_response(&self, cmd: DaemonCmd) -> HttpResponse {
+        match self.call(cmd) {
+            Some(response) => to_http_response(response),
+            None => HttpResponse::error(504, "daemon did not respond"),
+        }
+    }
+
+    /// Starts an install or update job and tracks its events in a separate thread.
+    fn start_job(&self, kind: JobKind, unit_id: String, cmd: DaemonCmd) -> HttpResponse {
+        let (client_id, responses) = self.connect("http-job");
+        match self.call_as(client_id, &responses, cmd) {
+            Some(DaemonResponse::Ok) => {
+                let job = {
+                    let mut jobs = self.jobs.lock().expect("lock job list");
+                    let job = Job {
+                        id: jobs.len(),
+                        kind,
+                        unit_id,
+                        state: JobState::Running,
+                        action: None,
+                        progress: None,
+                        maximum: None,
+                        error: None,
+                    };
+                    jobs.push(job.clone());
+                    job
+                };
+                let interface = self.clone();
+                let job_id = job.id;
+                spawn(move || {
+                    interface.track_job(job_id, responses);
+                    interface.disconnect(client_id);
+                });
+                HttpResponse::json(202, &job)
+            }
+            Some(response) => {
+                self.disconnect(client_id);
+                to_http_response(response)
+            }
+            None => {
+                self.disconnect(client_id);
+                HttpResponse::error(504, "daemon did not respond")
+            }
+        }
+    }
+
+    /// Updates a job from the events received for it until it has finished.
+    fn track_job(&self, job_id: usize, responses: Receiver<DaemonResponse>) {
+        while let Ok(response) = responses.recv() {
+            let event = match response {
+                DaemonResponse::ServerEvent { event } => event,
+                _ => continue,
+            };
+            let mut jobs = self.jobs.lock().expect("lock job list");
+            let job = &mut jobs[job_id];
+            match event {
+                ServerEvent::ActionProgress {
+                    action,
+                    progress,
+                    maximum,
+                    ..
+                } => {
+                    job.action = Some(action);
+                    job.progress = progress;
+                    job.maximum = maximum;
+                }
+                ServerEvent::InstallationComplete { .. } | ServerEvent::UpdateComplete { .. } => {
+                    job.state = JobState::Complete;
+                }
+                ServerEvent::InstallationFailed { error, .. }
+                | ServerEvent::UpdateFailed { error, .. } => {
+                    job.state = JobState::Failed;
+                    job.error = Some(error);
+                }
+                _ => {}
+            }
+            if job.state != JobState::Running {
+                break;
+            }
+        }
+    }
+}
+
+/// Returns the values of the `Host` header under which the interface bound to the given address
+/// can be reached.
+///
+/// Only loopback addresses are restricted, for other addresses `None` is returned.
+fn allowed_hosts(address: &SocketAddr) -> Option<Vec<String>> {
+    if !address.ip().is_loopback() {
+        return None;
+    }
+    let port = address.port();
+    let mut hosts = vec![address.to_string(), format!("localhost:{}", port)];
+    if port == 80 {
+        hosts.push(match address {
+            SocketAddr::V4(address) => address.ip().to_string(),
+            SocketAddr::V6(address) => format!("[{}]", address.ip()),
+        });
+        hosts.push("localhost".to_string());
+    }
+    Some(hosts)
+}
+
+/// Parses the JSON body of a request, returning an error response on failure.
+///
+/// The body is only accepted with `Content-Type: application/json`.
+fn parse_body<T: serde::de::DeserializeOwned>(request: &HttpRequest) -> Result<T, HttpResponse> {
+    let is_json = request.header("content-type").is_some_and(|content_type| {
+        content_type
+            .split(';')
+            .next()
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+    });
+    if !is_json {
+        return Err(HttpResponse::error(
+            415,
+            "request body must have content type application/json",
+        ));
+    }
+    serde_json::from_slice(&request.body)
+        .map_err(|e| HttpResponse::error(400, &format!("invalid request body: {}", e)))
+}
+
+/// Parses an optional numeric query parameter, returning an error response on failure.
+fn query_number(request: &HttpRequest, name: &str) -> Result<Option<usize>, HttpResponse> {
+    match request.query.get(name) {
+        Some(value) => value.parse().map(Some).map_err(|_| {
+            HttpResponse::error(400, &format!("invalid value for parameter {}", name))
+        }),
+        None => Ok(None),
+    }
+}
+
+/// Converts a daemon response to a HTTP response.
+fn to_http_response(response: DaemonResponse) -> HttpResponse {
+    match response {
+        DaemonResponse::List { servers } => HttpResponse::json(200, &servers),
+        DaemonResponse::Version { version } => {
+            HttpResponse::json(200, &serde_json::json!({ "version": version }))
+        }
+        DaemonResponse::ServerNotFound { server_id } => {
+            HttpResponse::error(404, &format!("unknown unit {}", server_id))
+        }
+        DaemonResponse::ServerStarted { server_id } => HttpResponse::json(
+            200,
+            &serde_json::json!({ "server_id": server_id, "status": "started" }),
+        ),
+        DaemonResponse::ServerStopped { server_id } => HttpResponse::json(
+            200,
+            &serde_json::json!({ "server_id": server_id, "status": "stopped" }),
+        ),
+        DaemonResponse::Ok => HttpResponse::json(200, &serde_json::json!({ "status": "ok" })),
+        DaemonResponse::LogSessionNotFound { unit_id, session } => HttpResponse::error(
+            404,
+            &format!("unknown log session {} of unit {}", session, unit_id),
+        ),
+        DaemonResponse::InvalidRequest { error } => HttpResponse::error(400, &error),
+        DaemonResponse::Error { error } => HttpResponse::error(500, &error),
+        DaemonResponse::HookFailed {
+            unit_id,
+            hook,
+            error,
+        } => HttpResponse::error(
+            409,
+            &format!("{} hook of unit {} has failed: {}", hook, unit_id, error),
+        ),
+        response => HttpResponse::error(500, &format!("unexpected response {:?}", response)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::HttpConfig;
+    use crate::daemon::client::ClientRegistry;
+    use crate::daemon::http::request::HttpRequest;
+    use crate::daemon::http::{allowed_hosts, HttpInterface};
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+
+    fn interface() -> HttpInterface {
+        let (daemon_queue, _) = channel();
+        let (event_manager_ctrl, _) = channel();
+        let mut interface = HttpInterface::new(
+            HttpConfig {
+                address: None,
+                socket_file: None,
+                dashboard: true,
+            },
+            daemon_queue,
+            event_manager_ctrl,
+            ClientRegistry::new(),
+        );
+        interface.allowed_hosts =
+            allowed_hosts(&"127.0.0.1:7520".parse().expect("parse address")).map(Arc::new);
+        interface
+    }
+
+    fn request(raw: &str) -> HttpRequest {
+        HttpRequest::read_from(&mut raw.as_bytes()).expect("parse request")
+    }
+
+    #[test]
+    fn test_foreign_origin_upgrade() {
+        let interface = interface();
+        let upgrade = "GET /api/events HTTP/1.1\r\nHost: 127.0.0.1:7520\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n";
+
+        let foreign = request(&format!("{}Origin: http://evil.example\r\n\r\n", upgrade));
+        let response = interface
+            .check_origin(&foreign)
+            .expect_err("reject upgrade");
+        assert_eq!(response.status, 403);
+
+        let own = request(&format!("{}Origin: http://127.0.0.1:7520\r\n\r\n", upgrade));
+        assert!(interface.check_origin(&own).is_ok());
+
+        let rebound = request("GET /api/units HTTP/1.1\r\nHost: evil.example:7520\r\n\r\n");
+        let response = interface.check_origin(&rebound).expect_err("reject host");
+        assert_eq!(response.status, 403);
+    }
+
+    #[test]
+    fn test_text_plain_post() {
+        let interface = interface();
+        let body = r#"{"command":"stop"}"#;
+        let post = request(&format!(
+            "POST /api/units/survival/command HTTP/1.1\r\nHost: localhost:7520\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+        assert!(interface.check_origin(&post).is_ok());
+        assert_eq!(interface.route(&post).status, 415);
+    }
+}
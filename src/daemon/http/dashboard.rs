@@ -0,0 +1,28 @@
+//! Static files of the web dashboard.
+//!
+//! The dashboard is a single page application that only uses the REST API and the WebSocket
+//! event stream of the HTTP interface. The files are embedded into the daemon binary.
+
+use crate::daemon::http::request::HttpResponse;
+
+/// The HTML page of the dashboard
+const INDEX_HTML: &str = include_str!("dashboard/index.html");
+/// The script of the dashboard
+const DASHBOARD_JS: &str = include_str!("dashboard/dashboard.js");
+/// The stylesheet of the dashboard
+const DASHBOARD_CSS: &str = include_str!("dashboard/dashboard.css");
+
+/// Returns the dashboard file for the given path segments, if there is one.
+pub fn file(segments: &[&str]) -> Option<HttpResponse> {
+    let (content_type, content) = match segments {
+        [] | ["index.html"] => ("text/html; charset=utf-8", INDEX_HTML),
+        ["dashboard.js"] => ("application/javascript; charset=utf-8", DASHBOARD_JS),
+        ["dashboard.css"] => ("text/css; charset=utf-8", DASHBOARD_CSS),
+        _ => return None,
+    };
+    Some(HttpResponse {
+        status: 200,
+        content_type,
+        body: content.as_bytes().to_vec(),
+    })
+}
@@ -0,0 +1,299 @@
+"use strict";
+
+const UNIT_POLL_INTERVAL = 10000;
+const LOG_PAGE_SIZE = 500;
+
+const state = {
+    units: [],
+    socket: null,
+    console: {unit: null, pending: null},
+    logs: {unit: null, session: null, from: 0, lines: []},
+    progress: {},
+};
+
+async function api(method, path, body) {
+    const options = {method, headers: {}};
+    if (body !== undefined) {
+        options.headers["Content-Type"] = "application/json";
+        options.body = JSON.stringify(body);
+    }
+    const response = await fetch(path, options);
+    const data = await response.json();
+    if (!response.ok) {
+        throw new Error(data.error || response.statusText);
+    }
+    return data;
+}
+
+function element(tag, properties, ...children) {
+    const node = document.createElement(tag);
+    Object.assign(node, properties || {});
+    for (const child of children) {
+        node.append(child);
+    }
+    return node;
+}
+
+function button(label, action) {
+    return element("button", {
+        type: "button",
+        textContent: label,
+        onclick: () => action().catch(error => alert(error.message)),
+    });
+}
+
+function unitPath(unitId) {
+    return "/api/units/" + encodeURIComponent(unitId);
+}
+
+async function refreshUnits() {
+    state.units = await api("GET", "/api/units");
+    const body = document.querySelector("#units tbody");
+    body.replaceChildren(...state.units.map(unit => {
+        const status = typeof unit.server_status === "string"
+            ? unit.server_status
+            : Object.keys(unit.server_status)[0];
+        const players = Array.isArray(unit.online_players) ? String(unit.online_players.length) : "–";
+        return element("tr", {},
+            element("td", {textContent: unit.name, title: unit.path}),
+            element("td", {textContent: unit.server_type}),
+            element("td", {textContent: unit.server_version}),
+            element("td", {}, element("span", {className: "badge status-" + status, textContent: status})),
+            element("td", {textContent: players}),
+            element("td", {className: "actions"},
+                button("Start", () => api("POST", unitPath(unit.name) + "/start").then(refreshUnits)),
+                button("Stop", () => api("POST", unitPath(unit.name) + "/stop").then(refreshUnits)),
+                button("Restart", () => api("POST", unitPath(unit.name) + "/restart").then(refreshUnits)),
+                button("Console", () => openConsole(unit.name)),
+                button("Logs", () => openLogs(unit.name)),
+            ),
+        );
+    }));
+
+    const select = document.getElementById("update-unit");
+    const selected = select.value;
+    select.replaceChildren(...state.units.map(unit => element("option", {value: unit.name, textContent: unit.name})));
+    select.value = selected;
+}
+
+function appendConsole(lines) {
+    const output = document.getElementById("console");
+    const atBottom = output.scrollTop + output.clientHeight >= output.scrollHeight - 5;
+    output.append(lines.map(line => line + "\n").join(""));
+    if (atBottom) {
+        output.scrollTop = output.scrollHeight;
+    }
+}
+
+async function openConsole(unitId) {
+    // lines that arrive while the backlog is read are appended after it
+    const consoleState = {unit: unitId, pending: []};
+    state.console = consoleState;
+    document.getElementById("console-unit").textContent = unitId;
+    document.getElementById("console").textContent = "";
+    document.getElementById("console-section").hidden = false;
+    let backlog = [];
+    try {
+        backlog = (await api("GET", unitPath(unitId) + "/logs/latest?limit=" + LOG_PAGE_SIZE)).lines;
+    } catch (error) {
+        // the unit has no logs yet
+    }
+    if (state.console !== consoleState) {
+        return;
+    }
+    // the backlog may already contain the first pending lines
+    let overlap = Math.min(backlog.length, consoleState.pending.length);
+    while (overlap > 0 && !backlog.slice(-overlap).every((line, i) => line === consoleState.pending[i])) {
+        overlap--;
+    }
+    appendConsole(backlog.concat(consoleState.pending.slice(overlap)));
+    consoleState.pending = null;
+}
+
+function receiveConsoleLine(event) {
+    const consoleState = state.console;
+    if (event.server_id !== consoleState.unit) {
+        return;
+    }
+    if (consoleState.pending !== null) {
+        consoleState.pending.push(event.line);
+    } else {
+        appendConsole([event.line]);
+    }
+}
+
+async function sendConsoleCommand(event) {
+    event.preventDefault();
+    const input = document.getElementById("console-input");
+    const command = input.value.trim();
+    if (command === "" || state.console.unit === null) {
+        return;
+    }
+    await api("POST", unitPath(state.console.unit) + "/command", {command});
+    input.value = "";
+}
+
+function renderLogs() {
+    const filter = document.getElementById("logs-filter").value.toLowerCase();
+    const lines = filter === ""
+        ? state.logs.lines
+        : state.logs.lines.filter(line => line.toLowerCase().includes(filter));
+    document.getElementById("logs").textContent = lines.join("\n");
+    document.getElementById("logs-older").disabled = state.logs.from === 0;
+}
+
+async function loadLogSession(session) {
+    const result = await api("GET", unitPath(state.logs.unit) + "/logs/" + encodeURIComponent(session) + "?limit=" + LOG_PAGE_SIZE);
+    state.logs.session = result.session;
+    state.logs.from = result.from;
+    state.logs.lines = result.lines;
+    renderLogs();
+    const output = document.getElementById("logs");
+    output.scrollTop = output.scrollHeight;
+}
+
+async function loadOlderLogs() {
+    const logs = state.logs;
+    const from = Math.max(0, logs.from - LOG_PAGE_SIZE);
+    const limit = logs.from - from;
+    const result = await api("GET", unitPath(logs.unit) + "/logs/" + encodeURIComponent(logs.session) + "?from=" + from + "&limit=" + limit);
+    logs.from = result.from;
+    logs.lines = result.lines.concat(logs.lines);
+    renderLogs();
+}
+
+async function openLogs(unitId) {
+    state.logs = {unit: unitId, session: null, from: 0, lines: []};
+    document.getElementById("logs-unit").textContent = unitId;
+    document.getElementById("logs-section").hidden = false;
+    const sessions = await api("GET", unitPath(unitId) + "/logs");
+    const select = document.getElementById("logs-session");
+    select.replaceChildren(...sessions.reverse().map(session =>
+        element("option", {value: session.id, textContent: session.id + " (" + Math.ceil(session.size / 1024) + " KiB)"})));
+    if (sessions.length > 0) {
+        await loadLogSession(sessions[0].id);
+    } else {
+        renderLogs();
+    }
+}
+
+async function refreshJobs() {
+    const jobs = await api("GET", "/api/jobs");
+    const container = document.getElementById("jobs");
+    container.replaceChildren(...jobs.slice().reverse().map(job => {
+        const progress = state.progress[job.unit_id] || job;
+        const bar = element("progress");
+        if (job.state !== "Running") {
+            bar.max = 1;
+            bar.value = 1;
+        } else if (progress.maximum !== null && progress.maximum !== undefined) {
+            bar.max = progress.maximum;
+            bar.value = progress.progress || 0;
+        }
+        let text = job.kind + " " + job.unit_id + ": " + job.state;
+        if (job.state === "Running" && progress.action) {
+            text += " – " + progress.action;
+        }
+        if (job.error) {
+            text += " – " + job.error;
+        }
+        return element("div", {className: "job " + job.state}, element("div", {textContent: text}), bar);
+    }));
+}
+
+function formValues(form) {
+    const values = {};
+    for (const [key, value] of new FormData(form).entries()) {
+        values[key] = value === "" ? null : value;
+    }
+    return values;
+}
+
+async function install(event) {
+    event.preventDefault();
+    const form = event.target;
+    const values = formValues(form);
+    values.accept_eula = form.elements.accept_eula.checked;
+    await api("POST", "/api/units", values);
+    form.reset();
+    await refreshJobs();
+}
+
+async function update(event) {
+    event.preventDefault();
+    const values = formValues(event.target);
+    await api("POST", unitPath(values.unit_id) + "/update", {server_version: values.server_version});
+    await refreshJobs();
+}
+
+function handleEvent(event) {
+    const [type, data] = Object.entries(event)[0];
+    switch (type) {
+        case "ActionProgress":
+            state.progress[data.server_id] = data;
+            refreshJobs();
+            break;
+        case "InstallationComplete":
+        case "InstallationFailed":
+        case "UpdateComplete":
+        case "UpdateFailed":
+            delete state.progress[data.server_id];
+            refreshJobs();
+            refreshUnits();
+            break;
+        case "ConsoleLine":
+            receiveConsoleLine(data);
+            break;
+        case "ClientConnected":
+        case "ClientDisconnected":
+        case "PlayerChat":
+        case "PlayerDeath":
+        case "PlayerAdvancement":
+        case "PerformanceDegraded":
+        case "Custom":
+            break;
+        default:
+            refreshUnits();
+    }
+}
+
+function connect() {
+    const protocol = location.protocol === "https:" ? "wss:" : "ws:";
+    const socket = new WebSocket(protocol + "//" + location.host + "/api/events");
+    const indicator = document.getElementById("connection");
+    socket.onopen = () => {
+        indicator.textContent = "connected";
+        indicator.className = "badge connected";
+    };
+    socket.onclose = () => {
+        indicator.textContent = "disconnected";
+        indicator.className = "badge disconnected";
+        state.socket = null;
+        setTimeout(connect, 2000);
+    };
+    socket.onmessage = message => {
+        const response = JSON.parse(message.data);
+        if (response.ServerEvent) {
+            handleEvent(response.ServerEvent.event);
+        }
+    };
+    state.socket = socket;
+}
+
+async function main() {
+    document.getElementById("console-form").addEventListener("submit", event => sendConsoleCommand(event).catch(error => alert(error.message)));
+    document.getElementById("install-form").addEventListener("submit", event => install(event).catch(error => alert(error.message)));
+    document.getElementById("update-form").addEventListener("submit", event => update(event).catch(error => alert(error.message)));
+    document.getElementById("logs-session").addEventListener("change", event => loadLogSession(event.target.value).catch(error => alert(error.message)));
+    document.getElementById("logs-filter").addEventListener("input", renderLogs);
+    document.getElementById("logs-older").addEventListener("click", () => loadOlderLogs().catch(error => alert(error.message)));
+
+    const version = await api("GET", "/api/version");
+    document.getElementById("daemon-version").textContent = "mcmand " + version.version;
+    await refreshUnits();
+    await refreshJobs();
+    connect();
+    setInterval(() => refreshUnits().catch(() => {}), UNIT_POLL_INTERVAL);
+}
+
+main().catch(error => alert(error.message));
@@ -0,0 +1,257 @@
+//! Minimal HTTP/1.1 primitives used by the HTTP interface.
+//!
+//! Only what is needed for a local management API is supported: every connection carries exactly
+//! one request (`Connection: close`) and bodies are delimited by `Content-Length`.
+
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+/// Maximum size of the request head (request line and headers).
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+/// Maximum size of a request body.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+/// Maximum number of headers parsed per request.
+const MAX_HEADERS: usize = 32;
+
+/// A connection accepted by the HTTP interface.
+pub enum HttpStream {
+    /// A connection on a TCP port
+    Tcp(TcpStream),
+    /// A connection on a unix socket
+    Unix(UnixStream),
+}
+
+impl HttpStream {
+    /// Sets the read timeout of the underlying socket.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            HttpStream::Tcp(stream) => stream.set_read_timeout(timeout),
+            HttpStream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            HttpStream::Tcp(stream) => stream.read(buf),
+            HttpStream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for HttpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            HttpStream::Tcp(stream) => stream.write(buf),
+            HttpStream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            HttpStream::Tcp(stream) => stream.flush(),
+            HttpStream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A parsed HTTP request.
+#[derive(Debug)]
+pub struct HttpRequest {
+    /// The request method (e.g. `GET`)
+    pub method: String,
+    /// The percent-decoded path segments of the request target
+    pub segments: Vec<String>,
+    /// The decoded query parameters of the request target
+    pub query: HashMap<String, String>,
+    /// The request headers, names are stored in lower case
+    pub headers: HashMap<String, String>,
+    /// The request body
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// Reads and parses a single request from the given stream.
+    pub fn read_from(stream: &mut impl Read) -> io::Result<HttpRequest> {
+        let mut buffer = Vec::with_capacity(1024);
+        let mut chunk = [0u8; 1024];
+        loop {
+            let read = stream.read(&mut chunk)?;
+            if read == 0 {
+                return Err(invalid_data(
+                    "connection closed before request was complete",
+                ));
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut request = httparse::Request::new(&mut headers);
+            match request.parse(&buffer) {
+                Ok(httparse::Status::Complete(head_length)) => {
+                    let method = request.method.unwrap_or("GET").to_string();
+                    let target = request.path.unwrap_or("/");
+                    let headers: HashMap<String, String> = request
+                        .headers
+                        .iter()
+                        .map(|header| {
+                            (
+                                header.name.to_ascii_lowercase(),
+                                String::from_utf8_lossy(header.value).to_string(),
+                            )
+                        })
+                        .collect();
+
+                    let (path, query) = match target.find('?') {
+                        Some(index) => (&target[..index], &target[index + 1..]),
+                        None => (target, ""),
+                    };
+                    let segments = path
+                        .split('/')
+                        .filter(|segment| !segment.is_empty())
+                        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().to_string())
+                        .collect();
+                    let query = url::form_urlencoded::parse(query.as_bytes())
+                        .into_owned()
+                        .collect();
+
+                    let content_length = match headers.get("content-length") {
+                        Some(length) => length
+                            .trim()
+                            .parse::<usize>()
+                            .map_err(|_| invalid_data("invalid content length"))?,
+                        None => 0,
+                    };
+                    if content_length > MAX_BODY_SIZE {
+                        return Err(invalid_data("request body too large"));
+                    }
+
+                    let mut body = buffer.split_off(head_length);
+                    if body.len() < content_length {
+                        let mut rest = vec![0u8; content_length - body.len()];
+                        stream.read_exact(&mut rest)?;
+                        body.extend_from_slice(&rest);
+                    }
+                    body.truncate(content_length);
+
+                    return Ok(HttpRequest {
+                        method,
+                        segments,
+                        query,
+                        headers,
+                        body,
+                    });
+                }
+                Ok(httparse::Status::Partial) => {
+                    if buffer.len() > MAX_HEAD_SIZE {
+                        return Err(invalid_data("request head too large"));
+                    }
+                }
+                Err(e) => return Err(invalid_data(&format!("malformed request: {}", e))),
+            }
+        }
+    }
+
+    /// Returns the value of a header (header names are case insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(|value| value.as_str())
+    }
+
+    /// Returns `true` if the client asks to upgrade the connection to a WebSocket.
+    pub fn is_websocket_upgrade(&self) -> bool {
+        self.header("upgrade")
+            .map(|value| value.eq_ignore_ascii_case("websocket"))
+            .unwrap_or(false)
+    }
+}
+
+/// A HTTP response that is written to the client before the connection is closed.
+#[derive(Debug)]
+pub struct HttpResponse {
+    /// The status code of the response
+    pub status: u16,
+    /// The value of the `Content-Type` header
+    pub content_type: &'static str,
+    /// The response body
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Creates a response with a JSON body.
+    pub fn json<T: Serialize + ?Sized>(status: u16, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Self {
+                status,
+                content_type: "application/json",
+                body,
+            },
+            Err(e) => Self::error(500, &format!("could not serialize response: {}", e)),
+        }
+    }
+
+    /// Creates a JSON response of the form `{"error": message}`.
+    pub fn error(status: u16, message: &str) -> Self {
+        Self::json(status, &serde_json::json!({ "error": message }))
+    }
+
+    /// Writes the response to the given stream.
+    pub fn write_to(&self, stream: &mut impl Write) -> io::Result<()> {
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            reason_phrase(self.status),
+            self.content_type,
+            self.body.len()
+        )?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+/// Returns the reason phrase for the status codes used by the HTTP interface.
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        415 => "Unsupported Media Type",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Unknown",
+    }
+}
+
+/// Creates an [`io::Error`] of kind [`io::ErrorKind::InvalidData`].
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::http::request::HttpRequest;
+
+    #[test]
+    fn test_parse_request() {
+        let raw = b"POST /api/units/my%20unit/command?wait=true HTTP/1.1\r\nHost: localhost\r\nContent-Length: 20\r\n\r\n{\"command\":\"list\"}  ";
+        let request = HttpRequest::read_from(&mut &raw[..]).expect("parse request");
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.segments, vec!["api", "units", "my unit", "command"]);
+        assert_eq!(request.query.get("wait").map(|s| s.as_str()), Some("true"));
+        assert_eq!(request.header("HOST"), Some("localhost"));
+        assert_eq!(request.body.len(), 20);
+    }
+}
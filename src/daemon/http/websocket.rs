@@ -0,0 +1,212 @@
+//! WebSocket event stream of the HTTP interface.
+//!
+//! A WebSocket client is registered as a regular daemon client. Every [`DaemonResponse`] the
+//! daemon sends to it (including subscribed [`crate::ipc::ServerEvent`]s) is forwarded as a JSON
+//! text message, and every text message received from it is parsed as a [`DaemonCmd`]. Only the
+//! commands that read information or change the subscriptions are accepted, everything else has
+//! to go through the REST API.
+
+use crate::daemon::event::EventHandler;
+use crate::daemon::http::request::{HttpRequest, HttpResponse, HttpStream};
+use crate::daemon::http::HttpInterface;
+use crate::ipc::{DaemonCmd, DaemonResponse, ServerEvent, ServerEventType};
+use log::{debug, warn};
+use std::io::{self, Write};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Duration;
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+/// Name under which WebSocket clients are registered.
+const CLIENT_NAME: &str = "http-websocket";
+
+/// Interval in which the connection checks for outgoing messages.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Performs the WebSocket handshake and serves the event stream until the client disconnects.
+///
+/// The query parameters `units` and `events` (comma separated) select the initial subscriptions.
+/// If `units` is missing, the events of all servers (and of the daemon itself) are subscribed. If
+/// `events` is missing, all event types are subscribed. Custom events of console triggers are
+/// selected with `Custom:<name>`, or `AnyCustom` for all names.
+pub fn serve(interface: &HttpInterface, request: HttpRequest, mut stream: HttpStream) {
+    let key = match request.header("sec-websocket-key") {
+        Some(key) => key.to_string(),
+        None => {
+            let _ =
+                HttpResponse::error(400, "missing Sec-WebSocket-Key header").write_to(&mut stream);
+            return;
+        }
+    };
+
+    let event_types = match request.query.get("events") {
+        Some(events) => match parse_event_types(events) {
+            Ok(event_types) => event_types,
+            Err(event_type) => {
+                let _ = HttpResponse::error(400, &format!("unknown event type {}", event_type))
+                    .write_to(&mut stream);
+                return;
+            }
+        },
+        None => ServerEventType::all().to_vec(),
+    };
+
+    let (client_id, responses) = interface.connect(CLIENT_NAME);
+    let mut event_handler = EventHandler::new(interface.event_manager_ctrl.clone());
+    event_handler.raise_daemon_event(ServerEvent::ClientConnected {
+        client_id,
+        client_name: CLIENT_NAME.to_string(),
+    });
+
+    let server_ids: Option<Vec<String>> = request.query.get("units").map(|units| {
+        units
+            .split(',')
+            .filter(|unit| !unit.is_empty())
+            .map(|unit| unit.to_string())
+            .collect()
+    });
+
+    for event_type in event_types {
+        interface.call_as(
+            client_id,
+            &responses,
+            DaemonCmd::SubscribeEvent {
+                event_type,
+                server_ids: server_ids.clone(),
+            },
+        );
+    }
+
+    if let Err(e) = accept(&mut stream, &key) {
+        debug!("websocket handshake failed: {}", e);
+        disconnect(interface, &mut event_handler, client_id);
+        return;
+    }
+    if let Err(e) = stream.set_read_timeout(Some(POLL_INTERVAL)) {
+        warn!("could not set read timeout on websocket connection: {}", e);
+        disconnect(interface, &mut event_handler, client_id);
+        return;
+    }
+
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+    forward(interface, client_id, &responses, &mut socket);
+
+    debug!("websocket client {} disconnected", client_id);
+    disconnect(interface, &mut event_handler, client_id);
+}
+
+/// Unregisters the client and announces its disconnect.
+fn disconnect(interface: &HttpInterface, event_handler: &mut EventHandler, client_id: u32) {
+    interface.disconnect(client_id);
+    event_handler.raise_daemon_event(ServerEvent::ClientDisconnected {
+        client_id,
+        client_name: CLIENT_NAME.to_string(),
+    });
+}
+
+/// Writes the handshake response for the given `Sec-WebSocket-Key`.
+fn accept(stream: &mut HttpStream, key: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes())
+    )?;
+    stream.flush()
+}
+
+/// Shuttles messages between the WebSocket and the daemon until either side goes away.
+fn forward(
+    interface: &HttpInterface,
+    client_id: u32,
+    responses: &Receiver<DaemonResponse>,
+    socket: &mut WebSocket<HttpStream>,
+) {
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let error = match serde_json::from_str::<DaemonCmd>(&text) {
+                    Ok(cmd) if is_allowed(&cmd) => {
+                        interface.send_as(client_id, cmd);
+                        None
+                    }
+                    Ok(cmd) => Some(format!("command {} is not allowed", cmd.name())),
+                    Err(e) => Some(format!("invalid command: {}", e)),
+                };
+                if let Some(error) = error {
+                    let error = serde_json::json!({ "error": error });
+                    if socket.send(Message::Text(error.to_string())).is_err() {
+                        return;
+                    }
+                }
+            }
+            Ok(Message::Close(_)) => return,
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+            }
+            Err(e) => {
+                debug!("websocket error: {}", e);
+                return;
+            }
+        }
+
+        loop {
+            match responses.try_recv() {
+                Ok(response) => match serde_json::to_string(&response) {
+                    Ok(text) => {
+                        if socket.send(Message::Text(text)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => warn!("could not serialize response {:?}: {}", response, e),
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+    }
+}
+
+/// Returns whether a command may be sent over the event stream.
+fn is_allowed(cmd: &DaemonCmd) -> bool {
+    matches!(
+        cmd,
+        DaemonCmd::List | DaemonCmd::GetVersion | DaemonCmd::SubscribeEvent { .. }
+    )
+}
+
+/// Parses a comma separated list of event type names, custom events are selected with
+/// `Custom:<name>`.
+///
+/// On failure the unknown event type is returned.
+fn parse_event_types(events: &str) -> Result<Vec<ServerEventType>, String> {
+    events
+        .split(',')
+        .filter(|event| !event.is_empty())
+        .map(|event| match event.strip_prefix("Custom:") {
+            Some(name) => Ok(ServerEventType::Custom(name.to_string())),
+            None => serde_json::from_value(serde_json::Value::String(event.to_string()))
+                .map_err(|_| event.to_string()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::http::websocket::is_allowed;
+    use crate::ipc::{DaemonCmd, ServerEventType};
+
+    #[test]
+    fn test_allowed_commands() {
+        assert!(is_allowed(&DaemonCmd::SubscribeEvent {
+            event_type: ServerEventType::ServerStarted,
+            server_ids: None,
+        }));
+        assert!(!is_allowed(&DaemonCmd::StopDaemon));
+        assert!(!is_allowed(&DaemonCmd::SendCommand {
+            unit_id: "a".to_string(),
+            command: "stop".to_string(),
+        }));
+    }
+}
@@ -0,0 +1,284 @@
+//! Implementation for the server software that runs as a Java jar.
+//!
+//! All supported server types are started and controlled the same way, they only differ in the
+//! flags they are started with and the console commands they understand. These are described by
+//! a [`Software`] per server type.
+
+use crate::config::{ServerConfig, TriggerConfig, UnitConfig};
+use crate::daemon::patterns::LogPatternSet;
+use crate::daemon::triggers::TriggerSet;
+use crate::daemon::{LogService, OutputState, Server};
+use crate::{ServerType, Unit};
+use semver::Version;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{Arc, RwLock};
+
+/// The flags recommended by Aikar for PaperMC servers, see <https://mcflags.emc.gs>
+const AIKAR_FLAGS: &[&str] = &[
+    "-XX:+UseG1GC",
+    "-XX:+ParallelRefProcEnabled",
+    "-XX:MaxGCPauseMillis=200",
+    "-XX:+UnlockExperimentalVMOptions",
+    "-XX:+DisableExplicitGC",
+    "-XX:+AlwaysPreTouch",
+    "-XX:G1NewSizePercent=30",
+    "-XX:G1MaxNewSizePercent=40",
+    "-XX:G1HeapRegionSize=8M",
+    "-XX:G1ReservePercent=20",
+    "-XX:G1HeapWastePercent=5",
+    "-XX:G1MixedGCCountTarget=4",
+    "-XX:InitiatingHeapOccupancyPercent=15",
+    "-XX:G1MixedGCLiveThresholdPercent=90",
+    "-XX:G1RSetUpdatingPauseTimePercent=5",
+    "-XX:SurvivorRatio=32",
+    "-XX:+PerfDisableSharedMem",
+    "-XX:MaxTenuringThreshold=1",
+    "-Dusing.aikars.flags=https://mcflags.emc.gs",
+    "-Daikars.new.flags=true",
+];
+
+/// The flags recommended by Velocity for proxies
+const PROXY_FLAGS: &[&str] = &[
+    "-XX:+UseG1GC",
+    "-XX:G1HeapRegionSize=4M",
+    "-XX:+UnlockExperimentalVMOptions",
+    "-XX:+ParallelRefProcEnabled",
+    "-XX:+AlwaysPreTouch",
+    "-XX:MaxInlineLevel=15",
+];
+
+/// How a server software is started and controlled
+pub struct Software {
+    /// The flags passed to the JVM in addition to the heap size
+    pub jvm_flags: &'static [&'static str],
+    /// The arguments passed to the jar
+    pub args: &'static [&'static str],
+    /// The console command that shuts the server down
+    pub stop_command: &'static str,
+    /// The console commands that make the server report its tick rate
+    pub performance_commands: &'static [&'static str],
+}
+
+/// Vanilla has no tick rate commands.
+static VANILLA: Software = Software {
+    jvm_flags: &[],
+    args: &["nogui"],
+    stop_command: "stop",
+    performance_commands: &[],
+};
+
+/// `tps` has been added by Spigot, `mspt` only exists in Paper.
+static SPIGOT: Software = Software {
+    jvm_flags: &[],
+    args: &["nogui"],
+    stop_command: "stop",
+    performance_commands: &["tps"],
+};
+
+/// Bukkit has neither `tps` nor `mspt`.
+static BUKKIT: Software = Software {
+    jvm_flags: &[],
+    args: &["nogui"],
+    stop_command: "stop",
+    performance_commands: &[],
+};
+
+/// Paper reports the tick rate with `tps` and `mspt`.
+static PAPER: Software = Software {
+    jvm_flags: AIKAR_FLAGS,
+    args: &["--nogui"],
+    stop_command: "stop",
+    performance_commands: &["tps", "mspt"],
+};
+
+/// Folia reports the tick rate of each region in its own format.
+static FOLIA: Software = Software {
+    jvm_flags: AIKAR_FLAGS,
+    args: &["--nogui"],
+    stop_command: "stop",
+    performance_commands: &[],
+};
+
+/// A proxy connects players to the servers of a network, it has no `nogui` argument and no tick
+/// rate.
+static VELOCITY: Software = Software {
+    jvm_flags: PROXY_FLAGS,
+    args: &[],
+    stop_command: "shutdown",
+    performance_commands: &[],
+};
+
+/// Waterfall shuts down with `end`, like BungeeCord.
+static WATERFALL: Software = Software {
+    jvm_flags: PROXY_FLAGS,
+    args: &[],
+    stop_command: "end",
+    performance_commands: &[],
+};
+
+impl Software {
+    /// Returns the software of the given server type.
+    pub fn of(server_type: &ServerType) -> &'static Software {
+        match server_type {
+            ServerType::Vanilla => &VANILLA,
+            ServerType::Spigot => &SPIGOT,
+            ServerType::Bukkit => &BUKKIT,
+            ServerType::Paper => &PAPER,
+            ServerType::Folia => &FOLIA,
+            ServerType::Velocity => &VELOCITY,
+            ServerType::Waterfall => &WATERFALL,
+        }
+    }
+}
+
+/// A server that runs as a Java jar
+pub struct JavaServer {
+    /// The config of this server
+    config: ServerConfig,
+    /// The config of the unit
+    unit_config: UnitConfig,
+    /// The input of the current server process
+    input: Option<ChildStdin>,
+    /// The path of the unit file
+    unit_file: PathBuf,
+    /// The rules used to parse the output of the server
+    patterns: LogPatternSet,
+    /// The console triggers of the unit
+    triggers: Vec<TriggerConfig>,
+    /// The type of the server
+    server_type: ServerType,
+    /// How the server software is started and controlled
+    software: &'static Software,
+}
+
+impl Server for JavaServer {
+    fn spawn(&mut self, log_service: &mut dyn LogService) -> (Child, Arc<RwLock<OutputState>>) {
+        let mut child = Command::new("java")
+            .arg(format!("-Xms{}G", self.config.memory))
+            .arg(format!("-Xmx{}G", self.config.memory))
+            .args(self.software.jvm_flags)
+            .arg("-jar")
+            .arg(&self.config.jar)
+            .args(self.software.args)
+            .current_dir(&self.config.path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .stdin(Stdio::piped())
+            .spawn()
+            .expect("spawn server process");
+        let output = child.stdout.take();
+        let errors = child.stderr.take();
+        self.input = child.stdin.take();
+
+        let status = log_service.manage_output(
+            output.expect("capture stdout of server process"),
+            errors.expect("capture stderr of server process"),
+            self.unit_config.id.clone(),
+            self.patterns.clone(),
+            self.config.logs.clone().unwrap_or_default(),
+            self.config.path.to_path_buf(),
+        );
+
+        (child, status)
+    }
+
+    fn send_command(&mut self, command: String) -> io::Result<()> {
+        match &mut self.input {
+            Some(input) => writeln!(input, "{}", command),
+            None => Ok(()),
+        }
+    }
+
+    fn server_type(&self) -> ServerType {
+        self.server_type.clone()
+    }
+
+    fn version(&self) -> Version {
+        self.config.version.clone()
+    }
+
+    fn path(&self) -> String {
+        self.config.path.to_string_lossy().to_string()
+    }
+
+    fn server_config(&self) -> ServerConfig {
+        self.config.clone()
+    }
+
+    fn triggers(&self) -> Vec<TriggerConfig> {
+        self.triggers.clone()
+    }
+
+    fn stop_command(&self) -> String {
+        self.software.stop_command.to_string()
+    }
+
+    fn performance_commands(&self) -> Vec<String> {
+        self.software
+            .performance_commands
+            .iter()
+            .map(|command| command.to_string())
+            .collect()
+    }
+}
+
+impl JavaServer {
+    /// Creates a new server of the given type from the given server config
+    ///
+    /// Fails if a log pattern or a trigger of the unit is not a valid regular expression.
+    pub fn create(
+        unit_config: UnitConfig,
+        config: ServerConfig,
+        triggers: Vec<TriggerConfig>,
+        unit_file: PathBuf,
+        server_type: ServerType,
+    ) -> Result<Self, regex::Error> {
+        let patterns = LogPatternSet::from_config(&server_type, config.log_patterns.as_ref())?
+            .with_triggers(TriggerSet::from_config(&triggers)?);
+        Ok(JavaServer {
+            config,
+            unit_config,
+            input: None,
+            unit_file,
+            patterns,
+            triggers,
+            software: Software::of(&server_type),
+            server_type,
+        })
+    }
+}
+
+impl Unit for JavaServer {
+    fn unit_file_path(&self) -> PathBuf {
+        self.unit_file.clone()
+    }
+
+    fn unit_config(&self) -> UnitConfig {
+        self.unit_config.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::java::Software;
+    use crate::ServerType;
+
+    #[test]
+    fn test_software() {
+        assert_eq!(Software::of(&ServerType::Vanilla).stop_command, "stop");
+        assert_eq!(Software::of(&ServerType::Velocity).stop_command, "shutdown");
+        assert_eq!(Software::of(&ServerType::Waterfall).stop_command, "end");
+        assert_eq!(Software::of(&ServerType::Paper).args, ["--nogui"]);
+        assert!(Software::of(&ServerType::Velocity).args.is_empty());
+        assert!(Software::of(&ServerType::Spigot).jvm_flags.is_empty());
+        assert_eq!(
+            Software::of(&ServerType::Paper).performance_commands,
+            ["tps", "mspt"]
+        );
+        assert!(Software::of(&ServerType::Folia)
+            .performance_commands
+            .is_empty());
+    }
+}
@@ -0,0 +1,369 @@
+//! Append-only journal of the commands and events of the daemon.
+//!
+//! Every command a client sends to the daemon is recorded together with the name and the user id
+//! of the client, every event that is raised is recorded as well. Console lines and the progress
+//! of actions are left out, the output of servers is kept in their log files.
+//!
+//! The journal is written as JSON lines to `journal.jsonl` in the state directory of the daemon.
+//! When the file exceeds its size limit, it is renamed to `journal-<time>.jsonl` and a new file is
+//! started; only the newest rotated files are kept.
+
+use crate::config::JournalConfig;
+use crate::daemon::notifier::event_type_name;
+use crate::ipc::{DaemonCmd, ServerEvent};
+use chrono::{DateTime, Utc};
+use log::warn;
+use std::collections::VecDeque;
+use std::fs::{create_dir_all, read_dir, remove_file, rename, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The name of the current journal file in the state directory.
+pub const JOURNAL_FILE: &str = "journal.jsonl";
+
+/// Bytes per MiB, the unit of [`JournalConfig::rotate_size_mb`].
+const MIB: u64 = 1024 * 1024;
+
+/// A journal that is shared between the daemon and the event manager.
+pub type SharedJournal = Arc<Mutex<Journal>>;
+
+/// An entry of the journal.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalEntry {
+    /// The time the entry has been recorded
+    pub time: DateTime<Utc>,
+    /// The unit the command or event refers to, `None` for the daemon itself
+    pub unit_id: Option<String>,
+    /// The recorded command or event
+    pub record: JournalRecord,
+}
+
+/// The content of a journal entry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum JournalRecord {
+    /// A client has sent a command
+    Command {
+        /// The id of the client, only unique while the daemon is running
+        client_id: u32,
+        /// The name the client has announced, e.g. `mcman` or `http`
+        client_name: Option<String>,
+        /// The user id of the client process, if it is connected over the IPC socket
+        client_uid: Option<u32>,
+        /// The command
+        cmd: DaemonCmd,
+    },
+    /// An event has been raised
+    Event {
+        /// The event
+        event: ServerEvent,
+    },
+}
+
+/// The entries that are read from the journal, see [`DaemonCmd::QueryJournal`].
+#[derive(Debug, Clone, Default)]
+pub struct JournalQuery {
+    /// Only entries of this unit are returned if set
+    pub unit_id: Option<String>,
+    /// Only entries recorded at or after this time are returned if set
+    pub since: Option<DateTime<Utc>>,
+    /// Only entries of these types are returned, all entries if empty
+    pub types: Vec<String>,
+    /// The maximum number of entries, the newest are returned; `0` returns all entries
+    pub limit: usize,
+}
+
+/// Writes and reads the journal files in the state directory.
+pub struct Journal {
+    /// The directory that contains the journal files
+    directory: PathBuf,
+    /// The rotation settings
+    config: JournalConfig,
+    /// The current journal file, opened for appending
+    file: File,
+    /// The size of the current journal file
+    size: u64,
+}
+
+impl JournalEntry {
+    /// Creates an entry for a command of a client.
+    pub fn command(
+        client_id: u32,
+        client_name: Option<String>,
+        client_uid: Option<u32>,
+        cmd: DaemonCmd,
+    ) -> Self {
+        Self {
+            time: Utc::now(),
+            unit_id: cmd.unit_id().map(str::to_string),
+            record: JournalRecord::Command {
+                client_id,
+                client_name,
+                client_uid,
+                cmd,
+            },
+        }
+    }
+
+    /// Creates an entry for an event.
+    pub fn event(event: ServerEvent) -> Self {
+        Self {
+            time: Utc::now(),
+            unit_id: event.server_id().map(str::to_string),
+            record: JournalRecord::Event { event },
+        }
+    }
+
+    /// Returns the type of the entry, the name of the command or the event type.
+    pub fn type_name(&self) -> String {
+        match &self.record {
+            JournalRecord::Command { cmd, .. } => cmd.name(),
+            JournalRecord::Event { event } => event_type_name(&event.get_event_type()),
+        }
+    }
+
+    /// Returns whether the entry is selected by a query, the limit is not considered.
+    pub fn matches(&self, query: &JournalQuery) -> bool {
+        let kind = match self.record {
+            JournalRecord::Command { .. } => "command",
+            JournalRecord::Event { .. } => "event",
+        };
+        let type_name = self.type_name();
+        query
+            .unit_id
+            .as_ref()
+            .is_none_or(|unit_id| self.unit_id.as_ref() == Some(unit_id))
+            && query.since.is_none_or(|since| self.time >= since)
+            && (query.types.is_empty()
+                || query.types.iter().any(|selected| {
+                    selected.eq_ignore_ascii_case(&type_name) || selected.eq_ignore_ascii_case(kind)
+                }))
+    }
+}
+
+impl JournalRecord {
+    /// Returns whether events of this kind are recorded.
+    pub fn is_recorded(event: &ServerEvent) -> bool {
+        !matches!(
+            event,
+            ServerEvent::ConsoleLine { .. } | ServerEvent::ActionProgress { .. }
+        )
+    }
+}
+
+impl Journal {
+    /// Opens the journal in the given directory, which is created if it does not exist.
+    pub fn open(directory: &Path, config: JournalConfig) -> io::Result<Self> {
+        create_dir_all(directory)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(directory.join(JOURNAL_FILE))?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            config,
+            file,
+            size,
+        })
+    }
+
+    /// Returns whether commands that only read information are recorded.
+    pub fn records_queries(&self) -> bool {
+        self.config.record_queries
+    }
+
+    /// Appends an entry and rotates the journal if it has become too large.
+    ///
+    /// Every entry is written with a single write, so entries are complete even if the daemon is
+    /// killed.
+    pub fn record(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        let mut line = serde_json::to_string(entry).expect("serialize journal entry");
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        if self.size >= self.config.rotate_size_mb * MIB {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Appends an entry and logs a failure instead of returning it.
+    pub fn record_or_warn(&mut self, entry: &JournalEntry) {
+        if let Err(e) = self.record(entry) {
+            warn!("could not write journal: {}", e);
+        }
+    }
+
+    /// Renames the current file, starts a new one and deletes the oldest rotated files.
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = format!("journal-{}.jsonl", Utc::now().format("%Y%m%dT%H%M%S%.3f"));
+        rename(
+            self.directory.join(JOURNAL_FILE),
+            self.directory.join(rotated),
+        )?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.directory.join(JOURNAL_FILE))?;
+        self.size = 0;
+
+        let rotated = self.rotated_files()?;
+        let expired = rotated.len().saturating_sub(self.config.keep_files);
+        for file in &rotated[..expired] {
+            remove_file(file)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the rotated journal files, oldest first.
+    fn rotated_files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        for entry in read_dir(&self.directory)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with("journal-") && name.ends_with(".jsonl") {
+                files.push(entry.path());
+            }
+        }
+        // the names contain the time of the rotation, so they sort chronologically
+        files.sort();
+        Ok(files)
+    }
+
+    /// Returns the journal files, oldest first, for [`query_files`].
+    pub fn files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files = self.rotated_files()?;
+        files.push(self.directory.join(JOURNAL_FILE));
+        Ok(files)
+    }
+
+    /// Returns the entries selected by a query, oldest first, see [`query_files`].
+    pub fn query(&self, query: &JournalQuery) -> io::Result<Vec<JournalEntry>> {
+        query_files(&self.files()?, query)
+    }
+}
+
+/// Returns the entries of the journal files selected by a query, oldest first.
+///
+/// The files are read without holding the lock of the journal, so a file may have been deleted by
+/// a rotation meanwhile; such files are skipped. Lines that can not be parsed, e.g. entries of an
+/// older version of the daemon, are skipped as well.
+pub fn query_files(files: &[PathBuf], query: &JournalQuery) -> io::Result<Vec<JournalEntry>> {
+    let mut entries = VecDeque::new();
+    for file in files {
+        let file = match File::open(file) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for line in BufReader::new(file).lines() {
+            let entry = match serde_json::from_str::<JournalEntry>(&line?) {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            if entry.matches(query) {
+                entries.push_back(entry);
+                if query.limit > 0 && entries.len() > query.limit {
+                    entries.pop_front();
+                }
+            }
+        }
+    }
+    Ok(entries.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::JournalConfig;
+    use crate::daemon::journal::{Journal, JournalEntry, JournalQuery, JOURNAL_FILE};
+    use crate::ipc::{DaemonCmd, ServerEvent};
+    use std::fs::read_dir;
+
+    #[test]
+    fn test_record_and_query() {
+        let directory = tempfile::tempdir().expect("create temp dir");
+        let mut journal =
+            Journal::open(directory.path(), JournalConfig::default()).expect("open journal");
+        let stop = DaemonCmd::Stop {
+            server_id: "a".to_string(),
+            wait: true,
+        };
+        journal
+            .record(&JournalEntry::command(
+                1,
+                Some("mcman".to_string()),
+                Some(1000),
+                stop,
+            ))
+            .expect("record command");
+        journal
+            .record(&JournalEntry::event(ServerEvent::ServerStopped {
+                server_id: "a".to_string(),
+            }))
+            .expect("record event");
+        journal
+            .record(&JournalEntry::event(ServerEvent::ServerStarted {
+                server_id: "b".to_string(),
+            }))
+            .expect("record event");
+
+        let entries = journal
+            .query(&JournalQuery {
+                unit_id: Some("a".to_string()),
+                ..Default::default()
+            })
+            .expect("query journal");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].type_name(), "Stop");
+        assert_eq!(entries[1].type_name(), "ServerStopped");
+
+        let query = |types: &[&str], limit| JournalQuery {
+            types: types.iter().map(|t| t.to_string()).collect(),
+            limit,
+            ..Default::default()
+        };
+        assert_eq!(
+            journal
+                .query(&query(&["command"], 0))
+                .expect("query journal")
+                .len(),
+            1
+        );
+        assert_eq!(
+            journal
+                .query(&query(&["serverstarted"], 0))
+                .expect("query journal")
+                .len(),
+            1
+        );
+        let newest = journal.query(&query(&["event"], 1)).expect("query journal");
+        assert_eq!(newest.len(), 1);
+        assert_eq!(newest[0].unit_id.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let directory = tempfile::tempdir().expect("create temp dir");
+        let config = JournalConfig {
+            rotate_size_mb: 0,
+            keep_files: 2,
+            ..JournalConfig::default()
+        };
+        let mut journal = Journal::open(directory.path(), config).expect("open journal");
+        for _ in 0..4 {
+            journal
+                .record(&JournalEntry::event(ServerEvent::ServerStarted {
+                    server_id: "a".to_string(),
+                }))
+                .expect("record event");
+            // rotated files are named by the time in milliseconds
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        let files = read_dir(directory.path()).expect("list journal").count();
+        assert_eq!(files, 3);
+        assert!(directory.path().join(JOURNAL_FILE).exists());
+        let entries = journal.query(&JournalQuery::default()).expect("query");
+        assert_eq!(entries.len(), 2);
+    }
+}
@@ -0,0 +1,85 @@
+//! Lockdown of servers during incidents, e.g. while an exploit is being abused.
+//!
+//! A lockdown kicks all players, lets the server write its worlds to disk with automatic saving
+//! turned off and suspends the server process with `SIGSTOP`. While the process is suspended, a
+//! snapshot of the worlds is taken with the label [`SNAPSHOT_LABEL`], so the state of the worlds at
+//! the time of the incident is kept. Unlocking resumes the process with `SIGCONT` and turns
+//! automatic saving on again.
+
+use std::io;
+
+/// The message players are kicked with if the lockdown has no message.
+pub const DEFAULT_MESSAGE: &str = "The server is in lockdown";
+
+/// The label of the snapshots taken during a lockdown.
+pub const SNAPSHOT_LABEL: &str = "lockdown";
+
+/// Returns the console command that kicks a player with a message.
+pub fn kick_command(player: &str, message: &str) -> String {
+    format!("kick {} {}", player, message)
+}
+
+/// Suspends a process.
+pub fn suspend(pid: u32) -> io::Result<()> {
+    signal(pid, libc::SIGSTOP)
+}
+
+/// Resumes a suspended process.
+pub fn resume(pid: u32) -> io::Result<()> {
+    signal(pid, libc::SIGCONT)
+}
+
+/// Sends a signal to a process.
+fn signal(pid: u32, signal: libc::c_int) -> io::Result<()> {
+    // SAFETY: kill has no memory safety preconditions
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::lockdown::{resume, suspend};
+    use std::process::Command;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    /// Returns the state of a process from `/proc/<pid>/stat`, e.g. `T` if it is stopped.
+    fn state(pid: u32) -> char {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).expect("read stat");
+        let fields = &stat[stat.rfind(')').expect("find end of command") + 2..];
+        fields.chars().next().expect("read state")
+    }
+
+    /// Waits up to a second for a process to reach a state, signals are delivered asynchronously.
+    fn wait_for_state(pid: u32, expected: char) -> char {
+        let mut current = state(pid);
+        for _ in 0..100 {
+            if current == expected {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+            current = state(pid);
+        }
+        current
+    }
+
+    #[test]
+    fn test_suspend_and_resume() {
+        let mut child = Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .expect("spawn sleep");
+        suspend(child.id()).expect("suspend process");
+        let suspended = wait_for_state(child.id(), 'T');
+        resume(child.id()).expect("resume process");
+        let resumed = wait_for_state(child.id(), 'S');
+        child.kill().expect("kill process");
+        child.wait().expect("wait for process");
+        assert_eq!(suspended, 'T');
+        assert_eq!(resumed, 'S');
+        assert!(suspend(child.id()).is_err());
+    }
+}
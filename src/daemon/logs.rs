@@ -0,0 +1,596 @@
+//! Access to the log files written by the log service.
+//!
+//! The output of every server process is stored in `log/<unit_id>/<session>_out.log`, where a
+//! session is one run of the server process or, if the log was rotated, a part of it. Closed
+//! sessions may be compressed (`_out.log.gz` or `_out.log.zst`), they are decompressed
+//! transparently when read.
+//!
+//! Next to the plain text every session is stored as JSON lines (`<session>_out.jsonl`), one
+//! [`LogRecord`] per line, for log shippers.
+
+use crate::config::LogCompression;
+use crate::daemon::records::{LogLevel, LogRecord, RecordParser};
+use crate::ipc::DaemonResponse;
+use flate2::read::GzDecoder;
+use regex::Regex;
+use std::collections::VecDeque;
+use std::fs::{read_dir, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// The directory (relative to the working directory of the daemon) in which logs are stored.
+pub const LOG_DIRECTORY: &str = "log";
+
+/// Suffix of the files that contain the output of a server process.
+pub const OUT_LOG_SUFFIX: &str = "_out.log";
+
+/// Suffix of the files that contain the output of a server process as JSON lines.
+pub const OUT_RECORDS_SUFFIX: &str = "_out.jsonl";
+
+/// The formats in which the output of a session is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The plain output lines (`_out.log`)
+    Text,
+    /// One JSON object per output line (`_out.jsonl`)
+    Records,
+}
+
+/// The compressions in the order in which their files are preferred when reading a session.
+///
+/// While a session is being compressed both files exist, the uncompressed one is complete.
+const READ_ORDER: [LogCompression; 3] = [
+    LogCompression::None,
+    LogCompression::Gzip,
+    LogCompression::Zstd,
+];
+
+/// A log session, i.e. the output of a single run of a server process.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogSession {
+    /// The id of the session
+    pub id: String,
+    /// The size of the log file in bytes (compressed size for compressed sessions)
+    pub size: u64,
+    /// Whether the log file is compressed
+    #[serde(default)]
+    pub compressed: bool,
+}
+
+/// A range of lines read from a log session.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogLines {
+    /// The session the lines were read from
+    pub session: String,
+    /// The index of the first returned line
+    pub from: usize,
+    /// The number of lines read from the session.
+    ///
+    /// If the lines were read from a given index, reading stops after the last returned line.
+    /// Otherwise this is the total number of lines in the session.
+    pub total: usize,
+    /// The lines read from the session
+    pub lines: Vec<String>,
+    /// The index of every returned line in the session
+    pub line_numbers: Vec<usize>,
+    /// The returned lines as structured records, only filled if requested
+    #[serde(default)]
+    pub records: Vec<LogRecord>,
+}
+
+/// The lines of a session that should be read, see [`crate::ipc::DaemonCmd::GetLogs`].
+#[derive(Debug, Clone, Default)]
+pub struct LogQuery {
+    /// The log session to read, the newest session if `None`
+    pub session: Option<String>,
+    /// The index of the first line to read. If `None` the last `limit` lines are read.
+    pub from: Option<usize>,
+    /// The maximum number of lines to read, `0` only lists the sessions
+    pub limit: usize,
+    /// A regular expression, only matching lines are returned if set
+    pub grep: Option<String>,
+    /// The minimum level of the returned lines
+    pub level: Option<LogLevel>,
+    /// Whether the lines should also be returned as structured records
+    pub records: bool,
+}
+
+/// Selects the lines returned from a session.
+#[derive(Debug, Default)]
+pub struct LineFilter {
+    /// Only lines matching the regular expression are returned if set
+    pub grep: Option<Regex>,
+    /// Only lines with at least this level are returned if set
+    pub level: Option<LogLevel>,
+}
+
+impl LineFilter {
+    /// Returns whether a line (as written to the log) and its record pass the filter.
+    pub fn matches(&self, line: &str, record: &LogRecord) -> bool {
+        self.grep.as_ref().is_none_or(|grep| grep.is_match(line))
+            && self
+                .level
+                .is_none_or(|level| record.level.is_some_and(|own| own >= level))
+    }
+}
+
+/// Returns the directory in which the logs of a unit are stored.
+pub fn unit_log_directory(log_directory: &Path, unit_id: &str) -> PathBuf {
+    log_directory.join(unit_id)
+}
+
+impl LogFormat {
+    /// Returns the suffix of uncompressed files of this format.
+    fn suffix(self) -> &'static str {
+        match self {
+            LogFormat::Text => OUT_LOG_SUFFIX,
+            LogFormat::Records => OUT_RECORDS_SUFFIX,
+        }
+    }
+}
+
+/// Returns the file name of a session stored in the given format and compression.
+pub fn log_file_name(session: &str, format: LogFormat, compression: LogCompression) -> String {
+    match compression {
+        LogCompression::None => format!("{}{}", session, format.suffix()),
+        LogCompression::Gzip => format!("{}{}.gz", session, format.suffix()),
+        LogCompression::Zstd => format!("{}{}.zst", session, format.suffix()),
+    }
+}
+
+/// Returns the session, format and compression of a log file, `None` if it is not a log file.
+pub fn parse_log_file_name(file_name: &str) -> Option<(&str, LogFormat, LogCompression)> {
+    let (name, compression) = if let Some(name) = file_name.strip_suffix(".gz") {
+        (name, LogCompression::Gzip)
+    } else if let Some(name) = file_name.strip_suffix(".zst") {
+        (name, LogCompression::Zstd)
+    } else {
+        (file_name, LogCompression::None)
+    };
+    [LogFormat::Text, LogFormat::Records]
+        .iter()
+        .find_map(|format| {
+            name.strip_suffix(format.suffix())
+                .map(|session| (session, *format, compression))
+        })
+}
+
+/// Returns a session id for a log file created now.
+///
+/// Session ids sort chronologically and only contain characters that are safe in file names on
+/// all platforms.
+pub fn new_session_id() -> String {
+    chrono::Utc::now()
+        .format("%Y-%m-%dT%H-%M-%S%.3fZ")
+        .to_string()
+}
+
+/// Lists the log sessions of a unit, oldest first.
+pub fn list_sessions(log_directory: &Path, unit_id: &str) -> io::Result<Vec<LogSession>> {
+    check_path_component(unit_id)?;
+    let directory = unit_log_directory(log_directory, unit_id);
+    if !directory.exists() {
+        return Ok(vec![]);
+    }
+    let mut sessions = vec![];
+    for entry in read_dir(directory)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if let Some((id, LogFormat::Text, compression)) = parse_log_file_name(&file_name) {
+            sessions.push((
+                compression,
+                LogSession {
+                    id: id.to_string(),
+                    size: entry.metadata()?.len(),
+                    compressed: compression != LogCompression::None,
+                },
+            ));
+        }
+    }
+    // a session that is being compressed is listed once, with its uncompressed file
+    sessions.sort_by_key(|(compression, session)| {
+        (
+            session.id.clone(),
+            READ_ORDER.iter().position(|c| c == compression),
+        )
+    });
+    sessions.dedup_by(|(_, a), (_, b)| a.id == b.id);
+    Ok(sessions.into_iter().map(|(_, session)| session).collect())
+}
+
+/// Reads up to `limit` lines of a log session.
+///
+/// If `session` is `None` the newest session is used. If `from` is `None` the last `limit` lines
+/// are returned. If `filter` is set, only matching lines are returned and `from` is the index of
+/// the first line that is searched. The records of the lines are parsed from the plain text, they
+/// are only returned if `records` is set.
+///
+/// The session is read line by line and only the selected lines are kept in memory.
+pub fn read_lines(
+    log_directory: &Path,
+    unit_id: &str,
+    session: Option<&str>,
+    from: Option<usize>,
+    limit: usize,
+    filter: &LineFilter,
+    records: bool,
+) -> io::Result<LogLines> {
+    let session = match session {
+        Some(session) => session.to_string(),
+        None => match list_sessions(log_directory, unit_id)?.pop() {
+            Some(session) => session.id,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no logs found for unit {}", unit_id),
+                ))
+            }
+        },
+    };
+    check_path_component(unit_id)?;
+    check_path_component(&session)?;
+
+    let reader = BufReader::new(open_session(
+        &unit_log_directory(log_directory, unit_id),
+        &session,
+    )?);
+    let mut parser = RecordParser::default();
+    let mut total = 0;
+    // only the selected lines are kept, with `from` reading stops once `limit` lines are selected
+    let mut selected: VecDeque<(usize, String, LogRecord)> = VecDeque::new();
+    for (number, line) in reader.lines().enumerate() {
+        if from.is_some() && selected.len() >= limit {
+            break;
+        }
+        let line = line?;
+        total = number + 1;
+        // the parser has to see every line, a record can inherit the level of its predecessor
+        let record = parser.parse_tagged(&line);
+        if from.is_some_and(|from| number < from) || !filter.matches(&line, &record) {
+            continue;
+        }
+        selected.push_back((number, line, record));
+        if from.is_none() && selected.len() > limit {
+            selected.pop_front();
+        }
+    }
+
+    let from = selected
+        .front()
+        .map(|(number, _, _)| *number)
+        .unwrap_or_else(|| from.unwrap_or(total).min(total));
+    let mut line_numbers = Vec::with_capacity(selected.len());
+    let mut lines = Vec::with_capacity(selected.len());
+    let mut selected_records = Vec::with_capacity(selected.len());
+    for (number, line, record) in selected {
+        line_numbers.push(number);
+        lines.push(line);
+        selected_records.push(record);
+    }
+
+    Ok(LogLines {
+        session,
+        from,
+        total,
+        lines,
+        line_numbers,
+        records: if records { selected_records } else { vec![] },
+    })
+}
+
+/// Opens the log file of a session for reading, decompressing it if required.
+fn open_session(directory: &Path, session: &str) -> io::Result<Box<dyn Read>> {
+    for compression in READ_ORDER.iter() {
+        let path = directory.join(log_file_name(session, LogFormat::Text, *compression));
+        if !path.exists() {
+            continue;
+        }
+        let file = File::open(path)?;
+        return Ok(match compression {
+            LogCompression::None => Box::new(file),
+            LogCompression::Gzip => Box::new(GzDecoder::new(file)),
+            LogCompression::Zstd => Box::new(zstd::Decoder::new(file)?),
+        });
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("log session {} not found", session),
+    ))
+}
+
+/// Answers a [`crate::ipc::DaemonCmd::GetLogs`] command.
+///
+/// Reading a large or compressed session takes long, so this should not be called on the main
+/// daemon thread. The lines are only read if `limit` is not `0` and the unit has at least one log session.
+pub fn get_logs(log_directory: &Path, unit_id: String, query: LogQuery) -> DaemonResponse {
+    let LogQuery {
+        session,
+        from,
+        limit,
+        grep,
+        level,
+        records,
+    } = query;
+    let grep = match grep.as_deref().map(Regex::new).transpose() {
+        Ok(grep) => grep,
+        Err(e) => {
+            return DaemonResponse::InvalidRequest {
+                error: format!("invalid search pattern: {}", e),
+            }
+        }
+    };
+    let sessions = match list_sessions(log_directory, &unit_id) {
+        Ok(sessions) => sessions,
+        Err(e) => return io_error_response(e),
+    };
+    let session = match session.or_else(|| sessions.last().map(|session| session.id.clone())) {
+        Some(session) if limit > 0 => session,
+        _ => {
+            return DaemonResponse::Logs {
+                unit_id,
+                sessions,
+                lines: None,
+            }
+        }
+    };
+    if !sessions.iter().any(|known| known.id == session) {
+        return DaemonResponse::LogSessionNotFound { unit_id, session };
+    }
+
+    match read_lines(
+        log_directory,
+        &unit_id,
+        Some(&session),
+        from,
+        limit,
+        &LineFilter { grep, level },
+        records,
+    ) {
+        Ok(lines) => DaemonResponse::Logs {
+            unit_id,
+            sessions,
+            lines: Some(lines),
+        },
+        Err(e) => io_error_response(e),
+    }
+}
+
+/// Converts an error while accessing the logs to a response.
+fn io_error_response(error: io::Error) -> DaemonResponse {
+    if error.kind() == io::ErrorKind::InvalidInput {
+        DaemonResponse::InvalidRequest {
+            error: error.to_string(),
+        }
+    } else {
+        DaemonResponse::Error {
+            error: error.to_string(),
+        }
+    }
+}
+
+/// Makes sure that a name received from a client can not be used to escape the log directory.
+fn check_path_component(name: &str) -> io::Result<()> {
+    if name.is_empty() || name.contains('/') || name.contains("..") {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid name {}", name),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::logs::{get_logs, list_sessions, read_lines, LineFilter, LogQuery};
+    use crate::daemon::records::LogLevel;
+    use crate::ipc::DaemonResponse;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use regex::Regex;
+    use std::fs::{create_dir_all, write, File};
+    use std::io::Write;
+
+    #[test]
+    fn test_read_lines() {
+        let log_directory = tempfile::tempdir().expect("create temp dir");
+        let unit_directory = log_directory.path().join("unit");
+        create_dir_all(&unit_directory).expect("create unit log dir");
+        write(unit_directory.join("2021-01-01_out.log"), "old\n").expect("write log");
+        write(unit_directory.join("2021-01-02_out.log"), "a\nb\nc\nd\n").expect("write log");
+
+        let sessions = list_sessions(log_directory.path(), "unit").expect("list sessions");
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[1].id, "2021-01-02");
+
+        let tail = read_lines(
+            log_directory.path(),
+            "unit",
+            None,
+            None,
+            2,
+            &LineFilter::default(),
+            false,
+        )
+        .expect("read tail");
+        assert_eq!(tail.session, "2021-01-02");
+        assert_eq!(tail.from, 2);
+        assert_eq!(tail.total, 4);
+        assert_eq!(tail.lines, vec!["c", "d"]);
+        assert_eq!(tail.line_numbers, vec![2, 3]);
+
+        let range = read_lines(
+            log_directory.path(),
+            "unit",
+            Some("2021-01-02"),
+            Some(1),
+            2,
+            &LineFilter::default(),
+            false,
+        )
+        .expect("read range");
+        assert_eq!(range.lines, vec!["b", "c"]);
+        // reading stops after the last requested line
+        assert_eq!(range.total, 3);
+
+        assert!(read_lines(
+            log_directory.path(),
+            "unit",
+            Some("../x"),
+            None,
+            2,
+            &LineFilter::default(),
+            false
+        )
+        .is_err());
+        assert!(list_sessions(log_directory.path(), "../unit").is_err());
+    }
+
+    #[test]
+    fn test_grep() {
+        let log_directory = tempfile::tempdir().expect("create temp dir");
+        let unit_directory = log_directory.path().join("unit");
+        create_dir_all(&unit_directory).expect("create unit log dir");
+        write(
+            unit_directory.join("2021-01-01_out.log"),
+            "Steve joined\nAlex joined\nSteve left\nAlex left\n",
+        )
+        .expect("write log");
+
+        let steve = LineFilter {
+            grep: Some(Regex::new("^Steve").expect("compile pattern")),
+            level: None,
+        };
+        let matches = read_lines(log_directory.path(), "unit", None, None, 10, &steve, false)
+            .expect("grep session");
+        assert_eq!(matches.lines, vec!["Steve joined", "Steve left"]);
+        assert_eq!(matches.line_numbers, vec![0, 2]);
+
+        let later = read_lines(
+            log_directory.path(),
+            "unit",
+            None,
+            Some(1),
+            10,
+            &steve,
+            false,
+        )
+        .expect("grep from line");
+        assert_eq!(later.line_numbers, vec![2]);
+
+        match get_logs(
+            log_directory.path(),
+            "unit".to_string(),
+            LogQuery::default(),
+        ) {
+            DaemonResponse::Logs {
+                sessions, lines, ..
+            } => {
+                assert_eq!(sessions.len(), 1);
+                assert!(lines.is_none());
+            }
+            response => panic!("unexpected response {:?}", response),
+        }
+        assert!(matches!(
+            get_logs(
+                log_directory.path(),
+                "unit".to_string(),
+                LogQuery {
+                    limit: 10,
+                    grep: Some("(".to_string()),
+                    ..LogQuery::default()
+                }
+            ),
+            DaemonResponse::InvalidRequest { .. }
+        ));
+        assert!(matches!(
+            get_logs(
+                log_directory.path(),
+                "unit".to_string(),
+                LogQuery {
+                    session: Some("2020-01-01".to_string()),
+                    limit: 10,
+                    ..LogQuery::default()
+                }
+            ),
+            DaemonResponse::LogSessionNotFound { .. }
+        ));
+    }
+
+    #[test]
+    fn test_compressed_sessions() {
+        let log_directory = tempfile::tempdir().expect("create temp dir");
+        let unit_directory = log_directory.path().join("unit");
+        create_dir_all(&unit_directory).expect("create unit log dir");
+
+        let mut gzip = GzEncoder::new(
+            File::create(unit_directory.join("2021-01-01_out.log.gz")).expect("create gzip log"),
+            Compression::default(),
+        );
+        gzip.write_all(b"gzip a\ngzip b\n").expect("write gzip log");
+        gzip.finish().expect("finish gzip log");
+        let zstd = zstd::encode_all(&b"zstd a\n"[..], 0).expect("compress zstd log");
+        write(unit_directory.join("2021-01-02_out.log.zst"), zstd).expect("write zstd log");
+        // a session that is being compressed
+        write(unit_directory.join("2021-01-03_out.log"), "plain\n").expect("write log");
+        write(unit_directory.join("2021-01-03_out.log.gz"), "partial").expect("write log");
+
+        let sessions = list_sessions(log_directory.path(), "unit").expect("list sessions");
+        let ids: Vec<&str> = sessions.iter().map(|session| session.id.as_str()).collect();
+        assert_eq!(ids, vec!["2021-01-01", "2021-01-02", "2021-01-03"]);
+        assert!(sessions[0].compressed && sessions[1].compressed);
+        assert!(!sessions[2].compressed);
+
+        let read = |session| {
+            read_lines(
+                log_directory.path(),
+                "unit",
+                Some(session),
+                None,
+                10,
+                &LineFilter::default(),
+                false,
+            )
+            .expect("read session")
+            .lines
+        };
+        assert_eq!(read("2021-01-01"), vec!["gzip a", "gzip b"]);
+        assert_eq!(read("2021-01-02"), vec!["zstd a"]);
+        assert_eq!(read("2021-01-03"), vec!["plain"]);
+    }
+
+    #[test]
+    fn test_level_filter() {
+        let log_directory = tempfile::tempdir().expect("create temp dir");
+        let unit_directory = log_directory.path().join("unit");
+        create_dir_all(&unit_directory).expect("create unit log dir");
+        write(
+            unit_directory.join("2021-01-01_out.log"),
+            "[12:00:00 INFO]: Starting\n[12:00:01 ERROR]: Could not pass event\n\tat Foo.bar\n\
+             [12:00:02 INFO]: Done\n[stderr] Exception in thread \"main\"\n",
+        )
+        .expect("write log");
+
+        match get_logs(
+            log_directory.path(),
+            "unit".to_string(),
+            LogQuery {
+                limit: 10,
+                level: Some(LogLevel::Warn),
+                records: true,
+                ..LogQuery::default()
+            },
+        ) {
+            DaemonResponse::Logs {
+                lines: Some(lines), ..
+            } => {
+                assert_eq!(lines.line_numbers, vec![1, 2, 4]);
+                assert_eq!(lines.records.len(), 3);
+                assert_eq!(lines.records[0].message, "Could not pass event");
+                assert_eq!(lines.records[1].level, Some(LogLevel::Error));
+                assert_eq!(lines.records[2].message, "Exception in thread \"main\"");
+            }
+            response => panic!("unexpected response {:?}", response),
+        }
+    }
+}
@@ -0,0 +1,632 @@
+//! Prometheus metrics endpoint of the daemon.
+//!
+//! The endpoint answers `GET /metrics` in the Prometheus text format. Like the HTTP interface, it
+//! registers itself as a local client and requests the current values with
+//! [`DaemonCmd::GetMetrics`]. All unit metrics are labeled with `unit` and `server_type`:
+//!
+//! | Metric                                          | Type    | Description                              |
+//! |-------------------------------------------------|---------|------------------------------------------|
+//! | `mcman_unit_status`                             | gauge   | 1 for the current `status` of the unit   |
+//! | `mcman_unit_up`                                 | gauge   | 1 if the server is running               |
+//! | `mcman_unit_process_cpu_seconds_total`          | counter | CPU time of the server process           |
+//! | `mcman_unit_process_resident_memory_bytes`      | gauge   | Resident memory of the server process    |
+//! | `mcman_unit_starts_total`                       | counter | Starts of the server                     |
+//! | `mcman_unit_restarts_total`                     | counter | Restarts requested by clients            |
+//! | `mcman_unit_failures_total`                     | counter | Failures of the server                   |
+//! | `mcman_unit_online_players`                     | gauge   | Players on the server                    |
+//! | `mcman_unit_tps`                                | gauge   | Ticks per second of the last minute      |
+//! | `mcman_unit_mspt`                               | gauge   | Milliseconds per tick of the last minute |
+//! | `mcman_unit_installs_total`                     | counter | Installations by `outcome`               |
+//! | `mcman_unit_install_duration_seconds`           | summary | Duration of installations                |
+//! | `mcman_unit_updates_total`                      | counter | Updates by `outcome`                     |
+//! | `mcman_unit_update_duration_seconds`            | summary | Duration of updates                      |
+//! | `mcman_clients`                                 | gauge   | Connected clients by `connection`        |
+//! | `mcman_event_queue_depth`                       | gauge   | Events waiting to be dispatched          |
+//!
+//! Counters are kept by the daemon while it is running, they survive reloads and updates of a
+//! unit but start over when the daemon is restarted. TPS and tick times are only exported when
+//! they are known from polling the server.
+
+use crate::config::MetricsConfig;
+use crate::daemon::client::{ClientConnection, ClientRegistry};
+use crate::daemon::http::request::{HttpRequest, HttpResponse, HttpStream};
+use crate::daemon::DaemonEvent;
+use crate::ipc::{DaemonCmd, DaemonResponse};
+use crate::{ServerStatus, ServerType};
+use log::{debug, info, warn};
+use std::fmt::Write;
+use std::fs::remove_file;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+/// Address the metrics endpoint binds to if neither an address nor a socket file is configured.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:9520";
+
+/// The content type of the Prometheus text format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// The time to wait for the daemon to answer a request for the metrics.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The names of the server states in the `status` label of `mcman_unit_status`.
+const STATUS_NAMES: [&str; 8] = [
+    "unknown", "starting", "running", "updating", "down", "lockdown", "errored", "stopping",
+];
+
+/// Outcomes and durations of installations or updates of a unit.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ActionStats {
+    /// The number of actions that have succeeded
+    pub successes: u64,
+    /// The number of actions that have failed
+    pub failures: u64,
+    /// The total duration of the finished actions in seconds
+    pub duration_seconds_sum: f64,
+    /// The number of finished actions whose duration is known
+    pub duration_count: u64,
+    /// The time the running action has been started
+    #[serde(skip)]
+    started: Option<Instant>,
+}
+
+/// Counters of a unit, kept by the daemon across reloads and updates of the unit.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UnitCounters {
+    /// The number of times the server has been started
+    pub starts: u64,
+    /// The number of restarts requested by clients
+    pub restarts: u64,
+    /// The number of times the server has failed
+    pub failures: u64,
+    /// The installations of the unit
+    pub installs: ActionStats,
+    /// The updates of the unit
+    pub updates: ActionStats,
+}
+
+/// Resource usage of a server process.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ProcessStats {
+    /// The CPU time the process has used in user and kernel mode, in seconds
+    pub cpu_seconds: f64,
+    /// The resident memory of the process in bytes
+    pub memory_bytes: u64,
+}
+
+/// The current metrics of a unit.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnitMetrics {
+    /// The id of the unit
+    pub unit_id: String,
+    /// The server type, `None` if the unit does not exist (anymore), e.g. after a failed install
+    pub server_type: Option<ServerType>,
+    /// The status of the server, `None` if the unit does not exist
+    pub status: Option<ServerStatus>,
+    /// The resource usage of the server process, if it is running
+    pub process: Option<ProcessStats>,
+    /// The number of players on the server
+    pub online_players: usize,
+    /// The ticks per second of the last minute, if known
+    pub tps: Option<f64>,
+    /// The milliseconds per tick of the last minute, if known
+    pub mspt: Option<f64>,
+    /// The counters of the unit
+    pub counters: UnitCounters,
+}
+
+/// The current metrics of the daemon itself.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DaemonMetrics {
+    /// The number of clients connected over the IPC socket
+    pub ipc_clients: usize,
+    /// The number of clients inside the daemon, e.g. HTTP requests and webhooks
+    pub local_clients: usize,
+    /// The number of events waiting to be dispatched
+    pub pending_events: usize,
+}
+
+/// The Prometheus metrics endpoint.
+pub struct MetricsExporter {
+    /// The configuration of the endpoint
+    config: MetricsConfig,
+    /// Queue to the main thread of the daemon
+    daemon_queue: Sender<DaemonEvent>,
+    /// The registry the endpoint registers its requests in
+    clients: ClientRegistry,
+}
+
+impl ActionStats {
+    /// Records the start of an action.
+    pub fn start(&mut self) {
+        self.started = Some(Instant::now());
+    }
+
+    /// Records the end of the running action.
+    pub fn finish(&mut self, success: bool) {
+        if success {
+            self.successes += 1;
+        } else {
+            self.failures += 1;
+        }
+        if let Some(started) = self.started.take() {
+            self.duration_seconds_sum += started.elapsed().as_secs_f64();
+            self.duration_count += 1;
+        }
+    }
+}
+
+impl ProcessStats {
+    /// Reads the resource usage of a process from `/proc`.
+    #[cfg(target_os = "linux")]
+    pub fn read(pid: u32) -> Option<Self> {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        // the command name may contain spaces, the fields after it start with the state
+        let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+        let utime: u64 = fields.get(11)?.parse().ok()?;
+        let stime: u64 = fields.get(12)?.parse().ok()?;
+        let statm = std::fs::read_to_string(format!("/proc/{}/statm", pid)).ok()?;
+        let resident_pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+
+        // SAFETY: sysconf has no preconditions
+        let (ticks_per_second, page_size) = unsafe {
+            (
+                libc::sysconf(libc::_SC_CLK_TCK),
+                libc::sysconf(libc::_SC_PAGESIZE),
+            )
+        };
+        if ticks_per_second <= 0 || page_size <= 0 {
+            return None;
+        }
+        Some(Self {
+            cpu_seconds: (utime + stime) as f64 / ticks_per_second as f64,
+            memory_bytes: resident_pages * page_size as u64,
+        })
+    }
+
+    /// Reads the resource usage of a process, only supported on Linux.
+    #[cfg(not(target_os = "linux"))]
+    pub fn read(_pid: u32) -> Option<Self> {
+        None
+    }
+}
+
+impl MetricsExporter {
+    /// Creates a new metrics endpoint.
+    pub fn new(
+        config: MetricsConfig,
+        daemon_queue: Sender<DaemonEvent>,
+        clients: ClientRegistry,
+    ) -> Self {
+        Self {
+            config,
+            daemon_queue,
+            clients,
+        }
+    }
+
+    /// Binds the configured socket and spawns a thread answering the requests one by one.
+    pub fn run(self) -> io::Result<()> {
+        if let Some(socket_file) = self.config.socket_file.clone() {
+            let path = Path::new(&socket_file);
+            if path.exists() {
+                remove_file(path)?;
+            }
+            let listener = UnixListener::bind(path)?;
+            info!("metrics endpoint listening on unix socket {}", socket_file);
+            spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => self.handle_connection(HttpStream::Unix(stream)),
+                        Err(e) => warn!("error on incoming metrics connection: {}", e),
+                    }
+                }
+            });
+        } else {
+            let address = self
+                .config
+                .address
+                .clone()
+                .unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+            let listener = TcpListener::bind(&address)?;
+            info!("metrics endpoint listening on {}", address);
+            spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => self.handle_connection(HttpStream::Tcp(stream)),
+                        Err(e) => warn!("error on incoming metrics connection: {}", e),
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Answers the request of a connection.
+    ///
+    /// Scrapes are cheap and infrequent, so they are answered on the accepting thread.
+    fn handle_connection(&self, mut stream: HttpStream) {
+        if let Err(e) = stream.set_read_timeout(Some(RESPONSE_TIMEOUT)) {
+            debug!("could not set read timeout of metrics connection: {}", e);
+        }
+        let response = match HttpRequest::read_from(&mut stream) {
+            Ok(request) => match (request.method.as_str(), request.segments.as_slice()) {
+                ("GET", [metrics]) if metrics == "metrics" => self.scrape(),
+                _ => HttpResponse::error(404, "not found"),
+            },
+            Err(e) => HttpResponse::error(400, &e.to_string()),
+        };
+        if let Err(e) = response.write_to(&mut stream) {
+            debug!("could not write metrics response: {}", e);
+        }
+    }
+
+    /// Requests the metrics from the daemon and renders them.
+    fn scrape(&self) -> HttpResponse {
+        let (sender, responses) = channel();
+        let client_id = self
+            .clients
+            .register("metrics".to_string(), ClientConnection::Local(sender));
+        self.daemon_queue
+            .send(DaemonEvent::IncomingCmd {
+                id: client_id,
+                cmd: DaemonCmd::GetMetrics,
+            })
+            .expect("send to daemon main event queue");
+        let response = responses.recv_timeout(RESPONSE_TIMEOUT);
+        self.clients.unregister(client_id);
+        match response {
+            Ok(DaemonResponse::Metrics { units, daemon }) => HttpResponse {
+                status: 200,
+                content_type: CONTENT_TYPE,
+                body: render(&units, &daemon).into_bytes(),
+            },
+            Ok(response) => {
+                HttpResponse::error(500, &format!("unexpected response {:?}", response))
+            }
+            Err(_) => HttpResponse::error(504, "daemon did not respond"),
+        }
+    }
+}
+
+/// Returns the value of the `status` label of a server status.
+fn status_name(status: &ServerStatus) -> &'static str {
+    match status {
+        ServerStatus::Unknown => "unknown",
+        ServerStatus::Starting => "starting",
+        ServerStatus::Running => "running",
+        ServerStatus::Updating => "updating",
+        ServerStatus::Down => "down",
+        ServerStatus::Lockdown => "lockdown",
+        ServerStatus::Errored(_) => "errored",
+        ServerStatus::Stopping => "stopping",
+    }
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Writes the samples of a metric family in the Prometheus text format.
+struct Family<'a> {
+    /// The rendered metrics
+    out: &'a mut String,
+    /// The name of the metric
+    name: &'static str,
+}
+
+impl<'a> Family<'a> {
+    /// Starts a metric family with its help text and type.
+    fn new(out: &'a mut String, name: &'static str, kind: &str, help: &str) -> Self {
+        writeln!(out, "# HELP {} {}", name, help).expect("write to string");
+        writeln!(out, "# TYPE {} {}", name, kind).expect("write to string");
+        Self { out, name }
+    }
+
+    /// Writes a sample of the metric, `suffix` is appended to the name (e.g. `_sum`).
+    fn sample(&mut self, suffix: &str, labels: &[(&str, &str)], value: f64) {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+            .collect();
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels.join(","))
+        };
+        writeln!(self.out, "{}{}{} {}", self.name, suffix, labels, value).expect("write to string");
+    }
+}
+
+/// Renders the metrics in the Prometheus text format.
+pub fn render(units: &[UnitMetrics], daemon: &DaemonMetrics) -> String {
+    let mut out = String::new();
+    let labels: Vec<(String, String)> = units
+        .iter()
+        .map(|unit| {
+            let server_type = match &unit.server_type {
+                Some(server_type) => server_type.to_string().to_lowercase(),
+                None => "unknown".to_string(),
+            };
+            (unit.unit_id.clone(), server_type)
+        })
+        .collect();
+    let unit_labels = |index: usize| -> [(&str, &str); 2] {
+        [
+            ("unit", labels[index].0.as_str()),
+            ("server_type", labels[index].1.as_str()),
+        ]
+    };
+    let gauge = |value: bool| if value { 1.0 } else { 0.0 };
+
+    let mut family = Family::new(
+        &mut out,
+        "mcman_unit_status",
+        "gauge",
+        "Status of the unit, 1 for the current status",
+    );
+    for (index, unit) in units.iter().enumerate() {
+        if let Some(status) = &unit.status {
+            let [unit_label, type_label] = unit_labels(index);
+            for name in STATUS_NAMES {
+                family.sample(
+                    "",
+                    &[unit_label, type_label, ("status", name)],
+                    gauge(status_name(status) == name),
+                );
+            }
+        }
+    }
+
+    let mut family = Family::new(
+        &mut out,
+        "mcman_unit_up",
+        "gauge",
+        "Whether the server is running",
+    );
+    for (index, unit) in units.iter().enumerate() {
+        if let Some(status) = &unit.status {
+            family.sample(
+                "",
+                &unit_labels(index),
+                gauge(matches!(status, ServerStatus::Running)),
+            );
+        }
+    }
+
+    let mut family = Family::new(
+        &mut out,
+        "mcman_unit_process_cpu_seconds_total",
+        "counter",
+        "CPU time used by the server process in seconds",
+    );
+    for (index, unit) in units.iter().enumerate() {
+        if let Some(process) = &unit.process {
+            family.sample("", &unit_labels(index), process.cpu_seconds);
+        }
+    }
+
+    let mut family = Family::new(
+        &mut out,
+        "mcman_unit_process_resident_memory_bytes",
+        "gauge",
+        "Resident memory of the server process in bytes",
+    );
+    for (index, unit) in units.iter().enumerate() {
+        if let Some(process) = &unit.process {
+            family.sample("", &unit_labels(index), process.memory_bytes as f64);
+        }
+    }
+
+    for (name, help, value) in [
+        (
+            "mcman_unit_starts_total",
+            "Number of starts of the server",
+            (|counters: &UnitCounters| counters.starts) as fn(&UnitCounters) -> u64,
+        ),
+        (
+            "mcman_unit_restarts_total",
+            "Number of restarts requested by clients",
+            |counters| counters.restarts,
+        ),
+        (
+            "mcman_unit_failures_total",
+            "Number of failures of the server",
+            |counters| counters.failures,
+        ),
+    ] {
+        let mut family = Family::new(&mut out, name, "counter", help);
+        for (index, unit) in units.iter().enumerate() {
+            family.sample("", &unit_labels(index), value(&unit.counters) as f64);
+        }
+    }
+
+    let mut family = Family::new(
+        &mut out,
+        "mcman_unit_online_players",
+        "gauge",
+        "Number of players on the server",
+    );
+    for (index, unit) in units.iter().enumerate() {
+        if unit.status.is_some() {
+            family.sample("", &unit_labels(index), unit.online_players as f64);
+        }
+    }
+
+    let mut family = Family::new(
+        &mut out,
+        "mcman_unit_tps",
+        "gauge",
+        "Ticks per second of the server over the last minute",
+    );
+    for (index, unit) in units.iter().enumerate() {
+        if let Some(tps) = unit.tps {
+            family.sample("", &unit_labels(index), tps);
+        }
+    }
+
+    let mut family = Family::new(
+        &mut out,
+        "mcman_unit_mspt",
+        "gauge",
+        "Average milliseconds per tick of the server over the last minute",
+    );
+    for (index, unit) in units.iter().enumerate() {
+        if let Some(mspt) = unit.mspt {
+            family.sample("", &unit_labels(index), mspt);
+        }
+    }
+
+    for (action, plural, stats) in [
+        (
+            "install",
+            "installs",
+            (|counters: &UnitCounters| &counters.installs) as fn(&UnitCounters) -> &ActionStats,
+        ),
+        ("update", "updates", |counters| &counters.updates),
+    ] {
+        let mut family = Family::new(
+            &mut out,
+            if action == "install" {
+                "mcman_unit_installs_total"
+            } else {
+                "mcman_unit_updates_total"
+            },
+            "counter",
+            &format!("Number of finished {} by outcome", plural),
+        );
+        for (index, unit) in units.iter().enumerate() {
+            let [unit_label, type_label] = unit_labels(index);
+            let stats = stats(&unit.counters);
+            for (outcome, count) in [("success", stats.successes), ("failure", stats.failures)] {
+                family.sample(
+                    "",
+                    &[unit_label, type_label, ("outcome", outcome)],
+                    count as f64,
+                );
+            }
+        }
+
+        let mut family = Family::new(
+            &mut out,
+            if action == "install" {
+                "mcman_unit_install_duration_seconds"
+            } else {
+                "mcman_unit_update_duration_seconds"
+            },
+            "summary",
+            &format!("Duration of {} in seconds", plural),
+        );
+        for (index, unit) in units.iter().enumerate() {
+            let stats = stats(&unit.counters);
+            family.sample("_sum", &unit_labels(index), stats.duration_seconds_sum);
+            family.sample("_count", &unit_labels(index), stats.duration_count as f64);
+        }
+    }
+
+    let mut family = Family::new(
+        &mut out,
+        "mcman_clients",
+        "gauge",
+        "Number of connected clients by connection",
+    );
+    family.sample("", &[("connection", "ipc")], daemon.ipc_clients as f64);
+    family.sample("", &[("connection", "local")], daemon.local_clients as f64);
+
+    let mut family = Family::new(
+        &mut out,
+        "mcman_event_queue_depth",
+        "gauge",
+        "Number of events waiting to be dispatched",
+    );
+    family.sample("", &[], daemon.pending_events as f64);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::metrics::{
+        render, ActionStats, DaemonMetrics, ProcessStats, UnitCounters, UnitMetrics,
+    };
+    use crate::{ServerStatus, ServerType};
+
+    #[test]
+    fn test_render() {
+        let mut installs = ActionStats::default();
+        installs.start();
+        installs.finish(true);
+        let units = vec![
+            UnitMetrics {
+                unit_id: "a".to_string(),
+                server_type: Some(ServerType::Paper),
+                status: Some(ServerStatus::Running),
+                process: Some(ProcessStats {
+                    cpu_seconds: 12.5,
+                    memory_bytes: 1024,
+                }),
+                online_players: 3,
+                tps: Some(19.5),
+                mspt: None,
+                counters: UnitCounters {
+                    starts: 2,
+                    installs,
+                    ..Default::default()
+                },
+            },
+            UnitMetrics {
+                unit_id: "broken \"unit\"".to_string(),
+                server_type: None,
+                status: None,
+                process: None,
+                online_players: 0,
+                tps: None,
+                mspt: None,
+                counters: UnitCounters::default(),
+            },
+        ];
+        let text = render(
+            &units,
+            &DaemonMetrics {
+                ipc_clients: 1,
+                local_clients: 2,
+                pending_events: 0,
+            },
+        );
+        let lines: Vec<&str> = text.lines().collect();
+        let a = r#"unit="a",server_type="paper""#;
+        for expected in [
+            format!(r#"mcman_unit_status{{{},status="running"}} 1"#, a),
+            format!(r#"mcman_unit_status{{{},status="down"}} 0"#, a),
+            format!("mcman_unit_up{{{}}} 1", a),
+            format!("mcman_unit_process_cpu_seconds_total{{{}}} 12.5", a),
+            format!("mcman_unit_starts_total{{{}}} 2", a),
+            format!("mcman_unit_tps{{{}}} 19.5", a),
+            format!(r#"mcman_unit_installs_total{{{},outcome="success"}} 1"#, a),
+            format!("mcman_unit_install_duration_seconds_count{{{}}} 1", a),
+            r#"mcman_unit_failures_total{unit="broken \"unit\"",server_type="unknown"} 0"#
+                .to_string(),
+            r#"mcman_clients{connection="local"} 2"#.to_string(),
+            "mcman_event_queue_depth 0".to_string(),
+        ] {
+            assert!(lines.contains(&expected.as_str()), "missing {}", expected);
+        }
+        assert!(!text.contains("mcman_unit_mspt{"));
+        assert!(!text.contains(r#"mcman_unit_up{unit="broken"#));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_process_stats() {
+        let stats = ProcessStats::read(std::process::id()).expect("read own process");
+        assert!(stats.memory_bytes > 0);
+    }
+}
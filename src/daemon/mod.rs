@@ -1,23 +1,48 @@
 //! Structs and traits used by the daemon.
 
+pub mod backup;
 pub mod basic_log;
+pub mod chunks;
+pub mod client;
+pub mod console;
+pub mod crash;
 pub mod event;
-pub mod paper;
+pub mod hooks;
+pub mod http;
+pub mod java;
+pub mod journal;
+pub mod lockdown;
+pub mod logs;
+pub mod metrics;
+pub mod notifier;
+pub mod patterns;
+pub mod performance;
+pub mod records;
+pub mod rotation;
+pub mod triggers;
 
-use crate::config::{ServerConfig, ServerUnitConfig};
-use crate::daemon::paper::PaperServer;
-use crate::ipc::{DaemonCmd, DaemonIpcEvent, ServerEvent};
+use crate::config::{LogConfig, ServerConfig, ServerUnitConfig, TriggerConfig};
+use crate::daemon::backup::BackupInfo;
+use crate::daemon::console::ConsoleBuffer;
+use crate::daemon::crash::LastFailure;
+use crate::daemon::hooks::LifecycleHook;
+use crate::daemon::java::JavaServer;
+use crate::daemon::patterns::LogPatternSet;
+use crate::daemon::performance::Performance;
+use crate::daemon::triggers::TriggerMatch;
+use crate::ipc::{DaemonCmd, DaemonIpcEvent, DaemonResponse, ServerEvent};
 use crate::{ServerType, Unit};
 use log::warn;
 use semver::Version;
+use std::collections::BTreeSet;
+use std::io;
 use std::path::PathBuf;
-use std::process::{Child, ChildStdout};
+use std::process::{Child, ChildStderr, ChildStdout};
 use std::sync::{Arc, RwLock};
 
 /// A server manages by the daemon.
 ///
-/// Implementations are:
-/// - [`paper::PaperServer`]
+/// The only implementation is [`java::JavaServer`].
 pub trait Server
 where
     Self: Unit,
@@ -30,7 +55,9 @@ where
     fn spawn(&mut self, log_service: &mut dyn LogService) -> (Child, Arc<RwLock<OutputState>>);
 
     /// Send a command to a running instance of the server.
-    fn send_command(&mut self, command: String);
+    ///
+    /// Fails if the console can not be written, e.g. because the server process has exited.
+    fn send_command(&mut self, command: String) -> io::Result<()>;
 
     /// Returns the type of the server.
     ///
@@ -45,6 +72,20 @@ where
 
     /// Server config parameters of this server unit
     fn server_config(&self) -> ServerConfig;
+
+    /// The console triggers of this server unit
+    fn triggers(&self) -> Vec<TriggerConfig>;
+
+    /// The console command that shuts the server down.
+    fn stop_command(&self) -> String {
+        "stop".to_string()
+    }
+
+    /// The console commands that make the server report its tick rate, see
+    /// [`crate::daemon::performance`].
+    fn performance_commands(&self) -> Vec<String> {
+        vec![]
+    }
 }
 
 /// State of a Minecraft server process based on the log output.
@@ -67,11 +108,55 @@ pub enum OutputState {
 
 /// Service to parse the log output of a Minecraft server and updates the server state while doing so.
 pub trait LogService {
-    /// Manage the output of a process.
+    /// Manage the output of a process, `out` and `err` are its stdout and stderr.
     /// This call must return the lock and update it whenever the log output of the server suggests the the state of the server has changed.
-    fn manage_output(&mut self, out: ChildStdout, server_name: String) -> Arc<RwLock<OutputState>>;
+    ///
+    /// `patterns` are the rules of the server that map output lines to states and events, together
+    /// with the console triggers of the unit.
+    /// `log_config` controls the rotation, compression and retention of the written log files.
+    /// `server_directory` is searched for crash reports when the server fails.
+    fn manage_output(
+        &mut self,
+        out: ChildStdout,
+        err: ChildStderr,
+        server_name: String,
+        patterns: LogPatternSet,
+        log_config: LogConfig,
+        server_directory: PathBuf,
+    ) -> Arc<RwLock<OutputState>>;
+
+    /// Returns the console buffer of the most recent process of a server.
+    ///
+    /// Log services that do not buffer the output return `None`.
+    fn console(&self, _server_name: &str) -> Option<ConsoleBuffer> {
+        None
+    }
+
+    /// Returns the players that are online on the most recent process of a server.
+    ///
+    /// Log services that do not track players return `None`.
+    fn online_players(&self, _server_name: &str) -> Option<OnlinePlayers> {
+        None
+    }
+
+    /// Returns the last failure of a server, which is kept across restarts of the server.
+    ///
+    /// Log services that do not detect failures return `None`.
+    fn last_failure(&self, _server_name: &str) -> Option<LastFailure> {
+        None
+    }
+
+    /// Returns the performance monitor of the most recent process of a server.
+    ///
+    /// Log services that do not monitor the performance return `None`.
+    fn performance(&self, _server_name: &str) -> Option<Performance> {
+        None
+    }
 }
 
+/// The names of the players that are online on a server process.
+pub type OnlinePlayers = Arc<RwLock<BTreeSet<String>>>;
+
 /// Event for the main daemon thread.
 pub enum DaemonEvent {
     /// An incoming command from a client
@@ -91,10 +176,93 @@ pub enum DaemonEvent {
     /// Add a server unit to the unit store of the daemon
     AddServerUnit {
         /// The config parameters of the server unit
-        server_unit_config: ServerUnitConfig,
+        server_unit_config: Box<ServerUnitConfig>,
         /// The file at which the configuration is stored
         unit_file: PathBuf,
     },
+    /// Start the server unit with the given id if it is not running
+    StartUnit {
+        /// The id of the server unit to start
+        server_id: String,
+    },
+    /// A console trigger of a server has fired, the daemon performs its actions
+    TriggerFired {
+        /// The id of the server
+        server_id: String,
+        /// The trigger and the line that has matched
+        trigger: TriggerMatch,
+    },
+    /// An event that a lifecycle hook can belong to has been raised
+    LifecycleEvent {
+        /// The raised event
+        event: ServerEvent,
+    },
+    /// A `pre_start` or `pre_stop` hook has finished on its worker thread, the daemon continues
+    /// the start or stop of the server
+    HookFinished {
+        /// The id of the server
+        server_id: String,
+        /// The hook that has finished
+        hook: LifecycleHook,
+        /// The reason why the hook has failed
+        result: Result<(), String>,
+    },
+    /// A backup has finished, the daemon turns automatic saving on again and raises its result
+    BackupFinished {
+        /// The id of the server
+        server_id: String,
+        /// The new backup or the reason why it has failed
+        result: Result<BackupInfo, String>,
+    },
+    /// A restore has finished, the daemon starts the server again and raises the result
+    RestoreFinished {
+        /// The id of the server
+        server_id: String,
+        /// The id of the restored backup
+        backup_id: String,
+        /// The id of the snapshot taken before the restore or the reason why it has failed
+        result: Result<Option<String>, String>,
+        /// Whether the server is started, it has been running before the restore and its worlds
+        /// have been restored or rolled back
+        start: bool,
+    },
+    /// A server in lockdown has saved its worlds or has not done so in time, the daemon suspends
+    /// its process and takes a snapshot
+    LockdownSaved {
+        /// The id of the server
+        server_id: String,
+        /// The reason why the worlds have not been saved
+        saved: Result<(), String>,
+    },
+    /// The snapshot of a server in lockdown has been taken
+    LockdownFinished {
+        /// The id of the server
+        server_id: String,
+        /// The snapshot or the reason why it has failed
+        snapshot: Result<BackupInfo, String>,
+    },
+    /// The chunks that no backup refers to have been deleted, the daemon allows backups again and
+    /// answers the client
+    GarbageCollected {
+        /// The id of the client that has sent the command
+        client_id: u32,
+        /// Whether no chunks have been deleted
+        dry_run: bool,
+        /// The deleted chunks or the reason why the collection has failed
+        response: DaemonResponse,
+    },
+    /// The expired backups of units have been deleted, the daemon allows backups and restores of
+    /// the units again and answers the client
+    BackupsPruned {
+        /// The id of the client that has sent the command
+        client_id: u32,
+        /// The units whose backups have been pruned, empty for a dry run
+        unit_ids: Vec<String>,
+        /// The deleted backups or the reason why pruning has failed
+        response: DaemonResponse,
+    },
+    /// Sent every second to perform periodic tasks, e.g. polling the performance of servers
+    Tick,
     /// Stop the daemon gracefully
     StopDaemon,
     /// Send an event to all currently connected IPC clients
@@ -107,18 +275,25 @@ pub fn create_server(
     server_unit_config: ServerUnitConfig,
     unit_file: PathBuf,
 ) -> Result<Box<dyn Server + Send>, ()> {
-    match server_unit_config.server.type_name.as_str() {
-        "paper" => {
-            let ServerUnitConfig { unit, server } = server_unit_config;
-            let server = PaperServer::create(unit, server, unit_file);
-            Ok(Box::new(server))
+    let ServerUnitConfig {
+        unit,
+        server,
+        triggers,
+    } = server_unit_config;
+    let server_type = match server.type_name.as_str() {
+        "paper" => ServerType::Paper,
+        "folia" => ServerType::Folia,
+        "vanilla" => ServerType::Vanilla,
+        "spigot" => ServerType::Spigot,
+        "bukkit" => ServerType::Bukkit,
+        "velocity" => ServerType::Velocity,
+        "waterfall" => ServerType::Waterfall,
+        type_name => {
+            warn!("unknown server type {}", type_name);
+            return Err(());
         }
-        _ => {
-            warn!(
-                "unknown server type {}",
-                server_unit_config.server.type_name
-            );
-            Err(())
-        }
-    }
+    };
+    JavaServer::create(unit, server, triggers, unit_file, server_type)
+        .map(|server| Box::new(server) as Box<dyn Server + Send>)
+        .map_err(|e| warn!("invalid log pattern or trigger in unit file: {}", e))
 }
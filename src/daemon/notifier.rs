@@ -0,0 +1,419 @@
+//! Webhook notifications on server events.
+//!
+//! Every configured webhook is registered as a local client of the daemon and subscribes to its
+//! event types through the [`crate::daemon::event::EventManager`], like any other client. The
+//! events it receives are posted to its URL on a separate thread, so a slow webhook does not delay
+//! the others.
+//!
+//! The request body is rendered from a template, in which these placeholders are replaced:
+//!
+//! | Placeholder      | Value                                                        |
+//! |------------------|--------------------------------------------------------------|
+//! | `{{server_id}}`  | The id of the server, `daemon` for events of the daemon      |
+//! | `{{event_type}}` | The event type, e.g. `ServerFailed` or `Custom:<name>`       |
+//! | `{{message}}`    | A short description of the event, e.g. `Server a has failed` |
+//! | `{{event}}`      | The complete event as a JSON object                          |
+//!
+//! All values except `{{event}}` are escaped for JSON strings, so they have to be placed inside
+//! quotes. Requests that fail are retried with an exponential backoff. If all attempts fail, the
+//! event is written to the failure log.
+
+use crate::config::{WebhookConfig, WebhookFormat};
+use crate::daemon::client::{ClientConnection, ClientRegistry};
+use crate::daemon::event::EventManagerCmd;
+use crate::ipc::{DaemonResponse, ServerEvent, ServerEventType};
+use chrono::Utc;
+use log::{debug, warn};
+use std::fs::{create_dir_all, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+/// The file webhook deliveries that have failed for good are appended to, relative to the
+/// working directory of the daemon.
+pub const FAILURE_LOG: &str = "log/webhook-failures.jsonl";
+
+/// The time to wait for the response of a webhook.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The maximum number of times the backoff is doubled.
+const MAX_BACKOFF_DOUBLINGS: u32 = 6;
+
+/// The body preset of [`WebhookFormat::Json`].
+const JSON_TEMPLATE: &str = r#"{"server_id": "{{server_id}}", "event_type": "{{event_type}}", "message": "{{message}}", "event": {{event}}}"#;
+
+/// The body preset of [`WebhookFormat::Discord`].
+const DISCORD_TEMPLATE: &str = r#"{"content": "{{message}}"}"#;
+
+/// The body preset of [`WebhookFormat::Slack`].
+const SLACK_TEMPLATE: &str = r#"{"text": "{{message}}"}"#;
+
+/// Sends server events to the configured webhooks.
+pub struct Notifier {
+    /// The configured webhooks
+    webhooks: Vec<WebhookConfig>,
+    /// The registry in which the webhooks are registered as clients
+    clients: ClientRegistry,
+    /// Queue of the event manager
+    event_manager_ctrl: Sender<EventManagerCmd>,
+    /// The file failed deliveries are appended to
+    failure_log: PathBuf,
+}
+
+/// An entry of the failure log.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FailedDelivery {
+    /// The time the last attempt has failed
+    pub time: chrono::DateTime<Utc>,
+    /// The URL of the webhook
+    pub url: String,
+    /// The number of attempts
+    pub attempts: u32,
+    /// The error of the last attempt
+    pub error: String,
+    /// The event that could not be delivered
+    pub event: Box<ServerEvent>,
+}
+
+impl Notifier {
+    /// Creates a notifier for the given webhooks.
+    pub fn new(
+        webhooks: Vec<WebhookConfig>,
+        clients: ClientRegistry,
+        event_manager_ctrl: Sender<EventManagerCmd>,
+        failure_log: PathBuf,
+    ) -> Self {
+        Self {
+            webhooks,
+            clients,
+            event_manager_ctrl,
+            failure_log,
+        }
+    }
+
+    /// Registers the webhooks as clients, subscribes to their events and spawns a thread for
+    /// every webhook which delivers the events.
+    pub fn run(self) {
+        // deliveries of all webhooks share the failure log
+        let failure_log = Arc::new(Mutex::new(self.failure_log));
+        for webhook in self.webhooks {
+            let (sender, responses) = channel();
+            let client_id = self.clients.register(
+                format!("webhook {}", webhook.url),
+                ClientConnection::Local(sender),
+            );
+            for event_type in &webhook.events {
+                let server_ids = match &webhook.units {
+                    Some(units) => units.iter().cloned().map(Some).collect(),
+                    None => vec![None],
+                };
+                for server_id in server_ids {
+                    self.event_manager_ctrl
+                        .send(EventManagerCmd::AddSubscription {
+                            server_id,
+                            event_type: event_type.clone(),
+                            client_id,
+                        })
+                        .expect("send to event manager");
+                }
+            }
+
+            let failure_log = failure_log.clone();
+            spawn(move || {
+                let client = reqwest::blocking::Client::builder()
+                    .timeout(REQUEST_TIMEOUT)
+                    .build()
+                    .expect("create http client");
+                for response in responses {
+                    if let DaemonResponse::ServerEvent { event } = response {
+                        if let Err(failure) = deliver(&client, &webhook, &event) {
+                            warn!(
+                                "could not notify webhook {}: {}",
+                                webhook.url, failure.error
+                            );
+                            let failure_log = failure_log.lock().expect("lock failure log");
+                            if let Err(e) = append_failure(&failure_log, &failure) {
+                                warn!("could not write webhook failure log: {}", e);
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Posts an event to a webhook, retrying failed requests.
+///
+/// Returns the failure if all attempts have failed.
+pub fn deliver(
+    client: &reqwest::blocking::Client,
+    webhook: &WebhookConfig,
+    event: &ServerEvent,
+) -> Result<(), FailedDelivery> {
+    let body = render(template(webhook), event);
+    let mut attempt = 0;
+    loop {
+        let result = client
+            .post(&webhook.url)
+            .header("content-type", "application/json")
+            .body(body.clone())
+            .send()
+            .map_err(|e| e.to_string())
+            .and_then(|response| match response.status() {
+                status if status.is_success() => Ok(()),
+                status => Err(format!("webhook responded with {}", status)),
+            });
+        let error = match result {
+            Ok(()) => {
+                debug!("notified webhook {}", webhook.url);
+                return Ok(());
+            }
+            Err(error) => error,
+        };
+        attempt += 1;
+        if attempt > webhook.retries {
+            return Err(FailedDelivery {
+                time: Utc::now(),
+                url: webhook.url.clone(),
+                attempts: attempt,
+                error,
+                event: Box::new(event.clone()),
+            });
+        }
+        debug!(
+            "attempt {} to notify webhook {} failed: {}",
+            attempt, webhook.url, error
+        );
+        let doublings = (attempt - 1).min(MAX_BACKOFF_DOUBLINGS);
+        sleep(Duration::from_secs(webhook.backoff_secs) * 2u32.pow(doublings));
+    }
+}
+
+/// Returns the body template of a webhook.
+fn template(webhook: &WebhookConfig) -> &str {
+    match (&webhook.template, webhook.format) {
+        (Some(template), _) => template,
+        (None, WebhookFormat::Json) => JSON_TEMPLATE,
+        (None, WebhookFormat::Discord) => DISCORD_TEMPLATE,
+        (None, WebhookFormat::Slack) => SLACK_TEMPLATE,
+    }
+}
+
+/// Renders a body template for an event.
+pub fn render(template: &str, event: &ServerEvent) -> String {
+    let event_json = serde_json::to_string(event).expect("serialize event");
+    template
+        .replace(
+            "{{server_id}}",
+            &escape(event.server_id().unwrap_or("daemon")),
+        )
+        .replace(
+            "{{event_type}}",
+            &escape(&event_type_name(&event.get_event_type())),
+        )
+        .replace("{{message}}", &escape(&message(event)))
+        .replace("{{event}}", &event_json)
+}
+
+/// Escapes a value for a JSON string, without the quotes.
+fn escape(value: &str) -> String {
+    let quoted = serde_json::to_string(value).expect("serialize string");
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+/// Returns the name of an event type, custom events are named `Custom:<name>`.
+pub fn event_type_name(event_type: &ServerEventType) -> String {
+    match event_type {
+        ServerEventType::Custom(name) => format!("Custom:{}", name),
+        event_type => format!("{:?}", event_type),
+    }
+}
+
+/// Returns a short description of an event.
+pub fn message(event: &ServerEvent) -> String {
+    let server = event.server_id().unwrap_or("daemon");
+    match event {
+        ServerEvent::ServerStarting { .. } => format!("Server {} is starting", server),
+        ServerEvent::ServerStarted { .. } => format!("Server {} has started", server),
+        ServerEvent::ServerStopping { .. } => format!("Server {} is stopping", server),
+        ServerEvent::ServerStopped { .. } => format!("Server {} has stopped", server),
+        ServerEvent::ServerFailed {
+            error,
+            crash_report,
+            ..
+        } => match crash_report {
+            Some(crash_report) => format!(
+                "Server {} has failed: {} (crash report {})",
+                server, error, crash_report.path
+            ),
+            None => format!("Server {} has failed: {}", server, error),
+        },
+        ServerEvent::InstallationComplete { .. } => {
+            format!("Installation of server {} is complete", server)
+        }
+        ServerEvent::InstallationFailed { error, .. } => {
+            format!("Installation of server {} has failed: {}", server, error)
+        }
+        ServerEvent::UpdateComplete { .. } => format!("Update of server {} is complete", server),
+        ServerEvent::UpdateFailed { error, .. } => {
+            format!("Update of server {} has failed: {}", server, error)
+        }
+        ServerEvent::BackupComplete { backup, .. } => {
+            format!("Backup {} of server {} is complete", backup.id, server)
+        }
+        ServerEvent::BackupFailed { error, .. } => {
+            format!("Backup of server {} has failed: {}", server, error)
+        }
+        ServerEvent::RestoreComplete { backup_id, .. } => {
+            format!(
+                "Backup {} of server {} has been restored",
+                backup_id, server
+            )
+        }
+        ServerEvent::RestoreFailed { error, .. } => {
+            format!("Restore of server {} has failed: {}", server, error)
+        }
+        ServerEvent::LockdownComplete {
+            snapshot_id: Some(snapshot_id),
+            ..
+        } => format!(
+            "Server {} is in lockdown, its worlds have been saved as {}",
+            server, snapshot_id
+        ),
+        ServerEvent::LockdownComplete { snapshot_error, .. } => format!(
+            "Server {} is in lockdown, no snapshot has been taken: {}",
+            server,
+            snapshot_error.as_deref().unwrap_or("unknown error")
+        ),
+        ServerEvent::LockdownFailed { error, .. } => {
+            format!("Lockdown of server {} has failed: {}", server, error)
+        }
+        ServerEvent::ServerUnlocked { .. } => format!("Server {} has been unlocked", server),
+        ServerEvent::PerformanceDegraded { reasons, .. } => format!(
+            "Performance of server {} is degraded: {}",
+            server,
+            reasons.join(", ")
+        ),
+        ServerEvent::PlayerJoined { player, .. } => format!("{} joined server {}", player, server),
+        ServerEvent::PlayerLeft { player, .. } => format!("{} left server {}", player, server),
+        ServerEvent::Custom { name, .. } => format!("Trigger {} fired on server {}", name, server),
+        ServerEvent::ClientConnected {
+            client_id,
+            client_name,
+        } => format!("Client {} ({}) connected", client_name, client_id),
+        ServerEvent::ClientDisconnected {
+            client_id,
+            client_name,
+        } => format!("Client {} ({}) disconnected", client_name, client_id),
+        event => format!("{} on {}", event_type_name(&event.get_event_type()), server),
+    }
+}
+
+/// Appends a failed delivery to the failure log.
+fn append_failure(failure_log: &Path, failure: &FailedDelivery) -> io::Result<()> {
+    if let Some(directory) = failure_log.parent() {
+        create_dir_all(directory)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(failure_log)?;
+    writeln!(
+        file,
+        "{}",
+        serde_json::to_string(failure).expect("serialize failed delivery")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{WebhookConfig, WebhookFormat};
+    use crate::daemon::notifier::{append_failure, deliver, render, DISCORD_TEMPLATE};
+    use crate::ipc::ServerEvent;
+    use crate::repo::fixture;
+    use std::sync::mpsc::{channel, Receiver};
+
+    /// Answers the requests on a local port with the given statuses (200 once they are used up)
+    /// and returns the URL and the received bodies.
+    fn serve(statuses: Vec<u16>) -> (String, Receiver<String>) {
+        let (sender, bodies) = channel();
+        let mut statuses = statuses.into_iter();
+        let base_url = fixture::serve_requests(move |request| {
+            sender
+                .send(String::from_utf8(request.body).expect("decode body"))
+                .expect("send body");
+            (statuses.next().unwrap_or(200), vec![])
+        });
+        (format!("{}/hook", base_url), bodies)
+    }
+
+    fn webhook(url: String) -> WebhookConfig {
+        WebhookConfig {
+            url,
+            events: vec![],
+            units: None,
+            format: WebhookFormat::Discord,
+            template: None,
+            retries: 2,
+            backoff_secs: 0,
+        }
+    }
+
+    fn failed_event() -> ServerEvent {
+        ServerEvent::ServerFailed {
+            server_id: "a".to_string(),
+            error: "server process has \"exited\"".to_string(),
+            stderr: vec![],
+            exception: vec![],
+            crash_report: None,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let body = render(DISCORD_TEMPLATE, &failed_event());
+        let json: serde_json::Value = serde_json::from_str(&body).expect("parse body");
+        assert_eq!(
+            json["content"],
+            "Server a has failed: server process has \"exited\""
+        );
+
+        let body = render(
+            r#"{"unit": "{{server_id}}", "type": "{{event_type}}", "event": {{event}}}"#,
+            &failed_event(),
+        );
+        let json: serde_json::Value = serde_json::from_str(&body).expect("parse body");
+        assert_eq!(json["unit"], "a");
+        assert_eq!(json["type"], "ServerFailed");
+        assert_eq!(json["event"]["ServerFailed"]["server_id"], "a");
+    }
+
+    #[test]
+    fn test_deliver() {
+        let client = reqwest::blocking::Client::new();
+
+        // the first attempt fails, the retry succeeds
+        let (url, bodies) = serve(vec![500, 204]);
+        assert!(deliver(&client, &webhook(url), &failed_event()).is_ok());
+        assert_eq!(bodies.iter().take(2).count(), 2);
+
+        // all attempts fail
+        let (url, bodies) = serve(vec![500, 502, 503]);
+        let failure = deliver(&client, &webhook(url), &failed_event()).expect_err("fail");
+        assert_eq!(failure.attempts, 3);
+        assert!(failure.error.contains("503"));
+        assert_eq!(bodies.iter().take(3).count(), 3);
+
+        let directory = tempfile::tempdir().expect("create temp dir");
+        let failure_log = directory.path().join("log/webhook-failures.jsonl");
+        append_failure(&failure_log, &failure).expect("append failure");
+        append_failure(&failure_log, &failure).expect("append failure");
+        let log = std::fs::read_to_string(&failure_log).expect("read failure log");
+        assert_eq!(log.lines().count(), 2);
+        assert!(log.contains("\"attempts\":3"));
+    }
+}
@@ -0,0 +1,636 @@
+//! Rules that derive the state of a server and player activity from its console output.
+//!
+//! Every server type has a built-in [`LogPatternSet`]. Units can add their own rules or replace
+//! the built-in ones in the unit file. Rules for player activity use the named capture groups
+//! `player` and `message` (the chat message, death message or advancement):
+//!
+//! ```toml
+//! [server.log_patterns]
+//! inherit = true
+//!
+//! [[server.log_patterns.rule]]
+//! pattern = "Server permissions file (.*) is malformed"
+//! action = "failed"
+//! error = "malformed permissions file $1"
+//! ```
+
+use crate::config::LogPatternConfig;
+use crate::daemon::triggers::{TriggerMatch, TriggerSet};
+use crate::daemon::OutputState;
+use crate::ipc::ServerEvent;
+use crate::ServerType;
+use regex::Regex;
+use std::time::Instant;
+
+/// Errors of the JVM, written to stderr before the server or proxy has started.
+const JVM_RULES: &[(&str, LogAction, Option<&str>)] = &[
+    (
+        r"^Error: Unable to access jarfile (.+)$",
+        LogAction::Failed,
+        Some("unable to access jar file $1"),
+    ),
+    (
+        r"^Error: Could not create the Java Virtual Machine",
+        LogAction::Failed,
+        Some("could not create the Java virtual machine"),
+    ),
+    (
+        r#"^Exception in thread "main" ([\w.$]+)"#,
+        LogAction::Failed,
+        Some("uncaught exception $1"),
+    ),
+];
+
+/// Rules shared by Vanilla and all servers based on CraftBukkit.
+///
+/// Chat is matched first, so players can not trigger other rules with chat messages. The state
+/// rules are anchored to the start of the message, so broadcasts do not trigger them either.
+const MINECRAFT_RULES: &[(&str, LogAction, Option<&str>)] = &[
+    (
+        r"\]: (\[Not Secure\] )?<(?P<player>[A-Za-z0-9_]{1,16})> (?P<message>.*)$",
+        LogAction::PlayerChat,
+        None,
+    ),
+    (
+        r"\]: (Failed to load eula\.txt|You need to agree to the EULA)",
+        LogAction::Failed,
+        Some("EULA not accepted"),
+    ),
+    (
+        r"\]: \*\*\*\* FAILED TO BIND TO PORT!",
+        LogAction::Failed,
+        Some("failed to bind to port"),
+    ),
+    (
+        r"\]: This crash report has been saved to: (.+)$",
+        LogAction::Failed,
+        Some("server crashed"),
+    ),
+    (
+        r"^Loading libraries|\]: Starting minecraft server version ",
+        LogAction::Starting,
+        None,
+    ),
+    (
+        r"\]: Done \(\d+(\.\d+)?s\)! For help, type",
+        LogAction::Started,
+        None,
+    ),
+    (r"\]: Stopping (the )?server$", LogAction::Stopping, None),
+    (r"\]: Closing Server$", LogAction::Stopped, None),
+    (
+        r"\]: (?P<player>[A-Za-z0-9_]{1,16}) joined the game$",
+        LogAction::PlayerJoined,
+        None,
+    ),
+    (
+        r"\]: (?P<player>[A-Za-z0-9_]{1,16}) left the game$",
+        LogAction::PlayerLeft,
+        None,
+    ),
+    (
+        r"\]: (?P<player>[A-Za-z0-9_]{1,16}) has (made the advancement|completed the challenge|reached the goal) \[(?P<message>.+)\]$",
+        LogAction::PlayerAdvancement,
+        None,
+    ),
+    (
+        r"\]: (?P<message>(?P<player>[A-Za-z0-9_]{1,16}) (was|walked into|drowned|died|blew up|burned to death|experienced kinetic energy|fell|froze to death|hit the ground too hard|starved to death|suffocated|tried to swim in lava|went up in flames|went off with a bang|withered away|discovered the floor was lava|didn't want to live)\b.*)$",
+        LogAction::PlayerDeath,
+        None,
+    ),
+];
+
+/// Additional rules of the Vanilla server, which does not announce the end of the shutdown.
+///
+/// The worlds are saved the same way by `save-all`, so these rules only apply while the server is
+/// stopping.
+const VANILLA_SHUTDOWN_RULES: &[(&str, LogAction, Option<&str>)] = &[(
+    r"\]: ThreadedAnvilChunkStorage: All dimensions are saved$",
+    LogAction::Stopped,
+    None,
+)];
+
+/// Rules of the Velocity proxy.
+const VELOCITY_RULES: &[(&str, LogAction, Option<&str>)] = &[
+    (r"Booting up Velocity", LogAction::Starting, None),
+    (r"Done \(\d+(\.\d+)?s\)!$", LogAction::Started, None),
+    (r"Shutting down the proxy", LogAction::Stopping, None),
+    (r"\]: Closing endpoint ", LogAction::Stopped, None),
+    (
+        r"Can't bind to (.+)$",
+        LogAction::Failed,
+        Some("failed to bind to $1"),
+    ),
+    (
+        r"\[connected player\] (?P<player>[A-Za-z0-9_]{1,16}) \([^)]*\) has connected$",
+        LogAction::PlayerJoined,
+        None,
+    ),
+    (
+        r"\[connected player\] (?P<player>[A-Za-z0-9_]{1,16}) \([^)]*\) has disconnected$",
+        LogAction::PlayerLeft,
+        None,
+    ),
+];
+
+/// Rules of the Waterfall proxy, which logs like BungeeCord.
+///
+/// The name of a player is only known once the player is connected to a backend server, so
+/// joining is matched on the server connection.
+const WATERFALL_RULES: &[(&str, LogAction, Option<&str>)] = &[
+    (
+        r"Enabled (BungeeCord|Waterfall) version",
+        LogAction::Starting,
+        None,
+    ),
+    (r"Listening on /", LogAction::Started, None),
+    (r"Closing listener", LogAction::Stopping, None),
+    (r"Thank you and goodbye", LogAction::Stopped, None),
+    (
+        r"Could not bind to host (.+)$",
+        LogAction::Failed,
+        Some("failed to bind to $1"),
+    ),
+    (
+        r"\]: \[(?P<player>[A-Za-z0-9_]{1,16})\] <-> ServerConnector \[[^\]]+\] has connected$",
+        LogAction::PlayerJoined,
+        None,
+    ),
+    (
+        r"\]: \[(?P<player>[A-Za-z0-9_]{1,16})\] -> UpstreamBridge has disconnected$",
+        LogAction::PlayerLeft,
+        None,
+    ),
+];
+
+/// What a line of the server output means for the state of the server or its players.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogAction {
+    /// The server has entered the starting stage
+    Starting,
+    /// The server has started and accepts connections
+    Started,
+    /// The server is shutting down
+    Stopping,
+    /// The server has shut down
+    Stopped,
+    /// The server has encountered an error it can not recover from
+    Failed,
+    /// A player has joined the server
+    PlayerJoined,
+    /// A player has left the server
+    PlayerLeft,
+    /// A player has written a chat message
+    PlayerChat,
+    /// A player has died
+    PlayerDeath,
+    /// A player has made an advancement
+    PlayerAdvancement,
+}
+
+/// A compiled log pattern rule.
+#[derive(Debug, Clone)]
+struct LogRule {
+    /// The pattern a line has to match
+    regex: Regex,
+    /// The action of a matching line
+    action: LogAction,
+    /// The error template for [`LogAction::Failed`]
+    error: Option<String>,
+    /// Whether the rule only applies while the server is stopping
+    shutdown: bool,
+}
+
+/// A line of output that has matched a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogMatch {
+    /// The action of the rule
+    pub action: LogAction,
+    /// The error message, only set for [`LogAction::Failed`]
+    pub error: Option<String>,
+    /// The `player` capture group of the rule
+    pub player: Option<String>,
+    /// The `message` capture group of the rule
+    pub message: Option<String>,
+    /// Whether the rule only applies while the server is stopping
+    pub shutdown: bool,
+}
+
+/// An ordered list of rules, the first matching rule wins.
+///
+/// The set also carries the console triggers of the unit, which are checked independently of the
+/// rules.
+#[derive(Debug, Clone)]
+pub struct LogPatternSet {
+    /// The rules in the order they are checked
+    rules: Vec<LogRule>,
+    /// The console triggers of the unit
+    triggers: TriggerSet,
+}
+
+impl LogPatternSet {
+    /// Returns the built-in rules of a server type.
+    pub fn for_server_type(server_type: &ServerType) -> Self {
+        let rules: &[&[(&str, LogAction, Option<&str>)]] = match server_type {
+            ServerType::Vanilla => &[MINECRAFT_RULES, JVM_RULES],
+            ServerType::Paper | ServerType::Folia | ServerType::Bukkit | ServerType::Spigot => {
+                &[MINECRAFT_RULES, JVM_RULES]
+            }
+            ServerType::Velocity => &[JVM_RULES, VELOCITY_RULES],
+            ServerType::Waterfall => &[JVM_RULES, WATERFALL_RULES],
+        };
+        let shutdown_rules = match server_type {
+            ServerType::Vanilla => VANILLA_SHUTDOWN_RULES,
+            _ => &[],
+        };
+        let rules = rules
+            .iter()
+            .flat_map(|rules| rules.iter())
+            .map(|rule| (rule, false));
+        let shutdown_rules = shutdown_rules.iter().map(|rule| (rule, true));
+        Self {
+            rules: rules
+                .chain(shutdown_rules)
+                .map(|((pattern, action, error), shutdown)| LogRule {
+                    regex: Regex::new(pattern).expect("compile built-in log pattern"),
+                    action: *action,
+                    error: error.map(|error| error.to_string()),
+                    shutdown,
+                })
+                .collect(),
+            triggers: TriggerSet::default(),
+        }
+    }
+
+    /// Creates the rules for a unit from the built-in rules of its server type and its own rules.
+    pub fn from_config(
+        server_type: &ServerType,
+        config: Option<&LogPatternConfig>,
+    ) -> Result<Self, regex::Error> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(Self::for_server_type(server_type)),
+        };
+        let mut rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok(LogRule {
+                    regex: Regex::new(&rule.pattern)?,
+                    action: rule.action,
+                    error: rule.error.clone(),
+                    shutdown: false,
+                })
+            })
+            .collect::<Result<Vec<LogRule>, regex::Error>>()?;
+        if config.inherit {
+            rules.extend(Self::for_server_type(server_type).rules);
+        }
+        Ok(Self {
+            rules,
+            triggers: TriggerSet::default(),
+        })
+    }
+
+    /// Adds the console triggers of the unit to the set.
+    pub fn with_triggers(self, triggers: TriggerSet) -> Self {
+        Self { triggers, ..self }
+    }
+
+    /// Returns the triggers that fire for a line, see [`TriggerSet::fire`].
+    pub fn fire_triggers(&mut self, line: &str) -> Vec<TriggerMatch> {
+        if self.triggers.is_empty() {
+            return vec![];
+        }
+        self.triggers.fire(line, Instant::now())
+    }
+
+    /// Returns the result of the first rule matching the line.
+    pub fn match_line(&self, line: &str) -> Option<LogMatch> {
+        self.rules.iter().find_map(|rule| {
+            let captures = rule.regex.captures(line)?;
+            let error = match rule.action {
+                LogAction::Failed => {
+                    let mut error = String::new();
+                    captures.expand(
+                        rule.error.as_deref().unwrap_or("server reported an error"),
+                        &mut error,
+                    );
+                    Some(error)
+                }
+                _ => None,
+            };
+            let group = |name| captures.name(name).map(|group| group.as_str().to_string());
+            Some(LogMatch {
+                action: rule.action,
+                error,
+                player: group("player"),
+                message: group("message"),
+                shutdown: rule.shutdown,
+            })
+        })
+    }
+}
+
+impl LogMatch {
+    /// Returns whether the line can change the state of a server in the given state.
+    pub fn applies_to(&self, state: &OutputState) -> bool {
+        !self.shutdown || matches!(state, OutputState::Stopping)
+    }
+
+    /// Returns the state the server is in after the matching line, `None` for player activity.
+    pub fn state(&self) -> Option<OutputState> {
+        match self.action {
+            LogAction::Starting => Some(OutputState::Starting),
+            LogAction::Started => Some(OutputState::Started),
+            LogAction::Stopping => Some(OutputState::Stopping),
+            LogAction::Stopped => Some(OutputState::Stopped),
+            LogAction::Failed => Some(OutputState::Errored),
+            _ => None,
+        }
+    }
+
+    /// Returns the event that is raised for the matching line.
+    ///
+    /// The details of [`ServerEvent::ServerFailed`] (stderr, exception and crash report) are left
+    /// empty for the log service to fill in.
+    pub fn event(&self, server_id: &str) -> ServerEvent {
+        let server_id = server_id.to_string();
+        match self.action {
+            LogAction::Starting => ServerEvent::ServerStarting { server_id },
+            LogAction::Started => ServerEvent::ServerStarted { server_id },
+            LogAction::Stopping => ServerEvent::ServerStopping { server_id },
+            LogAction::Stopped => ServerEvent::ServerStopped { server_id },
+            LogAction::Failed => ServerEvent::ServerFailed {
+                server_id,
+                error: self.error.clone().unwrap_or_default(),
+                stderr: vec![],
+                exception: vec![],
+                crash_report: None,
+            },
+            LogAction::PlayerJoined => ServerEvent::PlayerJoined {
+                server_id,
+                player: self.player.clone().unwrap_or_default(),
+            },
+            LogAction::PlayerLeft => ServerEvent::PlayerLeft {
+                server_id,
+                player: self.player.clone().unwrap_or_default(),
+            },
+            LogAction::PlayerChat => ServerEvent::PlayerChat {
+                server_id,
+                player: self.player.clone().unwrap_or_default(),
+                message: self.message.clone().unwrap_or_default(),
+            },
+            LogAction::PlayerDeath => ServerEvent::PlayerDeath {
+                server_id,
+                player: self.player.clone().unwrap_or_default(),
+                message: self.message.clone().unwrap_or_default(),
+            },
+            LogAction::PlayerAdvancement => ServerEvent::PlayerAdvancement {
+                server_id,
+                player: self.player.clone().unwrap_or_default(),
+                advancement: self.message.clone().unwrap_or_default(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{LogPatternConfig, LogRuleConfig};
+    use crate::daemon::patterns::{LogAction, LogPatternSet};
+    use crate::daemon::OutputState;
+    use crate::ServerType;
+
+    /// Replays a recorded log and returns the actions of all matching lines.
+    fn replay(patterns: &LogPatternSet, log: &str) -> Vec<LogAction> {
+        log.lines()
+            .filter_map(|line| patterns.match_line(line))
+            .map(|log_match| log_match.action)
+            .collect()
+    }
+
+    #[test]
+    fn test_paper_log() {
+        let patterns = LogPatternSet::for_server_type(&ServerType::Paper);
+        assert_eq!(
+            replay(&patterns, include_str!("patterns/paper-1.16.5.log")),
+            vec![
+                LogAction::Starting,
+                LogAction::Starting,
+                LogAction::Started,
+                LogAction::PlayerJoined,
+                LogAction::PlayerLeft,
+                LogAction::Stopping,
+                LogAction::Stopping,
+                LogAction::Stopped,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vanilla_log() {
+        let patterns = LogPatternSet::for_server_type(&ServerType::Vanilla);
+        assert_eq!(
+            replay(&patterns, include_str!("patterns/vanilla-1.17.1.log")),
+            vec![
+                LogAction::Starting,
+                LogAction::Started,
+                LogAction::PlayerJoined,
+                LogAction::PlayerChat,
+                LogAction::PlayerAdvancement,
+                LogAction::PlayerDeath,
+                LogAction::PlayerChat,
+                LogAction::PlayerLeft,
+                LogAction::Stopping,
+                LogAction::Stopped,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_proxy_logs() {
+        let velocity = LogPatternSet::for_server_type(&ServerType::Velocity);
+        let waterfall = LogPatternSet::for_server_type(&ServerType::Waterfall);
+        assert_eq!(
+            replay(&velocity, include_str!("patterns/velocity-3.1.1.log")),
+            vec![
+                LogAction::Starting,
+                LogAction::Started,
+                LogAction::PlayerJoined,
+                LogAction::PlayerLeft,
+                LogAction::Stopping,
+                LogAction::Stopped,
+            ]
+        );
+        assert_eq!(
+            replay(&waterfall, include_str!("patterns/waterfall-1.17.log")),
+            vec![
+                LogAction::Starting,
+                LogAction::Started,
+                LogAction::PlayerJoined,
+                LogAction::PlayerLeft,
+                LogAction::Stopping,
+                LogAction::Stopped,
+            ]
+        );
+        let joined = velocity
+            .match_line(
+                "[12:01:10 INFO]: [connected player] Steve (/127.0.0.1:51234) has connected",
+            )
+            .expect("join detected");
+        assert_eq!(joined.player.as_deref(), Some("Steve"));
+    }
+
+    #[test]
+    fn test_player_events() {
+        let patterns = LogPatternSet::for_server_type(&ServerType::Paper);
+        let chat = patterns
+            .match_line("[12:04:02 INFO]: <Steve> meet at spawn")
+            .expect("chat detected");
+        assert_eq!(chat.player.as_deref(), Some("Steve"));
+        assert_eq!(chat.message.as_deref(), Some("meet at spawn"));
+
+        let death = patterns
+            .match_line("[12:04:10 INFO]: Steve fell from a high place")
+            .expect("death detected");
+        assert_eq!(death.action, LogAction::PlayerDeath);
+        assert_eq!(
+            death.message.as_deref(),
+            Some("Steve fell from a high place")
+        );
+
+        let advancement = patterns
+            .match_line("[12:04:05 INFO]: Steve has completed the challenge [Monsters Hunted]")
+            .expect("advancement detected");
+        assert_eq!(advancement.message.as_deref(), Some("Monsters Hunted"));
+
+        assert!(patterns
+            .match_line("[12:05:12 INFO]: Steve[/127.0.0.1:51234] logged in with entity id 183")
+            .is_none());
+    }
+
+    #[test]
+    fn test_eula_log() {
+        let patterns = LogPatternSet::for_server_type(&ServerType::Paper);
+        let failure = include_str!("patterns/paper-eula.log")
+            .lines()
+            .filter_map(|line| patterns.match_line(line))
+            .find(|log_match| log_match.action == LogAction::Failed)
+            .expect("eula failure detected");
+        assert_eq!(failure.error.as_deref(), Some("EULA not accepted"));
+    }
+
+    #[test]
+    fn test_crash() {
+        let patterns = LogPatternSet::for_server_type(&ServerType::Paper);
+        let failure = patterns
+            .match_line("[12:00:00 ERROR]: This crash report has been saved to: /srv/mc/crash-reports/crash-2021-01-01_12.00.00-server.txt")
+            .expect("crash detected");
+        assert_eq!(failure.action, LogAction::Failed);
+        assert_eq!(failure.error.as_deref(), Some("server crashed"));
+    }
+
+    #[test]
+    fn test_vanilla_save() {
+        let patterns = LogPatternSet::for_server_type(&ServerType::Vanilla);
+        for line in &[
+            "[12:00:00] [Server thread/INFO]: Saved the game",
+            "[12:00:00] [Server thread/INFO]: ThreadedAnvilChunkStorage: All dimensions are saved",
+        ] {
+            assert!(patterns
+                .match_line(line)
+                .map_or(true, |log_match| !log_match
+                    .applies_to(&OutputState::Started)));
+        }
+        let stopped = patterns
+            .match_line(
+                "[12:00:00] [Server thread/INFO]: ThreadedAnvilChunkStorage: All dimensions are saved",
+            )
+            .expect("shutdown detected");
+        assert_eq!(stopped.action, LogAction::Stopped);
+        assert!(stopped.applies_to(&OutputState::Stopping));
+    }
+
+    #[test]
+    fn test_messages_keep_state() {
+        let patterns = LogPatternSet::for_server_type(&ServerType::Paper);
+        let chat = patterns
+            .match_line("[12:00:00 INFO]: <Steve> Closing Server")
+            .expect("chat detected");
+        assert_eq!(chat.action, LogAction::PlayerChat);
+        assert!(chat.state().is_none());
+        for line in &[
+            "[12:00:00 INFO]: [Server] Closing Server",
+            "[12:00:00 INFO]: [Server] Stopping the server",
+            "[12:00:00 INFO]: [Server] This crash report has been saved to: /tmp/crash.txt",
+            "[12:00:00 INFO]: [Server] Done (1.0s)! For help, type \"help\"",
+        ] {
+            assert!(patterns.match_line(line).and_then(|m| m.state()).is_none());
+        }
+    }
+
+    #[test]
+    fn test_jvm_errors() {
+        let patterns = LogPatternSet::for_server_type(&ServerType::Paper);
+        let failure = patterns
+            .match_line("Error: Unable to access jarfile paper.jar")
+            .expect("jvm error detected");
+        assert_eq!(failure.action, LogAction::Failed);
+        assert_eq!(
+            failure.error.as_deref(),
+            Some("unable to access jar file paper.jar")
+        );
+        let failure = patterns
+            .match_line(r#"Exception in thread "main" java.lang.UnsupportedClassVersionError: ..."#)
+            .expect("uncaught exception detected");
+        assert_eq!(
+            failure.error.as_deref(),
+            Some("uncaught exception java.lang.UnsupportedClassVersionError")
+        );
+    }
+
+    #[test]
+    fn test_unit_rules() {
+        let config = LogPatternConfig {
+            inherit: true,
+            rules: vec![LogRuleConfig {
+                pattern: r"Could not load plugin '(?P<plugin>[^']+)'".to_string(),
+                action: LogAction::Failed,
+                error: Some("broken plugin $plugin".to_string()),
+            }],
+        };
+        let patterns = LogPatternSet::from_config(&ServerType::Paper, Some(&config))
+            .expect("compile unit rules");
+        let failure = patterns
+            .match_line("[12:00:01 ERROR]: Could not load plugin 'Essentials' in folder 'plugins'")
+            .expect("unit rule matches");
+        assert_eq!(failure.error.as_deref(), Some("broken plugin Essentials"));
+        assert!(patterns
+            .match_line("[12:00:05 INFO]: Closing Server")
+            .is_some());
+
+        let replaced = LogPatternSet::from_config(
+            &ServerType::Paper,
+            Some(&LogPatternConfig {
+                inherit: false,
+                rules: config.rules.clone(),
+            }),
+        )
+        .expect("compile unit rules");
+        assert!(replaced
+            .match_line("[12:00:05 INFO]: Closing Server")
+            .is_none());
+
+        let invalid = LogPatternConfig {
+            inherit: true,
+            rules: vec![LogRuleConfig {
+                pattern: "(".to_string(),
+                action: LogAction::Started,
+                error: None,
+            }],
+        };
+        assert!(LogPatternSet::from_config(&ServerType::Paper, Some(&invalid)).is_err());
+    }
+}
@@ -0,0 +1,319 @@
+//! Monitoring of the tick rate of servers.
+//!
+//! The daemon periodically sends the `tps` and `mspt` commands to running servers that know them
+//! (`tps` to Spigot, both to Paper). The log service feeds every output line into the
+//! [`PerformanceMonitor`] of the server process, which picks up the replies (in the format of
+//! Paper) and the "Can't keep up!" warnings of the server.
+//! At the end of every poll interval the collected values are closed into a
+//! [`PerformanceSample`] and compared with the thresholds of the [`PerformanceConfig`].
+
+use crate::config::PerformanceConfig;
+use crate::daemon::records::LogRecord;
+use crate::ipc::ServerEvent;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use std::collections::VecDeque;
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// The reply to `tps`, e.g. `TPS from last 1m, 5m, 15m: 19.98, 20.0, *20.0`.
+const TPS_PATTERN: &str = r"^TPS from last 1m, 5m, 15m: \*?([\d.]+), \*?([\d.]+), \*?([\d.]+)";
+
+/// The header of the reply to `mspt`, the values follow on the next line.
+const MSPT_HEADER_PATTERN: &str = r"^Server tick times \(avg/min/max\) from last 5s, 10s, 1m:";
+
+/// The values of the reply to `mspt`, e.g. `◴ 1.2/0.8/3.4, 1.1/0.7/3.4, 1.0/0.5/5.0`.
+const MSPT_PATTERN: &str =
+    r"([\d.]+)/[\d.]+/[\d.]+, ([\d.]+)/[\d.]+/[\d.]+, ([\d.]+)/[\d.]+/([\d.]+)";
+
+/// The warning of the server when it falls behind.
+const OVERLOAD_PATTERN: &str =
+    r"^Can't keep up! Is the server overloaded\? Running (\d+)ms or (\d+) ticks behind";
+
+/// Formatting codes that servers may leave in their console output (`§a`, ANSI escapes).
+const FORMATTING_PATTERN: &str = "\u{a7}[0-9a-fk-orA-FK-OR]|\u{1b}\\[[0-9;]*m";
+
+/// The performance of a server during one poll interval.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PerformanceSample {
+    /// The end of the interval
+    pub time: DateTime<Utc>,
+    /// The ticks per second over the last 1, 5 and 15 minutes, `None` if the server has not
+    /// answered
+    pub tps: Option<[f64; 3]>,
+    /// The average milliseconds per tick over the last minute
+    pub mspt: Option<f64>,
+    /// The longest tick in milliseconds over the last minute
+    pub max_mspt: Option<f64>,
+    /// The number of "Can't keep up!" warnings in the interval
+    pub overloads: u32,
+    /// The number of ticks the server has skipped in the interval
+    pub skipped_ticks: u64,
+}
+
+impl PerformanceSample {
+    /// Returns the thresholds of the config that this sample crosses.
+    pub fn degradations(&self, config: &PerformanceConfig) -> Vec<String> {
+        let mut reasons = vec![];
+        if let Some([tps, _, _]) = self.tps {
+            if tps < config.min_tps {
+                reasons.push(format!("{:.1} TPS is below {:.1}", tps, config.min_tps));
+            }
+        }
+        if let Some(mspt) = self.mspt {
+            if mspt > config.max_mspt {
+                reasons.push(format!("{:.1} mspt is above {:.1}", mspt, config.max_mspt));
+            }
+        }
+        if config.max_overloads > 0 && self.overloads >= config.max_overloads {
+            reasons.push(format!(
+                "{} \"Can't keep up!\" warnings ({} ticks skipped)",
+                self.overloads, self.skipped_ticks
+            ));
+        }
+        reasons
+    }
+}
+
+/// The performance history of a server process, as returned by the daemon.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PerformanceStats {
+    /// The completed poll intervals, oldest first
+    pub samples: Vec<PerformanceSample>,
+    /// The number of "Can't keep up!" warnings since the process has started
+    pub total_overloads: u64,
+    /// The number of skipped ticks since the process has started
+    pub total_skipped_ticks: u64,
+    /// Whether the last sample has crossed a threshold
+    pub degraded: bool,
+}
+
+/// Collects the performance of a server process from its output.
+#[derive(Default)]
+pub struct PerformanceMonitor {
+    /// The ticks per second of the last reply to `tps` in the current interval
+    tps: Option<[f64; 3]>,
+    /// The average and maximum tick time of the last reply to `mspt` in the current interval
+    mspt: Option<(f64, f64)>,
+    /// Whether the previous line was the header of a reply to `mspt`
+    mspt_pending: bool,
+    /// The "Can't keep up!" warnings in the current interval
+    overloads: u32,
+    /// The skipped ticks in the current interval
+    skipped_ticks: u64,
+    /// The completed intervals, oldest first
+    history: VecDeque<PerformanceSample>,
+    /// The "Can't keep up!" warnings since the process has started
+    total_overloads: u64,
+    /// The skipped ticks since the process has started
+    total_skipped_ticks: u64,
+    /// Whether the last completed interval has crossed a threshold
+    degraded: bool,
+}
+
+/// The performance monitor of a server process, shared between the log service and the daemon.
+pub type Performance = Arc<RwLock<PerformanceMonitor>>;
+
+impl PerformanceMonitor {
+    /// Processes the next output line of the server.
+    ///
+    /// The patterns are anchored to the start of the message without formatting codes, so chat
+    /// messages can not fake replies or warnings.
+    pub fn push(&mut self, record: &LogRecord) {
+        let regexes = regexes();
+        let message = regexes.formatting.replace_all(&record.message, "");
+        let mspt_pending = std::mem::take(&mut self.mspt_pending);
+
+        if let Some(captures) = regexes.tps.captures(&message) {
+            let value = |group: usize| captures[group].parse().unwrap_or_default();
+            self.tps = Some([value(1), value(2), value(3)]);
+        } else if regexes.mspt_header.is_match(&message) {
+            self.mspt_pending = true;
+        } else if let Some(captures) = regexes.mspt.captures(&message).filter(|_| mspt_pending) {
+            let value = |group: usize| captures[group].parse().unwrap_or_default();
+            self.mspt = Some((value(3), value(4)));
+        } else if let Some(captures) = regexes.overload.captures(&message) {
+            let skipped: u64 = captures[2].parse().unwrap_or_default();
+            self.overloads += 1;
+            self.skipped_ticks += skipped;
+            self.total_overloads += 1;
+            self.total_skipped_ticks += skipped;
+        }
+    }
+
+    /// Closes the current interval and adds it to the history.
+    ///
+    /// Returns a [`ServerEvent::PerformanceDegraded`] event if the interval crosses a threshold of
+    /// the config and the previous one has not.
+    pub fn finish_sample(
+        &mut self,
+        server_id: &str,
+        config: &PerformanceConfig,
+    ) -> Option<ServerEvent> {
+        let sample = PerformanceSample {
+            time: Utc::now(),
+            tps: self.tps.take(),
+            mspt: self.mspt.map(|(average, _)| average),
+            max_mspt: self.mspt.take().map(|(_, max)| max),
+            overloads: std::mem::take(&mut self.overloads),
+            skipped_ticks: std::mem::take(&mut self.skipped_ticks),
+        };
+        let reasons = sample.degradations(config);
+        let was_degraded = std::mem::replace(&mut self.degraded, !reasons.is_empty());
+
+        while !self.history.is_empty() && self.history.len() >= config.history {
+            self.history.pop_front();
+        }
+        if config.history > 0 {
+            self.history.push_back(sample.clone());
+        }
+
+        if reasons.is_empty() || was_degraded {
+            None
+        } else {
+            Some(ServerEvent::PerformanceDegraded {
+                server_id: server_id.to_string(),
+                sample,
+                reasons,
+            })
+        }
+    }
+
+    /// Returns the history of the process.
+    pub fn stats(&self) -> PerformanceStats {
+        PerformanceStats {
+            samples: self.history.iter().cloned().collect(),
+            total_overloads: self.total_overloads,
+            total_skipped_ticks: self.total_skipped_ticks,
+            degraded: self.degraded,
+        }
+    }
+}
+
+/// The compiled patterns of this module.
+struct Regexes {
+    /// [`TPS_PATTERN`]
+    tps: Regex,
+    /// [`MSPT_HEADER_PATTERN`]
+    mspt_header: Regex,
+    /// [`MSPT_PATTERN`]
+    mspt: Regex,
+    /// [`OVERLOAD_PATTERN`]
+    overload: Regex,
+    /// [`FORMATTING_PATTERN`]
+    formatting: Regex,
+}
+
+/// Returns the compiled patterns of this module.
+fn regexes() -> &'static Regexes {
+    static REGEXES: OnceLock<Regexes> = OnceLock::new();
+    REGEXES.get_or_init(|| {
+        let compile = |pattern| Regex::new(pattern).expect("compile performance pattern");
+        Regexes {
+            tps: compile(TPS_PATTERN),
+            mspt_header: compile(MSPT_HEADER_PATTERN),
+            mspt: compile(MSPT_PATTERN),
+            overload: compile(OVERLOAD_PATTERN),
+            formatting: compile(FORMATTING_PATTERN),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::PerformanceConfig;
+    use crate::daemon::console::LogStream;
+    use crate::daemon::performance::PerformanceMonitor;
+    use crate::daemon::records::RecordParser;
+    use crate::ipc::ServerEvent;
+
+    fn push_lines(monitor: &mut PerformanceMonitor, lines: &[&str]) {
+        let mut parser = RecordParser::default();
+        for line in lines {
+            monitor.push(&parser.parse(line, LogStream::Stdout));
+        }
+    }
+
+    #[test]
+    fn test_paper_replies() {
+        let config = PerformanceConfig::default();
+        let mut monitor = PerformanceMonitor::default();
+        push_lines(
+            &mut monitor,
+            &[
+                "[12:00:00 INFO]: \u{a7}6TPS from last 1m, 5m, 15m: \u{a7}a19.5, \u{a7}a*20.0, \u{a7}a20.0",
+                "[12:00:00 INFO]: \u{a7}6Server tick times \u{a7}e(\u{a7}7avg\u{a7}e/\u{a7}7min\u{a7}e/\u{a7}7max\u{a7}e)\u{a7}6 from last 5s\u{a7}7,\u{a7}6 10s\u{a7}7,\u{a7}6 1m\u{a7}e:",
+                "[12:00:00 INFO]: \u{a7}6\u{25f4} \u{a7}a1.2\u{a7}7/\u{a7}a0.8\u{a7}7/\u{a7}a3.4\u{a7}e, \u{a7}a1.1\u{a7}7/\u{a7}a0.7\u{a7}7/\u{a7}a3.4\u{a7}e, \u{a7}a1.0\u{a7}7/\u{a7}a0.5\u{a7}7/\u{a7}a5.0",
+            ],
+        );
+        assert!(monitor.finish_sample("a", &config).is_none());
+
+        let sample = &monitor.stats().samples[0];
+        assert_eq!(sample.tps, Some([19.5, 20.0, 20.0]));
+        assert_eq!(sample.mspt, Some(1.0));
+        assert_eq!(sample.max_mspt, Some(5.0));
+        assert_eq!(sample.overloads, 0);
+    }
+
+    #[test]
+    fn test_chat_injection() {
+        let config = PerformanceConfig::default();
+        let mut monitor = PerformanceMonitor::default();
+        push_lines(
+            &mut monitor,
+            &[
+                "[12:00:00 INFO]: <Steve> TPS from last 1m, 5m, 15m: 1.0, 1.0, 1.0",
+                "[12:00:00] [Server thread/INFO]: <Steve> Can't keep up! Is the server overloaded? Running 5000ms or 100 ticks behind",
+                "[12:00:00 INFO]: <Steve> Server tick times (avg/min/max) from last 5s, 10s, 1m:",
+                "[12:00:00 INFO]: 1.0/1.0/100.0, 1.0/1.0/100.0, 100.0/100.0/100.0",
+            ],
+        );
+        assert!(monitor.finish_sample("a", &config).is_none());
+
+        let sample = &monitor.stats().samples[0];
+        assert_eq!(sample.tps, None);
+        assert_eq!(sample.mspt, None);
+        assert_eq!(sample.overloads, 0);
+    }
+
+    #[test]
+    fn test_degradation() {
+        let config = PerformanceConfig {
+            history: 2,
+            ..PerformanceConfig::default()
+        };
+        let overloaded = [
+            "[12:00:00 WARN]: Can't keep up! Is the server overloaded? Running 5000ms or 100 ticks behind",
+            "[12:00:10 WARN]: Can't keep up! Is the server overloaded? Running 2500ms or 50 ticks behind",
+            "[12:00:20 WARN]: Can't keep up! Is the server overloaded? Running 2500ms or 50 ticks behind",
+            "[12:00:30 INFO]: TPS from last 1m, 5m, 15m: 12.0, 18.0, 19.5",
+        ];
+        let mut monitor = PerformanceMonitor::default();
+        push_lines(&mut monitor, &overloaded);
+        match monitor.finish_sample("a", &config) {
+            Some(ServerEvent::PerformanceDegraded {
+                server_id,
+                sample,
+                reasons,
+            }) => {
+                assert_eq!(server_id, "a");
+                assert_eq!(sample.overloads, 3);
+                assert_eq!(sample.skipped_ticks, 200);
+                assert_eq!(reasons.len(), 2);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        // no new event while the server stays degraded
+        push_lines(&mut monitor, &overloaded);
+        assert!(monitor.finish_sample("a", &config).is_none());
+        assert!(monitor.stats().degraded);
+
+        // the history is limited
+        assert!(monitor.finish_sample("a", &config).is_none());
+        let stats = monitor.stats();
+        assert!(!stats.degraded);
+        assert_eq!(stats.samples.len(), 2);
+        assert_eq!(stats.total_overloads, 6);
+        assert_eq!(stats.total_skipped_ticks, 400);
+    }
+}
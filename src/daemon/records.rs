@@ -0,0 +1,233 @@
+//! Structured log records parsed from the console output of servers.
+//!
+//! Every output line is split into time, thread, level, logger and message. The formats of the
+//! supported server types are recognized:
+//!
+//! - Paper, Spigot and Velocity: `[12:00:00 INFO]: [Logger] message`
+//! - Vanilla, Fabric and Forge: `[12:00:00] [Server thread/INFO] [logger/]: message`
+//! - Waterfall and BungeeCord: `12:00:00 [INFO] [Logger] message`
+//!
+//! Lines without a header (e.g. stack traces) keep the level of the previous line of the same
+//! stream, lines on stderr are errors unless they say otherwise.
+
+use crate::daemon::console::LogStream;
+use regex::{Captures, Regex};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// The header formats of the supported server types, tried in order.
+const HEADER_PATTERNS: &[&str] = &[
+    r"^\[(?P<time>\d{2}:\d{2}:\d{2}(\.\d+)?) (?P<level>[A-Z]+)\]: (\[(?P<logger>[^\]]+)\] )?(?P<message>.*)$",
+    r"^\[(?P<time>\d{2}:\d{2}:\d{2}(\.\d+)?)\] \[(?P<thread>[^\]]+)/(?P<level>[A-Z]+)\]( \[(?P<logger>[^\]]*?)/?\]| \((?P<source>[^)]+)\))?:? (?P<message>.*)$",
+    r"^(?P<time>\d{2}:\d{2}:\d{2}(\.\d+)?) \[(?P<level>[A-Z]+)\] (\[(?P<logger>[^\]]+)\] )?(?P<message>.*)$",
+];
+
+/// The severity of a log record, ordered from least to most severe.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    /// Tracing output (`TRACE`, `FINEST`, `FINER`)
+    Trace,
+    /// Debug output (`DEBUG`, `FINE`, `CONFIG`)
+    Debug,
+    /// Regular output (`INFO`)
+    Info,
+    /// Warnings (`WARN`, `WARNING`)
+    Warn,
+    /// Errors (`ERROR`, `SEVERE`)
+    Error,
+    /// Errors the server can not recover from (`FATAL`)
+    Fatal,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    /// Parses a level name, including the names used by `java.util.logging`.
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level.to_ascii_uppercase().as_str() {
+            "TRACE" | "FINEST" | "FINER" => Ok(LogLevel::Trace),
+            "DEBUG" | "FINE" | "CONFIG" => Ok(LogLevel::Debug),
+            "INFO" => Ok(LogLevel::Info),
+            "WARN" | "WARNING" => Ok(LogLevel::Warn),
+            "ERROR" | "SEVERE" => Ok(LogLevel::Error),
+            "FATAL" => Ok(LogLevel::Fatal),
+            _ => Err(format!("unknown log level {}", level)),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogLevel::Trace => "trace",
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+            LogLevel::Fatal => "fatal",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A single output line of a server, split into its fields.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    /// The time of day printed by the server, without a date
+    pub time: Option<String>,
+    /// The thread that has written the line
+    pub thread: Option<String>,
+    /// The level of the line, `None` if it is not known
+    pub level: Option<LogLevel>,
+    /// The logger (e.g. a plugin) that has written the line
+    pub logger: Option<String>,
+    /// The message without the header
+    pub message: String,
+    /// The stream the line has been written to
+    pub stream: LogStream,
+}
+
+/// Parses the output lines of a server process into [`LogRecord`]s.
+///
+/// The parser has to see every line of a process in order, since lines without a header inherit
+/// the level of the previous line.
+#[derive(Default)]
+pub struct RecordParser {
+    /// The level of the previous line on stdout
+    stdout_level: Option<LogLevel>,
+    /// The level of the previous line on stderr
+    stderr_level: Option<LogLevel>,
+}
+
+impl RecordParser {
+    /// Parses an untagged output line of the given stream.
+    pub fn parse(&mut self, line: &str, stream: LogStream) -> LogRecord {
+        let previous = match stream {
+            LogStream::Stdout => &mut self.stdout_level,
+            LogStream::Stderr => &mut self.stderr_level,
+        };
+        let record = match header_regexes()
+            .iter()
+            .find_map(|regex| regex.captures(line))
+        {
+            Some(captures) => {
+                let group = |name| non_empty(&captures, name);
+                LogRecord {
+                    time: group("time"),
+                    thread: group("thread"),
+                    level: captures
+                        .name("level")
+                        .and_then(|level| level.as_str().parse().ok()),
+                    logger: group("logger").or_else(|| group("source")),
+                    message: group("message").unwrap_or_default(),
+                    stream,
+                }
+            }
+            None => LogRecord {
+                time: None,
+                thread: None,
+                level: match stream {
+                    LogStream::Stdout => *previous,
+                    LogStream::Stderr => previous.or(Some(LogLevel::Error)),
+                },
+                logger: None,
+                message: line.to_string(),
+                stream,
+            },
+        };
+        *previous = record.level;
+        record
+    }
+
+    /// Parses a line as written to the log, i.e. with a stream tag for stderr lines.
+    pub fn parse_tagged(&mut self, line: &str) -> LogRecord {
+        match line.strip_prefix(&LogStream::Stderr.tag("")) {
+            Some(line) => self.parse(line, LogStream::Stderr),
+            None => self.parse(line, LogStream::Stdout),
+        }
+    }
+}
+
+/// Returns the compiled [`HEADER_PATTERNS`].
+fn header_regexes() -> &'static [Regex] {
+    static REGEXES: OnceLock<Vec<Regex>> = OnceLock::new();
+    REGEXES.get_or_init(|| {
+        HEADER_PATTERNS
+            .iter()
+            .map(|pattern| Regex::new(pattern).expect("compile log header pattern"))
+            .collect()
+    })
+}
+
+/// Returns a capture group as a string if it has matched a non-empty string.
+fn non_empty(captures: &Captures, name: &str) -> Option<String> {
+    captures
+        .name(name)
+        .map(|group| group.as_str())
+        .filter(|group| !group.is_empty())
+        .map(|group| group.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::console::LogStream;
+    use crate::daemon::records::{LogLevel, RecordParser};
+
+    #[test]
+    fn test_formats() {
+        let mut parser = RecordParser::default();
+
+        let paper = parser.parse(
+            "[12:00:01 WARN]: [Essentials] Outdated config",
+            LogStream::Stdout,
+        );
+        assert_eq!(paper.time.as_deref(), Some("12:00:01"));
+        assert_eq!(paper.level, Some(LogLevel::Warn));
+        assert_eq!(paper.logger.as_deref(), Some("Essentials"));
+        assert_eq!(paper.message, "Outdated config");
+
+        let vanilla = parser.parse(
+            "[12:00:02] [Server thread/INFO]: Steve joined the game",
+            LogStream::Stdout,
+        );
+        assert_eq!(vanilla.thread.as_deref(), Some("Server thread"));
+        assert_eq!(vanilla.level, Some(LogLevel::Info));
+        assert_eq!(vanilla.logger, None);
+        assert_eq!(vanilla.message, "Steve joined the game");
+
+        let forge = parser.parse(
+            "[12:00:03] [Server thread/ERROR] [net.minecraft.server.MinecraftServer/]: Crashed",
+            LogStream::Stdout,
+        );
+        assert_eq!(forge.level, Some(LogLevel::Error));
+        assert_eq!(
+            forge.logger.as_deref(),
+            Some("net.minecraft.server.MinecraftServer")
+        );
+        assert_eq!(forge.message, "Crashed");
+
+        let waterfall = parser.parse("12:00:04 [SEVERE] Exception", LogStream::Stdout);
+        assert_eq!(waterfall.level, Some(LogLevel::Error));
+        assert_eq!(waterfall.message, "Exception");
+    }
+
+    #[test]
+    fn test_continuation_lines() {
+        let mut parser = RecordParser::default();
+        assert_eq!(
+            parser.parse("Loading libraries", LogStream::Stdout).level,
+            None
+        );
+        parser.parse("[12:00:00 ERROR]: Could not pass event", LogStream::Stdout);
+        let trace = parser.parse("\tat org.bukkit.Foo.bar(Foo.java:1)", LogStream::Stdout);
+        assert_eq!(trace.level, Some(LogLevel::Error));
+        assert_eq!(trace.message, "\tat org.bukkit.Foo.bar(Foo.java:1)");
+
+        let stderr = parser.parse_tagged("[stderr] Error: Unable to access jarfile paper.jar");
+        assert_eq!(stderr.stream, LogStream::Stderr);
+        assert_eq!(stderr.level, Some(LogLevel::Error));
+        assert_eq!(stderr.message, "Error: Unable to access jarfile paper.jar");
+    }
+}
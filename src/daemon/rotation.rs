@@ -0,0 +1,480 @@
+//! Writing of log files with rotation, compression and retention.
+//!
+//! A [`LogWriter`] writes the output of a server process to the session files in the log directory
+//! of the unit, as plain text and as JSON lines, and starts a new session when the current one
+//! becomes too large or too old. Closed sessions are compressed and expired according to the
+//! [`LogConfig`] of the unit.
+
+use crate::config::{LogCompression, LogConfig};
+use crate::daemon::logs::{
+    log_file_name, new_session_id, parse_log_file_name, unit_log_directory, LogFormat,
+};
+use crate::daemon::records::LogRecord;
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{debug, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{create_dir_all, read_dir, remove_file, rename, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Bytes per MiB, the unit of the size limits in a [`LogConfig`].
+const MIB: u64 = 1024 * 1024;
+
+/// A line of a JSON lines log file.
+#[derive(Serialize)]
+struct StoredRecord<'a> {
+    /// The time the daemon has received the line
+    received: DateTime<Utc>,
+    /// The unit that has written the line
+    unit: &'a str,
+    /// The fields of the line
+    #[serde(flatten)]
+    record: &'a LogRecord,
+}
+
+/// Writes log lines to the current session files of a unit and rotates them.
+pub struct LogWriter {
+    /// The id of the unit
+    unit_id: String,
+    /// The log directory of the unit
+    directory: PathBuf,
+    /// The rotation, compression and retention settings
+    config: LogConfig,
+    /// The id of the current session
+    session: String,
+    /// The writer for the plain text of the current session
+    writer: BufWriter<File>,
+    /// The writer for the records of the current session
+    records: BufWriter<File>,
+    /// The number of bytes written to the plain text of the current session
+    size: u64,
+    /// The time at which the current session file has been opened
+    opened: Instant,
+    /// Held while closed sessions are compressed and expired, shared by all writers of the
+    /// directory so that cleanups do not overlap
+    cleanup: Arc<Mutex<()>>,
+}
+
+impl LogWriter {
+    /// Opens a new session in the log directory of the unit.
+    ///
+    /// Closed sessions left behind by earlier processes are compressed and expired in the
+    /// background.
+    pub fn create(log_directory: &Path, unit_id: &str, config: LogConfig) -> io::Result<Self> {
+        let directory = unit_log_directory(log_directory, unit_id);
+        create_dir_all(&directory)?;
+        let session = new_session_id();
+        let (writer, records) = open_session(&directory, &session)?;
+        let cleanup = cleanup_lock(&directory);
+        let log_writer = Self {
+            unit_id: unit_id.to_string(),
+            directory,
+            config,
+            session,
+            writer,
+            records,
+            size: 0,
+            opened: Instant::now(),
+            cleanup,
+        };
+        log_writer.clean_up_in_background();
+        Ok(log_writer)
+    }
+
+    /// Writes a line and its record to the current session and flushes them, rotating the
+    /// session beforehand if it has exceeded a limit.
+    pub fn write_line(&mut self, line: &str, record: &LogRecord) -> io::Result<()> {
+        if self.should_rotate() {
+            self.rotate()?;
+        }
+        writeln!(self.writer, "{}", line)?;
+        let stored = StoredRecord {
+            received: Utc::now(),
+            unit: &self.unit_id,
+            record,
+        };
+        serde_json::to_writer(&mut self.records, &stored)?;
+        writeln!(self.records)?;
+        // keep the files current for clients reading the logs
+        self.writer.flush()?;
+        self.records.flush()?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Closes the current session, compresses it and expires old sessions.
+    ///
+    /// Sessions newer than the current one belong to a later process of the unit and are left
+    /// alone.
+    pub fn close(mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.records.flush()?;
+        let _cleanup = self.cleanup.lock().expect("lock log cleanup");
+        finish_sessions(
+            &self.directory,
+            &self.config,
+            Bound::Included(&self.session),
+        );
+        Ok(())
+    }
+
+    /// Returns whether the plain text of the current session has exceeded the size or age limit.
+    ///
+    /// A limit of `0` disables rotation for that limit.
+    fn should_rotate(&self) -> bool {
+        let size = self.config.rotate_size_mb * MIB;
+        let interval = Duration::from_secs(self.config.rotate_interval_hours * 60 * 60);
+        (size > 0 && self.size >= size)
+            || (!interval.is_zero() && self.opened.elapsed() >= interval)
+    }
+
+    /// Closes the current session and continues in a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.records.flush()?;
+        let mut session = new_session_id();
+        // a session id must not be reused, it has a resolution of a millisecond
+        while session == self.session {
+            sleep(Duration::from_millis(1));
+            session = new_session_id();
+        }
+        debug!(
+            "rotating log {} to session {}",
+            self.directory.display(),
+            session
+        );
+        let (writer, records) = open_session(&self.directory, &session)?;
+        self.writer = writer;
+        self.records = records;
+        self.session = session;
+        self.size = 0;
+        self.opened = Instant::now();
+        self.clean_up_in_background();
+        Ok(())
+    }
+
+    /// Compresses and expires all sessions older than the current one in a new thread, so that
+    /// the output of the server is not blocked.
+    fn clean_up_in_background(&self) {
+        let directory = self.directory.clone();
+        let config = self.config.clone();
+        let active = self.session.clone();
+        let cleanup = self.cleanup.clone();
+        spawn(move || {
+            let _cleanup = cleanup.lock().expect("lock log cleanup");
+            finish_sessions(&directory, &config, Bound::Excluded(&active))
+        });
+    }
+}
+
+/// Returns the lock held while the closed sessions in a log directory are compressed and expired.
+///
+/// The writer of a restarted process opens its session before the writer of the previous process
+/// is closed, so both have to share the lock of the directory.
+fn cleanup_lock(directory: &Path) -> Arc<Mutex<()>> {
+    static LOCKS: OnceLock<Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>> = OnceLock::new();
+    LOCKS
+        .get_or_init(Default::default)
+        .lock()
+        .expect("lock log cleanup locks")
+        .entry(directory.to_path_buf())
+        .or_default()
+        .clone()
+}
+
+/// Creates the files of a new session and returns the writers for the plain text and the
+/// records.
+fn open_session(directory: &Path, session: &str) -> io::Result<(BufWriter<File>, BufWriter<File>)> {
+    let open = |format| {
+        File::create(directory.join(log_file_name(session, format, LogCompression::None)))
+            .map(BufWriter::new)
+    };
+    Ok((open(LogFormat::Text)?, open(LogFormat::Records)?))
+}
+
+/// A closed session in the log directory of a unit.
+#[derive(Debug, Clone)]
+struct ClosedSession {
+    /// The id of the session
+    session: String,
+    /// The files of the session and their compression
+    files: Vec<(PathBuf, LogCompression)>,
+    /// The total size of the files in bytes
+    size: u64,
+    /// The time of the last modification, i.e. when the session has been closed
+    modified: SystemTime,
+}
+
+/// Compresses the uncompressed sessions up to `last` and deletes expired sessions.
+///
+/// Errors are logged, a failed cleanup is retried with the next rotation.
+fn finish_sessions(directory: &Path, config: &LogConfig, last: Bound<&str>) {
+    if config.compression != LogCompression::None {
+        match closed_sessions(directory, last) {
+            Ok(sessions) => {
+                for (path, _) in sessions
+                    .iter()
+                    .flat_map(|session| &session.files)
+                    .filter(|(_, compression)| *compression == LogCompression::None)
+                {
+                    if let Err(e) = compress(path, config.compression) {
+                        warn!("failed to compress log {}: {}", path.display(), e);
+                    }
+                }
+            }
+            Err(e) => warn!("failed to list logs in {}: {}", directory.display(), e),
+        }
+    }
+    match closed_sessions(directory, last) {
+        Ok(sessions) => {
+            for path in expired(sessions, config, SystemTime::now()) {
+                debug!("deleting expired log {}", path.display());
+                if let Err(e) = remove_file(&path) {
+                    warn!("failed to delete log {}: {}", path.display(), e);
+                }
+            }
+        }
+        Err(e) => warn!("failed to list logs in {}: {}", directory.display(), e),
+    }
+}
+
+/// Lists the sessions up to the session `last`, oldest first.
+fn closed_sessions(directory: &Path, last: Bound<&str>) -> io::Result<Vec<ClosedSession>> {
+    let mut sessions: BTreeMap<String, ClosedSession> = BTreeMap::new();
+    for entry in read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some((session, _, compression)) = parse_log_file_name(&name) {
+            let closed = match last {
+                Bound::Included(last) => session <= last,
+                Bound::Excluded(last) => session < last,
+                Bound::Unbounded => true,
+            };
+            if !closed {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            let modified = metadata.modified()?;
+            let closed = sessions
+                .entry(session.to_string())
+                .or_insert_with(|| ClosedSession {
+                    session: session.to_string(),
+                    files: vec![],
+                    size: 0,
+                    modified,
+                });
+            closed.files.push((entry.path(), compression));
+            closed.size += metadata.len();
+            closed.modified = closed.modified.max(modified);
+        }
+    }
+    Ok(sessions.into_values().collect())
+}
+
+/// Returns the files that exceed the retention limits, given the closed sessions oldest first.
+///
+/// Sessions are deleted oldest first until the number of sessions, their age and their total size
+/// are within the limits.
+fn expired(sessions: Vec<ClosedSession>, config: &LogConfig, now: SystemTime) -> Vec<PathBuf> {
+    let mut total: u64 = sessions.iter().map(|session| session.size).sum();
+    let mut remaining = sessions.len();
+    let max_age = config
+        .keep_days
+        .map(|days| Duration::from_secs(days * 24 * 60 * 60));
+    let mut expired = vec![];
+    for session in sessions {
+        let too_many = config.keep_files.is_some_and(|keep| remaining > keep);
+        let too_large = config.keep_total_mb.is_some_and(|keep| total > keep * MIB);
+        let too_old = max_age.is_some_and(|max_age| {
+            now.duration_since(session.modified)
+                .is_ok_and(|age| age > max_age)
+        });
+        if !(too_many || too_large || too_old) {
+            break;
+        }
+        debug!("session {} has expired", session.session);
+        total -= session.size;
+        remaining -= 1;
+        expired.extend(session.files.into_iter().map(|(path, _)| path));
+    }
+    expired
+}
+
+/// Compresses a closed session file and deletes the uncompressed file afterwards.
+///
+/// The compressed data is written to a temporary file first, so that a session is never listed
+/// with an incomplete compressed file only.
+fn compress(path: &Path, compression: LogCompression) -> io::Result<()> {
+    let name = file_name(path);
+    let (session, format, _) = parse_log_file_name(&name).expect("compress a log file");
+    let target = path.with_file_name(log_file_name(session, format, compression));
+    let partial = path.with_file_name(format!("{}.part", file_name(&target)));
+
+    let mut input = File::open(path)?;
+    let output = File::create(&partial)?;
+    match compression {
+        LogCompression::None => return Ok(()),
+        LogCompression::Gzip => {
+            let mut encoder = GzEncoder::new(output, Compression::default());
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?.sync_all()?;
+        }
+        LogCompression::Zstd => {
+            let mut encoder = zstd::Encoder::new(output, 0)?;
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?.sync_all()?;
+        }
+    }
+    rename(&partial, &target)?;
+    remove_file(path)
+}
+
+/// Returns the file name of a path as a string.
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{LogCompression, LogConfig};
+    use crate::daemon::console::LogStream;
+    use crate::daemon::logs::{list_sessions, read_lines, LineFilter};
+    use crate::daemon::records::RecordParser;
+    use crate::daemon::rotation::{expired, ClosedSession, LogWriter, MIB};
+    use std::fs::read_dir;
+    use std::path::PathBuf;
+    use std::thread::sleep;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_rotation() {
+        let log_directory = tempfile::tempdir().expect("create temp dir");
+        let config = LogConfig {
+            rotate_size_mb: 1,
+            compression: LogCompression::Zstd,
+            keep_files: Some(1),
+            ..LogConfig::default()
+        };
+        let mut writer =
+            LogWriter::create(log_directory.path(), "unit", config).expect("create writer");
+        let mut parser = RecordParser::default();
+        let mut write = |line: &str| {
+            let record = parser.parse(line, LogStream::Stdout);
+            writer.write_line(line, &record).expect("write line");
+        };
+        let line = "x".repeat(1023);
+        for _ in 0..(MIB / 1024) * 3 {
+            write(&line);
+        }
+        write("last");
+        writer.close().expect("close writer");
+
+        let sessions = list_sessions(log_directory.path(), "unit").expect("list sessions");
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].compressed);
+        let tail = read_lines(
+            log_directory.path(),
+            "unit",
+            None,
+            None,
+            1,
+            &LineFilter::default(),
+            false,
+        )
+        .expect("read last session");
+        assert_eq!(tail.lines, vec!["last"]);
+        let mut files: Vec<String> = read_dir(log_directory.path().join("unit"))
+            .expect("list log files")
+            .map(|entry| {
+                entry
+                    .expect("read entry")
+                    .file_name()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .map(|name| name.trim_start_matches(&sessions[0].id).to_string())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["_out.jsonl.zst", "_out.log.zst"]);
+    }
+
+    #[test]
+    fn test_close_after_restart() {
+        let log_directory = tempfile::tempdir().expect("create temp dir");
+        let config = LogConfig {
+            compression: LogCompression::Gzip,
+            ..LogConfig::default()
+        };
+        let mut parser = RecordParser::default();
+        let mut write = |writer: &mut LogWriter, line: &str| {
+            let record = parser.parse(line, LogStream::Stdout);
+            writer.write_line(line, &record).expect("write line");
+        };
+        let mut old =
+            LogWriter::create(log_directory.path(), "unit", config.clone()).expect("create writer");
+        write(&mut old, "old");
+        sleep(Duration::from_millis(5));
+        let mut new =
+            LogWriter::create(log_directory.path(), "unit", config).expect("create writer");
+        write(&mut new, "new");
+        old.close().expect("close old writer");
+        write(&mut new, "still running");
+
+        let sessions = list_sessions(log_directory.path(), "unit").expect("list sessions");
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions[0].compressed);
+        assert!(!sessions[1].compressed);
+        let tail = read_lines(
+            log_directory.path(),
+            "unit",
+            None,
+            None,
+            2,
+            &LineFilter::default(),
+            false,
+        )
+        .expect("read last session");
+        assert_eq!(tail.lines, vec!["new", "still running"]);
+        new.close().expect("close new writer");
+    }
+
+    #[test]
+    fn test_expired() {
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let sessions: Vec<ClosedSession> = (0..4)
+            .map(|i| ClosedSession {
+                session: format!("{}", i),
+                files: vec![(PathBuf::from(format!("{}", i)), LogCompression::None)],
+                size: MIB,
+                modified: now - day * (4 - i),
+            })
+            .collect();
+        let expire = |config: LogConfig| expired(sessions.clone(), &config, now);
+
+        assert!(expire(LogConfig::default()).is_empty());
+        let by_count = expire(LogConfig {
+            keep_files: Some(3),
+            ..LogConfig::default()
+        });
+        assert_eq!(by_count, vec![PathBuf::from("0")]);
+        let by_age = expire(LogConfig {
+            keep_days: Some(2),
+            ..LogConfig::default()
+        });
+        assert_eq!(by_age, vec![PathBuf::from("0"), PathBuf::from("1")]);
+        let by_size = expire(LogConfig {
+            keep_total_mb: Some(1),
+            ..LogConfig::default()
+        });
+        assert_eq!(by_size.len(), 3);
+    }
+}
@@ -0,0 +1,207 @@
+//! User-defined console triggers.
+//!
+//! Units declare triggers in their unit file to react to messages of plugins, e.g. alerts of an
+//! anti-cheat plugin, without changes to the log service:
+//!
+//! ```toml
+//! [[trigger]]
+//! pattern = "\\[AntiCheat\\] (?P<player>\\w+) failed (?P<check>\\w+)"
+//! cooldown_secs = 30
+//! event = "anticheat_alert"
+//! command = "kick $player Cheating is not allowed"
+//! hook = "./notify-moderators.sh"
+//! ```
+//!
+//! The log service checks every output line against the [`TriggerSet`] of the process and passes
+//! the [`TriggerMatch`]es to the daemon, which performs the actions.
+
+use crate::config::TriggerConfig;
+use crate::ipc::ServerEvent;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// A compiled trigger.
+#[derive(Debug, Clone)]
+struct Trigger {
+    /// The pattern a line has to match
+    regex: Regex,
+    /// The minimum time between two firings
+    cooldown: Duration,
+    /// The name of the custom event
+    event: Option<String>,
+    /// The command template
+    command: Option<String>,
+    /// The shell command of the hook
+    hook: Option<String>,
+    /// The time at which the trigger has fired last
+    last_fired: Option<Instant>,
+}
+
+/// The triggers of a unit.
+///
+/// Every server process gets its own copy, so cooldowns start over when a server is restarted.
+#[derive(Debug, Clone, Default)]
+pub struct TriggerSet {
+    /// The triggers in the order of the unit file
+    triggers: Vec<Trigger>,
+}
+
+/// A trigger that has fired, with the actions to perform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TriggerMatch {
+    /// The output line that has matched
+    pub line: String,
+    /// The capture groups of the pattern by number and name, groups that did not participate in
+    /// the match are missing
+    pub captures: BTreeMap<String, String>,
+    /// The name of the custom event to raise
+    pub event: Option<String>,
+    /// The command to send to the console, with the capture groups substituted
+    pub command: Option<String>,
+    /// The shell command to run
+    pub hook: Option<String>,
+}
+
+impl TriggerSet {
+    /// Compiles the triggers of a unit file.
+    pub fn from_config(config: &[TriggerConfig]) -> Result<Self, regex::Error> {
+        let triggers = config
+            .iter()
+            .map(|trigger| {
+                Ok(Trigger {
+                    regex: Regex::new(&trigger.pattern)?,
+                    cooldown: Duration::from_secs(trigger.cooldown_secs),
+                    event: trigger.event.clone(),
+                    command: trigger.command.clone(),
+                    hook: trigger.hook.clone(),
+                    last_fired: None,
+                })
+            })
+            .collect::<Result<Vec<Trigger>, regex::Error>>()?;
+        Ok(Self { triggers })
+    }
+
+    /// Returns whether the set contains no triggers.
+    pub fn is_empty(&self) -> bool {
+        self.triggers.is_empty()
+    }
+
+    /// Returns the triggers that fire for a line at time `now`.
+    ///
+    /// Triggers that match during their cooldown are skipped.
+    pub fn fire(&mut self, line: &str, now: Instant) -> Vec<TriggerMatch> {
+        let mut matches = vec![];
+        for trigger in &mut self.triggers {
+            let captures = match trigger.regex.captures(line) {
+                Some(captures) => captures,
+                None => continue,
+            };
+            if let Some(last_fired) = trigger.last_fired {
+                if now.saturating_duration_since(last_fired) < trigger.cooldown {
+                    continue;
+                }
+            }
+            trigger.last_fired = Some(now);
+
+            let mut groups = BTreeMap::new();
+            for (index, name) in trigger.regex.capture_names().enumerate().skip(1) {
+                if let Some(group) = captures.get(index) {
+                    groups.insert(index.to_string(), group.as_str().to_string());
+                    if let Some(name) = name {
+                        groups.insert(name.to_string(), group.as_str().to_string());
+                    }
+                }
+            }
+            let command = trigger.command.as_ref().map(|template| {
+                let mut command = String::new();
+                captures.expand(template, &mut command);
+                command
+            });
+            matches.push(TriggerMatch {
+                line: line.to_string(),
+                captures: groups,
+                event: trigger.event.clone(),
+                command,
+                hook: trigger.hook.clone(),
+            });
+        }
+        matches
+    }
+}
+
+impl TriggerMatch {
+    /// Returns the [`ServerEvent::Custom`] event of the trigger, if it raises one.
+    pub fn event(&self, server_id: &str) -> Option<ServerEvent> {
+        self.event.as_ref().map(|name| ServerEvent::Custom {
+            server_id: server_id.to_string(),
+            name: name.clone(),
+            captures: self.captures.clone(),
+        })
+    }
+
+    /// Returns the environment variables of the hook.
+    ///
+    /// These are `MCMAN_UNIT`, `MCMAN_LINE` and `MCMAN_CAPTURE_<GROUP>` for every capture group.
+    pub fn hook_environment(&self, server_id: &str) -> Vec<(String, String)> {
+        let mut environment = vec![
+            ("MCMAN_UNIT".to_string(), server_id.to_string()),
+            ("MCMAN_LINE".to_string(), self.line.clone()),
+        ];
+        environment.extend(self.captures.iter().map(|(group, value)| {
+            (
+                format!("MCMAN_CAPTURE_{}", group.to_ascii_uppercase()),
+                value.clone(),
+            )
+        }));
+        environment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::TriggerConfig;
+    use crate::daemon::triggers::TriggerSet;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_fire() {
+        let mut triggers = TriggerSet::from_config(&[
+            TriggerConfig {
+                pattern: r"\[AntiCheat\] (?P<player>\w+) failed (\w+)".to_string(),
+                cooldown_secs: 30,
+                event: Some("anticheat_alert".to_string()),
+                command: Some("kick $player failed $2".to_string()),
+                hook: None,
+            },
+            TriggerConfig {
+                pattern: r"failed".to_string(),
+                cooldown_secs: 0,
+                event: None,
+                command: None,
+                hook: Some("./notify.sh".to_string()),
+            },
+        ])
+        .expect("compile triggers");
+
+        let now = Instant::now();
+        let line = "[12:00:00 INFO]: [AntiCheat] Steve failed Fly";
+        let matches = triggers.fire(line, now);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].command.as_deref(), Some("kick Steve failed Fly"));
+        assert_eq!(matches[0].captures["player"], "Steve");
+        assert_eq!(matches[0].captures["1"], "Steve");
+        assert_eq!(matches[0].captures["2"], "Fly");
+        assert!(matches[0]
+            .hook_environment("a")
+            .contains(&("MCMAN_CAPTURE_PLAYER".to_string(), "Steve".to_string())));
+        assert_eq!(matches[1].hook.as_deref(), Some("./notify.sh"));
+
+        // the first trigger is in its cooldown
+        let matches = triggers.fire(line, now + Duration::from_secs(10));
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].hook.as_deref(), Some("./notify.sh"));
+        assert_eq!(triggers.fire(line, now + Duration::from_secs(30)).len(), 2);
+        assert!(triggers.fire("Done (1.0s)!", now).is_empty());
+    }
+}
@@ -1,12 +1,15 @@
 //! Contains basic traits and some implemetations for installing servers.
 
+pub mod buildtools;
 mod iops;
 
 use crate::config::ServerConfig;
 use crate::daemon::event::EventHandler;
+use crate::ipc::install::buildtools::BuildTools;
 use crate::ipc::ServerEvent;
 use crate::repo::paper::PaperRepository;
-use crate::repo::Repository;
+use crate::repo::vanilla::VanillaRepository;
+use crate::repo::{Artifact, Repository};
 use crate::ServerType;
 use semver::Version;
 use std::error::Error;
@@ -68,22 +71,67 @@ pub enum InstallError {
     UnitAlreadyExists,
 }
 
-/// Installer implementation for PaperMC
+/// Raises the progress of an installation.
+fn progress(event_handler: &mut EventHandler, unit_id: &str, action: &str) {
+    event_handler.raise_event(
+        unit_id,
+        ServerEvent::ActionProgress {
+            server_id: unit_id.to_string(),
+            action: action.to_string(),
+            progress: None,
+            maximum: None,
+            action_number: 1,
+        },
+    );
+}
+
+/// Returns the config of a newly installed server, which uses the default settings.
+fn installed_config(
+    server_name: Option<String>,
+    path: &Path,
+    type_name: &str,
+    jar: String,
+    version: Version,
+) -> ServerConfig {
+    ServerConfig {
+        name: server_name.unwrap_or_else(|| "A Minecraft server".to_string()),
+        path: Box::from(path),
+        type_name: type_name.to_string(),
+        jar,
+        version,
+        memory: 10,
+        log_patterns: None,
+        logs: None,
+        performance: None,
+        hooks: None,
+        backup: None,
+    }
+}
+
+/// Installer implementation for the projects of PaperMC (Paper, Folia, Velocity and Waterfall)
+///
+/// The jars of Paper and Folia are patched after the download, the proxies have neither a patch
+/// step nor an EULA.
 pub struct PaperServerInstaller {
     /// An event handler with a connection to the main event manager
     event_handler: EventHandler,
     /// The id of the unit to be installed
     unit_id: String,
+    /// The repository of the project that is installed
     repo: PaperRepository,
 }
 
 impl PaperServerInstaller {
-    /// Create a new PaperServerInstaller
-    pub fn new(event_handler: EventHandler, unit_id: String) -> PaperServerInstaller {
+    /// Create a new PaperServerInstaller for the project of the given repository
+    pub fn new(
+        event_handler: EventHandler,
+        unit_id: String,
+        repo: PaperRepository,
+    ) -> PaperServerInstaller {
         Self {
             event_handler,
             unit_id,
-            repo: PaperRepository {},
+            repo,
         }
     }
 }
@@ -101,31 +149,18 @@ impl ServerInstaller for PaperServerInstaller {
             return Err(InstallError::DirExists);
         }
 
-        self.event_handler.raise_event(
-            self.unit_id.as_str(),
-            ServerEvent::ActionProgress {
-                server_id: self.unit_id.clone(),
-                action: "creating server directory".to_string(),
-                progress: None,
-                maximum: None,
-                action_number: 1,
-            },
-        );
+        progress(&mut self.event_handler, &self.unit_id, "creating server directory");
 
         create_dir_all(path).map_err(InstallError::CreateDir)?;
 
         //TODO symlink cache here
 
-        if eula {
-            self.event_handler.raise_event(
-                self.unit_id.as_str(),
-                ServerEvent::ActionProgress {
-                    server_id: self.unit_id.clone(),
-                    action: "creating initial server configuration".to_string(),
-                    progress: None,
-                    maximum: None,
-                    action_number: 1,
-                },
+        let project = self.repo.project();
+        if eula && !project.server_type().is_proxy() {
+            progress(
+                &mut self.event_handler,
+                &self.unit_id,
+                "creating initial server configuration",
             );
 
             let mut eula_file = PathBuf::new();
@@ -134,16 +169,7 @@ impl ServerInstaller for PaperServerInstaller {
             fs::write(eula_file, "eula=true\n").map_err(InstallError::WriteInitialSettings)?
         }
 
-        self.event_handler.raise_event(
-            self.unit_id.as_str(),
-            ServerEvent::ActionProgress {
-                server_id: self.unit_id.clone(),
-                action: "downloading jar".to_string(),
-                progress: None,
-                maximum: None,
-                action_number: 1,
-            },
-        );
+        progress(&mut self.event_handler, &self.unit_id, "downloading jar");
 
         let server_version = match server_version {
             Some(server_version) => server_version,
@@ -154,26 +180,28 @@ impl ServerInstaller for PaperServerInstaller {
         let mut dest_path = PathBuf::new();
         dest_path.push(path);
 
-        let jar_name = format!("paper_{}.jar", artifact.version()).replace("+", "-");
+        let jar_name = format!("{}_{}.jar", project.id(), artifact.version()).replace("+", "-");
         dest_path.push(&jar_name);
 
         artifact.download_to(dest_path.as_path())?;
 
-        self.event_handler.raise_event(
-            self.unit_id.as_str(),
-            ServerEvent::ActionProgress {
-                server_id: self.unit_id.clone(),
-                action: "patching jar".to_string(),
-                progress: None,
-                maximum: None,
-                action_number: 1,
-            },
+        let config = installed_config(
+            server_name,
+            path,
+            project.id(),
+            jar_name,
+            artifact.version(),
         );
+        if !project.is_patched() {
+            return Ok(config);
+        }
+
+        progress(&mut self.event_handler, &self.unit_id, "patching jar");
 
         let child = Command::new("java".to_string())
             .arg("-Dpaperclip.patchonly=true")
             .arg("-jar")
-            .arg(&jar_name)
+            .arg(&config.jar)
             .current_dir(&path)
             .output()
             .expect("spawn path process");
@@ -183,14 +211,248 @@ impl ServerInstaller for PaperServerInstaller {
                 String::from_utf8_lossy(child.stderr.as_slice()).to_string(),
             ))
         } else {
-            Ok(ServerConfig {
-                name: server_name.unwrap_or_else(|| "A Minecraft server".to_string()),
-                path: Box::from(path),
-                type_name: "paper".to_string(),
-                jar: jar_name,
-                version: artifact.version(),
-                memory: 10,
-            })
+            Ok(config)
+        }
+    }
+}
+
+/// Installer implementation for vanilla servers
+///
+/// The jar from the repository is the server itself, so no patch step is needed.
+pub struct VanillaServerInstaller {
+    /// An event handler with a connection to the main event manager
+    event_handler: EventHandler,
+    /// The id of the unit to be installed
+    unit_id: String,
+    /// The repository the server jars are downloaded from
+    repo: VanillaRepository,
+}
+
+impl VanillaServerInstaller {
+    /// Create a new VanillaServerInstaller that installs servers from the given repository
+    pub fn new(
+        event_handler: EventHandler,
+        unit_id: String,
+        repo: VanillaRepository,
+    ) -> VanillaServerInstaller {
+        Self {
+            event_handler,
+            unit_id,
+            repo,
+        }
+    }
+}
+
+impl ServerInstaller for VanillaServerInstaller {
+    fn install_server(
+        &mut self,
+        install_path: String,
+        server_version: Option<Version>,
+        eula: bool,
+        server_name: Option<String>,
+    ) -> Result<ServerConfig, InstallError> {
+        let path = Path::new(install_path.as_str());
+        if path.exists() {
+            return Err(InstallError::DirExists);
+        }
+
+        progress(&mut self.event_handler, &self.unit_id, "creating server directory");
+        create_dir_all(path).map_err(InstallError::CreateDir)?;
+
+        if eula {
+            progress(
+                &mut self.event_handler,
+                &self.unit_id,
+                "creating initial server configuration",
+            );
+            fs::write(path.join("eula.txt"), "eula=true\n")
+                .map_err(InstallError::WriteInitialSettings)?
+        }
+
+        progress(&mut self.event_handler, &self.unit_id, "downloading jar");
+        let server_version = match server_version {
+            Some(server_version) => server_version,
+            None => self
+                .repo
+                .latest_version()
+                .map_err(|e| InstallError::DownloadFailed(Box::new(e)))?,
+        };
+        let artifact = self
+            .repo
+            .artifact(&server_version)
+            .map_err(|e| InstallError::DownloadFailed(Box::new(e)))?;
+        let jar_name = format!("vanilla_{}.jar", artifact.version());
+        artifact.download_to(path.join(&jar_name).as_path())?;
+
+        Ok(installed_config(server_name, path, "vanilla", jar_name, artifact.version()))
+    }
+}
+
+/// Installer implementation for Spigot and Bukkit (CraftBukkit) servers
+///
+/// The jars are built by BuildTools in a build cache and copied into the server directory.
+pub struct SpigotServerInstaller {
+    /// An event handler with a connection to the main event manager
+    event_handler: EventHandler,
+    /// The id of the unit to be installed
+    unit_id: String,
+    /// Either [`ServerType::Spigot`] or [`ServerType::Bukkit`]
+    server_type: ServerType,
+    /// The build cache in which the jars are built
+    build_tools: BuildTools,
+}
+
+impl SpigotServerInstaller {
+    /// Create a new SpigotServerInstaller for Spigot or Bukkit servers that uses the given build
+    /// cache
+    pub fn new(
+        event_handler: EventHandler,
+        unit_id: String,
+        server_type: ServerType,
+        build_tools: BuildTools,
+    ) -> Result<SpigotServerInstaller, InstallError> {
+        match server_type {
+            ServerType::Spigot | ServerType::Bukkit => Ok(Self {
+                event_handler,
+                unit_id,
+                server_type,
+                build_tools,
+            }),
+            _ => Err(InstallError::UnsupportedServerType(server_type)),
+        }
+    }
+}
+
+impl ServerInstaller for SpigotServerInstaller {
+    fn install_server(
+        &mut self,
+        install_path: String,
+        server_version: Option<Version>,
+        eula: bool,
+        server_name: Option<String>,
+    ) -> Result<ServerConfig, InstallError> {
+        let path = Path::new(install_path.as_str());
+        if path.exists() {
+            return Err(InstallError::DirExists);
+        }
+
+        progress(&mut self.event_handler, &self.unit_id, "creating server directory");
+        create_dir_all(path).map_err(InstallError::CreateDir)?;
+
+        if eula {
+            progress(
+                &mut self.event_handler,
+                &self.unit_id,
+                "creating initial server configuration",
+            );
+            fs::write(path.join("eula.txt"), "eula=true\n")
+                .map_err(InstallError::WriteInitialSettings)?
+        }
+
+        progress(&mut self.event_handler, &self.unit_id, "building jar");
+        let event_handler = &mut self.event_handler;
+        let unit_id = &self.unit_id;
+        let (built_jar, version) =
+            self.build_tools
+                .jar(&self.server_type, server_version.as_ref(), &mut |line| {
+                    progress(event_handler, unit_id, &format!("building jar: {}", line))
+                })?;
+
+        progress(&mut self.event_handler, &self.unit_id, "copying jar");
+        let (type_name, jar_name) = match self.server_type {
+            ServerType::Bukkit => ("bukkit", format!("craftbukkit_{}.jar", version)),
+            _ => ("spigot", format!("spigot_{}.jar", version)),
+        };
+        fs::copy(&built_jar, path.join(&jar_name)).map_err(|e| {
+            InstallError::PerformPatch(format!("could not copy the built jar: {}", e))
+        })?;
+
+        Ok(installed_config(server_name, path, type_name, jar_name, version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::event::EventHandler;
+    use crate::ipc::install::buildtools::BuildTools;
+    use crate::ipc::install::{ServerInstaller, SpigotServerInstaller, VanillaServerInstaller};
+    use crate::repo::fixture;
+    use crate::repo::vanilla::VanillaRepository;
+    use crate::ServerType;
+    use semver::Version;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_vanilla_installer() {
+        let jar = b"vanilla server jar".to_vec();
+        let repo = VanillaRepository::new(fixture::serve_vanilla(&jar));
+        let (sender, _events) = channel();
+        let mut installer =
+            VanillaServerInstaller::new(EventHandler::new(sender), "vanilla".to_string(), repo);
+        let dir = tempfile::tempdir().expect("create temporary directory");
+        let path = dir.path().join("server");
+
+        let config = installer
+            .install_server(
+                path.to_string_lossy().to_string(),
+                None,
+                true,
+                Some("Vanilla".to_string()),
+            )
+            .expect("install server");
+        assert_eq!(config.type_name, "vanilla");
+        assert_eq!(config.version, Version::new(1, 16, 5));
+        assert_eq!(config.jar, "vanilla_1.16.5.jar");
+        assert_eq!(
+            std::fs::read(path.join(&config.jar)).expect("read jar"),
+            jar
+        );
+        assert_eq!(
+            std::fs::read_to_string(path.join("eula.txt")).expect("read eula"),
+            "eula=true\n"
+        );
+
+        assert!(installer
+            .install_server(path.to_string_lossy().to_string(), None, true, None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_spigot_installer_reuses_jars() {
+        let cache = tempfile::tempdir().expect("create temporary directory");
+        std::fs::create_dir(cache.path().join("1.16.5")).expect("create build directory");
+        std::fs::write(
+            cache.path().join("1.16.5/craftbukkit-1.16.5.jar"),
+            "craftbukkit",
+        )
+        .expect("write jar");
+        let (sender, _events) = channel();
+        let mut installer = SpigotServerInstaller::new(
+            EventHandler::new(sender),
+            "bukkit".to_string(),
+            ServerType::Bukkit,
+            BuildTools::new(cache.path().to_path_buf()),
+        )
+        .expect("create installer");
+        let dir = tempfile::tempdir().expect("create temporary directory");
+
+        for unit in ["first", "second"] {
+            let path = dir.path().join(unit);
+            let config = installer
+                .install_server(
+                    path.to_string_lossy().to_string(),
+                    Some(Version::new(1, 16, 5)),
+                    false,
+                    None,
+                )
+                .expect("install server");
+            assert_eq!(config.type_name, "bukkit");
+            assert_eq!(config.jar, "craftbukkit_1.16.5.jar");
+            assert_eq!(
+                std::fs::read_to_string(path.join(&config.jar)).expect("read jar"),
+                "craftbukkit"
+            );
+            assert!(!path.join("eula.txt").exists());
         }
     }
 }
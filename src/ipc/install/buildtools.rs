@@ -0,0 +1,235 @@
+//! Builds Spigot and CraftBukkit (Bukkit) servers with BuildTools.
+//!
+//! Spigot does not distribute server jars, they have to be built from source by BuildTools. The
+//! builds happen in a build cache with a directory per revision, e.g. `<cache>/1.16.5/`, in which
+//! BuildTools keeps its repositories and in which the built jars stay. A jar that has been built
+//! once is reused by all units that install the same version, only the `latest` revision is
+//! rebuilt every time (BuildTools updates its repositories incrementally).
+
+use crate::ipc::install::InstallError;
+use crate::repo::{parse_version, version_id};
+use crate::ServerType;
+use semver::Version;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread::spawn;
+
+/// The URL of the latest successful build of BuildTools.
+pub const BUILD_TOOLS_URL: &str =
+    "https://hub.spigotmc.org/jenkins/job/BuildTools/lastSuccessfulBuild/artifact/target/BuildTools.jar";
+
+/// The name of BuildTools in the build cache.
+const BUILD_TOOLS_JAR: &str = "BuildTools.jar";
+
+/// The revision that BuildTools builds if no version is requested.
+const LATEST_REVISION: &str = "latest";
+
+/// The number of output lines that are included in the error of a failed build.
+const ERROR_LINES: usize = 20;
+
+/// Serializes the builds of the daemon, BuildTools must not run twice in the same directory.
+static BUILD_LOCK: Mutex<()> = Mutex::new(());
+
+/// A build cache of BuildTools.
+pub struct BuildTools {
+    /// The root of the build cache
+    cache: PathBuf,
+}
+
+impl BuildTools {
+    /// Creates a build cache at the given directory, it is created when the first jar is built.
+    pub fn new(cache: PathBuf) -> Self {
+        Self { cache }
+    }
+
+    /// Returns a jar of the given server type and version, building it first if it is not in
+    /// the cache yet.
+    ///
+    /// Without a version the latest version is built. Each line BuildTools prints is passed to
+    /// `output`. Returns the path of the jar in the cache and its version.
+    pub fn jar(
+        &self,
+        server_type: &ServerType,
+        version: Option<&Version>,
+        output: &mut dyn FnMut(&str),
+    ) -> Result<(PathBuf, Version), InstallError> {
+        let name = match server_type {
+            ServerType::Spigot => "spigot",
+            ServerType::Bukkit => "craftbukkit",
+            _ => return Err(InstallError::UnsupportedServerType(server_type.clone())),
+        };
+        let revision = version
+            .map(version_id)
+            .unwrap_or_else(|| LATEST_REVISION.to_string());
+        let directory = self.cache.join(&revision);
+
+        let _guard = BUILD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(version) = version {
+            let jar = directory.join(format!("{}-{}.jar", name, revision));
+            if jar.is_file() {
+                return Ok((jar, version.clone()));
+            }
+        }
+
+        fs::create_dir_all(&directory).map_err(InstallError::CreateDir)?;
+        let build_tools = self.build_tools()?;
+        self.run(&build_tools, &directory, &revision, name, output)?;
+
+        built_jar(&directory, name)
+            .map_err(|e| {
+                InstallError::PerformPatch(format!("could not read the build directory: {}", e))
+            })?
+            .ok_or_else(|| {
+                InstallError::PerformPatch(format!("BuildTools has not built a {} jar", name))
+            })
+    }
+
+    /// Returns the path of BuildTools, downloading it if it is not in the cache yet.
+    fn build_tools(&self) -> Result<PathBuf, InstallError> {
+        let path = self.cache.join(BUILD_TOOLS_JAR);
+        if path.is_file() {
+            return Ok(path);
+        }
+        let mut response = reqwest::blocking::get(BUILD_TOOLS_URL)
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| InstallError::DownloadFailed(Box::new(e)))?;
+        let partial = path.with_extension("jar.part");
+        let mut file =
+            File::create(&partial).map_err(|e| InstallError::DownloadFailed(Box::new(e)))?;
+        response
+            .copy_to(&mut file)
+            .map_err(|e| InstallError::DownloadFailed(Box::new(e)))?;
+        fs::rename(&partial, &path).map_err(|e| InstallError::DownloadFailed(Box::new(e)))?;
+        Ok(path)
+    }
+
+    /// Runs BuildTools in the given directory to build a server (`spigot` or `craftbukkit`) and
+    /// passes its output to `output`.
+    ///
+    /// Fails with the last lines of the output if BuildTools fails.
+    fn run(
+        &self,
+        build_tools: &Path,
+        directory: &Path,
+        revision: &str,
+        name: &str,
+        output: &mut dyn FnMut(&str),
+    ) -> Result<(), InstallError> {
+        let build_error =
+            |e: io::Error| InstallError::PerformPatch(format!("could not run BuildTools: {}", e));
+        let mut child = Command::new("java")
+            .arg("-jar")
+            .arg(build_tools)
+            .arg("--rev")
+            .arg(revision)
+            .arg("--compile")
+            .arg(name)
+            .current_dir(directory)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(build_error)?;
+
+        let errors = BufReader::new(child.stderr.take().expect("capture stderr of BuildTools"));
+        let errors = spawn(move || errors.lines().map_while(Result::ok).collect::<Vec<_>>());
+        let mut last_lines = VecDeque::with_capacity(ERROR_LINES);
+        let lines = BufReader::new(child.stdout.take().expect("capture stdout of BuildTools"));
+        for line in lines.lines() {
+            let line = line.map_err(build_error)?;
+            output(&line);
+            if last_lines.len() == ERROR_LINES {
+                last_lines.pop_front();
+            }
+            last_lines.push_back(line);
+        }
+
+        let status = child.wait().map_err(build_error)?;
+        let errors = errors.join().unwrap_or_default();
+        if status.success() {
+            Ok(())
+        } else {
+            last_lines.extend(errors);
+            let skipped = last_lines.len().saturating_sub(ERROR_LINES);
+            Err(InstallError::PerformPatch(format!(
+                "BuildTools has failed ({}):\n{}",
+                status,
+                last_lines
+                    .into_iter()
+                    .skip(skipped)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )))
+        }
+    }
+}
+
+/// Returns the jar with the highest version of the given server (`spigot` or `craftbukkit`) in a
+/// build directory and its version, e.g. `spigot-1.16.5.jar`.
+fn built_jar(directory: &Path, name: &str) -> io::Result<Option<(PathBuf, Version)>> {
+    let mut newest: Option<(PathBuf, Version)> = None;
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let version = entry
+            .file_name()
+            .to_str()
+            .and_then(|file_name| file_name.strip_prefix(name))
+            .and_then(|file_name| file_name.strip_prefix('-'))
+            .and_then(|file_name| file_name.strip_suffix(".jar"))
+            .and_then(parse_version);
+        if let Some(version) = version {
+            if newest
+                .as_ref()
+                .is_none_or(|(_, newest_version)| version > *newest_version)
+            {
+                newest = Some((entry.path(), version));
+            }
+        }
+    }
+    Ok(newest)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ipc::install::buildtools::{built_jar, BuildTools};
+    use crate::ServerType;
+    use semver::Version;
+    use std::fs;
+
+    #[test]
+    fn test_cached_jar() {
+        let cache = tempfile::tempdir().expect("create temporary directory");
+        fs::create_dir(cache.path().join("1.17")).expect("create build directory");
+        let jar = cache.path().join("1.17").join("spigot-1.17.jar");
+        fs::write(&jar, "spigot").expect("write jar");
+        let build_tools = BuildTools::new(cache.path().to_path_buf());
+
+        // a cached jar is returned without running BuildTools
+        let mut output = |line: &str| panic!("unexpected output {}", line);
+        let version = Version::new(1, 17, 0);
+        assert_eq!(
+            build_tools
+                .jar(&ServerType::Spigot, Some(&version), &mut output)
+                .expect("cached jar"),
+            (jar.clone(), version.clone())
+        );
+        assert!(build_tools
+            .jar(&ServerType::Paper, Some(&version), &mut output)
+            .is_err());
+
+        fs::write(cache.path().join("1.17").join("spigot-1.17.1.jar"), "newer").expect("write jar");
+        fs::write(cache.path().join("1.17").join("BuildData.jar"), "").expect("write jar");
+        let (path, version) = built_jar(&cache.path().join("1.17"), "spigot")
+            .expect("read build directory")
+            .expect("built jar");
+        assert_eq!(path.file_name().expect("file name"), "spigot-1.17.1.jar");
+        assert_eq!(version, Version::new(1, 17, 1));
+        assert!(built_jar(&cache.path().join("1.17"), "craftbukkit")
+            .expect("read build directory")
+            .is_none());
+    }
+}
@@ -3,13 +3,23 @@
 pub mod install;
 pub mod update;
 
+use crate::daemon::backup::{BackupInfo, BackupVerification};
+use crate::daemon::console::LogStream;
+use crate::daemon::crash::CrashReport;
+use crate::daemon::journal::JournalEntry;
+use crate::daemon::logs::{LogLines, LogSession};
+use crate::daemon::metrics::{DaemonMetrics, UnitMetrics};
+use crate::daemon::performance::{PerformanceSample, PerformanceStats};
+use crate::daemon::records::LogLevel;
 use crate::{ServerInfo, ServerType};
+use chrono::{DateTime, Utc};
 use ipc_channel::ipc::IpcSender;
 use semver::Version;
+use std::collections::BTreeMap;
 
 /// Commands sent from the client to the daemon.
 /// The expected responses are (/will be) documented in a separate document.
-#[derive(Serialize, Debug, Deserialize)]
+#[derive(Serialize, Debug, Deserialize, Clone)]
 pub enum DaemonCmd {
     /// List all currently managed units
     List,
@@ -31,13 +41,22 @@ pub enum DaemonCmd {
         /// of the server specified in `server_name`
         wait: bool,
     },
+    /// Restart a server.
+    ///
+    /// A running server is stopped and started again once its process has exited, a server that
+    /// is not running is started.
+    Restart {
+        /// The server id of the server to restart
+        server_id: String,
+    },
     /// Send events of the the given type for the given servers to the client.
     /// If [`server_ids`] is `None` the subscription is made for all servers.
     SubscribeEvent {
         /// The type of event, the client wants to subscribe to
         event_type: ServerEventType,
         /// The ids of the servers the client want to listen on.
-        /// If this value is `None` the client wants to receive events from all servers.
+        /// If this value is `None` the client wants to receive events from all servers, including
+        /// events of the daemon itself (e.g. [`ServerEvent::ClientConnected`]).
         server_ids: Option<Vec<String>>,
     },
     /// Install a new server
@@ -75,11 +94,228 @@ pub enum DaemonCmd {
     SendCommand {
         unit_id: String,
         command: String,
+    },
+    /// Scan the unit directories again.
+    ///
+    /// New units are added, units whose unit file has disappeared are removed and all other units
+    /// are reloaded from their unit files. Units that are not stopped are left untouched.
+    ReloadUnits,
+    /// Attach to the console of a server.
+    ///
+    /// The daemon answers with [`DaemonResponse::ConsoleBacklog`] and subscribes the client to the
+    /// [`ServerEvent::ConsoleLine`] events of the server. Every output line of the server is
+    /// delivered exactly once, either as part of the backlog or as an event.
+    AttachConsole {
+        /// The unit id of the server
+        unit_id: String,
+        /// The maximum number of previous output lines that should be sent
+        backlog: usize,
+    },
+    /// Stop receiving the console output of a server.
+    DetachConsole {
+        /// The unit id of the server
+        unit_id: String,
+    },
+    /// List the log sessions of a server and read or search a range of lines of one session.
+    ///
+    /// The daemon answers with [`DaemonResponse::Ok`] and sends [`DaemonResponse::Logs`] when the
+    /// lines have been read.
+    GetLogs {
+        /// The unit id of the server
+        unit_id: String,
+        /// The log session to read, the newest session if `None`
+        session: Option<String>,
+        /// The index of the first line to read. If `None` the last `limit` lines are read.
+        from: Option<usize>,
+        /// The maximum number of lines to read, `0` only lists the sessions
+        limit: usize,
+        /// A regular expression, only matching lines are returned if set
+        grep: Option<String>,
+        /// The minimum level of the returned lines, lines without a known level are skipped
+        #[serde(default)]
+        level: Option<LogLevel>,
+        /// Whether the lines should also be returned as structured records
+        #[serde(default)]
+        records: bool,
+    },
+    /// Get the tick rate history of a running server.
+    ///
+    /// The daemon answers with [`DaemonResponse::Performance`].
+    GetPerformance {
+        /// The unit id of the server
+        unit_id: String,
+    },
+    /// Read the journal of commands and events.
+    ///
+    /// The daemon answers with [`DaemonResponse::Ok`] and sends [`DaemonResponse::Journal`] when
+    /// the journal has been read.
+    QueryJournal {
+        /// Only entries of this unit are returned if set
+        unit_id: Option<String>,
+        /// Only entries recorded at or after this time are returned if set
+        since: Option<DateTime<Utc>>,
+        /// Only entries of these types are returned, all entries if empty.
+        ///
+        /// A type is the name of a command (e.g. `Stop`) or an event type (e.g. `ServerFailed`),
+        /// `command` and `event` select all commands or events.
+        types: Vec<String>,
+        /// The maximum number of entries, the newest entries are returned. `0` returns all
+        /// matching entries.
+        limit: usize,
+    },
+    /// Get the values of the metrics of all units and of the daemon.
+    ///
+    /// The daemon answers with [`DaemonResponse::Metrics`].
+    GetMetrics,
+    /// Back up the worlds of a server.
+    ///
+    /// The daemon answers with [`DaemonResponse::Ok`] and subscribes the client to the
+    /// [`ServerEvent::ActionProgress`], [`ServerEvent::BackupComplete`] and
+    /// [`ServerEvent::BackupFailed`] events of the unit.
+    Backup {
+        /// The unit id of the server
+        unit_id: String,
+        /// A label that describes the backup, e.g. `before update`
+        label: Option<String>,
+    },
+    /// List the backups of a unit.
+    ///
+    /// The daemon answers with [`DaemonResponse::Backups`].
+    ListBackups {
+        /// The unit id of the server, the unit does not have to exist anymore
+        unit_id: String,
+    },
+    /// Delete the backups that are not kept by the retention policies of the units.
+    ///
+    /// The daemon answers with [`DaemonResponse::Ok`] and sends [`DaemonResponse::BackupsPruned`]
+    /// when the backups have been deleted. Backups of a unit can not be pruned while a backup or
+    /// restore of the unit is running, and vice versa.
+    PruneBackups {
+        /// Only the backups of this unit are pruned if set
+        unit_id: Option<String>,
+        /// Only return the backups that would be deleted
+        dry_run: bool,
+    },
+    /// Restore a backup of a server.
+    ///
+    /// A running server is stopped and started again after the restore. The current worlds are
+    /// backed up first; if the restore fails, they are restored from this snapshot. The daemon
+    /// answers with [`DaemonResponse::Ok`] and subscribes the client to the
+    /// [`ServerEvent::ActionProgress`], [`ServerEvent::RestoreComplete`] and
+    /// [`ServerEvent::RestoreFailed`] events of the unit.
+    Restore {
+        /// The unit id of the server
+        unit_id: String,
+        /// The id of the backup
+        backup_id: String,
+        /// Whether the server jar is restored as well
+        jar: bool,
+        /// Whether the unit file is restored as well
+        config: bool,
+    },
+    /// Check that backups can be restored.
+    ///
+    /// The daemon answers with [`DaemonResponse::Ok`] and sends
+    /// [`DaemonResponse::BackupsVerified`] when all backups have been checked.
+    VerifyBackups {
+        /// Only the backups of this unit are verified if set
+        unit_id: Option<String>,
+    },
+    /// Put a running server into lockdown, see [`crate::daemon::lockdown`].
+    ///
+    /// The daemon answers with [`DaemonResponse::Ok`] and subscribes the client to the
+    /// [`ServerEvent::ActionProgress`], [`ServerEvent::LockdownComplete`] and
+    /// [`ServerEvent::LockdownFailed`] events of the unit.
+    Lockdown {
+        /// The unit id of the server
+        unit_id: String,
+        /// The message the players are kicked with
+        message: Option<String>,
+    },
+    /// Resume a server in lockdown.
+    ///
+    /// The daemon answers with [`DaemonResponse::Ok`].
+    Unlock {
+        /// The unit id of the server
+        unit_id: String,
+    },
+    /// Delete the chunks that no chunked backup refers to anymore.
+    ///
+    /// The daemon answers with [`DaemonResponse::Ok`] and sends
+    /// [`DaemonResponse::BackupGarbageCollected`] when the chunks have been deleted. No backups,
+    /// restores or lockdowns can be started meanwhile.
+    CollectBackupGarbage {
+        /// Only return the size of the chunks that would be deleted
+        dry_run: bool,
+    },
+}
+
+impl DaemonCmd {
+    /// Returns the name of the command, e.g. `Start`.
+    pub fn name(&self) -> String {
+        let debug = format!("{:?}", self);
+        debug
+            .split(|c: char| !c.is_alphanumeric())
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Returns the id of the unit the command refers to, if any.
+    pub fn unit_id(&self) -> Option<&str> {
+        match self {
+            DaemonCmd::Start { server_id, .. }
+            | DaemonCmd::Stop { server_id, .. }
+            | DaemonCmd::Restart { server_id } => Some(server_id),
+            DaemonCmd::InstallServer { unit_id, .. }
+            | DaemonCmd::UpdateServer { unit_id, .. }
+            | DaemonCmd::SendMessage { unit_id, .. }
+            | DaemonCmd::SendCommand { unit_id, .. }
+            | DaemonCmd::AttachConsole { unit_id, .. }
+            | DaemonCmd::DetachConsole { unit_id }
+            | DaemonCmd::GetLogs { unit_id, .. }
+            | DaemonCmd::GetPerformance { unit_id }
+            | DaemonCmd::Backup { unit_id, .. }
+            | DaemonCmd::ListBackups { unit_id }
+            | DaemonCmd::Restore { unit_id, .. }
+            | DaemonCmd::Lockdown { unit_id, .. }
+            | DaemonCmd::Unlock { unit_id } => Some(unit_id),
+            DaemonCmd::QueryJournal { unit_id, .. }
+            | DaemonCmd::PruneBackups { unit_id, .. }
+            | DaemonCmd::VerifyBackups { unit_id } => unit_id.as_deref(),
+            DaemonCmd::List
+            | DaemonCmd::GetVersion
+            | DaemonCmd::SubscribeEvent { .. }
+            | DaemonCmd::StopDaemon
+            | DaemonCmd::ReloadUnits
+            | DaemonCmd::GetMetrics
+            | DaemonCmd::CollectBackupGarbage { .. } => None,
+        }
+    }
+
+    /// Returns whether the command only reads information and changes nothing.
+    pub fn is_query(&self) -> bool {
+        matches!(
+            self,
+            DaemonCmd::List
+                | DaemonCmd::GetVersion
+                | DaemonCmd::SubscribeEvent { .. }
+                | DaemonCmd::AttachConsole { .. }
+                | DaemonCmd::DetachConsole { .. }
+                | DaemonCmd::GetLogs { .. }
+                | DaemonCmd::GetPerformance { .. }
+                | DaemonCmd::QueryJournal { .. }
+                | DaemonCmd::GetMetrics
+                | DaemonCmd::ListBackups { .. }
+                | DaemonCmd::PruneBackups { dry_run: true, .. }
+                | DaemonCmd::VerifyBackups { .. }
+                | DaemonCmd::CollectBackupGarbage { dry_run: true }
+        )
     }
 }
 
 /// Responses sent from the daemon to a client
-#[derive(Serialize, Debug, Deserialize)]
+#[derive(Serialize, Debug, Deserialize, Clone)]
 pub enum DaemonResponse {
     /// A list of all currently managed units
     List {
@@ -134,6 +370,109 @@ pub enum DaemonResponse {
     /// Note: This response does not specify to which request it belongs, this should be changed.
     Ok,
     DaemonEvent(DaemonIpcEvent),
+    /// The unit directories have been scanned again
+    UnitsReloaded {
+        /// Units that have been added
+        added: Vec<String>,
+        /// Units that have been reloaded from their unit file
+        reloaded: Vec<String>,
+        /// Units that have been removed
+        removed: Vec<String>,
+        /// Units that have not been touched because they are not stopped
+        skipped: Vec<String>,
+    },
+    /// The log sessions of a server and the requested lines
+    Logs {
+        /// The id of the server
+        unit_id: String,
+        /// All log sessions of the server, oldest first
+        sessions: Vec<LogSession>,
+        /// The requested lines, `None` if no lines were requested or no session exists
+        lines: Option<LogLines>,
+    },
+    /// The requested log session does not exist
+    LogSessionNotFound {
+        /// The id of the server
+        unit_id: String,
+        /// The requested session
+        session: String,
+    },
+    /// The command contained invalid arguments
+    InvalidRequest {
+        /// Description of the problem
+        error: String,
+    },
+    /// The command could not be executed because of an error in the daemon
+    Error {
+        /// Description of the error
+        error: String,
+    },
+    /// A `pre_start` hook of a unit has failed and the start has been cancelled.
+    ///
+    /// The hook runs after the daemon has answered the start command, so this is sent to the
+    /// client that has asked for the start once the hook has finished.
+    HookFailed {
+        /// The id of the unit
+        unit_id: String,
+        /// The name of the hook, e.g. `pre_start`
+        hook: String,
+        /// Description of the failure
+        error: String,
+    },
+    /// The most recent output lines of a server, sent when attaching to its console
+    ConsoleBacklog {
+        /// The id of the server
+        server_id: String,
+        /// The output lines as written to the log (see [`LogStream::tag`]), oldest first
+        lines: Vec<String>,
+    },
+    /// The tick rate history of the current process of a server
+    Performance {
+        /// The id of the server
+        unit_id: String,
+        /// The history, empty if the server is not running or is not monitored
+        stats: PerformanceStats,
+    },
+    /// The matching entries of the journal, oldest first
+    Journal {
+        /// The entries
+        entries: Vec<JournalEntry>,
+    },
+    /// The backups of a unit, oldest first
+    Backups {
+        /// The id of the unit
+        unit_id: String,
+        /// The backups
+        backups: Vec<BackupInfo>,
+    },
+    /// The backups that have been deleted by [`DaemonCmd::PruneBackups`]
+    BackupsPruned {
+        /// The deleted backups, or the backups that would be deleted in a dry run
+        backups: Vec<BackupInfo>,
+        /// Whether the backups have been kept because it was a dry run
+        dry_run: bool,
+    },
+    /// The results of [`DaemonCmd::VerifyBackups`]
+    BackupsVerified {
+        /// The verified backups, oldest first per unit
+        verifications: Vec<BackupVerification>,
+    },
+    /// The chunks that have been deleted by [`DaemonCmd::CollectBackupGarbage`]
+    BackupGarbageCollected {
+        /// The number of deleted chunks
+        chunks: usize,
+        /// The size of the deleted chunks in bytes
+        size: u64,
+        /// Whether the chunks have been kept because it was a dry run
+        dry_run: bool,
+    },
+    /// The current values of the metrics
+    Metrics {
+        /// The metrics of the units, including units that only have counters left
+        units: Vec<UnitMetrics>,
+        /// The metrics of the daemon
+        daemon: DaemonMetrics,
+    },
 }
 
 /// Information for a new connection used when establishing a new connection to the daemon.
@@ -195,9 +534,171 @@ pub enum ServerEvent {
         server_id: String,
         error: String,
     },
+    /// The worlds of a server have been backed up
+    BackupComplete {
+        /// The id of the server
+        server_id: String,
+        /// The new backup
+        backup: Box<BackupInfo>,
+    },
+    /// A backup of a server has failed
+    BackupFailed {
+        /// The id of the server
+        server_id: String,
+        /// The reason of the failure
+        error: String,
+    },
+    /// A backup of a server has been restored
+    RestoreComplete {
+        /// The id of the server
+        server_id: String,
+        /// The id of the restored backup
+        backup_id: String,
+        /// The id of the backup of the worlds before the restore, `None` if there were none
+        snapshot_id: Option<String>,
+    },
+    /// A restore of a server has failed
+    RestoreFailed {
+        /// The id of the server
+        server_id: String,
+        /// The reason of the failure, including whether the previous state has been restored
+        error: String,
+    },
+    /// A server has been put into lockdown, its process is suspended
+    LockdownComplete {
+        /// The id of the server
+        server_id: String,
+        /// The id of the snapshot of the worlds, `None` if it could not be taken
+        snapshot_id: Option<String>,
+        /// The reason why no snapshot has been taken
+        snapshot_error: Option<String>,
+    },
+    /// A server could not be put into lockdown, it keeps running
+    LockdownFailed {
+        /// The id of the server
+        server_id: String,
+        /// The reason of the failure
+        error: String,
+    },
+    /// A server in lockdown has been resumed
+    ServerUnlocked {
+        /// The id of the server
+        server_id: String,
+    },
     ServerFailed {
         server_id: String,
         error: String,
+        /// The last lines the server process has written to stderr before it failed
+        #[serde(default)]
+        stderr: Vec<String>,
+        /// The last exception stack trace in the output of the server
+        #[serde(default)]
+        exception: Vec<String>,
+        /// The newest crash report written by the server process
+        #[serde(default)]
+        crash_report: Option<CrashReport>,
+    },
+    /// A unit has been added to the daemon
+    UnitAdded {
+        /// The id of the new unit
+        server_id: String,
+    },
+    /// A unit has been removed from the daemon
+    UnitRemoved {
+        /// The id of the removed unit
+        server_id: String,
+    },
+    /// The configuration of a unit has been reloaded from its unit file
+    UnitReloaded {
+        /// The id of the reloaded unit
+        server_id: String,
+    },
+    /// A client has connected to the daemon.
+    ///
+    /// This is a daemon event, it is only sent to subscriptions for all servers.
+    ClientConnected {
+        /// The id assigned to the client
+        client_id: u32,
+        /// The name the client has announced
+        client_name: String,
+    },
+    /// A client has disconnected from the daemon.
+    ///
+    /// This is a daemon event, it is only sent to subscriptions for all servers.
+    ClientDisconnected {
+        /// The id of the client
+        client_id: u32,
+        /// The name of the client
+        client_name: String,
+    },
+    /// A server has written a line to its console
+    ConsoleLine {
+        /// The id of the server
+        server_id: String,
+        /// The line without the line terminator
+        line: String,
+        /// The stream the line was written to
+        #[serde(default)]
+        stream: LogStream,
+    },
+    /// A player has joined a server
+    PlayerJoined {
+        /// The id of the server
+        server_id: String,
+        /// The name of the player
+        player: String,
+    },
+    /// A player has left a server
+    PlayerLeft {
+        /// The id of the server
+        server_id: String,
+        /// The name of the player
+        player: String,
+    },
+    /// A player has written a chat message
+    PlayerChat {
+        /// The id of the server
+        server_id: String,
+        /// The name of the player
+        player: String,
+        /// The chat message
+        message: String,
+    },
+    /// A player has died
+    PlayerDeath {
+        /// The id of the server
+        server_id: String,
+        /// The name of the player
+        player: String,
+        /// The death message as shown in the chat
+        message: String,
+    },
+    /// A player has made an advancement
+    PlayerAdvancement {
+        /// The id of the server
+        server_id: String,
+        /// The name of the player
+        player: String,
+        /// The name of the advancement
+        advancement: String,
+    },
+    /// The performance of a server has crossed a threshold of its unit file
+    PerformanceDegraded {
+        /// The id of the server
+        server_id: String,
+        /// The poll interval that has crossed the thresholds
+        sample: PerformanceSample,
+        /// The thresholds that have been crossed
+        reasons: Vec<String>,
+    },
+    /// A console trigger of a unit has raised an event
+    Custom {
+        /// The id of the server
+        server_id: String,
+        /// The event name of the trigger
+        name: String,
+        /// The capture groups of the trigger pattern by number and name
+        captures: BTreeMap<String, String>,
     },
 }
 
@@ -214,7 +715,62 @@ impl ServerEvent {
             ServerEvent::InstallationFailed { .. } => ServerEventType::InstallationFailed,
             ServerEvent::UpdateComplete { .. } => ServerEventType::UpdateComplete,
             ServerEvent::UpdateFailed { .. } => ServerEventType::UpdateFailed,
+            ServerEvent::BackupComplete { .. } => ServerEventType::BackupComplete,
+            ServerEvent::BackupFailed { .. } => ServerEventType::BackupFailed,
+            ServerEvent::RestoreComplete { .. } => ServerEventType::RestoreComplete,
+            ServerEvent::RestoreFailed { .. } => ServerEventType::RestoreFailed,
+            ServerEvent::LockdownComplete { .. } => ServerEventType::LockdownComplete,
+            ServerEvent::LockdownFailed { .. } => ServerEventType::LockdownFailed,
+            ServerEvent::ServerUnlocked { .. } => ServerEventType::ServerUnlocked,
             ServerEvent::ServerFailed { .. } => ServerEventType::ServerFailed,
+            ServerEvent::UnitAdded { .. } => ServerEventType::UnitAdded,
+            ServerEvent::UnitRemoved { .. } => ServerEventType::UnitRemoved,
+            ServerEvent::UnitReloaded { .. } => ServerEventType::UnitReloaded,
+            ServerEvent::ClientConnected { .. } => ServerEventType::ClientConnected,
+            ServerEvent::ClientDisconnected { .. } => ServerEventType::ClientDisconnected,
+            ServerEvent::ConsoleLine { .. } => ServerEventType::ConsoleLine,
+            ServerEvent::PlayerJoined { .. } => ServerEventType::PlayerJoined,
+            ServerEvent::PlayerLeft { .. } => ServerEventType::PlayerLeft,
+            ServerEvent::PlayerChat { .. } => ServerEventType::PlayerChat,
+            ServerEvent::PlayerDeath { .. } => ServerEventType::PlayerDeath,
+            ServerEvent::PlayerAdvancement { .. } => ServerEventType::PlayerAdvancement,
+            ServerEvent::PerformanceDegraded { .. } => ServerEventType::PerformanceDegraded,
+            ServerEvent::Custom { name, .. } => ServerEventType::Custom(name.clone()),
+        }
+    }
+
+    /// Returns the id of the server the event belongs to, `None` for daemon events.
+    pub fn server_id(&self) -> Option<&str> {
+        match self {
+            ServerEvent::ServerStarting { server_id }
+            | ServerEvent::ServerStarted { server_id }
+            | ServerEvent::ServerStopping { server_id }
+            | ServerEvent::ServerStopped { server_id }
+            | ServerEvent::ActionProgress { server_id, .. }
+            | ServerEvent::InstallationComplete { server_id }
+            | ServerEvent::InstallationFailed { server_id, .. }
+            | ServerEvent::UpdateComplete { server_id }
+            | ServerEvent::UpdateFailed { server_id, .. }
+            | ServerEvent::BackupComplete { server_id, .. }
+            | ServerEvent::BackupFailed { server_id, .. }
+            | ServerEvent::RestoreComplete { server_id, .. }
+            | ServerEvent::RestoreFailed { server_id, .. }
+            | ServerEvent::LockdownComplete { server_id, .. }
+            | ServerEvent::LockdownFailed { server_id, .. }
+            | ServerEvent::ServerUnlocked { server_id }
+            | ServerEvent::ServerFailed { server_id, .. }
+            | ServerEvent::UnitAdded { server_id }
+            | ServerEvent::UnitRemoved { server_id }
+            | ServerEvent::UnitReloaded { server_id }
+            | ServerEvent::ConsoleLine { server_id, .. }
+            | ServerEvent::PlayerJoined { server_id, .. }
+            | ServerEvent::PlayerLeft { server_id, .. }
+            | ServerEvent::PlayerChat { server_id, .. }
+            | ServerEvent::PlayerDeath { server_id, .. }
+            | ServerEvent::PlayerAdvancement { server_id, .. }
+            | ServerEvent::PerformanceDegraded { server_id, .. }
+            | ServerEvent::Custom { server_id, .. } => Some(server_id),
+            ServerEvent::ClientConnected { .. } | ServerEvent::ClientDisconnected { .. } => None,
         }
     }
 }
@@ -222,8 +778,9 @@ impl ServerEvent {
 /// Types of server events.
 ///
 /// There exists a 1:1 mapping between [`ServerEvent`]s and [`ServerEventType`]s, defined by
-/// [`ServerEvent::get_event_type(&self)`].
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// [`ServerEvent::get_event_type(&self)`]. [`ServerEvent::Custom`] events have a type per name,
+/// subscribing to [`ServerEventType::AnyCustom`] receives the custom events of every name.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ServerEventType {
     /// A server has entered the starting stage
     ServerStarting,
@@ -238,7 +795,88 @@ pub enum ServerEventType {
     InstallationFailed,
     UpdateComplete,
     UpdateFailed,
+    /// The worlds of a server have been backed up
+    BackupComplete,
+    /// A backup of a server has failed
+    BackupFailed,
+    /// A backup of a server has been restored
+    RestoreComplete,
+    /// A restore of a server has failed
+    RestoreFailed,
+    /// A server has been put into lockdown
+    LockdownComplete,
+    /// A server could not be put into lockdown
+    LockdownFailed,
+    /// A server in lockdown has been resumed
+    ServerUnlocked,
     ServerFailed,
+    /// A unit has been added to the daemon
+    UnitAdded,
+    /// A unit has been removed from the daemon
+    UnitRemoved,
+    /// The configuration of a unit has been reloaded
+    UnitReloaded,
+    /// A client has connected to the daemon
+    ClientConnected,
+    /// A client has disconnected from the daemon
+    ClientDisconnected,
+    /// A server has written a line to its console
+    ConsoleLine,
+    /// A player has joined a server
+    PlayerJoined,
+    /// A player has left a server
+    PlayerLeft,
+    /// A player has written a chat message
+    PlayerChat,
+    /// A player has died
+    PlayerDeath,
+    /// A player has made an advancement
+    PlayerAdvancement,
+    /// The performance of a server has crossed a threshold
+    PerformanceDegraded,
+    /// A console trigger has raised a custom event with the given name
+    Custom(String),
+    /// A console trigger has raised a custom event with any name
+    AnyCustom,
+}
+
+impl ServerEventType {
+    /// Returns all event types, the custom events of all names are covered by
+    /// [`ServerEventType::AnyCustom`].
+    pub fn all() -> &'static [ServerEventType] {
+        &[
+            ServerEventType::ServerStarting,
+            ServerEventType::ServerStarted,
+            ServerEventType::ServerStopping,
+            ServerEventType::ServerStopped,
+            ServerEventType::ActionProgress,
+            ServerEventType::InstallationComplete,
+            ServerEventType::InstallationFailed,
+            ServerEventType::UpdateComplete,
+            ServerEventType::UpdateFailed,
+            ServerEventType::BackupComplete,
+            ServerEventType::BackupFailed,
+            ServerEventType::RestoreComplete,
+            ServerEventType::RestoreFailed,
+            ServerEventType::LockdownComplete,
+            ServerEventType::LockdownFailed,
+            ServerEventType::ServerUnlocked,
+            ServerEventType::ServerFailed,
+            ServerEventType::UnitAdded,
+            ServerEventType::UnitRemoved,
+            ServerEventType::UnitReloaded,
+            ServerEventType::ClientConnected,
+            ServerEventType::ClientDisconnected,
+            ServerEventType::ConsoleLine,
+            ServerEventType::PlayerJoined,
+            ServerEventType::PlayerLeft,
+            ServerEventType::PlayerChat,
+            ServerEventType::PlayerDeath,
+            ServerEventType::PlayerAdvancement,
+            ServerEventType::PerformanceDegraded,
+            ServerEventType::AnyCustom,
+        ]
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -5,7 +5,8 @@ use crate::daemon::event::EventHandler;
 use crate::ipc::install::InstallError;
 use crate::ipc::ServerEvent;
 use crate::repo::paper::PaperRepository;
-use crate::repo::Repository;
+use crate::repo::vanilla::VanillaRepository;
+use crate::repo::{Artifact, Repository};
 use crate::ServerType;
 use semver::Version;
 use std::error::Error;
@@ -45,9 +46,25 @@ pub enum UpdateError {
     AlreadyUpToDate,
     /// The updater does not support updating the requested server port
     UnsupportedServerType(ServerType),
+    /// Another step of the update has failed, the String describes the error
+    Other(String),
 }
 
-/// Updater for paper servers
+impl From<InstallError> for UpdateError {
+    fn from(error: InstallError) -> Self {
+        match error {
+            InstallError::DownloadFailed(e) => UpdateError::DownloadFailed(e),
+            InstallError::PerformPatch(message) => UpdateError::PerformPatch(message),
+            InstallError::WriteUnitFile(e) => UpdateError::WriteUnitFile(e),
+            InstallError::UnsupportedServerType(server_type) => {
+                UpdateError::UnsupportedServerType(server_type)
+            }
+            error => UpdateError::Other(format!("{:?}", error)),
+        }
+    }
+}
+
+/// Updater for the projects of PaperMC, only Paper and Folia jars are patched
 pub struct PaperServerUpdater {
     /// The id of the unit, that should be updated
     unit_id: String,
@@ -56,17 +73,23 @@ pub struct PaperServerUpdater {
     unit_file_path: PathBuf,
     /// an event handler which is connected to the main event manager
     event_handler: EventHandler,
+    /// The repository of the project of the server
     repo: PaperRepository,
 }
 
 impl PaperServerUpdater {
-    /// Create a new updater for paper servers
-    pub fn new(unit_id: String, unit_file_path: PathBuf, event_handler: EventHandler) -> Self {
+    /// Create a new updater for servers of the project of the given repository
+    pub fn new(
+        unit_id: String,
+        unit_file_path: PathBuf,
+        event_handler: EventHandler,
+        repo: PaperRepository,
+    ) -> Self {
         Self {
             unit_id,
             unit_file_path,
             event_handler,
-            repo: PaperRepository {},
+            repo,
         }
     }
 }
@@ -126,15 +149,17 @@ impl ServerUpdater for PaperServerUpdater {
         let mut dest_path = PathBuf::new();
         dest_path.push(&path);
 
-        let jar_name = format!("paper_{}.jar", target_artifact.version()).replace("+", "-");
+        let project = self.repo.project();
+        let jar_name =
+            format!("{}_{}.jar", project.id(), target_artifact.version()).replace("+", "-");
         dest_path.push(&jar_name);
 
-        target_artifact
-            .download_to(dest_path.as_path())
-            .map_err(|e| match e {
-                InstallError::DownloadFailed(e) => UpdateError::DownloadFailed(e),
-                _ => unimplemented!(),
-            })?;
+        target_artifact.download_to(dest_path.as_path())?;
+        if !project.is_patched() {
+            server_config.version = target_artifact.version();
+            server_config.jar = jar_name;
+            return Ok(server_config);
+        }
 
         self.event_handler.raise_event(
             self.unit_id.as_str(),
@@ -166,3 +191,119 @@ impl ServerUpdater for PaperServerUpdater {
         }
     }
 }
+
+/// Updater for vanilla servers
+///
+/// The new jar is downloaded next to the old one, no patch step is needed.
+pub struct VanillaServerUpdater {
+    /// The id of the unit, that should be updated
+    unit_id: String,
+    /// an event handler which is connected to the main event manager
+    event_handler: EventHandler,
+    /// The repository the server jars are downloaded from
+    repo: VanillaRepository,
+}
+
+impl VanillaServerUpdater {
+    /// Create a new updater for vanilla servers that uses the given repository
+    pub fn new(unit_id: String, event_handler: EventHandler, repo: VanillaRepository) -> Self {
+        Self {
+            unit_id,
+            event_handler,
+            repo,
+        }
+    }
+
+    /// Raises the progress of the update
+    fn progress(&mut self, action: &str) {
+        self.event_handler.raise_event(
+            self.unit_id.as_str(),
+            ServerEvent::ActionProgress {
+                server_id: self.unit_id.clone(),
+                action: action.to_string(),
+                progress: None,
+                maximum: None,
+                action_number: 1,
+            },
+        );
+    }
+}
+
+impl ServerUpdater for VanillaServerUpdater {
+    fn update_server(
+        &mut self,
+        server_version: Option<Version>,
+        mut server_config: ServerConfig,
+    ) -> Result<ServerConfig, UpdateError> {
+        self.progress("checking for update");
+        let target_version = match server_version {
+            Some(target_version) => target_version,
+            None => self
+                .repo
+                .latest_version()
+                .map_err(|e| UpdateError::DownloadFailed(Box::new(e)))?,
+        };
+        if server_config.version >= target_version {
+            return Err(UpdateError::AlreadyUpToDate);
+        }
+        let target_artifact = self
+            .repo
+            .artifact(&target_version)
+            .map_err(|e| UpdateError::DownloadFailed(Box::new(e)))?;
+
+        self.progress("downloading jar");
+        let jar_name = format!("vanilla_{}.jar", target_artifact.version());
+        target_artifact.download_to(server_config.path.join(&jar_name).as_path())?;
+
+        server_config.version = target_artifact.version();
+        server_config.jar = jar_name;
+        Ok(server_config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ServerConfig;
+    use crate::daemon::event::EventHandler;
+    use crate::ipc::update::{ServerUpdater, UpdateError, VanillaServerUpdater};
+    use crate::repo::fixture;
+    use crate::repo::vanilla::VanillaRepository;
+    use semver::Version;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_vanilla_updater() {
+        let jar = b"vanilla server jar".to_vec();
+        let repo = VanillaRepository::new(fixture::serve_vanilla(&jar));
+        let (sender, _events) = channel();
+        let mut updater =
+            VanillaServerUpdater::new("vanilla".to_string(), EventHandler::new(sender), repo);
+        let dir = tempfile::tempdir().expect("create temporary directory");
+        let config = ServerConfig {
+            name: "Vanilla".to_string(),
+            path: Box::from(dir.path()),
+            type_name: "vanilla".to_string(),
+            jar: "vanilla_1.16.4.jar".to_string(),
+            version: Version::new(1, 16, 4),
+            memory: 2,
+            log_patterns: None,
+            logs: None,
+            performance: None,
+            hooks: None,
+            backup: None,
+        };
+
+        let updated = updater.update_server(None, config).expect("update server");
+        assert_eq!(updated.version, Version::new(1, 16, 5));
+        assert_eq!(updated.jar, "vanilla_1.16.5.jar");
+        assert_eq!(
+            std::fs::read(dir.path().join(&updated.jar)).expect("read jar"),
+            jar
+        );
+
+        assert!(matches!(
+            updater.update_server(None, updated),
+            Err(UpdateError::AlreadyUpToDate)
+        ));
+    }
+}
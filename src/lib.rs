@@ -13,6 +13,7 @@ pub mod repo;
 extern crate serde_derive;
 
 use crate::config::UnitConfig;
+use crate::daemon::crash::ServerFailure;
 use semver::Version;
 use serde::export::Formatter;
 use std::fmt::Display;
@@ -61,6 +62,19 @@ pub enum ServerType {
     Bukkit,
     /// Spigot server
     Spigot,
+    /// Folia server, the regionised multithreading fork of PaperMC
+    Folia,
+    /// Velocity proxy
+    Velocity,
+    /// Waterfall proxy
+    Waterfall,
+}
+
+impl ServerType {
+    /// Returns whether the server is a proxy, which has no worlds and does not need an EULA.
+    pub fn is_proxy(&self) -> bool {
+        matches!(self, ServerType::Velocity | ServerType::Waterfall)
+    }
 }
 
 impl Display for ServerType {
@@ -84,7 +98,8 @@ pub enum ServerStatus {
     Down,
     /// The server is in lockdown.
     ///
-    /// If possible (in the future) the process in which the server is running is suspended and a backup of the worlds in made.
+    /// The players have been kicked, the process in which the server is running is suspended and
+    /// a backup of the worlds is made, see [`daemon::lockdown`].
     Lockdown,
     /// The process of the server has stopped.
     /// The error code is contained in this variant.
@@ -112,6 +127,10 @@ pub struct ServerInfo {
     pub server_version: Version,
     /// The current status of the server
     pub server_status: ServerStatus,
+    /// The names of the players that are currently online, sorted alphabetically
+    pub online_players: Vec<String>,
+    /// The last failure of the server since the daemon has started
+    pub last_failure: Option<ServerFailure>,
 }
 
 /// General properties of any unit.
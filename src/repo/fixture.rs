@@ -0,0 +1,227 @@
+//! A local HTTP server that answers the requests of tests, e.g. of repositories or webhooks, without
+//! network access.
+
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread::spawn;
+
+/// A request received by a server of [`serve_requests`]
+pub struct Request {
+    /// The path of the request, including the query
+    pub path: String,
+    /// The body of the request
+    pub body: Vec<u8>,
+}
+
+/// Serves the given files, keyed by their path, on a local port until the test process exits.
+///
+/// `files` is called with the base URL of the server (without a trailing slash), so the files
+/// can refer to each other.
+pub fn serve<F>(files: F) -> String
+where
+    F: FnOnce(&str) -> HashMap<String, Vec<u8>>,
+{
+    let (listener, base_url) = listen();
+    let files = files(&base_url);
+    answer(listener, move |request| match files.get(&request.path) {
+        Some(body) => (200, body.clone()),
+        None => (404, vec![]),
+    });
+    base_url
+}
+
+/// Answers every request on a local port with the status and the body returned by `handler`
+/// until the test process exits, and returns the base URL of the server.
+pub fn serve_requests<F>(handler: F) -> String
+where
+    F: FnMut(Request) -> (u16, Vec<u8>) + Send + 'static,
+{
+    let (listener, base_url) = listen();
+    answer(listener, handler);
+    base_url
+}
+
+/// Binds a listener to a free local port and returns it with its base URL.
+fn listen() -> (TcpListener, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind listener");
+    let base_url = format!("http://{}", listener.local_addr().expect("local address"));
+    (listener, base_url)
+}
+
+/// Answers the requests of `listener` with `handler` on a new thread.
+fn answer<F>(listener: TcpListener, mut handler: F)
+where
+    F: FnMut(Request) -> (u16, Vec<u8>) + Send + 'static,
+{
+    spawn(move || {
+        for stream in listener.incoming() {
+            let mut reader = BufReader::new(stream.expect("accept connection"));
+            let mut request_line = String::new();
+            reader
+                .read_line(&mut request_line)
+                .expect("read request line");
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).expect("read header");
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().expect("parse content length");
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).expect("read body");
+            let path = request_line.split(' ').nth(1).unwrap_or_default();
+            let (status, body) = handler(Request {
+                path: path.to_string(),
+                body,
+            });
+            let stream = reader.get_mut();
+            write!(
+                stream,
+                "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            )
+            .expect("write response");
+            stream.write_all(&body).expect("write body");
+        }
+    });
+}
+
+/// Serves a version manifest with the releases `1.16.4` and `1.16.5` and the snapshots `21w03a`
+/// and `1.17-pre1`, all with `jar` as their server jar, and returns the URL of the manifest.
+///
+/// The jar of `1.16.4` is served reversed, so its hash does not match the announced one.
+pub fn serve_vanilla(jar: &[u8]) -> String {
+    let sha1: String = Sha1::digest(jar)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let base_url = serve(|base_url| {
+        let versions = [
+            ("1.17-pre1", "snapshot"),
+            ("21w03a", "snapshot"),
+            ("1.16.5", "release"),
+            ("1.16.4", "release"),
+        ];
+        let mut files = HashMap::new();
+        let entries: Vec<_> = versions
+            .iter()
+            .map(|(id, version_type)| {
+                serde_json::json!({
+                    "id": id,
+                    "type": version_type,
+                    "url": format!("{}/v1/packages/{}.json", base_url, id),
+                    "time": "2021-01-15T16:05:32+00:00",
+                    "releaseTime": "2021-01-15T16:05:32+00:00",
+                })
+            })
+            .collect();
+        let manifest = serde_json::json!({
+            "latest": { "release": "1.16.5", "snapshot": "1.17-pre1" },
+            "versions": entries,
+        });
+        files.insert(
+            "/mc/game/version_manifest_v2.json".to_string(),
+            manifest.to_string().into_bytes(),
+        );
+        for (id, version_type) in versions.iter() {
+            let details = serde_json::json!({
+                "id": id,
+                "type": version_type,
+                "downloads": {
+                    "server": {
+                        "sha1": sha1,
+                        "size": jar.len(),
+                        "url": format!("{}/v1/objects/{}/server.jar", base_url, id),
+                    },
+                },
+            });
+            files.insert(
+                format!("/v1/packages/{}.json", id),
+                details.to_string().into_bytes(),
+            );
+            let mut served = jar.to_vec();
+            if *id == "1.16.4" {
+                served.reverse();
+            }
+            files.insert(format!("/v1/objects/{}/server.jar", id), served);
+        }
+        files
+    });
+    format!("{}/mc/game/version_manifest_v2.json", base_url)
+}
+
+/// Serves the `velocity` project of the PaperMC API with the versions `3.0.0`, `3.1.1` and
+/// `3.1.2-SNAPSHOT`, each with the builds 1 and 2 that have `jar` as their download, and returns
+/// the base URL of the API.
+///
+/// The jar of `3.1.1` is served reversed, so its hash does not match the announced one.
+pub fn serve_paper(jar: &[u8]) -> String {
+    let sha256: String = Sha256::digest(jar)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let base_url = serve(|_| {
+        let versions = ["3.0.0", "3.1.1", "3.1.2-SNAPSHOT"];
+        let mut files = HashMap::new();
+        let project = serde_json::json!({
+            "project_id": "velocity",
+            "project_name": "Velocity",
+            "version_groups": ["3.0.0", "3.1.0"],
+            "versions": versions,
+        });
+        files.insert(
+            "/v2/projects/velocity".to_string(),
+            project.to_string().into_bytes(),
+        );
+        for version in versions.iter() {
+            let name = format!("velocity-{}-2.jar", version);
+            let builds: Vec<_> = (1..=2)
+                .map(|build| {
+                    serde_json::json!({
+                        "build": build,
+                        "time": "2021-12-24T21:35:15.321Z",
+                        "channel": "default",
+                        "promoted": false,
+                        "changes": [],
+                        "downloads": {
+                            "application": {
+                                "name": format!("velocity-{}-{}.jar", version, build),
+                                "sha256": sha256,
+                            },
+                        },
+                    })
+                })
+                .collect();
+            let path = format!("/v2/projects/velocity/versions/{}/builds", version);
+            let mut build = builds[1].clone();
+            build["project_id"] = "velocity".into();
+            build["project_name"] = "Velocity".into();
+            build["version"] = (*version).into();
+            files.insert(format!("{}/2", path), build.to_string().into_bytes());
+            let list = serde_json::json!({
+                "project_id": "velocity",
+                "project_name": "Velocity",
+                "version": version,
+                "builds": builds,
+            });
+            files.insert(path.clone(), list.to_string().into_bytes());
+            let mut served = jar.to_vec();
+            if *version == "3.1.1" {
+                served.reverse();
+            }
+            files.insert(format!("{}/2/downloads/{}", path, name), served);
+        }
+        files
+    });
+    format!("{}/v2", base_url)
+}
@@ -1,12 +1,17 @@
 //! Basic traits and implementations for dynamic repositories.
 
+#[cfg(test)]
+pub mod fixture;
 pub mod paper;
+pub mod vanilla;
 
 use std::borrow::Cow;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use crate::ipc::install::InstallError;
-use semver::Version;
+use semver::{Identifier, Version};
+use sha2::Digest;
+use std::io::{self, Write};
 use std::path::Path;
 
 #[derive(Debug)]
@@ -83,3 +88,72 @@ pub trait Artifact: Debug {
 
     fn download_to(&self, path: &Path) -> Result<u64, InstallError>;
 }
+
+/// A writer that hashes everything written to the inner writer, used to verify downloads.
+pub(crate) struct HashingWriter<W, D> {
+    /// The writer the data is written to
+    pub inner: W,
+    /// The hash of the data written so far
+    pub hasher: D,
+}
+
+impl<W: Write, D: Digest> Write for HashingWriter<W, D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Returns the hex representation of a hash.
+pub(crate) fn hex(hash: &[u8]) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Parses a version as it is named by Mojang and most server projects, e.g. `1.17` as `1.17.0`
+/// or `1.17-pre1` as `1.17.0-pre1`.
+///
+/// Returns `None` for names that are not of the form `MAJOR.MINOR[.PATCH][-PRE]`.
+pub fn parse_version(id: &str) -> Option<Version> {
+    let (release, pre) = match id.split_once('-') {
+        Some((release, pre)) => (release, Some(pre)),
+        None => (id, None),
+    };
+    let numbers = release
+        .split('.')
+        .map(|number| number.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    let mut version = match numbers.as_slice() {
+        [major, minor] => Version::new(*major, *minor, 0),
+        [major, minor, patch] => Version::new(*major, *minor, *patch),
+        _ => return None,
+    };
+    if let Some(pre) = pre {
+        if pre.is_empty() || !pre.bytes().all(|byte| byte.is_ascii_alphanumeric()) {
+            return None;
+        }
+        version.pre.push(Identifier::AlphaNumeric(pre.to_string()));
+    }
+    Some(version)
+}
+
+/// Returns the name of a version, the inverse of [`parse_version`], e.g. `1.17` for
+/// `1.17.0`.
+///
+/// Build information is ignored.
+pub fn version_id(version: &Version) -> String {
+    let mut id = if version.patch == 0 {
+        format!("{}.{}", version.major, version.minor)
+    } else {
+        format!("{}.{}.{}", version.major, version.minor, version.patch)
+    };
+    for pre in &version.pre {
+        id.push('-');
+        id.push_str(&pre.to_string());
+    }
+    id
+}
@@ -1,15 +1,79 @@
-use std::collections::HashMap;
 use crate::ipc::install::InstallError;
-use crate::repo::{Artifact, Repository, RepositoryError, RepositoryResult};
+use crate::repo::{
+    hex, parse_version, Artifact, HashingWriter, Repository, RepositoryError, RepositoryResult,
+};
+use crate::ServerType;
+use chrono::{DateTime, Utc};
 use semver::{Identifier, Version};
-use std::fs::File;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
 use std::path::Path;
-use chrono::{DateTime, Utc};
+use std::sync::OnceLock;
+
+/// The base URL of the v2 API of PaperMC.
+pub const API_URL: &str = "https://papermc.io/api/v2";
+
+/// A project of PaperMC, all projects are served by the same API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaperProject {
+    /// The Paper server
+    Paper,
+    /// The regionized multithreading fork of Paper
+    Folia,
+    /// The Velocity proxy
+    Velocity,
+    /// The Waterfall proxy, a fork of BungeeCord
+    Waterfall,
+}
+
+impl PaperProject {
+    /// Returns the id of the project in the API, e.g. `paper`.
+    pub fn id(&self) -> &'static str {
+        match self {
+            PaperProject::Paper => "paper",
+            PaperProject::Folia => "folia",
+            PaperProject::Velocity => "velocity",
+            PaperProject::Waterfall => "waterfall",
+        }
+    }
+
+    /// Returns the project that distributes a server type, if the server type is a PaperMC
+    /// project.
+    pub fn for_server_type(server_type: &ServerType) -> Option<Self> {
+        match server_type {
+            ServerType::Paper => Some(PaperProject::Paper),
+            ServerType::Folia => Some(PaperProject::Folia),
+            ServerType::Velocity => Some(PaperProject::Velocity),
+            ServerType::Waterfall => Some(PaperProject::Waterfall),
+            ServerType::Vanilla | ServerType::Spigot | ServerType::Bukkit => None,
+        }
+    }
+
+    /// Returns the server type of the project.
+    pub fn server_type(&self) -> ServerType {
+        match self {
+            PaperProject::Paper => ServerType::Paper,
+            PaperProject::Folia => ServerType::Folia,
+            PaperProject::Velocity => ServerType::Velocity,
+            PaperProject::Waterfall => ServerType::Waterfall,
+        }
+    }
+
+    /// Returns whether the jar of the project is a paperclip jar, which patches the vanilla
+    /// server on its first start. The jars of the proxies run as they are.
+    pub fn is_patched(&self) -> bool {
+        matches!(self, PaperProject::Paper | PaperProject::Folia)
+    }
+}
 
 #[derive(Debug)]
 pub struct PaperArtifact {
     version: Version,
-    download: Download,
+    /// The URL of the jar of the build
+    url: String,
+    /// The SHA-256 hash of the jar as announced by the API
+    sha256: String,
 }
 
 impl Artifact for PaperArtifact {
@@ -17,125 +81,201 @@ impl Artifact for PaperArtifact {
         self.version.clone()
     }
 
+    /// Downloads the jar and checks its hash, the file is removed if it does not match.
     fn download_to(&self, path: &Path) -> Result<u64, InstallError> {
-        let reduced_version =
-            Version::new(self.version.major, self.version.minor, self.version.patch);
-        let build = self.version.build.first().unwrap().to_string();
+        let mut response = reqwest::blocking::get(self.url.as_str())
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| InstallError::DownloadFailed(Box::new(e)))?;
+        let file = File::create(path).map_err(|e| InstallError::DownloadFailed(Box::new(e)))?;
+        let mut writer = HashingWriter {
+            inner: file,
+            hasher: Sha256::new(),
+        };
+        let size = response
+            .copy_to(&mut writer)
+            .map_err(|e| InstallError::DownloadFailed(Box::new(e)))?;
 
+        let sha256 = hex(&writer.hasher.finalize());
+        if sha256.eq_ignore_ascii_case(&self.sha256) {
+            return Ok(size);
+        }
+        let _ = fs::remove_file(path);
+        Err(InstallError::DownloadFailed(Box::new(
+            RepositoryError::owned(format!(
+                "the SHA-256 hash of the jar is {} instead of {}",
+                sha256, self.sha256
+            )),
+        )))
+    }
+}
+
+/// The repository of a PaperMC project.
+pub struct PaperRepository {
+    /// The project of the repository
+    project: PaperProject,
+    /// The base URL of the API
+    api_url: String,
+    /// The versions of the project, fetched once per repository
+    versions: OnceLock<Vec<String>>,
+}
+
+impl PaperRepository {
+    /// Creates the repository of a project.
+    pub fn new(project: PaperProject) -> Self {
+        Self::with_api_url(project, API_URL.to_string())
+    }
+
+    /// Creates the repository of a project that uses the API at the given base URL.
+    pub fn with_api_url(project: PaperProject, api_url: String) -> Self {
+        Self {
+            project,
+            api_url,
+            versions: OnceLock::new(),
+        }
+    }
+
+    /// Returns the project of the repository.
+    pub fn project(&self) -> PaperProject {
+        self.project
+    }
+
+    /// Fetches and parses a JSON document of the project, `path` is relative to the project.
+    fn fetch<T: serde::de::DeserializeOwned>(&self, path: &str) -> RepositoryResult<T> {
         let response = reqwest::blocking::get(
-            format!(
-                "https://papermc.io/api/v2/projects/paper/versions/{}/builds/{}/downloads/{}",
-                reduced_version, build, self.download.name
-            )
-            .as_str(),
-        );
+            format!("{}/projects/{}{}", self.api_url, self.project.id(), path).as_str(),
+        )
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| {
+            RepositoryError::borrowed("failed to execute repository request")
+                .with_inner(Box::new(e))
+        })?;
+
+        response.json().map_err(|e| {
+            RepositoryError::borrowed("failed to parse json response").with_inner(Box::new(e))
+        })
+    }
 
-        match response {
-            Ok(mut response) => {
-                let mut file = File::create(&path).unwrap();
-                Ok(response
-                    .copy_to(&mut file)
-                    .map_err(|e| InstallError::DownloadFailed(Box::new(e)))?)
-            }
-            Err(e) => Err(InstallError::DownloadFailed(Box::new(e))),
+    /// Returns the names of the versions of the project.
+    fn versions(&self) -> RepositoryResult<&[String]> {
+        if let Some(versions) = self.versions.get() {
+            return Ok(versions);
         }
+        let project: ProjectResponse = self.fetch("")?;
+        Ok(self.versions.get_or_init(|| project.versions))
+    }
+
+    /// Returns the name of a version in the API.
+    ///
+    /// The projects name their versions differently (Paper `1.17`, Velocity `3.0.0`), so the name
+    /// is looked up in the versions of the project. Build information is ignored.
+    fn version_string(&self, version: &Version) -> RepositoryResult<String> {
+        let mut version = version.clone();
+        version.build.clear();
+        self.versions()?
+            .iter()
+            .find(|version_string| parse_version(version_string).as_ref() == Some(&version))
+            .cloned()
+            .ok_or_else(|| {
+                RepositoryError::owned(format!("unknown {} version {}", self.project.id(), version))
+            })
     }
-}
 
-pub struct PaperRepository {}
+    /// Returns the artifact of a build.
+    fn artifact(
+        &self,
+        mut version: Version,
+        version_string: &str,
+        build: u64,
+        mut downloads: HashMap<String, Download>,
+    ) -> RepositoryResult<Box<dyn Artifact>> {
+        let download = downloads
+            .remove("application")
+            .ok_or(RepositoryError::borrowed("no application download present"))?;
+        version.build.clear();
+        version.build.push(Identifier::Numeric(build));
+        let url = format!(
+            "{}/projects/{}/versions/{}/builds/{}/downloads/{}",
+            self.api_url,
+            self.project.id(),
+            version_string,
+            build,
+            download.name
+        );
+        Ok(Box::new(PaperArtifact {
+            version,
+            url,
+            sha256: download.sha256,
+        }))
+    }
+}
 
 impl Repository for PaperRepository {
     fn get_artifact(&self, version: Version) -> RepositoryResult<Box<dyn Artifact>> {
         if version.build.is_empty() {
             self.get_latest_artifact(version)
         } else {
-            let build = version.build.first().expect("other case is handled above").to_string();
-            let version_string = if version.patch == 0 {
-                format!("{}.{}", version.major, version.minor)
-            } else {
-                version.to_string()
-            };
-
-            let response = reqwest::blocking::get(
-                &format!("https://papermc.io/api/v2/projects/paper/versions/{}/builds/{}", version_string, build)
-            )
-                .map_err(|e| RepositoryError::borrowed("failed to execute repository request").with_inner(Box::new(e)))?;
-
-            let mut build_response: BuildResponse = response.json().map_err(|e| RepositoryError::borrowed("failed to parse json response").with_inner(Box::new(e)))?;
-
-            let download = build_response.downloads.remove("application").ok_or(RepositoryError::borrowed("no application download present"))?;
-            Ok(
-                Box::new(PaperArtifact { version, download })
+            let build = version
+                .build
+                .first()
+                .expect("other case is handled above")
+                .to_string();
+            let version_string = self.version_string(&version)?;
+
+            let build_response: BuildResponse =
+                self.fetch(&format!("/versions/{}/builds/{}", version_string, build))?;
+            self.artifact(
+                version,
+                &version_string,
+                build_response.build,
+                build_response.downloads,
             )
         }
     }
 
     fn get_latest_artifact(&self, version: Version) -> RepositoryResult<Box<dyn Artifact>> {
-        let version_string = if version.patch == 0 {
-            format!("{}.{}", version.major, version.minor)
-        } else {
-            version.to_string()
-        };
-        let response = reqwest::blocking::get(
-            format!("https://papermc.io/api/v2/projects/paper/versions/{}/builds", version_string).as_str(),
-        )
-            .map_err(|e| RepositoryError::borrowed("failed to execute repository request").with_inner(Box::new(e)))?;
-
-        let build_list: BuildList = response.json().map_err(|e| RepositoryError::borrowed("failed to parse json response").with_inner(Box::new(e)))?;
-
-        let mut last_build = build_list.builds.into_iter().max_by_key(|build| build.build)
-            .ok_or(RepositoryError::borrowed("no build found for the given version"))?;
-
-        let mut vers = version.clone();
-        vers.build.clear();
-        vers.build
-            .push(Identifier::Numeric(last_build.build));
-
-        let download = last_build.downloads.remove("application").ok_or(RepositoryError::borrowed("no application download present"))?;
-        Ok(
-            Box::new(PaperArtifact { version: vers, download })
+        let version_string = self.version_string(&version)?;
+        let build_list: BuildList = self.fetch(&format!("/versions/{}/builds", version_string))?;
+
+        let last_build = build_list
+            .builds
+            .into_iter()
+            .max_by_key(|build| build.build)
+            .ok_or(RepositoryError::borrowed(
+                "no build found for the given version",
+            ))?;
+
+        self.artifact(
+            version,
+            &version_string,
+            last_build.build,
+            last_build.downloads,
         )
     }
 
+    /// Lists the versions of the project, versions that are not semantic versions are skipped.
+    ///
+    /// Pre-releases are kept, e.g. the snapshots of Velocity (`3.1.2-SNAPSHOT`).
     fn list_versions(&self) -> RepositoryResult<Vec<Version>> {
-        let response = reqwest::blocking::get("https://papermc.io/api/v2/projects/paper/").map_err(|e| RepositoryError::borrowed("failed to execute repository request").with_inner(Box::new(e)))?;
-
-        let list: ProjectResponse = response.json().map_err(|e| RepositoryError::borrowed("failed to parse json response").with_inner(Box::new(e)))?;
-
-        list.versions
+        Ok(self
+            .versions()?
             .iter()
-            .map(|version_string| {
-                let vers = lenient_semver::parse(version_string.as_str()).map_err(|e| RepositoryError::borrowed("failed to parse version string"))?;
-                Ok(
-                    Version::new(vers.major, vers.minor, vers.patch)
-                )
-            })
-            .collect()
+            .filter_map(|version_string| parse_version(version_string))
+            .collect())
     }
 
     fn list_builds(&self, mut version: Version) -> RepositoryResult<Vec<Version>> {
-        let version_string = if version.patch == 0 {
-            format!("{}.{}", version.major, version.minor)
-        } else {
-            version.to_string()
-        };
-        let response = reqwest::blocking::get(
-            format!("https://papermc.io/api/v2/projects/paper/versions/{}/builds", version_string).as_str(),
-        )
-            .map_err(|e| RepositoryError::borrowed("failed to execute repository request").with_inner(Box::new(e)))?;
-
-        let list: BuildList = response.json().map_err(|e| RepositoryError::borrowed("failed to parse json response").with_inner(Box::new(e)))?;
+        let version_string = self.version_string(&version)?;
+        let list: BuildList = self.fetch(&format!("/versions/{}/builds", version_string))?;
         version.build.clear();
-        Ok(
-            list.builds
+        Ok(list
+            .builds
             .iter()
             .map(|build_number| {
                 let mut vers = version.clone();
                 vers.build.push(Identifier::Numeric(build_number.build));
                 vers
             })
-            .collect()
-        )
+            .collect())
     }
 }
 
@@ -200,19 +340,22 @@ struct BuildResponse {
 
 #[cfg(test)]
 mod tests {
-    use crate::repo::paper::PaperRepository;
+    use crate::repo::fixture;
+    use crate::repo::paper::{PaperProject, PaperRepository};
     use crate::repo::Repository;
+    use crate::ServerType;
+    use semver::{Identifier, Version};
 
     #[test]
     fn test_versions() {
-        let repo = PaperRepository {};
+        let repo = PaperRepository::new(PaperProject::Paper);
         let versions = repo.list_versions();
         println!("versions: {:?}", versions);
     }
 
     #[test]
     fn test_latest_artifact() {
-        let repo = PaperRepository {};
+        let repo = PaperRepository::new(PaperProject::Paper);
         let latest_version = repo.latest_version();
         assert!(latest_version.is_ok());
         let latest_version = latest_version.unwrap();
@@ -221,4 +364,68 @@ mod tests {
 
         println!("latest artifact: {:?}", latest_artifact)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_projects() {
+        for server_type in [
+            ServerType::Paper,
+            ServerType::Folia,
+            ServerType::Velocity,
+            ServerType::Waterfall,
+        ] {
+            let project = PaperProject::for_server_type(&server_type).expect("paper project");
+            assert_eq!(
+                PaperProject::for_server_type(&project.server_type()),
+                Some(project)
+            );
+            assert_eq!(project.is_patched(), !server_type.is_proxy());
+        }
+        assert_eq!(PaperProject::for_server_type(&ServerType::Spigot), None);
+    }
+
+    #[test]
+    fn test_velocity_repository() {
+        let jar = b"velocity proxy jar".to_vec();
+        let repo =
+            PaperRepository::with_api_url(PaperProject::Velocity, fixture::serve_paper(&jar));
+
+        let snapshot = Version::parse("3.1.2-SNAPSHOT").expect("parse version");
+        assert_eq!(repo.latest_version().expect("latest version"), snapshot);
+
+        // Velocity names its versions with a patch version, even if it is zero
+        let mut build = Version::new(3, 0, 0);
+        build.build.push(Identifier::Numeric(2));
+        let artifact = repo
+            .get_latest_artifact(Version::new(3, 0, 0))
+            .expect("latest artifact");
+        assert_eq!(artifact.version(), build);
+        assert_eq!(
+            repo.list_builds(Version::new(3, 0, 0))
+                .expect("list builds")
+                .len(),
+            2
+        );
+        assert!(repo.get_latest_artifact(Version::new(3, 2, 0)).is_err());
+
+        let dir = tempfile::tempdir().expect("create temporary directory");
+        let path = dir.path().join("velocity.jar");
+        let artifact = repo.get_artifact(build).expect("artifact of build");
+        assert_eq!(
+            artifact.download_to(&path).expect("download jar"),
+            jar.len() as u64
+        );
+        assert_eq!(std::fs::read(&path).expect("read jar"), jar);
+
+        let artifact = repo
+            .get_latest_artifact(snapshot)
+            .expect("snapshot artifact");
+        assert!(artifact.download_to(&path).is_ok());
+
+        // the fixture serves a different jar for 3.1.1 than the one announced by its hash
+        let artifact = repo
+            .get_latest_artifact(Version::new(3, 1, 1))
+            .expect("artifact with wrong hash");
+        assert!(artifact.download_to(&path).is_err());
+        assert!(!path.exists());
+    }
+}
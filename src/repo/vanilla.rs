@@ -0,0 +1,313 @@
+//! Repository of the vanilla server, backed by the version manifest of Mojang.
+//!
+//! The manifest lists every version with its type and the URL of a per-version JSON document,
+//! which contains the download of the server jar and its SHA-1 hash. Vanilla servers have no
+//! builds, so the versions of this repository never carry build information.
+
+use crate::ipc::install::InstallError;
+use crate::repo::{
+    hex, parse_version, Artifact, HashingWriter, Repository, RepositoryError, RepositoryResult,
+};
+use semver::Version;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::Path;
+
+/// The URL of the version manifest of Mojang.
+pub const MANIFEST_URL: &str = "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
+
+/// The type of a vanilla version.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionType {
+    /// A release
+    Release,
+    /// A snapshot, pre-release or release candidate
+    Snapshot,
+    /// A beta version from before the first release
+    OldBeta,
+    /// An alpha version from before the first release
+    OldAlpha,
+}
+
+/// The server jar of a vanilla version.
+#[derive(Debug)]
+pub struct VanillaArtifact {
+    /// The version of the artifact
+    version: Version,
+    /// The type of the version
+    version_type: VersionType,
+    /// The download of the server jar
+    download: Download,
+}
+
+impl VanillaArtifact {
+    /// Returns the type of the version of the artifact.
+    pub fn version_type(&self) -> VersionType {
+        self.version_type
+    }
+
+    /// Returns the hex encoded SHA-1 hash of the server jar.
+    pub fn sha1(&self) -> &str {
+        &self.download.sha1
+    }
+}
+
+impl Artifact for VanillaArtifact {
+    fn version(&self) -> Version {
+        self.version.clone()
+    }
+
+    /// Downloads the server jar and checks its size and hash, the file is removed if they do
+    /// not match.
+    fn download_to(&self, path: &Path) -> Result<u64, InstallError> {
+        let mut response = reqwest::blocking::get(self.download.url.as_str())
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| InstallError::DownloadFailed(Box::new(e)))?;
+        let file = File::create(path).map_err(|e| InstallError::DownloadFailed(Box::new(e)))?;
+        let mut writer = HashingWriter {
+            inner: file,
+            hasher: Sha1::new(),
+        };
+        let size = response
+            .copy_to(&mut writer)
+            .map_err(|e| InstallError::DownloadFailed(Box::new(e)))?;
+
+        let sha1 = hex(&writer.hasher.finalize());
+        let error = if size != self.download.size {
+            format!(
+                "the server jar has {} bytes instead of {}",
+                size, self.download.size
+            )
+        } else if !sha1.eq_ignore_ascii_case(&self.download.sha1) {
+            format!(
+                "the SHA-1 hash of the server jar is {} instead of {}",
+                sha1, self.download.sha1
+            )
+        } else {
+            return Ok(size);
+        };
+        let _ = fs::remove_file(path);
+        Err(InstallError::DownloadFailed(Box::new(
+            RepositoryError::owned(error),
+        )))
+    }
+}
+
+/// The repository of vanilla servers.
+pub struct VanillaRepository {
+    /// The URL of the version manifest
+    manifest_url: String,
+}
+
+impl Default for VanillaRepository {
+    fn default() -> Self {
+        Self::new(MANIFEST_URL.to_string())
+    }
+}
+
+impl VanillaRepository {
+    /// Creates a repository that uses the version manifest at the given URL.
+    pub fn new(manifest_url: String) -> Self {
+        Self { manifest_url }
+    }
+
+    /// Fetches and parses a JSON document.
+    fn fetch<T: serde::de::DeserializeOwned>(url: &str) -> RepositoryResult<T> {
+        let response = reqwest::blocking::get(url)
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                RepositoryError::borrowed("failed to execute repository request")
+                    .with_inner(Box::new(e))
+            })?;
+        response.json().map_err(|e| {
+            RepositoryError::borrowed("failed to parse json response").with_inner(Box::new(e))
+        })
+    }
+
+    /// Fetches the version manifest.
+    fn manifest(&self) -> RepositoryResult<VersionManifest> {
+        Self::fetch(&self.manifest_url)
+    }
+
+    /// Returns the artifact of a version, including the type of the version.
+    ///
+    /// Build information of the version is ignored.
+    pub fn artifact(&self, version: &Version) -> RepositoryResult<VanillaArtifact> {
+        let mut version = version.clone();
+        version.build.clear();
+        let entry = self
+            .manifest()?
+            .versions
+            .into_iter()
+            .find(|entry| parse_version(&entry.id).as_ref() == Some(&version))
+            .ok_or_else(|| RepositoryError::owned(format!("unknown version {}", version)))?;
+
+        let mut details: VersionDetails = Self::fetch(&entry.url)?;
+        let download = details
+            .downloads
+            .remove("server")
+            .ok_or(RepositoryError::borrowed("no server download present"))?;
+        Ok(VanillaArtifact {
+            version,
+            version_type: details.version_type,
+            download,
+        })
+    }
+}
+
+impl Repository for VanillaRepository {
+    fn get_artifact(&self, version: Version) -> RepositoryResult<Box<dyn Artifact>> {
+        Ok(Box::new(self.artifact(&version)?))
+    }
+
+    fn get_latest_artifact(&self, version: Version) -> RepositoryResult<Box<dyn Artifact>> {
+        self.get_artifact(version)
+    }
+
+    /// Lists all versions of the manifest whose id can be expressed as a semantic version.
+    ///
+    /// Weekly snapshots (e.g. `21w03a`) are skipped, pre-releases and release candidates are
+    /// listed as pre-release versions (e.g. `1.17.0-pre1`).
+    fn list_versions(&self) -> RepositoryResult<Vec<Version>> {
+        Ok(self
+            .manifest()?
+            .versions
+            .iter()
+            .filter_map(|entry| parse_version(&entry.id))
+            .collect())
+    }
+
+    fn list_builds(&self, version: Version) -> RepositoryResult<Vec<Version>> {
+        Ok(self
+            .list_versions()?
+            .into_iter()
+            .filter(|listed| *listed == version)
+            .collect())
+    }
+
+    /// Returns the latest release, snapshots are only installed if they are requested.
+    fn latest_version(&self) -> RepositoryResult<Version> {
+        let latest = self.manifest()?.latest.release;
+        parse_version(&latest)
+            .ok_or_else(|| RepositoryError::owned(format!("invalid latest release {}", latest)))
+    }
+}
+
+/// The version manifest, the fields that are not needed are skipped.
+#[derive(Deserialize, Debug)]
+struct VersionManifest {
+    /// The ids of the latest versions
+    latest: LatestVersions,
+    /// All versions, the newest first
+    versions: Vec<ManifestEntry>,
+}
+
+/// The ids of the latest versions in the manifest.
+#[derive(Deserialize, Debug)]
+struct LatestVersions {
+    /// The id of the latest release
+    release: String,
+}
+
+/// A version in the manifest.
+#[derive(Deserialize, Debug)]
+struct ManifestEntry {
+    /// The id of the version, e.g. `1.16.5` or `21w03a`
+    id: String,
+    /// The URL of the JSON document of the version
+    url: String,
+}
+
+/// The JSON document of a version.
+#[derive(Deserialize, Debug)]
+struct VersionDetails {
+    /// The type of the version
+    #[serde(rename = "type")]
+    version_type: VersionType,
+    /// The downloads of the version, keyed by their name (e.g. `server` or `client`)
+    downloads: HashMap<String, Download>,
+}
+
+/// A download of a version.
+#[derive(Deserialize, Debug)]
+struct Download {
+    /// The hex encoded SHA-1 hash of the file
+    sha1: String,
+    /// The size of the file in bytes
+    size: u64,
+    /// The URL of the file
+    url: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ipc::install::InstallError;
+    use crate::repo::fixture;
+    use crate::repo::vanilla::{VanillaRepository, VersionType};
+    use crate::repo::{parse_version, version_id, Artifact, Repository};
+    use semver::Version;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("1.16.5"), Some(Version::new(1, 16, 5)));
+        assert_eq!(parse_version("1.17"), Some(Version::new(1, 17, 0)));
+        assert_eq!(
+            parse_version("1.17-pre1"),
+            Some(Version::parse("1.17.0-pre1").expect("parse version"))
+        );
+        assert_eq!(parse_version("21w03a"), None);
+        assert_eq!(parse_version("1.14 Pre-Release 5"), None);
+        assert_eq!(parse_version("1.RV-Pre1"), None);
+
+        for id in ["1.16.5", "1.17", "1.17-pre1"] {
+            assert_eq!(version_id(&parse_version(id).expect("parse version")), id);
+        }
+    }
+
+    #[test]
+    fn test_repository() {
+        let jar = b"vanilla server jar".to_vec();
+        let repo = VanillaRepository::new(fixture::serve_vanilla(&jar));
+
+        assert_eq!(
+            repo.list_versions().expect("list versions"),
+            vec![
+                Version::parse("1.17.0-pre1").expect("parse version"),
+                Version::new(1, 16, 5),
+                Version::new(1, 16, 4),
+            ]
+        );
+        let latest = repo.latest_version().expect("latest version");
+        assert_eq!(latest, Version::new(1, 16, 5));
+        assert_eq!(
+            repo.list_builds(latest.clone()).expect("list builds"),
+            vec![latest.clone()]
+        );
+
+        let snapshot = repo
+            .artifact(&Version::parse("1.17.0-pre1").expect("parse version"))
+            .expect("snapshot artifact");
+        assert_eq!(snapshot.version_type(), VersionType::Snapshot);
+        let release = repo.artifact(&latest).expect("release artifact");
+        assert_eq!(release.version_type(), VersionType::Release);
+        assert!(repo.artifact(&Version::new(1, 15, 2)).is_err());
+
+        let dir = tempfile::tempdir().expect("create temporary directory");
+        let path = dir.path().join("server.jar");
+        assert_eq!(
+            release.download_to(&path).expect("download jar"),
+            jar.len() as u64
+        );
+        assert_eq!(std::fs::read(&path).expect("read jar"), jar);
+
+        // the fixture serves a different jar for 1.16.4 than the one announced by its hash
+        let damaged = repo.artifact(&Version::new(1, 16, 4)).expect("artifact");
+        assert!(matches!(
+            damaged.download_to(&path),
+            Err(InstallError::DownloadFailed(_))
+        ));
+        assert!(!path.exists());
+    }
+}